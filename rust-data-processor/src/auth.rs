@@ -0,0 +1,531 @@
+//! Bearer token authentication against an OpenID Connect identity provider. Tokens are JWTs
+//! signed with one of the provider's published keys (RS256/384/512 or ES256/384); the claim
+//! named by `roles_claim` is mapped to a `Role`, and each route needs at least the role
+//! `required_role` gives it.
+//!
+//! Keys come inline from the config, from `jwks_url`, or from the issuer's discovery document.
+//! Fetched keys are refreshed hourly, and sooner when a token names a key id that is not
+//! known yet, so the provider can rotate keys without a restart.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+/// How long fetched keys are used before they are fetched again.
+const JWKS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// Least time between fetches, so tokens naming unknown key ids cannot hammer the provider.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+const JWKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Reads jobs, results, sources and metrics
+    Viewer,
+    /// Also submits, cancels and changes jobs, sources, queries and schedules
+    Operator,
+    /// Also manages credentials, calendars, maintenance windows and bulk job imports and purges
+    Admin,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthConfig {
+    /// Must equal the token's `iss`; also where the discovery document is looked up
+    pub issuer: String,
+    /// Must be the token's `aud` or one of them
+    pub audience: String,
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    /// Keys to trust instead of fetching them
+    #[serde(default)]
+    pub jwks: Option<Jwks>,
+    /// Dotted path to the claim holding the caller's roles or groups, such as
+    /// `realm_access.roles`; a string claim is split on whitespace like `scope`
+    #[serde(default = "default_roles_claim")]
+    pub roles_claim: String,
+    /// Claim values to the role they grant; when empty, the values `viewer`, `operator` and
+    /// `admin` grant those roles
+    #[serde(default)]
+    pub role_map: HashMap<String, Role>,
+    /// Clock skew allowed when checking `exp` and `nbf`
+    #[serde(default = "default_leeway_secs")]
+    pub leeway_secs: i64,
+}
+
+fn default_roles_claim() -> String {
+    "roles".to_string()
+}
+
+fn default_leeway_secs() -> i64 {
+    60
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub alg: Option<String>,
+    #[serde(default)]
+    pub n: Option<String>,
+    #[serde(default)]
+    pub e: Option<String>,
+    #[serde(default)]
+    pub crv: Option<String>,
+    #[serde(default)]
+    pub x: Option<String>,
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+/// Why a request was turned away.
+#[derive(Debug)]
+pub enum Denied {
+    /// No token, or one that does not verify (401)
+    Unauthenticated(String),
+    /// A valid token whose role is too low for the route (403)
+    Forbidden(String),
+}
+
+impl warp::reject::Reject for Denied {}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+}
+
+pub struct Authenticator {
+    config: AuthConfig,
+    client: reqwest::Client,
+    keys: RwLock<KeyCache>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> Result<Self, String> {
+        if config.issuer.is_empty() || config.audience.is_empty() {
+            return Err("issuer and audience are required".to_string());
+        }
+        if config.jwks.as_ref().is_some_and(|jwks| jwks.keys.is_empty()) {
+            return Err("jwks has no keys".to_string());
+        }
+        let client = reqwest::Client::builder().timeout(JWKS_TIMEOUT).build().map_err(|e| e.to_string())?;
+        let keys = KeyCache { keys: config.jwks.clone().unwrap_or_default().keys, fetched_at: None };
+        Ok(Self { config, client, keys: RwLock::new(keys) })
+    }
+
+    /// Fetches the provider's keys unless they were given inline.
+    pub async fn refresh(&self) -> Result<usize, String> {
+        if self.config.jwks.is_some() {
+            return Ok(self.keys.read().await.keys.len());
+        }
+        let url = match &self.config.jwks_url {
+            Some(url) => url.clone(),
+            None => {
+                let discovery = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+                self.fetch::<Discovery>(&discovery).await?.jwks_uri
+            },
+        };
+        let jwks: Jwks = self.fetch(&url).await?;
+        let count = jwks.keys.len();
+        *self.keys.write().await = KeyCache { keys: jwks.keys, fetched_at: Some(Instant::now()) };
+        Ok(count)
+    }
+
+    async fn fetch<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let response = self.client.get(url).send().await.map_err(|e| format!("Could not fetch {}: {}", url, e))?;
+        let response = response.error_for_status().map_err(|e| format!("Could not fetch {}: {}", url, e))?;
+        response.json().await.map_err(|e| format!("Invalid response from {}: {}", url, e))
+    }
+
+    /// Checks a request's token against the role its route needs.
+    pub async fn authorize(&self, method: &str, path: &str, token: Option<&str>) -> Result<(), Denied> {
+        let Some(required) = required_role(method, path) else {
+            return Ok(());
+        };
+        let token = token.ok_or_else(|| Denied::Unauthenticated("A bearer token is required".to_string()))?;
+        let role = self.role(token).await.map_err(Denied::Unauthenticated)?
+            .ok_or_else(|| Denied::Forbidden("The token grants no role".to_string()))?;
+        if role < required {
+            return Err(Denied::Forbidden(format!(
+                "Role {} may not {} {}; {} is required",
+                role.name(), method, path, required.name(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// The highest role a verified token grants, if any.
+    pub async fn role(&self, token: &str) -> Result<Option<Role>, String> {
+        let claims = self.verify(token).await?;
+        let values = claim_values(&claims, &self.config.roles_claim);
+        let role = values.iter()
+            .filter_map(|value| match self.config.role_map.is_empty() {
+                true => serde_json::from_value(Value::String(value.to_string())).ok(),
+                false => self.config.role_map.get(*value).copied(),
+            })
+            .max();
+        Ok(role)
+    }
+
+    /// Checks the signature and the registered claims, returning the token's claims.
+    async fn verify(&self, token: &str) -> Result<Value, String> {
+        let [header, payload, signature] = token.split('.').collect::<Vec<_>>()[..] else {
+            return Err("Malformed token".to_string());
+        };
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| "Malformed token".to_string());
+        let header: TokenHeader = serde_json::from_slice(&decode(header)?).map_err(|_| "Malformed token header".to_string())?;
+        let claims: Value = serde_json::from_slice(&decode(payload)?).map_err(|_| "Malformed token claims".to_string())?;
+        let key = self.key(header.kid.as_deref()).await?;
+        if key.alg.as_deref().is_some_and(|alg| alg != header.alg) {
+            return Err(format!("Key {} is not used with {}", key.kid.as_deref().unwrap_or("?"), header.alg));
+        }
+        let message = token.rsplit_once('.').map_or("", |(message, _)| message);
+        verify_signature(&key, &header.alg, message.as_bytes(), &decode(signature)?)?;
+
+        if claims.get("iss").and_then(Value::as_str) != Some(self.config.issuer.as_str()) {
+            return Err("Token was not issued by the configured issuer".to_string());
+        }
+        let audience = match claims.get("aud") {
+            Some(Value::String(aud)) => *aud == self.config.audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(self.config.audience.as_str())),
+            _ => false,
+        };
+        if !audience {
+            return Err("Token is not for this audience".to_string());
+        }
+        let now = Utc::now().timestamp();
+        let exp = claims.get("exp").and_then(Value::as_i64).ok_or("Token has no expiry")?;
+        if now > exp + self.config.leeway_secs {
+            return Err("Token has expired".to_string());
+        }
+        if claims.get("nbf").and_then(Value::as_i64).is_some_and(|nbf| now + self.config.leeway_secs < nbf) {
+            return Err("Token is not valid yet".to_string());
+        }
+        Ok(claims)
+    }
+
+    /// The key with this id, fetching the keys again when they are stale or it is not among them.
+    async fn key(&self, kid: Option<&str>) -> Result<Jwk, String> {
+        let find = |cache: &KeyCache| match kid {
+            Some(kid) => cache.keys.iter().find(|key| key.kid.as_deref() == Some(kid)).cloned(),
+            // Without a key id only an unambiguous key will do
+            None => match cache.keys.as_slice() {
+                [key] => Some(key.clone()),
+                _ => None,
+            },
+        };
+        let (found, fetched_at) = {
+            let cache = self.keys.read().await;
+            (find(&cache), cache.fetched_at)
+        };
+        let stale = fetched_at.is_none_or(|at| at.elapsed() > JWKS_MAX_AGE);
+        let may_refresh = fetched_at.is_none_or(|at| at.elapsed() > JWKS_MIN_REFRESH);
+        if self.config.jwks.is_none() && may_refresh && (stale || found.is_none()) {
+            match self.refresh().await {
+                Ok(_) => return find(&*self.keys.read().await).ok_or_else(|| unknown_key(kid)),
+                // Keys that have merely grown old are still better than none
+                Err(e) if found.is_none() => return Err(e),
+                Err(e) => eprintln!("Could not refresh signing keys: {}", e),
+            }
+        }
+        found.ok_or_else(|| unknown_key(kid))
+    }
+}
+
+fn unknown_key(kid: Option<&str>) -> String {
+    match kid {
+        Some(kid) => format!("Unknown signing key {}", kid),
+        None => "Token names no signing key".to_string(),
+    }
+}
+
+fn verify_signature(key: &Jwk, alg: &str, message: &[u8], signature: &[u8]) -> Result<(), String> {
+    let part = |value: &Option<String>, name: &str| {
+        let value = value.as_deref().ok_or_else(|| format!("Signing key has no {}", name))?;
+        URL_SAFE_NO_PAD.decode(value).map_err(|_| format!("Signing key has an invalid {}", name))
+    };
+    let verified = match (key.kty.as_str(), alg) {
+        ("RSA", "RS256" | "RS384" | "RS512") => {
+            let parameters = match alg {
+                "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+            };
+            RsaPublicKeyComponents { n: part(&key.n, "n")?, e: part(&key.e, "e")? }.verify(parameters, message, signature)
+        },
+        ("EC", "ES256" | "ES384") => {
+            let (algorithm, curve) = match alg {
+                "ES256" => (&signature::ECDSA_P256_SHA256_FIXED, "P-256"),
+                _ => (&signature::ECDSA_P384_SHA384_FIXED, "P-384"),
+            };
+            if key.crv.as_deref() != Some(curve) {
+                return Err(format!("{} needs a {} key", alg, curve));
+            }
+            // An uncompressed point: 0x04 then the coordinates
+            let mut point = vec![4];
+            point.extend(part(&key.x, "x")?);
+            point.extend(part(&key.y, "y")?);
+            UnparsedPublicKey::new(algorithm, point).verify(message, signature)
+        },
+        _ => return Err(format!("Unsupported token algorithm {} for a {} key", alg, key.kty)),
+    };
+    verified.map_err(|_| "Invalid token signature".to_string())
+}
+
+/// The strings at a dotted claim path: an array's strings, or a string's words.
+fn claim_values<'a>(claims: &'a Value, path: &str) -> Vec<&'a str> {
+    let claim = path.split('.').try_fold(claims, |value, key| value.get(key));
+    match claim {
+        Some(Value::String(value)) => value.split_whitespace().collect(),
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The least role a route needs, or `None` for routes anyone may call: health checks, the API
+/// description, and export and share links, which carry their own tokens. Takes concrete
+/// paths, with or without their `/api/v{N}` prefix, as well as the `{id}` templates in the API
+/// description.
+pub fn required_role(method: &str, path: &str) -> Option<Role> {
+    let method = method.to_ascii_uppercase();
+    let segments: Vec<&str> = crate::versioning::unversioned(path).trim_matches('/').split('/').collect();
+    let read = matches!(method.as_str(), "GET" | "HEAD");
+    match segments[..] {
+        ["health"] | ["health", "live" | "ready"] | ["readyz"] | ["openapi.json"] | ["docs"] | ["exports", _] | ["shared", _] => None,
+        ["credentials", ..] | ["admin", ..] => Some(Role::Admin),
+        ["jobs", "import"] if !read => Some(Role::Admin),
+        ["jobs"] if method == "DELETE" => Some(Role::Admin),
+        ["calendars", ..] | ["maintenance-windows", ..] if !read => Some(Role::Admin),
+        ["jobs", "estimate"] | ["graphql"] => Some(Role::Viewer),
+        _ if read => Some(Role::Viewer),
+        _ => Some(Role::Operator),
+    }
+}
+
+/// Routes whose clients (browser `EventSource` and `WebSocket`) cannot send headers, so they
+/// may pass the token as `access_token` in the query string instead.
+pub fn takes_query_token(path: &str) -> bool {
+    path == "/ws" || (path.starts_with("/jobs/") && path.ends_with("/events"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://issuer.example.com";
+    const AUDIENCE: &str = "data-processor";
+
+    struct Signer {
+        key_pair: EcdsaKeyPair,
+        rng: SystemRandom,
+    }
+
+    impl Signer {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+            Signer { key_pair, rng }
+        }
+
+        fn jwk(&self, kid: &str) -> Jwk {
+            // The public key is an uncompressed point: 0x04 then x and y
+            let point = self.key_pair.public_key().as_ref();
+            Jwk {
+                kty: "EC".to_string(),
+                kid: Some(kid.to_string()),
+                alg: Some("ES256".to_string()),
+                n: None,
+                e: None,
+                crv: Some("P-256".to_string()),
+                x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+                y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+            }
+        }
+
+        fn token(&self, header: Value, claims: Value) -> String {
+            let message = format!(
+                "{}.{}",
+                URL_SAFE_NO_PAD.encode(header.to_string()),
+                URL_SAFE_NO_PAD.encode(claims.to_string()),
+            );
+            let signature = self.key_pair.sign(&self.rng, message.as_bytes()).unwrap();
+            format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+        }
+    }
+
+    fn authenticator(signer: &Signer) -> Authenticator {
+        Authenticator::new(AuthConfig {
+            issuer: ISSUER.to_string(),
+            audience: AUDIENCE.to_string(),
+            jwks_url: None,
+            jwks: Some(Jwks { keys: vec![signer.jwk("k1")] }),
+            roles_claim: default_roles_claim(),
+            role_map: HashMap::new(),
+            leeway_secs: default_leeway_secs(),
+        }).unwrap()
+    }
+
+    fn claims() -> Value {
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "exp": Utc::now().timestamp() + 600,
+            "roles": ["operator"],
+        })
+    }
+
+    #[tokio::test]
+    async fn verifies_a_signed_token() {
+        let signer = Signer::new();
+        let token = signer.token(json!({"alg": "ES256", "kid": "k1"}), claims());
+        let verified = authenticator(&signer).verify(&token).await.unwrap();
+        assert_eq!(verified["roles"], json!(["operator"]));
+    }
+
+    #[tokio::test]
+    async fn rejects_an_unknown_key_id() {
+        let signer = Signer::new();
+        let token = signer.token(json!({"alg": "ES256", "kid": "k2"}), claims());
+        let error = authenticator(&signer).verify(&token).await.unwrap_err();
+        assert_eq!(error, "Unknown signing key k2");
+    }
+
+    #[tokio::test]
+    async fn rejects_an_algorithm_the_key_is_not_used_with() {
+        let signer = Signer::new();
+        let token = signer.token(json!({"alg": "ES384", "kid": "k1"}), claims());
+        let error = authenticator(&signer).verify(&token).await.unwrap_err();
+        assert_eq!(error, "Key k1 is not used with ES384");
+    }
+
+    #[tokio::test]
+    async fn rejects_a_token_signed_by_another_key() {
+        let (signer, impostor) = (Signer::new(), Signer::new());
+        let token = impostor.token(json!({"alg": "ES256", "kid": "k1"}), claims());
+        let error = authenticator(&signer).verify(&token).await.unwrap_err();
+        assert_eq!(error, "Invalid token signature");
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_and_not_yet_valid_tokens() {
+        let signer = Signer::new();
+        let authenticator = authenticator(&signer);
+        let now = Utc::now().timestamp();
+
+        let mut expired = claims();
+        expired["exp"] = json!(now - default_leeway_secs() - 10);
+        let token = signer.token(json!({"alg": "ES256", "kid": "k1"}), expired);
+        assert_eq!(authenticator.verify(&token).await.unwrap_err(), "Token has expired");
+
+        // Within the leeway a token that just expired is still accepted
+        let mut skewed = claims();
+        skewed["exp"] = json!(now - default_leeway_secs() / 2);
+        let token = signer.token(json!({"alg": "ES256", "kid": "k1"}), skewed);
+        assert!(authenticator.verify(&token).await.is_ok());
+
+        let mut early = claims();
+        early["nbf"] = json!(now + default_leeway_secs() + 60);
+        let token = signer.token(json!({"alg": "ES256", "kid": "k1"}), early);
+        assert_eq!(authenticator.verify(&token).await.unwrap_err(), "Token is not valid yet");
+    }
+
+    #[tokio::test]
+    async fn accepts_the_audience_among_several() {
+        let signer = Signer::new();
+        let authenticator = authenticator(&signer);
+
+        let mut listed = claims();
+        listed["aud"] = json!(["other", AUDIENCE]);
+        let token = signer.token(json!({"alg": "ES256", "kid": "k1"}), listed);
+        assert!(authenticator.verify(&token).await.is_ok());
+
+        let mut missing = claims();
+        missing["aud"] = json!(["other", "another"]);
+        let token = signer.token(json!({"alg": "ES256", "kid": "k1"}), missing);
+        assert_eq!(authenticator.verify(&token).await.unwrap_err(), "Token is not for this audience");
+    }
+
+    #[test]
+    fn verify_signature_checks_the_key_type_and_curve() {
+        let signer = Signer::new();
+        let key = signer.jwk("k1");
+        let error = verify_signature(&key, "RS256", b"message", b"signature").unwrap_err();
+        assert_eq!(error, "Unsupported token algorithm RS256 for a EC key");
+        let error = verify_signature(&key, "ES384", b"message", b"signature").unwrap_err();
+        assert_eq!(error, "ES384 needs a P-384 key");
+        let error = verify_signature(&key, "ES256", b"message", b"signature").unwrap_err();
+        assert_eq!(error, "Invalid token signature");
+    }
+
+    #[test]
+    fn required_role_by_route() {
+        assert_eq!(required_role("GET", "/health"), None);
+        assert_eq!(required_role("GET", "/exports/abc"), None);
+        assert_eq!(required_role("GET", "/jobs"), Some(Role::Viewer));
+        assert_eq!(required_role("POST", "/jobs"), Some(Role::Operator));
+        assert_eq!(required_role("DELETE", "/jobs"), Some(Role::Admin));
+        assert_eq!(required_role("POST", "/jobs/estimate"), Some(Role::Viewer));
+        assert_eq!(required_role("GET", "/credentials"), Some(Role::Admin));
+        assert_eq!(required_role("GET", "/calendars/{id}"), Some(Role::Viewer));
+        assert_eq!(required_role("PUT", "/calendars/{id}"), Some(Role::Admin));
+    }
+
+    #[test]
+    fn versioned_paths_need_the_role_of_their_unversioned_form() {
+        let routes = [
+            ("GET", "/health"),
+            ("GET", "/exports/abc"),
+            ("GET", "/jobs"),
+            ("POST", "/jobs"),
+            ("DELETE", "/jobs"),
+            ("POST", "/jobs/import"),
+            ("GET", "/credentials"),
+            ("POST", "/admin/scheduler/pause"),
+            ("PUT", "/maintenance-windows/{id}"),
+        ];
+        for (method, path) in routes {
+            let versioned = format!("/api/v1{}", path);
+            assert_eq!(required_role(method, &versioned), required_role(method, path), "{} {}", method, versioned);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Cron expressions for schedule triggers: the five classic fields (minute, hour, day of month,
+//! month, day of week) or one of the `@hourly` style shorthands, evaluated in UTC.
+//!
+//! A field is `*`, a value, a range `a-b`, a step `*/n` or `a-b/n`, or a comma list of those.
+//! Months and weekdays also take three-letter names (`JAN`, `MON`) and Sunday is 0 or 7. As in
+//! classic cron, when both day of month and day of week are restricted, a day matching either
+//! one fires.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+const MONTHS: [&str; 12] = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// Days searched for the next fire time; long enough for any leap day rule to come round.
+const MAX_SEARCH_DAYS: usize = 8 * 366;
+
+/// Each field is a bit set of the values it allows. Serialized as the text it was parsed from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronExpression {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day of month or day of week was anything but `*`
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronExpression {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let expanded = match source.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Cron expression '{}' needs 5 fields, found {}", source, fields.len()));
+        };
+        let invalid = |e: String| format!("Invalid cron expression '{}': {}", source, e);
+        // 7 is Sunday too, so it folds onto 0
+        let weekday_bits = parse_field(weekdays, 0, 7, &WEEKDAYS).map_err(invalid)?;
+        Ok(Self {
+            source: source.trim().to_string(),
+            minutes: parse_field(minutes, 0, 59, &[]).map_err(invalid)?,
+            hours: parse_field(hours, 0, 23, &[]).map_err(invalid)?,
+            days: parse_field(days, 1, 31, &[]).map_err(invalid)?,
+            months: parse_field(months, 1, 12, &MONTHS).map_err(invalid)?,
+            weekdays: (weekday_bits | (weekday_bits >> 7)) & 0x7f,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+
+    /// The first matching minute strictly after `after`, or `None` when the expression can
+    /// never match (such as `0 0 30 2 *`).
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after + Duration::minutes(1);
+        let first_day = start.date_naive();
+        for date in first_day.iter_days().take(MAX_SEARCH_DAYS) {
+            if !self.matches_day(date) {
+                continue;
+            }
+            let (from_hour, from_minute) = match date == first_day {
+                true => (start.hour(), start.minute()),
+                false => (0, 0),
+            };
+            for hour in (from_hour..24).filter(|hour| has(self.hours, *hour)) {
+                let from = if hour == from_hour { from_minute } else { 0 };
+                if let Some(minute) = (from..60).find(|minute| has(self.minutes, *minute)) {
+                    return date.and_hms_opt(hour, minute, 0).map(|fire_at| fire_at.and_utc());
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if !has(self.months, date.month()) {
+            return false;
+        }
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl TryFrom<String> for CronExpression {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Self::parse(&source)
+    }
+}
+
+impl From<CronExpression> for String {
+    fn from(expression: CronExpression) -> Self {
+        expression.source
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let named = names.iter().position(|name| name.eq_ignore_ascii_case(text));
+        let value = match named {
+            // Month names count from 1, weekday names from 0
+            Some(index) => index as u32 + min,
+            None => text.parse::<u32>().map_err(|_| format!("'{}' is not a number", text))?,
+        };
+        if value < min || value > max {
+            return Err(format!("{} is outside {}-{}", value, min, max));
+        }
+        Ok(value)
+    };
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse::<u32>().ok().filter(|step| *step > 0)
+                    .ok_or_else(|| format!("'{}' is not a valid step", step))?;
+                (range, step)
+            },
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/15` runs from 5 to the end of the field
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("range {}-{} runs backwards", start, end));
+        }
+        bits |= (start..=end).step_by(step as usize).fold(0, |bits, value| bits | (1 << value));
+    }
+    Ok(bits)
+}
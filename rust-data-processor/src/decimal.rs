@@ -0,0 +1,109 @@
+//! Exact decimal handling for numbers that must not pass through `f64`: large integer IDs and
+//! monetary amounts. JSON numbers keep the text they were read with, so they are converted to
+//! `BigDecimal` from that text and written back the same way.
+
+use std::str::FromStr;
+
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+/// Exact view of a number, or of a string that reads as one.
+pub fn to_decimal(value: &Value) -> Option<BigDecimal> {
+    match value {
+        Value::Number(n) => BigDecimal::from_str(&n.to_string()).ok(),
+        Value::String(text) => BigDecimal::from_str(text.trim()).ok(),
+        _ => None,
+    }
+}
+
+/// A JSON number carrying every digit of `decimal`, trailing fractional zeros included.
+pub fn decimal_value(decimal: &BigDecimal) -> Value {
+    Number::from_str(&decimal.to_plain_string()).map(Value::Number).unwrap_or(Value::Null)
+}
+
+/// Running total that stays in `i128` while the values are integers.
+#[derive(Default)]
+pub struct ExactSum {
+    integer: i128,
+    decimal: Option<BigDecimal>,
+    seen: bool,
+}
+
+impl ExactSum {
+    /// Adds `value` when it is numeric, returning whether it was.
+    pub fn add(&mut self, value: &Value) -> bool {
+        let integer = match value {
+            Value::Number(n) => n.as_i64(),
+            Value::String(text) => text.trim().parse::<i64>().ok(),
+            _ => None,
+        };
+        if let Some(total) = integer.and_then(|n| self.integer.checked_add(n as i128)) {
+            self.integer = total;
+        } else if let Some(decimal) = to_decimal(value) {
+            self.decimal = Some(self.decimal.take().unwrap_or_default() + decimal);
+        } else {
+            return false;
+        }
+        self.seen = true;
+        true
+    }
+
+    /// The exact total, or `None` when no numeric value was added.
+    pub fn total(self) -> Option<BigDecimal> {
+        self.seen.then(|| self.decimal.unwrap_or_default() + BigDecimal::from(self.integer))
+    }
+}
+
+/// A fixed-point type: `precision` significant digits, `scale` of them after the point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DecimalPrecision {
+    pub precision: u8,
+    pub scale: u8,
+}
+
+impl DecimalPrecision {
+    /// Widest decimal whose unscaled value fits an `i128`
+    pub const MAX_PRECISION: u8 = 38;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.precision == 0 || self.precision > Self::MAX_PRECISION {
+            return Err(format!("Decimal precision must be between 1 and {}", Self::MAX_PRECISION));
+        }
+        if self.scale > self.precision {
+            return Err(format!("Decimal scale {} exceeds precision {}", self.scale, self.precision));
+        }
+        Ok(())
+    }
+
+    /// Rounds `value` half up to the scale, failing when it is not numeric or needs more digits
+    /// than the precision allows.
+    pub fn fit(&self, value: &Value) -> Result<BigDecimal, String> {
+        let decimal = to_decimal(value).ok_or_else(|| format!("{} is not a number", value))?;
+        let rounded = decimal.with_scale_round(self.scale as i64, RoundingMode::HalfUp);
+        let (unscaled, _) = rounded.as_bigint_and_exponent();
+        if unscaled.to_string().trim_start_matches('-').len() > self.precision as usize {
+            return Err(format!("{} does not fit {}", value, self));
+        }
+        Ok(rounded)
+    }
+
+    /// The value as an integer count of `10^-scale` units, as Parquet and Avro store decimals.
+    pub fn unscaled(&self, value: &Value) -> Result<i128, String> {
+        let (unscaled, _): (BigInt, i64) = self.fit(value)?.as_bigint_and_exponent();
+        unscaled.to_i128().ok_or_else(|| format!("{} does not fit {}", value, self))
+    }
+
+    /// Bytes of the big-endian two's complement form Parquet uses above 18 digits.
+    pub fn byte_width(&self) -> usize {
+        let max = 10f64.powi(self.precision as i32);
+        ((max.log2() + 1.0) / 8.0).ceil() as usize
+    }
+}
+
+impl std::fmt::Display for DecimalPrecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DECIMAL({}, {})", self.precision, self.scale)
+    }
+}
@@ -0,0 +1,181 @@
+//! The one shape every failed API call replies with. `code` is a stable machine-readable
+//! reason that decides the HTTP status, `message` says what went wrong for a person, `details`
+//! carries whatever structured context the failure has, and `request_id` ties the reply to the
+//! server's logs. `error` repeats `message` for clients written before the other fields existed.
+//!
+//! Rejections from warp's own filters (bad JSON bodies, bad query strings, unknown routes) and
+//! from the auth and rate limiting filters end up here too, through `handle_rejection`.
+
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Rejection, Reply};
+
+use crate::{auth, ratelimit, versioning};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request could not be read: malformed JSON, a bad query string or header
+    InvalidRequest,
+    /// The request was read but what it asks for is not valid
+    ValidationFailed,
+    Unauthenticated,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    /// The API version asked for is not served
+    UnsupportedVersion,
+    /// The resource is not in a state that allows this, such as results of an unfinished job
+    Conflict,
+    /// The resource existed but has expired or been pruned
+    Gone,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RateLimited,
+    QueueFull,
+    QuotaExceeded,
+    /// The server cannot take the work right now; retrying later may succeed
+    Unavailable,
+    /// A system the request reads from, such as a source's API or database, failed
+    Upstream,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest | ErrorCode::ValidationFailed => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthenticated => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::UnsupportedVersion => StatusCode::NOT_ACCEPTABLE,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::Gone => StatusCode::GONE,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::RateLimited | ErrorCode::QueueFull | ErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Upstream => StatusCode::BAD_GATEWAY,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiError {
+    success: bool,
+    pub code: ErrorCode,
+    pub message: String,
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+    pub request_id: String,
+    #[serde(skip)]
+    retry_after: Option<u64>,
+    #[serde(skip)]
+    challenge: bool,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Self {
+            success: false,
+            code,
+            error: message.clone(),
+            message,
+            details: None,
+            request_id: Uuid::new_v4().to_string(),
+            retry_after: None,
+            challenge: false,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::ValidationFailed, message)
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    /// Sends `Retry-After` with the reply.
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+}
+
+impl Reply for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = warp::reply::with_status(warp::reply::json(&self), self.code.status()).into_response();
+        let headers = response.headers_mut();
+        if let Ok(value) = self.request_id.parse() {
+            headers.insert("x-request-id", value);
+        }
+        if let Some(value) = self.retry_after.and_then(|seconds| seconds.to_string().parse().ok()) {
+            headers.insert("retry-after", value);
+        }
+        if self.challenge {
+            headers.insert("www-authenticate", warp::http::HeaderValue::from_static("Bearer"));
+        }
+        // Kept so `trace::TraceContext::decorate` can render it again under the client's request id
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Renders any rejection as an `ApiError`, so no request gets warp's plain-text replies.
+pub async fn handle_rejection(rejection: Rejection) -> Result<Response, Rejection> {
+    let error = if let Some(denied) = rejection.find::<auth::Denied>() {
+        match denied {
+            auth::Denied::Unauthenticated(e) => ApiError { challenge: true, ..ApiError::new(ErrorCode::Unauthenticated, e.clone()) },
+            auth::Denied::Forbidden(e) => ApiError::new(ErrorCode::Forbidden, e.clone()),
+        }
+    } else if let Some(ratelimit::Limited(retry_after)) = rejection.find::<ratelimit::Limited>() {
+        ApiError::new(ErrorCode::RateLimited, "Rate limit exceeded")
+            .with_details(serde_json::json!({"retry_after_seconds": retry_after}))
+            .retry_after(*retry_after)
+    } else if let Some(versioning::Unsupported(e)) = rejection.find::<versioning::Unsupported>() {
+        ApiError::new(ErrorCode::UnsupportedVersion, e.clone())
+            .with_details(serde_json::json!({"supported_versions": versioning::SUPPORTED}))
+    } else if rejection.is_not_found() {
+        ApiError::not_found("No such route")
+    } else if let Some(e) = rejection.find::<warp::body::BodyDeserializeError>() {
+        ApiError::new(ErrorCode::InvalidRequest, format!("Invalid request body: {}", e))
+    } else if let Some(e) = rejection.find::<async_graphql_warp::GraphQLBadRequest>() {
+        ApiError::new(ErrorCode::InvalidRequest, format!("Invalid GraphQL request: {}", e.0))
+    } else if let Some(e) = rejection.find::<warp::reject::InvalidQuery>() {
+        ApiError::new(ErrorCode::InvalidRequest, e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::InvalidHeader>() {
+        ApiError::new(ErrorCode::InvalidRequest, e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::MissingHeader>() {
+        ApiError::new(ErrorCode::InvalidRequest, e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::LengthRequired>() {
+        ApiError::new(ErrorCode::InvalidRequest, e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::PayloadTooLarge>() {
+        ApiError::new(ErrorCode::PayloadTooLarge, e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::UnsupportedMediaType>() {
+        ApiError::new(ErrorCode::UnsupportedMediaType, e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::MethodNotAllowed>() {
+        ApiError::new(ErrorCode::MethodNotAllowed, e.to_string())
+    } else {
+        eprintln!("Unhandled rejection: {:?}", rejection);
+        ApiError::new(ErrorCode::Internal, "Internal server error")
+    };
+    Ok(error.into_response())
+}
@@ -0,0 +1,701 @@
+//! Expression language used by `Filter` conditions and `Transform` expressions.
+//!
+//! An expression is parsed once per job and compiled into a tree of closures, so evaluating a
+//! record never touches the source text again. Field names are interned while compiling: every
+//! reference to the same field shares one `Arc<str>`, and field paths are split up front rather
+//! than per record. A compiled expression is shared by all of a job's worker threads.
+//!
+//! Syntax:
+//! - literals: `42`, `1.5`, `'text'` or `"text"`, `true`, `false`, `null`
+//! - fields: `amount`, nested paths `customer.address.city`, array elements `items.0`,
+//!   and `` `field with spaces` `` for names that are not plain identifiers
+//! - record metadata: `$id`, `$source`, `$timestamp`
+//! - operators, loosest first: `or`/`||`, `and`/`&&`, `not`/`!`,
+//!   `==` `=` `!=` `<` `<=` `>` `>=`, `+` `-`, `*` `/` `%`, unary `-`
+//! - functions: `lower`, `upper`, `trim`, `len`, `concat`, `coalesce`, `if`, `is_null`,
+//!   `abs`, `round`, `floor`, `ceil`, `contains`, `starts_with`, `ends_with`,
+//!   `to_string`, `to_number`
+//!
+//! Strings holding numbers (as CSV input does) compare and combine numerically with numbers;
+//! `+` concatenates when either side is a non-numeric string. Missing fields evaluate to `null`,
+//! arithmetic involving `null` yields `null`, and ordering comparisons against `null` are false.
+//! `+`, `-` and `*` are exact on integers and decimals of any size; `/` and the numeric
+//! functions work in double precision.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::{Number, Value};
+
+use crate::decimal::{decimal_value, to_decimal};
+use crate::DataRecord;
+
+type Evaluator = Box<dyn Fn(&DataRecord) -> Value + Send + Sync>;
+
+/// Shares one allocation per distinct field name across all expressions of a job.
+#[derive(Default)]
+pub struct FieldInterner {
+    names: HashMap<String, Arc<str>>,
+}
+
+impl FieldInterner {
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(name.to_string(), interned.clone());
+        interned
+    }
+}
+
+pub struct CompiledExpression {
+    eval: Evaluator,
+    /// Fields the expression reads, as written, in first-use order
+    fields: Vec<String>,
+    comparisons: Vec<Comparison>,
+}
+
+/// A field compared to a literal, written either way round (`amount >= 10`, `'EU' == region`)
+/// and normalized to have the field on the left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub field: String,
+    /// One of `==` `<` `<=` `>` `>=`
+    pub op: &'static str,
+    pub value: Value,
+}
+
+impl CompiledExpression {
+    pub fn compile(source: &str, fields: &mut FieldInterner) -> Result<Self, String> {
+        let parse = || -> Result<Expr, String> {
+            let mut parser = ExprParser { tokens: tokenize(source)?, position: 0, depth: 0, operators: 0 };
+            let expr = parser.expression(0)?;
+            match parser.tokens.get(parser.position) {
+                Some(token) => Err(format!("unexpected {}", token)),
+                None => Ok(expr),
+            }
+        };
+        let (eval, referenced, comparisons) = parse()
+            .and_then(|expr| {
+                let mut referenced = Vec::new();
+                expr.collect_fields(&mut referenced);
+                let mut comparisons = Vec::new();
+                expr.collect_comparisons(&mut comparisons);
+                compile(expr, fields).map(|eval| (eval, referenced, comparisons))
+            })
+            .map_err(|e| format!("Invalid expression '{}': {}", source, e))?;
+        Ok(Self { eval, fields: referenced, comparisons })
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Comparisons that must all hold for the expression, as a condition, to match: the
+    /// expression itself or the operands of its top-level `and`s. A record they rule out
+    /// never matches, so an index may pick the records to evaluate.
+    pub fn comparisons(&self) -> &[Comparison] {
+        &self.comparisons
+    }
+
+    pub fn evaluate(&self, record: &DataRecord) -> Value {
+        (self.eval)(record)
+    }
+
+    /// Evaluates the expression as a condition.
+    pub fn matches(&self, record: &DataRecord) -> bool {
+        truthy(&(self.eval)(record))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(Value),
+    Ident(String),
+    Field(String),
+    Meta(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Literal(value) => write!(f, "{}", value),
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Field(name) => write!(f, "`{}`", name),
+            Token::Meta(name) => write!(f, "'${}'", name),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+/// Two-character operators come first so `<=` is not read as `<` followed by `=`.
+const OPERATORS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "=", "!", "+", "-", "*", "/", "%",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        match c {
+            '(' => { tokens.push(Token::LParen); i += 1; },
+            ')' => { tokens.push(Token::RParen); i += 1; },
+            ',' => { tokens.push(Token::Comma); i += 1; },
+            '\'' | '"' | '`' => {
+                let (text, next) = read_quoted(&chars, i)?;
+                tokens.push(if c == '`' { Token::Field(text) } else { Token::Literal(Value::String(text)) });
+                i = next;
+            },
+            '0'..='9' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                    i += 1;
+                    if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                        i += 1;
+                    }
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                let text: String = chars[start..i].iter().collect();
+                // Parsed from the text so long literals keep every digit
+                let number = text.parse::<Number>().map_err(|_| format!("invalid number '{}'", text))?;
+                tokens.push(Token::Literal(Value::Number(number)));
+            },
+            '$' => {
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Meta(chars[start..i].iter().collect()));
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(match word.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    "and" => Token::Op("&&"),
+                    "or" => Token::Op("||"),
+                    "not" => Token::Op("!"),
+                    _ => Token::Ident(word),
+                });
+            },
+            _ => {
+                let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+                let op = OPERATORS.iter()
+                    .find(|op| rest.starts_with(**op))
+                    .ok_or_else(|| format!("unexpected character '{}'", c))?;
+                tokens.push(Token::Op(if *op == "=" { "==" } else { op }));
+                i += op.len();
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads a quoted string starting at `start`; a doubled quote or a backslash escapes the quote.
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let quote = chars[start];
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                text.push(match chars[i + 1] {
+                    'n' => '\n',
+                    't' => '\t',
+                    other => other,
+                });
+                i += 2;
+            },
+            c if c == quote && chars.get(i + 1) == Some(&quote) => {
+                text.push(quote);
+                i += 2;
+            },
+            c if c == quote => return Ok((text, i + 1)),
+            c => {
+                text.push(c);
+                i += 1;
+            },
+        }
+    }
+    Err(format!("unterminated {} quote", quote))
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Field(String),
+    Meta(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+impl Expr {
+    fn collect_fields(&self, fields: &mut Vec<String>) {
+        match self {
+            Expr::Field(name) if !fields.contains(name) => fields.push(name.clone()),
+            Expr::Unary(_, operand) => operand.collect_fields(fields),
+            Expr::Binary(_, left, right) => {
+                left.collect_fields(fields);
+                right.collect_fields(fields);
+            },
+            Expr::Call(_, args) => args.iter().for_each(|arg| arg.collect_fields(fields)),
+            _ => {},
+        }
+    }
+
+    fn collect_comparisons(&self, comparisons: &mut Vec<Comparison>) {
+        let literal = |expr: &Expr| match expr {
+            Expr::Literal(value) => Some(value.clone()),
+            Expr::Unary("-", operand) => match &**operand {
+                Expr::Literal(value @ Value::Number(_)) => Some(negate(value)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Expr::Binary(op, left, right) = self else {
+            return;
+        };
+        let op = *op;
+        let flipped = match op {
+            "&&" => {
+                left.collect_comparisons(comparisons);
+                right.collect_comparisons(comparisons);
+                return;
+            },
+            "==" => "==",
+            "<" => ">",
+            "<=" => ">=",
+            ">" => "<",
+            ">=" => "<=",
+            _ => return,
+        };
+        let comparison = match (&**left, &**right) {
+            (Expr::Field(field), value) => literal(value).map(|value| Comparison { field: field.clone(), op, value }),
+            (value, Expr::Field(field)) => literal(value).map(|value| Comparison { field: field.clone(), op: flipped, value }),
+            _ => None,
+        };
+        comparisons.extend(comparison);
+    }
+}
+
+/// Deepest nesting of parentheses, calls and prefix operators an expression may have.
+const MAX_NESTING: usize = 64;
+/// Most infix operators in one expression. Together with `MAX_NESTING` this bounds the depth of
+/// the parsed tree, which compiling and evaluating it recurse through.
+const MAX_OPERATORS: usize = 512;
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+    operators: usize,
+}
+
+/// Binding power of each infix operator; higher binds tighter.
+fn infix_power(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        _ => None,
+    }
+}
+
+const NOT_POWER: u8 = 3;
+const NEGATE_POWER: u8 = 7;
+
+impl ExprParser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {}, found {}", expected, token)),
+            None => Err(format!("expected {}, found end of expression", expected)),
+        }
+    }
+
+    fn expression(&mut self, min_power: u8) -> Result<Expr, String> {
+        if self.depth == MAX_NESTING {
+            return Err(format!("nested more than {} levels deep", MAX_NESTING));
+        }
+        self.depth += 1;
+        let expr = self.nested_expression(min_power);
+        self.depth -= 1;
+        expr
+    }
+
+    fn nested_expression(&mut self, min_power: u8) -> Result<Expr, String> {
+        let mut left = match self.next() {
+            Some(Token::Literal(value)) => Expr::Literal(value),
+            Some(Token::Field(name)) => Expr::Field(name),
+            Some(Token::Meta(name)) => Expr::Meta(name),
+            Some(Token::Ident(name)) if self.tokens.get(self.position) == Some(&Token::LParen) => {
+                self.position += 1;
+                let mut args = Vec::new();
+                if self.tokens.get(self.position) == Some(&Token::RParen) {
+                    self.position += 1;
+                } else {
+                    loop {
+                        args.push(self.expression(0)?);
+                        match self.next() {
+                            Some(Token::Comma) => continue,
+                            Some(Token::RParen) => break,
+                            Some(token) => return Err(format!("expected ',' or ')', found {}", token)),
+                            None => return Err("unclosed '(' in function call".to_string()),
+                        }
+                    }
+                }
+                Expr::Call(name, args)
+            },
+            Some(Token::Ident(name)) => Expr::Field(name),
+            Some(Token::LParen) => {
+                let inner = self.expression(0)?;
+                self.expect(Token::RParen)?;
+                inner
+            },
+            Some(Token::Op("!")) => Expr::Unary("!", Box::new(self.expression(NOT_POWER)?)),
+            Some(Token::Op("-")) => Expr::Unary("-", Box::new(self.expression(NEGATE_POWER)?)),
+            Some(token) => return Err(format!("unexpected {}", token)),
+            None => return Err("unexpected end of expression".to_string()),
+        };
+
+        while let Some(Token::Op(op)) = self.tokens.get(self.position) {
+            let op = *op;
+            let power = match infix_power(op) {
+                Some(power) if power > min_power => power,
+                Some(_) => break,
+                None => return Err(format!("unexpected '{}'", op)),
+            };
+            self.position += 1;
+            self.operators += 1;
+            if self.operators > MAX_OPERATORS {
+                return Err(format!("more than {} operators", MAX_OPERATORS));
+            }
+            let right = self.expression(power)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+}
+
+fn compile(expr: Expr, fields: &mut FieldInterner) -> Result<Evaluator, String> {
+    Ok(match expr {
+        Expr::Literal(value) => Box::new(move |_| value.clone()),
+        Expr::Field(name) => compile_field(&name, fields),
+        Expr::Meta(name) => match name.as_str() {
+            "id" => Box::new(|record: &DataRecord| Value::String(record.id.clone())),
+            "source" => Box::new(|record: &DataRecord| Value::String(record.source.clone())),
+            "timestamp" => Box::new(|record: &DataRecord| Value::String(record.timestamp.to_rfc3339())),
+            _ => return Err(format!("unknown record attribute '${}'", name)),
+        },
+        Expr::Unary(op, operand) => {
+            let operand = compile(*operand, fields)?;
+            match op {
+                "!" => Box::new(move |record| Value::Bool(!truthy(&operand(record)))),
+                _ => Box::new(move |record| negate(&operand(record))),
+            }
+        },
+        Expr::Binary(op, left, right) => {
+            let left = compile(*left, fields)?;
+            let right = compile(*right, fields)?;
+            match op {
+                "&&" => Box::new(move |record| Value::Bool(truthy(&left(record)) && truthy(&right(record)))),
+                "||" => Box::new(move |record| Value::Bool(truthy(&left(record)) || truthy(&right(record)))),
+                "==" => Box::new(move |record| Value::Bool(equals(&left(record), &right(record)))),
+                "!=" => Box::new(move |record| Value::Bool(!equals(&left(record), &right(record)))),
+                "<" | "<=" | ">" | ">=" => Box::new(move |record| {
+                    let ordering = compare(&left(record), &right(record));
+                    Value::Bool(match op {
+                        "<" => ordering == Some(Ordering::Less),
+                        "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                        ">" => ordering == Some(Ordering::Greater),
+                        _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                    })
+                }),
+                _ => Box::new(move |record| arithmetic(op, &left(record), &right(record))),
+            }
+        },
+        Expr::Call(name, args) => compile_call(&name, args, fields)?,
+    })
+}
+
+fn compile_field(name: &str, fields: &mut FieldInterner) -> Evaluator {
+    let whole = fields.intern(name);
+    if !name.contains('.') {
+        return Box::new(move |record| record.data.get(&*whole).cloned().unwrap_or(Value::Null));
+    }
+
+    // A literal dotted key wins over the nested path it would otherwise describe
+    let path: Vec<(Arc<str>, Option<usize>)> = name.split('.')
+        .map(|segment| (fields.intern(segment), segment.parse().ok()))
+        .collect();
+    Box::new(move |record| {
+        if let Some(value) = record.data.get(&*whole) {
+            return value.clone();
+        }
+        let mut current = &record.data;
+        for (key, index) in &path {
+            let next = match (current, index) {
+                (Value::Array(items), Some(index)) => items.get(*index),
+                (value, _) => value.get(&**key),
+            };
+            match next {
+                Some(value) => current = value,
+                None => return Value::Null,
+            }
+        }
+        current.clone()
+    })
+}
+
+fn compile_call(name: &str, args: Vec<Expr>, fields: &mut FieldInterner) -> Result<Evaluator, String> {
+    let (min_args, max_args) = match name {
+        "lower" | "upper" | "trim" | "len" | "abs" | "floor" | "ceil" | "is_null" | "to_string" | "to_number" => (1, 1),
+        "round" => (1, 2),
+        "contains" | "starts_with" | "ends_with" => (2, 2),
+        "if" => (3, 3),
+        "concat" | "coalesce" => (1, usize::MAX),
+        _ => return Err(format!("unknown function '{}'", name)),
+    };
+    if args.len() < min_args || args.len() > max_args {
+        return Err(match (min_args, max_args) {
+            (min, max) if min == max => format!("{}() takes {} argument(s), got {}", name, min, args.len()),
+            (min, usize::MAX) => format!("{}() takes at least {} argument(s), got {}", name, min, args.len()),
+            (min, max) => format!("{}() takes {} to {} arguments, got {}", name, min, max, args.len()),
+        });
+    }
+    let mut args = args.into_iter()
+        .map(|arg| compile(arg, fields))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Functions that must not evaluate every argument
+    match name {
+        "if" => {
+            let otherwise = args.pop().unwrap();
+            let then = args.pop().unwrap();
+            let condition = args.pop().unwrap();
+            return Ok(Box::new(move |record| {
+                if truthy(&condition(record)) { then(record) } else { otherwise(record) }
+            }));
+        },
+        "coalesce" => {
+            return Ok(Box::new(move |record| {
+                args.iter().map(|arg| arg(record)).find(|value| !value.is_null()).unwrap_or(Value::Null)
+            }));
+        },
+        _ => {},
+    }
+
+    let function: fn(&[Value]) -> Value = match name {
+        "lower" => |args| map_text(&args[0], |text| text.to_lowercase()),
+        "upper" => |args| map_text(&args[0], |text| text.to_uppercase()),
+        "trim" => |args| map_text(&args[0], |text| text.trim().to_string()),
+        "len" => |args| match &args[0] {
+            Value::String(text) => Value::from(text.chars().count()),
+            Value::Array(items) => Value::from(items.len()),
+            Value::Object(map) => Value::from(map.len()),
+            Value::Null => Value::Null,
+            other => Value::from(to_text(other).chars().count()),
+        },
+        "abs" => |args| map_number(&args[0], f64::abs),
+        "floor" => |args| map_number(&args[0], f64::floor),
+        "ceil" => |args| map_number(&args[0], f64::ceil),
+        "round" => |args| {
+            let digits = args.get(1).and_then(as_number).unwrap_or(0.0) as i32;
+            let scale = 10f64.powi(digits);
+            map_number(&args[0], |n| (n * scale).round() / scale)
+        },
+        "is_null" => |args| Value::Bool(args[0].is_null()),
+        "to_string" => |args| match &args[0] {
+            Value::Null => Value::Null,
+            other => Value::String(to_text(other)),
+        },
+        "to_number" => |args| as_number(&args[0]).map(number_value).unwrap_or(Value::Null),
+        "concat" => |args| Value::String(args.iter().map(to_text).collect()),
+        "contains" => |args| Value::Bool(match &args[0] {
+            Value::Array(items) => items.iter().any(|item| equals(item, &args[1])),
+            Value::Null => false,
+            haystack => to_text(haystack).contains(&to_text(&args[1])),
+        }),
+        "starts_with" => |args| Value::Bool(!args[0].is_null() && to_text(&args[0]).starts_with(&to_text(&args[1]))),
+        _ => |args| Value::Bool(!args[0].is_null() && to_text(&args[0]).ends_with(&to_text(&args[1]))),
+    };
+
+    Ok(Box::new(move |record| {
+        let values: Vec<Value> = args.iter().map(|arg| arg(record)).collect();
+        function(&values)
+    }))
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// Numeric view of a value; strings count when they parse as numbers.
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn number_value(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        return Value::from(n as i64);
+    }
+    Number::from_f64(n).map(Value::Number).unwrap_or(Value::Null)
+}
+
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn map_text(value: &Value, f: impl Fn(&str) -> String) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        Value::String(text) => Value::String(f(text)),
+        other => Value::String(f(&to_text(other))),
+    }
+}
+
+fn map_number(value: &Value, f: impl Fn(f64) -> f64) -> Value {
+    as_number(value).map(|n| number_value(f(n))).unwrap_or(Value::Null)
+}
+
+fn negate(value: &Value) -> Value {
+    if let Some(integer) = as_integer(value).and_then(i64::checked_neg) {
+        return Value::from(integer);
+    }
+    map_number(value, |n| -n)
+}
+
+/// Numbers compare numerically with numeric strings; other mixed types are never equal.
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(_), _) | (_, Value::Number(_)) => compare_numbers(left, right) == Some(Ordering::Equal),
+        _ => left == right,
+    }
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+        _ => compare_numbers(left, right),
+    }
+}
+
+/// Compares as doubles, falling back to exact decimals when they are equal as doubles, so
+/// integers beyond 2^53 still tell apart.
+fn compare_numbers(left: &Value, right: &Value) -> Option<Ordering> {
+    match as_number(left)?.partial_cmp(&as_number(right)?)? {
+        Ordering::Equal => match (to_decimal(left), to_decimal(right)) {
+            (Some(l), Some(r)) => Some(l.cmp(&r)),
+            _ => Some(Ordering::Equal),
+        },
+        ordering => Some(ordering),
+    }
+}
+
+fn arithmetic(op: &str, left: &Value, right: &Value) -> Value {
+    if left.is_null() || right.is_null() {
+        return Value::Null;
+    }
+
+    let (l, r) = match (as_number(left), as_number(right)) {
+        (Some(l), Some(r)) => (l, r),
+        _ if op == "+" => return Value::String(to_text(left) + &to_text(right)),
+        _ => return Value::Null,
+    };
+
+    // Integer inputs stay integers unless the result overflows
+    if let (Some(li), Some(ri)) = (as_integer(left), as_integer(right)) {
+        let exact = match op {
+            "+" => li.checked_add(ri),
+            "-" => li.checked_sub(ri),
+            "*" => li.checked_mul(ri),
+            "%" => li.checked_rem(ri),
+            _ => None,
+        };
+        if let Some(result) = exact {
+            return Value::from(result);
+        }
+    }
+    if let ("+" | "-" | "*", Some(l), Some(r)) = (op, to_decimal(left), to_decimal(right)) {
+        return decimal_value(&match op {
+            "+" => l + r,
+            "-" => l - r,
+            _ => l * r,
+        });
+    }
+
+    let result = match op {
+        "+" => l + r,
+        "-" => l - r,
+        "*" => l * r,
+        "/" if r == 0.0 => return Value::Null,
+        "/" => l / r,
+        _ if r == 0.0 => return Value::Null,
+        _ => l % r,
+    };
+    Number::from_f64(result).map(Value::Number).unwrap_or(Value::Null)
+}
@@ -0,0 +1,327 @@
+//! Read-only GraphQL view of jobs (with their operation results and records), sources and
+//! metrics at `/graphql`, so a client can fetch what one screen needs in a single request and
+//! only the fields it shows. Everything resolves through the same `DataProcessor` calls as the
+//! REST routes; nothing here changes state.
+
+use std::sync::Arc;
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Json, Object, Result, Schema, SimpleObject, ID};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::{DataProcessor, DataRecord, ProcessingJob, ProcessingResult, ResultWindow, SourceInfo, TagFilter};
+
+/// Deepest nesting a query may use; jobs, their records and those records' fields need four
+const MAX_DEPTH: usize = 8;
+/// Most fields one query may resolve, counting each list item once
+const MAX_COMPLEXITY: usize = 2_000;
+
+pub type ApiSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub fn schema(processor: Arc<DataProcessor>) -> ApiSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(processor)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+fn processor<'a>(ctx: &Context<'a>) -> &'a Arc<DataProcessor> {
+    ctx.data_unchecked::<Arc<DataProcessor>>()
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Json<Value> {
+    Json(serde_json::to_value(value).unwrap_or(Value::Null))
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn job(&self, ctx: &Context<'_>, id: ID) -> Option<Job> {
+        processor(ctx).get_job_status(&id).await.map(Job)
+    }
+
+    /// Newest first. `tags` filters like the REST `tag` parameter: `team=billing,env=prod`.
+    async fn jobs(
+        &self,
+        ctx: &Context<'_>,
+        status: Option<String>,
+        tags: Option<String>,
+        limit: Option<usize>,
+        #[graphql(default)] offset: usize,
+    ) -> Result<Vec<Job>> {
+        let processor = processor(ctx);
+        let window = ResultWindow { limit, offset: Some(offset), all: false };
+        let (offset, page_size) = window.resolve(processor.max_result_rows)?;
+        let filter = tags.as_deref().map(TagFilter::parse).unwrap_or_default();
+        let mut jobs = processor.list_jobs(&filter).await;
+        jobs.retain(|job| status.as_deref().is_none_or(|status| format!("{:?}", job.status).eq_ignore_ascii_case(status)));
+        jobs.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        Ok(jobs.into_iter().skip(offset).take(page_size).map(Job).collect())
+    }
+
+    async fn source(&self, ctx: &Context<'_>, id: ID) -> Option<Source> {
+        processor(ctx).get_source(&id).await.map(Source)
+    }
+
+    async fn sources(&self, ctx: &Context<'_>) -> Vec<Source> {
+        processor(ctx).list_sources().await.into_iter().map(Source).collect()
+    }
+
+    async fn metrics(&self, ctx: &Context<'_>) -> Metrics {
+        let processor = processor(ctx);
+        let metrics = processor.get_metrics().await;
+        let (queue_depth, queue_capacity) = processor.queue_depth();
+        Metrics {
+            cpu_usage: metrics.cpu_usage,
+            memory_usage: metrics.memory_usage,
+            disk_usage: metrics.disk_usage,
+            active_jobs: metrics.active_jobs,
+            queue_depth,
+            queue_capacity,
+            total_records_processed: metrics.total_records_processed,
+            average_processing_time_ms: metrics.average_processing_time_ms,
+            error_rate: metrics.error_rate,
+            uptime_seconds: metrics.uptime_seconds,
+            catch_up_pending: metrics.catch_up_pending,
+            catch_up_completed: metrics.catch_up_completed,
+        }
+    }
+}
+
+pub struct Job(ProcessingJob);
+
+#[Object]
+impl Job {
+    async fn id(&self) -> ID {
+        ID(self.0.id.clone())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn status(&self) -> String {
+        format!("{:?}", self.0.status)
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn started_at(&self) -> Option<DateTime<Utc>> {
+        self.0.started_at
+    }
+
+    async fn completed_at(&self) -> Option<DateTime<Utc>> {
+        self.0.completed_at
+    }
+
+    async fn input_count(&self) -> usize {
+        self.0.input_count
+    }
+
+    async fn processed_count(&self) -> usize {
+        self.0.processed_count
+    }
+
+    async fn error_count(&self) -> usize {
+        self.0.error_count
+    }
+
+    async fn error(&self) -> Option<&str> {
+        self.0.error.as_deref()
+    }
+
+    async fn priority(&self) -> i32 {
+        self.0.priority
+    }
+
+    async fn tags(&self) -> Json<Value> {
+        to_json(&self.0.tags)
+    }
+
+    async fn tenant(&self) -> Option<&str> {
+        self.0.tenant.as_deref()
+    }
+
+    async fn depends_on(&self) -> Vec<ID> {
+        self.0.depends_on.iter().cloned().map(ID).collect()
+    }
+
+    async fn configuration(&self) -> Json<Value> {
+        to_json(&self.0.configuration)
+    }
+
+    async fn progress(&self) -> Option<Json<Value>> {
+        self.0.progress.as_ref().map(to_json)
+    }
+
+    /// Per operation and sink, as the job reported them
+    async fn results(&self) -> Vec<OperationResult> {
+        self.0.results.iter().map(OperationResult::from).collect()
+    }
+
+    /// A page of the records the job produced; only for a completed job whose results are retained
+    async fn records(&self, ctx: &Context<'_>, limit: Option<usize>, #[graphql(default)] offset: usize) -> Result<RecordPage> {
+        let processor = processor(ctx);
+        let window = ResultWindow { limit, offset: Some(offset), all: false };
+        let (offset, page_size) = window.resolve(processor.max_result_rows)?;
+        let records = processor.get_job_results(&self.0.id).await?;
+        Ok(RecordPage::new(&records, offset, page_size))
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct OperationResult {
+    operation: String,
+    records_processed: usize,
+    execution_time_ms: u64,
+    memory_used_bytes: usize,
+    error_count: usize,
+    errors: Json<Value>,
+    metadata: Json<Value>,
+}
+
+impl From<&ProcessingResult> for OperationResult {
+    fn from(result: &ProcessingResult) -> Self {
+        Self {
+            operation: result.operation.clone(),
+            records_processed: result.records_processed,
+            execution_time_ms: result.execution_time_ms.try_into().unwrap_or(u64::MAX),
+            memory_used_bytes: result.memory_used_bytes,
+            error_count: result.errors.len(),
+            errors: to_json(&result.errors),
+            metadata: to_json(&result.metadata),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Record {
+    id: ID,
+    timestamp: DateTime<Utc>,
+    source: String,
+    data: Json<Value>,
+    metadata: Json<Value>,
+}
+
+impl From<&DataRecord> for Record {
+    fn from(record: &DataRecord) -> Self {
+        Self {
+            id: ID(record.id.clone()),
+            timestamp: record.timestamp,
+            source: record.source.clone(),
+            data: Json(record.data.clone()),
+            metadata: to_json(&record.metadata),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct RecordPage {
+    total: usize,
+    count: usize,
+    offset: usize,
+    truncated: bool,
+    next_offset: Option<usize>,
+    records: Vec<Record>,
+}
+
+impl RecordPage {
+    fn new(records: &[DataRecord], offset: usize, page_size: usize) -> Self {
+        let total = records.len();
+        let offset = offset.min(total);
+        let page = &records[offset..(offset + page_size).min(total)];
+        let truncated = offset + page.len() < total;
+        Self {
+            total,
+            count: page.len(),
+            offset,
+            truncated,
+            next_offset: truncated.then_some(offset + page.len()),
+            records: page.iter().map(Record::from).collect(),
+        }
+    }
+}
+
+pub struct Source(SourceInfo);
+
+#[Object]
+impl Source {
+    async fn id(&self) -> ID {
+        ID(self.0.id.clone())
+    }
+
+    async fn record_count(&self) -> usize {
+        self.0.record_count
+    }
+
+    async fn version(&self) -> u64 {
+        self.0.version
+    }
+
+    async fn loaded_at(&self) -> Option<DateTime<Utc>> {
+        self.0.loaded_at
+    }
+
+    /// The records are on disk, spilled or in the storage backend, until something reads them
+    async fn spilled(&self) -> bool {
+        self.0.spilled
+    }
+
+    /// How records deviated from the source's expected schema, when one is registered
+    async fn schema_drift(&self) -> Option<Json<Value>> {
+        self.0.schema_drift.as_ref().map(to_json)
+    }
+
+    async fn definition(&self) -> Option<Json<Value>> {
+        self.0.definition.as_ref().map(to_json)
+    }
+
+    /// Data fields, their types and nullability, inferred as the records loaded
+    async fn schema(&self, ctx: &Context<'_>) -> Option<Json<Value>> {
+        match &self.0.schema {
+            Some(schema) => Some(to_json(schema)),
+            // Listed sources leave out their schema until a query asks for it
+            None => processor(ctx).get_source(&self.0.id).await?.schema.as_ref().map(to_json),
+        }
+    }
+
+    /// A page of the source's records, with only `fields` of their data when given
+    async fn records(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<usize>,
+        #[graphql(default)] offset: usize,
+        fields: Option<Vec<String>>,
+    ) -> Result<RecordPage> {
+        let window = ResultWindow { limit, offset: Some(offset), all: false };
+        let page = processor(ctx).preview_source(&self.0.id, &window, fields.as_deref()).await?;
+        Ok(RecordPage {
+            total: page.total,
+            count: page.count,
+            offset: page.offset,
+            truncated: page.truncated,
+            next_offset: page.next_offset,
+            records: page.records.iter().map(Record::from).collect(),
+        })
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Metrics {
+    cpu_usage: f64,
+    memory_usage: f64,
+    disk_usage: f64,
+    active_jobs: usize,
+    queue_depth: usize,
+    queue_capacity: usize,
+    total_records_processed: u64,
+    average_processing_time_ms: f64,
+    error_rate: f64,
+    uptime_seconds: u64,
+    catch_up_pending: usize,
+    catch_up_completed: u64,
+}
@@ -0,0 +1,219 @@
+//! Secondary indexes on a source's fields, mapping each value of the field to the positions of
+//! the records holding it. A hash index answers equality; a sorted one also answers ranges.
+//!
+//! Values are keyed the way the expression language compares them: numbers and strings that
+//! parse as numbers share a key, and a missing field is keyed as null. An index therefore
+//! names every record that could match, sometimes a few more (`"7"` and `7` share a key but
+//! are different join keys), and callers check the records it names as they would otherwise.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::expression::Comparison;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexKind {
+    #[default]
+    Hash,
+    /// Also serves `<`, `<=`, `>` and `>=` against numbers
+    Sorted,
+}
+
+/// An index to keep on a source, as `POST /sources/{id}/indexes` takes it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexSpec {
+    /// A top-level data field
+    pub field: String,
+    #[serde(default)]
+    pub kind: IndexKind,
+}
+
+impl IndexSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.field.trim().is_empty() {
+            return Err("Index field is required".to_string());
+        }
+        // Expressions read a dotted name as a path when no field has it, which the index cannot
+        if self.field.contains('.') {
+            return Err(format!("Cannot index {}: only top-level fields without dots can be indexed", self.field));
+        }
+        Ok(())
+    }
+}
+
+/// An index as `GET /sources/{id}/indexes` lists it.
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexInfo {
+    pub field: String,
+    pub kind: IndexKind,
+    pub distinct_values: usize,
+    /// Records of the current version the index covers
+    pub records: usize,
+}
+
+/// A field's value as the index keys it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Null,
+    Bool(bool),
+    /// The number's bits, mapped so integer order is numeric order
+    Number(i64),
+    Text(String),
+}
+
+impl Key {
+    pub fn of(value: Option<&Value>) -> Key {
+        match value {
+            None | Some(Value::Null) => Key::Null,
+            Some(Value::Bool(b)) => Key::Bool(*b),
+            Some(Value::Number(n)) => match n.as_f64() {
+                Some(n) => Key::number(n),
+                None => Key::Text(n.to_string()),
+            },
+            Some(Value::String(text)) => Key::of_text(text),
+            Some(value) => Key::Text(value.to_string()),
+        }
+    }
+
+    pub fn of_text(text: &str) -> Key {
+        match text.trim().parse::<f64>() {
+            Ok(n) => Key::number(n),
+            Err(_) => Key::Text(text.to_string()),
+        }
+    }
+
+    fn number(n: f64) -> Key {
+        // -0.0 equals 0.0, so both take the same key
+        let bits = (n + 0.0).to_bits() as i64;
+        Key::Number(bits ^ (((bits >> 63) as u64) >> 1) as i64)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Entries {
+    Hash(HashMap<Key, Vec<usize>>),
+    Sorted(BTreeMap<Key, Vec<usize>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldIndex {
+    entries: Entries,
+    len: usize,
+}
+
+impl FieldIndex {
+    /// Indexes the keys of records at positions `0..`, in order.
+    pub fn build(kind: IndexKind, keys: impl IntoIterator<Item = Key>) -> Self {
+        let entries = match kind {
+            IndexKind::Hash => Entries::Hash(HashMap::new()),
+            IndexKind::Sorted => Entries::Sorted(BTreeMap::new()),
+        };
+        let mut index = FieldIndex { entries, len: 0 };
+        index.extend(keys);
+        index
+    }
+
+    /// Indexes the keys of records appended after those already covered.
+    pub fn extend(&mut self, keys: impl IntoIterator<Item = Key>) {
+        for key in keys {
+            let position = self.len;
+            self.positions_mut(key).push(position);
+            self.len += 1;
+        }
+    }
+
+    /// Moves the record at `position` from the key it had to the one it has now.
+    pub fn update(&mut self, position: usize, before: Key, after: Key) {
+        if before == after {
+            return;
+        }
+        let emptied = {
+            let positions = self.positions_mut(before.clone());
+            positions.retain(|at| *at != position);
+            positions.is_empty()
+        };
+        if emptied {
+            match &mut self.entries {
+                Entries::Hash(entries) => entries.remove(&before),
+                Entries::Sorted(entries) => entries.remove(&before),
+            };
+        }
+        let positions = self.positions_mut(after);
+        let at = positions.partition_point(|at| *at < position);
+        positions.insert(at, position);
+    }
+
+    fn positions_mut(&mut self, key: Key) -> &mut Vec<usize> {
+        match &mut self.entries {
+            Entries::Hash(entries) => entries.entry(key).or_default(),
+            Entries::Sorted(entries) => entries.entry(key).or_default(),
+        }
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        match self.entries {
+            Entries::Hash(_) => IndexKind::Hash,
+            Entries::Sorted(_) => IndexKind::Sorted,
+        }
+    }
+
+    pub fn distinct_values(&self) -> usize {
+        match &self.entries {
+            Entries::Hash(entries) => entries.len(),
+            Entries::Sorted(entries) => entries.len(),
+        }
+    }
+
+    /// Records the index covers.
+    pub fn records(&self) -> usize {
+        self.len
+    }
+
+    /// Positions of the records keyed as `key`, ascending.
+    pub fn get(&self, key: &Key) -> &[usize] {
+        let positions = match &self.entries {
+            Entries::Hash(entries) => entries.get(key),
+            Entries::Sorted(entries) => entries.get(key),
+        };
+        positions.map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Positions, ascending, of the records that could satisfy `comparison`, when the index
+    /// can tell: equality always, ranges against a number in a sorted index.
+    pub fn candidates(&self, comparison: &Comparison) -> Option<Vec<usize>> {
+        let value = Key::of(Some(&comparison.value));
+        // Bounds are inclusive either way: numbers equal as doubles may still differ exactly
+        let (low, high) = match (comparison.op, &comparison.value) {
+            ("==", _) => return Some(self.get(&value).to_vec()),
+            ("<" | "<=", Value::Number(_)) => (Key::number(f64::NEG_INFINITY), value),
+            (">" | ">=", Value::Number(_)) => (value, Key::number(f64::INFINITY)),
+            _ => return None,
+        };
+        let Entries::Sorted(entries) = &self.entries else {
+            return None;
+        };
+        let mut positions: Vec<usize> = entries.range((Bound::Included(low), Bound::Included(high)))
+            .flat_map(|(_, positions)| positions.iter().copied())
+            .collect();
+        positions.sort_unstable();
+        Some(positions)
+    }
+
+    /// Positions, ascending, of the records whose field could be `key` as the records API
+    /// matches a business key: the text itself, or the JSON value it parses as.
+    pub fn lookup(&self, key: &str) -> Vec<usize> {
+        let mut positions = self.get(&Key::of_text(key)).to_vec();
+        if let Ok(parsed) = serde_json::from_str::<Value>(key) {
+            let parsed = Key::of(Some(&parsed));
+            if parsed != Key::of_text(key) {
+                positions.extend_from_slice(self.get(&parsed));
+                positions.sort_unstable();
+            }
+        }
+        positions
+    }
+}
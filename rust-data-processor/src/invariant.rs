@@ -0,0 +1,106 @@
+//! Pipeline invariants: properties of a job's output relative to its input that the engine
+//! verifies on every run before any sink is written. What each invariant needs from the input
+//! is noted before the operations run, so the input itself does not have to be kept.
+
+use std::collections::HashSet;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{DataRecord, ProcessingError};
+
+/// Violations reported per invariant; the rest are only counted.
+const MAX_REPORTED_VIOLATIONS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Invariant {
+    /// The operations never add records
+    RowCountAtMostInput,
+    /// Records unique on `fields` in the input are still unique on them in the output
+    UniqueKeyPreserved { fields: Vec<String> },
+    /// No output record has a null (or missing) `field` unless the input record with its id did
+    NoNewNulls { field: String },
+}
+
+/// What an invariant is checked against, taken from the job's input. Checkpoints keep it so a
+/// resumed run is still checked against the original input.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Baseline {
+    Rows(usize),
+    Unique(bool),
+    /// Ids of the input records whose field was null
+    Nulls(HashSet<String>),
+}
+
+impl Invariant {
+    pub fn baseline(&self, input: &[DataRecord]) -> Baseline {
+        match self {
+            Invariant::RowCountAtMostInput => Baseline::Rows(input.len()),
+            Invariant::UniqueKeyPreserved { fields } => {
+                let mut seen = HashSet::new();
+                Baseline::Unique(input.iter().all(|record| seen.insert(key(record, fields))))
+            },
+            Invariant::NoNewNulls { field } => Baseline::Nulls(
+                input.iter().filter(|record| is_null(record, field)).map(|record| record.id.clone()).collect(),
+            ),
+        }
+    }
+
+    /// One error per violation, keyed by the offending record where there is one.
+    pub fn check(&self, index: usize, baseline: &Baseline, output: &[DataRecord]) -> Vec<ProcessingError> {
+        let violation = |message: String, record_id: Option<&str>| ProcessingError {
+            error_type: "InvariantViolated".to_string(),
+            message,
+            record_id: record_id.map(str::to_string),
+            timestamp: Utc::now(),
+            context: [("invariant".to_string(), json!(index)), ("kind".to_string(), json!(self.kind()))].into(),
+        };
+        match (self, baseline) {
+            (Invariant::RowCountAtMostInput, Baseline::Rows(input)) if output.len() > *input => {
+                vec![violation(format!("Output has {} records but the input had {}", output.len(), input), None)]
+            },
+            (Invariant::UniqueKeyPreserved { fields }, Baseline::Unique(true)) => {
+                let mut seen = HashSet::new();
+                output.iter()
+                    .filter(|record| !seen.insert(key(record, fields)))
+                    .map(|record| violation(format!("Duplicate key on {}", fields.join(", ")), Some(&record.id)))
+                    .collect()
+            },
+            (Invariant::NoNewNulls { field }, Baseline::Nulls(input_nulls)) => output.iter()
+                .filter(|record| is_null(record, field) && !input_nulls.contains(&record.id))
+                .map(|record| violation(format!("{} became null", field), Some(&record.id)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Invariant::RowCountAtMostInput => "RowCountAtMostInput",
+            Invariant::UniqueKeyPreserved { .. } => "UniqueKeyPreserved",
+            Invariant::NoNewNulls { .. } => "NoNewNulls",
+        }
+    }
+}
+
+/// Checks every invariant, keeping the first violations of each and the total count.
+pub fn check_all(invariants: &[(Invariant, Baseline)], output: &[DataRecord]) -> (Vec<ProcessingError>, usize) {
+    let mut reported = Vec::new();
+    let mut total = 0;
+    for (index, (invariant, baseline)) in invariants.iter().enumerate() {
+        let violations = invariant.check(index, baseline, output);
+        total += violations.len();
+        reported.extend(violations.into_iter().take(MAX_REPORTED_VIOLATIONS));
+    }
+    (reported, total)
+}
+
+fn key(record: &DataRecord, fields: &[String]) -> String {
+    let values: Vec<&Value> = fields.iter().map(|field| record.data.get(field).unwrap_or(&Value::Null)).collect();
+    serde_json::to_string(&values).unwrap_or_default()
+}
+
+fn is_null(record: &DataRecord, field: &str) -> bool {
+    record.data.get(field).is_none_or(Value::is_null)
+}
@@ -0,0 +1,148 @@
+//! Locale conventions for reading text values: decimal and digit-grouping separators, date
+//! formats, and the collation Sort orders strings by. Text that reads as a number or date under
+//! the locale is normalized to a JSON number or an ISO 8601 string, so every later operation sees
+//! `1.234,56` from a European CSV as `1234.56`.
+
+use std::cmp::Ordering;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Locale {
+    pub decimal_separator: char,
+    /// Separator between thousands groups, such as `.` in `1.234,56`; groups must be three digits
+    pub grouping_separator: Option<char>,
+    /// chrono formats (`%d.%m.%Y`, `%d/%m/%Y %H:%M`) tried in order; matching text is rewritten
+    /// as `YYYY-MM-DD`, or `YYYY-MM-DDTHH:MM:SS` when the format has a time
+    pub date_formats: Vec<String>,
+    pub collation: Collation,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            grouping_separator: None,
+            date_formats: Vec::new(),
+            collation: Collation::Binary,
+        }
+    }
+}
+
+impl Locale {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.decimal_separator.is_ascii_digit() || self.decimal_separator == '-' {
+            return Err(format!("Invalid decimal separator {:?}", self.decimal_separator));
+        }
+        match self.grouping_separator {
+            Some(separator) if separator == self.decimal_separator => {
+                Err("Grouping and decimal separators must differ".to_string())
+            },
+            Some(separator) if separator.is_ascii_digit() || separator == '-' => {
+                Err(format!("Invalid grouping separator {:?}", separator))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Rewrites every string in `value` (recursing into objects and arrays) that reads as a date
+    /// or number under this locale.
+    pub fn normalize(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => {
+                if let Some(normalized) = self.parse_date(text).or_else(|| self.parse_number(text)) {
+                    *value = normalized;
+                }
+            },
+            Value::Array(items) => items.iter_mut().for_each(|item| self.normalize(item)),
+            Value::Object(map) => map.values_mut().for_each(|item| self.normalize(item)),
+            _ => {},
+        }
+    }
+
+    fn parse_date(&self, text: &str) -> Option<Value> {
+        let text = text.trim();
+        self.date_formats.iter().find_map(|format| {
+            NaiveDateTime::parse_from_str(text, format)
+                .map(|value| value.format("%Y-%m-%dT%H:%M:%S").to_string())
+                .or_else(|_| NaiveDate::parse_from_str(text, format).map(|value| value.format("%Y-%m-%d").to_string()))
+                .ok()
+                .map(Value::String)
+        })
+    }
+
+    /// Numbers are an optional `-`, grouped or ungrouped digits and an optional fraction.
+    /// Integers with leading zeros (`007`) are left as text since they are usually codes.
+    fn parse_number(&self, text: &str) -> Option<Value> {
+        let text = text.trim();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (integer, fraction) = match digits.split_once(self.decimal_separator) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits, None),
+        };
+
+        let integer = match self.grouping_separator {
+            Some(separator) if integer.contains(separator) => {
+                let mut groups = integer.split(separator);
+                let leading = groups.next().unwrap_or_default();
+                if leading.is_empty() || leading.len() > 3 || groups.any(|group| group.len() != 3) {
+                    return None;
+                }
+                integer.replace(separator, "")
+            },
+            _ => integer.to_string(),
+        };
+        let all_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        if !all_digits(&integer) || (integer.len() > 1 && integer.starts_with('0')) {
+            return None;
+        }
+
+        // Numbers keep the digits they were written with, however many there are
+        let sign = if negative { "-" } else { "" };
+        let text = match fraction {
+            None => format!("{}{}", sign, integer),
+            Some(fraction) if all_digits(fraction) => format!("{}{}.{}", sign, integer, fraction),
+            Some(_) => return None,
+        };
+        text.parse::<Number>().ok().map(Value::Number)
+    }
+}
+
+/// How Sort orders strings. Non-string values always compare by their JSON text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Collation {
+    /// By code point, so `Zebra` sorts before `apple` and `é` after `z`
+    #[default]
+    Binary,
+    CaseInsensitive,
+    /// Ignores case and diacritics, so `école` sorts with `ecole`
+    AccentInsensitive,
+}
+
+impl Collation {
+    pub fn compare(self, a: &Value, b: &Value) -> Ordering {
+        let (Value::String(a), Value::String(b)) = (a, b) else {
+            return a.to_string().cmp(&b.to_string());
+        };
+        let folded = match self {
+            Collation::Binary => Ordering::Equal,
+            Collation::CaseInsensitive => a.to_lowercase().cmp(&b.to_lowercase()),
+            Collation::AccentInsensitive => fold(a).cmp(fold(b)),
+        };
+        // Values equal under the collation still get a stable order
+        folded.then_with(|| a.cmp(b))
+    }
+}
+
+fn fold(text: &str) -> impl Iterator<Item = char> + '_ {
+    text.nfd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase)
+}
@@ -1,24 +1,88 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#![recursion_limit = "256"]
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::net::{IpAddr, SocketAddr};
 
-use tokio::time::sleep;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock, RwLockReadGuard};
+use tokio_util::sync::CancellationToken;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use uuid::Uuid;
 use csv::ReaderBuilder;
 use reqwest::Client;
-use futures::stream::{self, StreamExt};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc, Weekday};
 use rayon::prelude::*;
+use bigdecimal::ToPrimitive;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use clap::{Parser, Subcommand, ValueEnum};
 
-use warp::{Filter, Rejection, Reply};
+use bytes::Bytes;
+use futures::{FutureExt, StreamExt};
+use apache_avro::{Codec, Decimal as AvroDecimal, DeflateSettings, Schema as AvroSchema, Writer as AvroWriter, ZstandardSettings};
+use apache_avro::types::Value as AvroValue;
+use apache_avro::writer::datum::GenericDatumWriter;
+use flate2::write::GzEncoder;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use aes_gcm::aead::Aead;
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use ipnet::IpNet;
+use sqlx::{AnyConnection, Connection};
+use rskafka::client::ClientBuilder;
+use rskafka::client::partition::{Compression as KafkaCompression, UnknownTopicHandling};
+use rskafka::record::Record as KafkaRecord;
+use parquet::basic::{Compression, DecimalType, LogicalType, Repetition, Type as PhysicalType, ZstdLevel};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, FixedLenByteArray, FixedLenByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::Field as ParquetField;
+use parquet::schema::types::Type as ParquetType;
+use warp::multipart::FormData;
+use warp::{Buf, Filter, Rejection, Reply, Stream};
 use warp::http::StatusCode;
 
+use error::{ApiError, ErrorCode};
+
+mod auth;
+mod cron;
+mod decimal;
+mod error;
+mod expression;
+mod graphql;
+mod index;
+mod invariant;
+mod locale;
+mod openapi;
+mod ratelimit;
+mod schema;
+mod sketch;
+mod storage;
+mod tls;
+mod trace;
+mod versioning;
+
+use cron::CronExpression;
+use decimal::{decimal_value, to_decimal, DecimalPrecision, ExactSum};
+use expression::{CompiledExpression, FieldInterner};
+use invariant::{Baseline, Invariant};
+use locale::Locale;
+use sketch::{HyperLogLog, TDigest, TopK};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataRecord {
     pub id: String,
@@ -38,29 +102,864 @@ pub struct ProcessingJob {
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
     pub input_count: usize,
+    /// Records the current stage has got through while the job runs, and the records it
+    /// produced once it completes
     pub processed_count: usize,
     pub error_count: usize,
     pub configuration: ProcessingConfig,
     pub results: Vec<ProcessingResult>,
+    #[serde(default)]
+    pub comments: Vec<JobComment>,
+    /// Projected peak memory from the cost estimator, set on submission
+    #[serde(default)]
+    pub estimated_memory_bytes: Option<u64>,
+    /// Projected work directory space for staged file outputs, set on submission
+    #[serde(default)]
+    pub estimated_disk_bytes: Option<u64>,
+    /// Why the job failed
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Schedule that submitted the job, making it one of that pipeline's runs
+    #[serde(default)]
+    pub schedule_id: Option<String>,
+    /// Version of every source the job read, recorded when it starts
+    #[serde(default)]
+    pub input_versions: BTreeMap<String, u64>,
+    /// Engine version the job ran under, recorded when it starts
+    #[serde(default)]
+    pub engine_version: Option<u32>,
+    /// Deprecated behaviours the job's configuration relies on, found on submission
+    #[serde(default)]
+    pub warnings: Vec<DeprecationWarning>,
+    /// Every run of the job, the latest last
+    #[serde(default)]
+    pub attempts: Vec<JobAttempt>,
+    /// When a failed job waiting for a retry runs again
+    #[serde(default)]
+    pub retry_at: Option<DateTime<Utc>>,
+    /// Queued jobs with a higher priority run first; equal priorities run in submission order
+    #[serde(default)]
+    pub priority: i32,
+    /// Free-form labels such as `team: billing`, for finding and grouping jobs
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Job this one was created from by a rerun
+    #[serde(default)]
+    pub rerun_of: Option<String>,
+    /// Jobs that must complete before this one starts; if one of them fails, is cancelled or
+    /// times out, this job fails without running
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// How far the running attempt has got; cleared when it ends
+    #[serde(default)]
+    pub progress: Option<JobProgress>,
+    /// When the job failed on its last allowed attempt and went to the dead-letter queue;
+    /// cleared when it is re-driven
+    #[serde(default)]
+    pub dead_lettered_at: Option<DateTime<Utc>>,
+    /// URLs notified as the job starts and ends, on top of the server's `--job-webhook`s
+    #[serde(default)]
+    pub callbacks: Vec<JobCallback>,
+    /// Whose quota the job counts against: the tenant of the API key it was submitted with
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// When the job was brought in from another instance's archive rather than run here
+    #[serde(default)]
+    pub imported_at: Option<DateTime<Utc>>,
+    /// The request that submitted the job and the trace it belongs to; absent for scheduled
+    /// and watched jobs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<trace::TraceContext>,
+}
+
+/// Tags one job may carry
+const MAX_JOB_TAGS: usize = 32;
+/// Callbacks one job may register
+const MAX_JOB_CALLBACKS: usize = 8;
+/// Tries a lifecycle webhook gets before the event is dropped
+const WEBHOOK_DELIVERY_ATTEMPTS: u32 = 5;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Job lifecycle transitions a callback can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobEvent {
+    Started,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobEvent {
+    /// The event a job moving into `status` raises; timing out counts as failing.
+    fn for_status(status: &JobStatus) -> Option<Self> {
+        match status {
+            JobStatus::Running => Some(JobEvent::Started),
+            JobStatus::Completed => Some(JobEvent::Completed),
+            JobStatus::Failed | JobStatus::TimedOut => Some(JobEvent::Failed),
+            JobStatus::Cancelled => Some(JobEvent::Cancelled),
+            JobStatus::Waiting | JobStatus::Pending => None,
+        }
+    }
+}
+
+/// A URL that gets a POST of `{"event", "job"}` when the job reaches one of `events` (any of
+/// them when empty). `X-Webhook-Signature` is `sha256=` and the hex HMAC-SHA256 of
+/// `<X-Webhook-Timestamp>.<body>`, keyed with `secret` or else `DATA_PROCESSOR_WEBHOOK_SECRET`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobCallback {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<JobEvent>,
+    /// Signing key; a `${credential:name}` reference keeps it out of the job itself
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// The job as a lifecycle webhook describes it.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEventSummary {
+    pub id: String,
+    pub name: String,
+    pub status: JobStatus,
+    pub tags: BTreeMap<String, String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub input_count: usize,
+    pub processed_count: usize,
+    pub error_count: usize,
+    pub error: Option<String>,
+    pub attempts: usize,
+    pub schedule_id: Option<String>,
+    pub rerun_of: Option<String>,
+    /// Of the request that submitted the job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Sent as the delivery's `traceparent`, continuing the submitting request's trace
+    #[serde(skip)]
+    pub traceparent: Option<String>,
+}
+
+impl From<&ProcessingJob> for JobEventSummary {
+    fn from(job: &ProcessingJob) -> Self {
+        JobEventSummary {
+            id: job.id.clone(),
+            name: job.name.clone(),
+            status: job.status.clone(),
+            tags: job.tags.clone(),
+            created_at: job.created_at,
+            started_at: job.started_at,
+            completed_at: job.completed_at,
+            input_count: job.input_count,
+            processed_count: job.processed_count,
+            error_count: job.error_count,
+            error: job.error.clone(),
+            attempts: job.attempts.len(),
+            schedule_id: job.schedule_id.clone(),
+            rerun_of: job.rerun_of.clone(),
+            request_id: job.trace.as_ref().map(|trace| trace.request_id.clone()),
+            traceparent: job.trace.as_ref().map(trace::TraceContext::traceparent),
+        }
+    }
+}
+
+/// Tag names are filtered on as `name=value` and listed comma separated, so they cannot hold
+/// `=` or `,`; values cannot hold `,`.
+fn validate_tags(tags: &BTreeMap<String, String>) -> Result<(), String> {
+    if tags.len() > MAX_JOB_TAGS {
+        return Err(format!("A job may have at most {} tags", MAX_JOB_TAGS));
+    }
+    for (name, value) in tags {
+        if name.trim().is_empty() || name.contains(['=', ',']) {
+            return Err(format!("Invalid tag name '{}'", name));
+        }
+        if value.contains(',') {
+            return Err(format!("Invalid value '{}' for tag {}", value, name));
+        }
+    }
+    Ok(())
+}
+
+/// Selects jobs by tag: `team=billing,env=prod` matches jobs with both tags, and a bare `team`
+/// matches any job with a `team` tag.
+#[derive(Debug, Clone, Default)]
+pub struct TagFilter {
+    tags: Vec<(String, Option<String>)>,
+}
+
+impl TagFilter {
+    pub fn parse(text: &str) -> Self {
+        let tags = text.split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| match tag.split_once('=') {
+                Some((name, value)) => (name.trim().to_string(), Some(value.trim().to_string())),
+                None => (tag.to_string(), None),
+            })
+            .collect();
+        Self { tags }
+    }
+
+    pub fn matches(&self, job: &ProcessingJob) -> bool {
+        self.tags.iter().all(|(name, value)| match (job.tags.get(name), value) {
+            (Some(actual), Some(value)) => actual == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+    }
+}
+
+/// Jobs sharing one value of the tag they were grouped by; `value` is null for jobs without it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TagGroup<T> {
+    pub value: Option<String>,
+    #[serde(flatten)]
+    pub content: T,
+}
+
+/// Groups `jobs` by their value of tag `name`, in value order with untagged jobs last.
+fn group_by_tag<'a>(jobs: impl IntoIterator<Item = &'a ProcessingJob>, name: &str) -> Vec<(Option<String>, Vec<&'a ProcessingJob>)> {
+    let mut groups: BTreeMap<Option<&str>, Vec<&ProcessingJob>> = BTreeMap::new();
+    for job in jobs {
+        groups.entry(job.tags.get(name).map(String::as_str)).or_default().push(job);
+    }
+    let untagged = groups.remove(&None);
+    groups.into_iter()
+        .map(|(value, jobs)| (value.map(str::to_string), jobs))
+        .chain(untagged.map(|jobs| (None, jobs)))
+        .collect()
+}
+
+/// Job totals for a share of the instance, such as one team's tagged jobs.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobCounts {
+    pub total: usize,
+    /// Waiting on dependencies or queued
+    pub queued: usize,
+    pub running: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub records_processed: u64,
+}
+
+impl JobCounts {
+    fn of<'a>(jobs: impl IntoIterator<Item = &'a ProcessingJob>) -> Self {
+        let mut counts = Self::default();
+        for job in jobs {
+            counts.total += 1;
+            match job.status {
+                JobStatus::Waiting | JobStatus::Pending => counts.queued += 1,
+                JobStatus::Running => counts.running += 1,
+                JobStatus::Completed => {
+                    counts.completed += 1;
+                    counts.records_processed += job.processed_count as u64;
+                },
+                JobStatus::Failed | JobStatus::Cancelled | JobStatus::TimedOut => counts.failed += 1,
+            }
+        }
+        counts
+    }
+}
+
+/// A job in the dead-letter queue, with the error of every attempt.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetteredJob {
+    pub job_id: String,
+    pub name: String,
+    pub schedule_id: Option<String>,
+    pub dead_lettered_at: DateTime<Utc>,
+    pub error: Option<String>,
+    pub attempts: Vec<JobAttempt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobAttempt {
+    pub number: u32,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobProgress {
+    /// The operations or sink being run, named as in the results
+    pub stage: String,
+    /// Records the stage has finished out of those it started with
+    pub completed: usize,
+    pub total: usize,
+    /// Share of the whole attempt done, from 0 to 1. Every operation counts the same and writing
+    /// the output counts as one more operation
+    pub fraction: f64,
+    /// Seconds left at the pace so far; unknown until some work is done
+    pub eta_seconds: Option<u64>,
+}
+
+/// Version of the operation and expression semantics. Bumped whenever a change can alter the
+/// output of an existing configuration; the behaviours such a change retires are reported as
+/// deprecation warnings beforehand.
+pub const ENGINE_VERSION: u32 = 1;
+
+/// Behaviour a configuration relies on that a later engine version will change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeprecationWarning {
+    pub code: String,
+    /// Where in the configuration, e.g. `operations[2]`
+    pub path: String,
+    pub message: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobComment {
+    #[serde(default)]
+    pub id: String,
+    pub author: String,
+    pub text: String,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Layout version of job archives; archives written by a later version are refused.
+const JOB_ARCHIVE_VERSION: u32 = 1;
+
+/// Finished jobs and their results, moved between instances as gzipped JSON by
+/// `GET /jobs/archive` and `POST /jobs/import`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobArchive {
+    pub archive_version: u32,
+    /// Engine version of the exporting instance
+    pub engine_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub jobs: Vec<ArchivedJob>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedJob {
+    pub job: ProcessingJob,
+    /// The records the job produced, when the exporting instance still retained them
+    #[serde(default)]
+    pub results: Option<Vec<DataRecord>>,
+}
+
+impl JobArchive {
+    fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        serde_json::to_writer(&mut encoder, self).map_err(|e| e.to_string())?;
+        encoder.finish().map_err(|e| e.to_string())
+    }
+
+    /// Reads a gzipped archive or, for one unpacked by hand, plain JSON.
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let archive: Self = match bytes.starts_with(&[0x1f, 0x8b]) {
+            true => serde_json::from_reader(flate2::read::GzDecoder::new(bytes)),
+            false => serde_json::from_slice(bytes),
+        }.map_err(|e| format!("Invalid job archive: {}", e))?;
+        if archive.archive_version > JOB_ARCHIVE_VERSION {
+            return Err(format!(
+                "Job archive version {} is newer than this instance supports ({})",
+                archive.archive_version, JOB_ARCHIVE_VERSION,
+            ));
+        }
+        Ok(archive)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobImport {
+    pub imported: Vec<String>,
+    /// Jobs left out because a job with their id already exists here
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum JobStatus {
+    /// Held back until every job in `depends_on` has completed
+    Waiting,
     Pending,
     Running,
     Completed,
     Failed,
     Cancelled,
+    /// Ran past `timeout_seconds`; it is stopped like a cancelled job and not retried
+    TimedOut,
+}
+
+impl JobStatus {
+    /// Whether the job is done for good; failed jobs waiting for a retry are back to `Pending`.
+    fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled | JobStatus::TimedOut)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessingConfig {
+    /// Sources the job reads, concatenated in this order; without any it reads the source whose
+    /// id sorts first
+    #[serde(default)]
+    pub input_sources: Vec<String>,
     pub operations: Vec<Operation>,
     pub batch_size: usize,
+    /// Threads the job's operations run on, each taking a contiguous share of the records;
+    /// 0 uses one per CPU
     pub parallel_workers: usize,
+    /// Longest a single attempt may run before it is stopped; 0 means no limit
     pub timeout_seconds: u64,
+    /// Reruns of a failed job, with exponential backoff and jitter between them; webhook
+    /// outputs also retry each failed batch this many times
     pub retry_attempts: u32,
+    #[serde(deserialize_with = "OutputFormat::deserialize_compat")]
     pub output_format: OutputFormat,
+    /// Output file path template supporting `{job_id}`, `{date}` and `{source}` (the input
+    /// source ids joined by `+`), relative to the server's output directory
+    #[serde(default)]
+    pub output_path: Option<String>,
+    /// Splits file output into Hive-style partition directories
+    #[serde(default)]
+    pub partition_by: Option<Partitioning>,
+    /// Compression applied to JSON, JSON Lines and CSV output files
+    #[serde(default)]
+    pub output_compression: OutputCompression,
+    /// Encryption applied to file output after serialization and compression
+    #[serde(default)]
+    pub output_encryption: Option<OutputEncryption>,
+    /// Column contract of the output, applied to each record's data just before it is written
+    #[serde(default)]
+    pub output_columns: Option<Vec<OutputColumn>>,
+    /// Additional sinks written alongside `output_format`, each succeeding or failing on its own
+    #[serde(default)]
+    pub outputs: Vec<OutputSpec>,
+    /// Sink for records that fail an operation (such as validation). They are removed from the
+    /// run and written here, carrying `dead_letter_error`, `dead_letter_operation` (its index)
+    /// and `dead_letter_operation_kind` in their metadata. Without one, failing records pass through.
+    #[serde(default)]
+    pub dead_letter: Option<OutputSpec>,
+    /// Engine version the configuration was written against; configurations for a newer
+    /// engine than this server's are refused
+    #[serde(default)]
+    pub engine_version: Option<u32>,
+    /// Separators and date formats the input's text values are read with, and the collation
+    /// Sort uses; applied at job start on top of any locale of the source
+    #[serde(default)]
+    pub locale: Option<Locale>,
+    /// Top-level fields held as exact decimals: rounded half up to their scale before output,
+    /// failing the job when a value does not fit, and written as DECIMAL to Parquet, Avro and
+    /// Delta and as NUMERIC to databases
+    #[serde(default)]
+    pub decimal_fields: BTreeMap<String, DecimalPrecision>,
+    #[serde(default)]
+    pub null_semantics: NullSemantics,
+    /// Checked against every run's output before any sink is written; a violation fails the job
+    #[serde(default)]
+    pub invariants: Vec<Invariant>,
+    /// Least time between checkpoints of the run's intermediate records in the work directory.
+    /// A retry, or the server after a crash or restart, resumes the run from the last one.
+    #[serde(default)]
+    pub checkpoint_interval_secs: Option<u64>,
+    /// Versions to read input and joined sources at, by source id; others are read at their
+    /// current version. A pinned version must be current or retained when the job runs.
+    #[serde(default)]
+    pub pinned_versions: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputSpec {
+    /// Label for the sink's entry in the job results
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(deserialize_with = "OutputFormat::deserialize_compat")]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub output_path: Option<String>,
+    #[serde(default)]
+    pub partition_by: Option<Partitioning>,
+    #[serde(default)]
+    pub output_compression: OutputCompression,
+    #[serde(default)]
+    pub output_encryption: Option<OutputEncryption>,
+    #[serde(default)]
+    pub output_columns: Option<Vec<OutputColumn>>,
+}
+
+/// One column of a mapped output, in output order: copied from `from` (a dotted data path,
+/// or `$id`, `$timestamp` or `$source`; defaults to `name`) or set to the constant `value`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputColumn {
+    pub name: String,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+impl OutputColumn {
+    fn resolve(&self, record: &DataRecord) -> Value {
+        if let Some(value) = &self.value {
+            return value.clone();
+        }
+        match self.from.as_deref().unwrap_or(&self.name) {
+            "$id" => json!(record.id),
+            "$timestamp" => json!(record.timestamp),
+            "$source" => json!(record.source),
+            path => path.split('.')
+                .try_fold(&record.data, |value, segment| value.get(segment))
+                .cloned()
+                .unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// Upper bound on `parallel_workers`; each worker is an OS thread for the job's lifetime.
+const MAX_PARALLEL_WORKERS: usize = 256;
+
+impl ProcessingConfig {
+    /// Refuses configurations this server cannot run as written.
+    fn validate(&self) -> Result<(), String> {
+        self.check_engine_version()?;
+        if let Some(locale) = &self.locale {
+            locale.validate()?;
+        }
+        for (field, precision) in &self.decimal_fields {
+            precision.validate().map_err(|e| format!("Decimal field {}: {}", field, e))?;
+        }
+        for (index, invariant) in self.invariants.iter().enumerate() {
+            if let Invariant::UniqueKeyPreserved { fields } = invariant {
+                if fields.is_empty() {
+                    return Err(format!("Invariant {} needs at least one key field", index));
+                }
+            }
+        }
+        if self.parallel_workers > MAX_PARALLEL_WORKERS {
+            return Err(format!("parallel_workers may be at most {}", MAX_PARALLEL_WORKERS));
+        }
+        if let Some(duplicate) = self.input_sources.iter().enumerate().find(|(index, id)| self.input_sources[..*index].contains(id)) {
+            return Err(format!("Input source {} is listed twice", duplicate.1));
+        }
+        Ok(())
+    }
+
+    fn check_engine_version(&self) -> Result<(), String> {
+        match self.engine_version {
+            Some(version) if version > ENGINE_VERSION => Err(format!(
+                "Configuration targets engine version {} but this server runs version {}",
+                version, ENGINE_VERSION,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deprecated behaviours the configuration relies on, whose results will change in a
+    /// later engine version.
+    pub fn deprecation_warnings(&self) -> Vec<DeprecationWarning> {
+        let mut warnings = Vec::new();
+        let mut warn = |code: &str, path: String, message: String| {
+            warnings.push(DeprecationWarning { code: code.to_string(), path, message });
+        };
+        if self.input_sources.is_empty() {
+            warn("implicit_input_source", "input_sources".to_string(),
+                "Without input_sources the job reads whichever source id sorts first".to_string());
+        }
+        for (index, operation) in self.operations.iter().enumerate() {
+            let path = format!("operations[{}]", index);
+            match operation {
+                Operation::Sort { fields, .. } => {
+                    warn("sort_text_order", path.clone(),
+                        "Sort compares values by their JSON text, so numbers order as strings (10 before 9)".to_string());
+                    if fields.len() > 1 {
+                        warn("sort_first_field_only", path,
+                            format!("Sort orders by {} only and ignores {}", fields[0], fields[1..].join(", ")));
+                    }
+                },
+                Operation::Validate { rules } => {
+                    if self.dead_letter.is_none() {
+                        warn("validate_log_only", path.clone(),
+                            "Without a dead_letter sink, failing records are only logged and stay in the output".to_string());
+                    }
+                    for (rule_index, rule) in rules.iter().enumerate() {
+                        if let ValidationType::Custom { .. } = rule.rule_type {
+                            warn("validation_rule_unchecked", format!("{}.rules[{}]", path, rule_index),
+                                "Custom rules are not checked yet; every record passes them".to_string());
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        warnings
+    }
+
+    /// The primary output followed by any additional sinks, with the label each reports under.
+    fn sinks(&self) -> Vec<(String, OutputSpec)> {
+        let primary = OutputSpec {
+            name: None,
+            format: self.output_format.clone(),
+            output_path: self.output_path.clone(),
+            partition_by: self.partition_by.clone(),
+            output_compression: self.output_compression,
+            output_encryption: self.output_encryption.clone(),
+            output_columns: self.output_columns.clone(),
+        };
+        let additional = self.outputs.iter().enumerate().map(|(index, sink)| {
+            let label = sink.name.clone().unwrap_or_else(|| (index + 1).to_string());
+            (format!("Output: {}", label), sink.clone())
+        });
+        std::iter::once(("Output".to_string(), primary)).chain(additional).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl OutputCompression {
+    fn extension(&self, base: &str) -> String {
+        match self {
+            OutputCompression::None => base.to_string(),
+            OutputCompression::Gzip => format!("{}.gz", base),
+            OutputCompression::Zstd => format!("{}.zst", base),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OutputEncryption {
+    /// AES-256-GCM keyed by the SHA-256 of the passphrase in the `key_env` environment
+    /// variable; decrypt with `data-processor decrypt`
+    Aes256Gcm {
+        #[serde(default = "default_output_key_env")]
+        key_env: String,
+    },
+}
+
+fn default_output_key_env() -> String {
+    "DATA_PROCESSOR_OUTPUT_KEY".to_string()
+}
+
+impl OutputEncryption {
+    fn key(&self) -> Result<[u8; 32], String> {
+        match self {
+            OutputEncryption::Aes256Gcm { key_env } => {
+                let passphrase = std::env::var(key_env)
+                    .map_err(|_| format!("Output encryption key variable {} is not set", key_env))?;
+                Ok(Sha256::digest(passphrase.as_bytes()).into())
+            },
+        }
+    }
+}
+
+/// Leading bytes of an encrypted output file, followed by the 7-byte stream nonce prefix.
+const ENCRYPTED_OUTPUT_MAGIC: &[u8; 6] = b"DPENC1";
+/// Plaintext bytes sealed per segment; each segment carries its own 16-byte tag.
+const ENCRYPTED_SEGMENT_BYTES: usize = 64 * 1024;
+
+fn output_cipher(key: &[u8; 32]) -> Aes256Gcm {
+    <Aes256Gcm as aes_gcm::KeyInit>::new(key.into())
+}
+
+/// Seals output in fixed-size segments with the AEAD STREAM construction, so files of any
+/// size encrypt without buffering and truncating, reordering or splicing segments fails
+/// decryption.
+struct EncryptingWriter<W: Write> {
+    inner: W,
+    encryptor: Option<EncryptorBE32<Aes256Gcm>>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    fn new(mut inner: W, key: &[u8; 32]) -> std::io::Result<Self> {
+        let nonce: [u8; 7] = rand::random();
+        inner.write_all(ENCRYPTED_OUTPUT_MAGIC)?;
+        inner.write_all(&nonce)?;
+        Ok(Self {
+            inner,
+            encryptor: Some(EncryptorBE32::from_aead(output_cipher(key), nonce.as_slice().into())),
+            buffer: Vec::with_capacity(ENCRYPTED_SEGMENT_BYTES),
+        })
+    }
+
+    /// Seals the final segment (possibly empty) and returns the inner writer.
+    fn finish(mut self) -> Result<W, String> {
+        let encryptor = self.encryptor.take().ok_or("Encrypted output already finished")?;
+        let sealed = encryptor.encrypt_last(self.buffer.as_slice()).map_err(|e| e.to_string())?;
+        self.inner.write_all(&sealed).map_err(|e| e.to_string())?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        // Hold back a full segment so the last one can always be sealed as final
+        while self.buffer.len() > ENCRYPTED_SEGMENT_BYTES {
+            let encryptor = self.encryptor.as_mut().ok_or_else(|| std::io::Error::other("Encrypted output already finished"))?;
+            let sealed = encryptor.encrypt_next(&self.buffer[..ENCRYPTED_SEGMENT_BYTES])
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            self.inner.write_all(&sealed)?;
+            self.buffer.drain(..ENCRYPTED_SEGMENT_BYTES);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a file written with output encryption into `output`.
+fn decrypt_output_file(path: &Path, key: &[u8; 32], output: &mut impl Write) -> Result<(), String> {
+    let mut input = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut header = [0u8; 13];
+    input.read_exact(&mut header).map_err(|_| "File is not an encrypted output file".to_string())?;
+    if &header[..6] != ENCRYPTED_OUTPUT_MAGIC {
+        return Err("File is not an encrypted output file".to_string());
+    }
+    let mut decryptor = DecryptorBE32::from_aead(output_cipher(key), header[6..].into());
+
+    let failed = || "Decryption failed: wrong key or corrupted file".to_string();
+    let mut segment = vec![0u8; ENCRYPTED_SEGMENT_BYTES + 16];
+    loop {
+        let mut filled = 0;
+        while filled < segment.len() {
+            match input.read(&mut segment[filled..]).map_err(|e| e.to_string())? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        let at_end = input.fill_buf().map_err(|e| e.to_string())?.is_empty();
+        if at_end {
+            let plaintext = decryptor.decrypt_last(&segment[..filled]).map_err(|_| failed())?;
+            return output.write_all(&plaintext).map_err(|e| e.to_string());
+        }
+        let plaintext = decryptor.decrypt_next(&segment[..filled]).map_err(|_| failed())?;
+        output.write_all(&plaintext).map_err(|e| e.to_string())?;
+    }
+}
+
+/// Output file, encrypted when the sink configures a key.
+enum OutputFile {
+    Plain(BufWriter<File>),
+    // Boxed: the AES key schedule would make every plain file writer as large
+    Encrypted(Box<EncryptingWriter<BufWriter<File>>>),
+}
+
+impl OutputFile {
+    fn create(path: &str, key: Option<&[u8; 32]>) -> Result<Self, String> {
+        let file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        Ok(match key {
+            Some(key) => OutputFile::Encrypted(Box::new(EncryptingWriter::new(file, key).map_err(|e| e.to_string())?)),
+            None => OutputFile::Plain(file),
+        })
+    }
+
+    fn finish(self) -> Result<(), String> {
+        let mut file = match self {
+            OutputFile::Plain(file) => file,
+            OutputFile::Encrypted(writer) => writer.finish()?,
+        };
+        file.flush().map_err(|e| e.to_string())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            OutputFile::Plain(file) => file.write(buf),
+            OutputFile::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputFile::Plain(file) => file.flush(),
+            OutputFile::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// File writer that optionally compresses everything written through it.
+enum OutputWriter {
+    Plain(OutputFile),
+    Gzip(GzEncoder<OutputFile>),
+    Zstd(zstd::Encoder<'static, OutputFile>),
+}
+
+impl OutputWriter {
+    fn create(path: &str, compression: OutputCompression, key: Option<&[u8; 32]>) -> Result<Self, String> {
+        let file = OutputFile::create(path, key)?;
+        Ok(match compression {
+            OutputCompression::None => OutputWriter::Plain(file),
+            OutputCompression::Gzip => OutputWriter::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            OutputCompression::Zstd => OutputWriter::Zstd(zstd::Encoder::new(file, 0).map_err(|e| e.to_string())?),
+        })
+    }
+
+    /// Writes the compression trailer and flushes; dropping without finishing truncates the file.
+    fn finish(self) -> Result<(), String> {
+        let file = match self {
+            OutputWriter::Plain(file) => file,
+            OutputWriter::Gzip(encoder) => encoder.finish().map_err(|e| e.to_string())?,
+            OutputWriter::Zstd(encoder) => encoder.finish().map_err(|e| e.to_string())?,
+        };
+        file.finish()
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            OutputWriter::Plain(file) => file.write(buf),
+            OutputWriter::Gzip(encoder) => encoder.write(buf),
+            OutputWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputWriter::Plain(file) => file.flush(),
+            OutputWriter::Gzip(encoder) => encoder.flush(),
+            OutputWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Partitioning {
+    Field { field: String },
+    Timestamp { granularity: TimeGranularity },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeGranularity {
+    Daily,
+    Hourly,
+}
+
+impl Partitioning {
+    /// Returns the partition directory for a record, e.g. `country=US` or `date=2024-01-15/hour=13`.
+    fn partition_path(&self, record: &DataRecord) -> String {
+        match self {
+            Partitioning::Field { field } => {
+                let value = match record.data.get(field) {
+                    None | Some(Value::Null) => "__HIVE_DEFAULT_PARTITION__".to_string(),
+                    Some(Value::String(text)) => text.clone(),
+                    Some(other) => other.to_string(),
+                };
+                let value: String = value.chars()
+                    .map(|c| if matches!(c, '/' | '\\' | '=' | ':') { '_' } else { c })
+                    .collect();
+                format!("{}={}", field, value.replace("..", "_"))
+            },
+            Partitioning::Timestamp { granularity: TimeGranularity::Daily } => {
+                format!("date={}", record.timestamp.format("%Y-%m-%d"))
+            },
+            Partitioning::Timestamp { granularity: TimeGranularity::Hourly } => {
+                format!("date={}/hour={}", record.timestamp.format("%Y-%m-%d"), record.timestamp.format("%H"))
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,6 +973,69 @@ pub enum Operation {
     Validate { rules: Vec<ValidationRule> },
 }
 
+impl Operation {
+    /// Filter, Transform and Validate look at one record at a time, so they work on any batch.
+    fn is_record_wise(&self) -> bool {
+        matches!(self, Operation::Filter { .. } | Operation::Transform { .. } | Operation::Validate { .. })
+    }
+
+    /// The variant name, such as `Filter`.
+    fn kind(&self) -> String {
+        format!("{:?}", self).split([' ', '{', '(']).next().unwrap_or_default().to_string()
+    }
+}
+
+/// How operations treat null values. A missing field counts as null everywhere. The defaults
+/// follow SQL: null join keys match nothing while Deduplicate, like `DISTINCT`, treats nulls
+/// as equal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NullSemantics {
+    pub sort: NullOrder,
+    /// Whether records with null join keys match each other
+    pub join: NullEquality,
+    /// Whether null values make records duplicates of each other
+    pub deduplicate: NullEquality,
+    /// What Sum, Average, Min and Max make of null values
+    pub aggregates: NullAggregation,
+}
+
+/// Where Sort places nulls, whichever the direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NullOrder {
+    First,
+    #[default]
+    Last,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NullEquality {
+    /// Null equals null, as in `IS NOT DISTINCT FROM`
+    Equal,
+    /// Null equals nothing, itself included, as in SQL `=`
+    Distinct,
+}
+
+impl Default for NullSemantics {
+    fn default() -> Self {
+        Self {
+            sort: NullOrder::Last,
+            join: NullEquality::Distinct,
+            deduplicate: NullEquality::Equal,
+            aggregates: NullAggregation::Skip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NullAggregation {
+    /// Nulls are left out, so they do not count towards an average
+    #[default]
+    Skip,
+    /// Nulls count as 0
+    Zero,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AggregateFunction {
     Count,
@@ -82,6 +1044,20 @@ pub enum AggregateFunction {
     Min { field: String },
     Max { field: String },
     Custom { name: String, expression: String },
+    /// HyperLogLog distinct count; each group holds `2^precision` bytes
+    ApproxDistinct {
+        field: String,
+        #[serde(default = "default_hll_precision")]
+        precision: u8,
+    },
+    /// t-digest estimates of quantiles between 0.0 and 1.0
+    ApproxQuantiles { field: String, quantiles: Vec<f64> },
+    /// The `k` most frequent values (at most 10,000) with count-min sketch counts
+    TopK { field: String, k: usize },
+}
+
+fn default_hll_precision() -> u8 {
+    12
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -104,696 +1080,13132 @@ pub enum ValidationType {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OutputFormat {
     Json,
-    Csv,
-    Parquet,
-    Database { connection_string: String, table: String },
-    Api { endpoint: String, headers: HashMap<String, String> },
+    /// One JSON object per line; `data_only` drops the id/timestamp/source wrapper
+    JsonLines {
+        #[serde(default)]
+        data_only: bool,
+    },
+    /// Columns default to id, timestamp and source followed by every data field seen, with
+    /// nested objects flattened into dotted names (`address.city`). Listed columns named id,
+    /// timestamp or source take the data field of that name when records have one.
+    Csv {
+        #[serde(default)]
+        columns: Option<Vec<String>>,
+    },
+    Parquet {
+        #[serde(default)]
+        schema: Option<Vec<SchemaField>>,
+        #[serde(default = "default_row_group_size")]
+        row_group_size: usize,
+        #[serde(default)]
+        compression: ParquetCompression,
+    },
+    Avro {
+        /// Avro record schema (JSON); generated from the records when omitted
+        #[serde(default)]
+        schema: Option<Value>,
+        #[serde(default)]
+        codec: AvroCodec,
+    },
+    Database {
+        connection_string: String,
+        table: String,
+        #[serde(default = "default_insert_batch_size")]
+        batch_size: usize,
+        #[serde(default)]
+        create_table: bool,
+        /// Columns identifying a row, made the primary key by `create_table`; an existing table
+        /// needs a unique index over them for `on_conflict` to take effect
+        #[serde(default)]
+        conflict_keys: Vec<String>,
+        #[serde(default)]
+        on_conflict: ConflictAction,
+    },
+    Api {
+        endpoint: String,
+        headers: HashMap<String, String>,
+        /// Records per request; the whole dataset is sent in one request when omitted
+        #[serde(default)]
+        batch_size: Option<usize>,
+    },
+    /// Publishes one message per record, keyed by `key_field` when set
+    Kafka {
+        brokers: Vec<String>,
+        topic: String,
+        #[serde(default)]
+        key_field: Option<String>,
+        #[serde(default)]
+        encoding: KafkaEncoding,
+    },
+    /// Indexes records through the Elasticsearch/OpenSearch `_bulk` API
+    Elasticsearch {
+        url: String,
+        /// Index name template; `{date}` is the record's day (`YYYY.MM.DD`), `{job_id}`
+        /// the job, and any other `{name}` the value of that output field
+        index: String,
+        /// Field used as the document `_id`; Elasticsearch assigns ids when omitted
+        #[serde(default)]
+        id_field: Option<String>,
+        #[serde(default = "default_bulk_batch_size")]
+        batch_size: usize,
+        /// Attempts for documents rejected with 429 before they are reported as failed
+        #[serde(default = "default_bulk_retries")]
+        max_retries: u32,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// Writes one document per record; `_id` comes from `id_field` when set
+    MongoDb {
+        connection_string: String,
+        database: String,
+        collection: String,
+        #[serde(default)]
+        mode: MongoWriteMode,
+        #[serde(default)]
+        id_field: Option<String>,
+        #[serde(default = "default_insert_batch_size")]
+        batch_size: usize,
+    },
+    /// Appends to a Delta Lake table at a local path or `s3://` URL, creating it when missing
+    DeltaLake {
+        /// Object store URI, or a path relative to the output directory
+        table_uri: String,
+        /// Object store settings such as `aws_region`, `aws_access_key_id` or `aws_endpoint`
+        #[serde(default)]
+        storage_options: HashMap<String, String>,
+        /// Partition columns of a new table; existing tables keep their own
+        #[serde(default)]
+        partition_columns: Vec<String>,
+        #[serde(default)]
+        schema_mode: DeltaSchemaMode,
+        #[serde(default)]
+        compression: ParquetCompression,
+        #[serde(default = "default_row_group_size")]
+        row_group_size: usize,
+    },
+    /// Writes each record under `key_prefix` followed by its `key_field` value, replacing
+    /// whatever the key held before
+    Redis {
+        url: String,
+        key_field: String,
+        #[serde(default)]
+        key_prefix: String,
+        #[serde(default)]
+        value_type: RedisValueType,
+        #[serde(default)]
+        ttl_seconds: Option<u64>,
+        /// Records per pipelined transaction
+        #[serde(default = "default_bulk_batch_size")]
+        batch_size: usize,
+    },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProcessingResult {
-    pub operation: String,
-    pub records_processed: usize,
-    pub execution_time_ms: u128,
-    pub memory_used_bytes: usize,
-    pub errors: Vec<ProcessingError>,
-    pub metadata: HashMap<String, Value>,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeltaSchemaMode {
+    /// Columns missing from the table are added to its schema
+    #[default]
+    Merge,
+    /// Output with columns the table does not have is rejected
+    Strict,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProcessingError {
-    pub error_type: String,
-    pub message: String,
-    pub record_id: Option<String>,
-    pub timestamp: DateTime<Utc>,
-    pub context: HashMap<String, Value>,
+/// Attempts to commit a Delta version before giving up on concurrent writers
+const DELTA_COMMIT_ATTEMPTS: usize = 10;
+/// Highest Delta writer protocol version whose requirements appends satisfy
+const DELTA_MAX_WRITER_VERSION: i64 = 2;
+/// Widest decimal stored as a Parquet INT64; wider ones use fixed-length byte arrays
+const PARQUET_INT64_DECIMAL_DIGITS: u8 = 18;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RedisValueType {
+    /// The record's data as a JSON string value
+    #[default]
+    Json,
+    /// A hash of the top-level fields; non-string values are stored as JSON
+    Hash,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SystemMetrics {
-    pub cpu_usage: f64,
-    pub memory_usage: f64,
-    pub disk_usage: f64,
-    pub active_jobs: usize,
-    pub total_records_processed: u64,
-    pub average_processing_time_ms: f64,
-    pub error_rate: f64,
-    pub uptime_seconds: u64,
+impl OutputFormat {
+    /// Also reads the bare names (`"Csv"`, `"Parquet"`) that formats which have since gained
+    /// options were once given as, taking the defaults for every option.
+    fn deserialize_compat<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = match Value::deserialize(deserializer)? {
+            Value::String(name) if name != "Json" => Value::Object(serde_json::Map::from_iter([(name, json!({}))])),
+            value => value,
+        };
+        OutputFormat::deserialize(value).map_err(serde::de::Error::custom)
+    }
+
+    /// Whether the sink writes a local file rather than sending records to a service.
+    fn is_file_output(&self) -> bool {
+        !matches!(
+            self,
+            OutputFormat::Database { .. }
+                | OutputFormat::Api { .. }
+                | OutputFormat::Kafka { .. }
+                | OutputFormat::Elasticsearch { .. }
+                | OutputFormat::MongoDb { .. }
+                | OutputFormat::Redis { .. }
+                | OutputFormat::DeltaLake { .. }
+        )
+    }
+
+    /// File extension of a file output, before any compression suffix.
+    fn file_extension(&self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Json => Some("json"),
+            OutputFormat::JsonLines { .. } => Some("jsonl"),
+            OutputFormat::Csv { .. } => Some("csv"),
+            OutputFormat::Parquet { .. } => Some("parquet"),
+            OutputFormat::Avro { .. } => Some("avro"),
+            _ => None,
+        }
+    }
 }
 
-pub struct DataProcessor {
-    jobs: Arc<RwLock<HashMap<String, ProcessingJob>>>,
-    data_store: Arc<RwLock<HashMap<String, Vec<DataRecord>>>>,
-    metrics: Arc<RwLock<SystemMetrics>>,
-    job_sender: mpsc::UnboundedSender<ProcessingJob>,
-    start_time: Instant,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MongoWriteMode {
+    /// Documents without `id_field` get a server-assigned `_id`
+    #[default]
+    Insert,
+    /// Replaces the document with the same `_id`, inserting it when missing; the record id
+    /// is the key when `id_field` is not set
+    Upsert,
 }
 
-impl DataProcessor {
-    pub fn new() -> Self {
-        let (job_sender, job_receiver) = mpsc::unbounded_channel();
-        
-        let processor = Self {
-            jobs: Arc::new(RwLock::new(HashMap::new())),
-            data_store: Arc::new(RwLock::new(HashMap::new())),
-            metrics: Arc::new(RwLock::new(SystemMetrics {
-                cpu_usage: 0.0,
-                memory_usage: 0.0,
-                disk_usage: 0.0,
-                active_jobs: 0,
-                total_records_processed: 0,
-                average_processing_time_ms: 0.0,
-                error_rate: 0.0,
-                uptime_seconds: 0,
-            })),
-            job_sender,
-            start_time: Instant::now(),
-        };
+/// What a database write does with a record whose conflict keys match an existing row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictAction {
+    /// Plain insert, failing the write on a unique key violation
+    #[default]
+    Fail,
+    /// Overwrites the row's other columns; of several records with the same key the last wins
+    Update,
+    /// Keeps the existing row; of several records with the same key the first wins
+    Ignore,
+}
 
-        // Start background job processor
-        let jobs_clone = processor.jobs.clone();
-        let metrics_clone = processor.metrics.clone();
-        let data_store_clone = processor.data_store.clone();
-        
-        tokio::spawn(async move {
-            Self::job_processor(job_receiver, jobs_clone, metrics_clone, data_store_clone).await;
-        });
+/// Upserts in flight at once; MongoDB 6/7 has no multi-document replace in one command
+const MONGO_UPSERT_CONCURRENCY: usize = 16;
 
-        // Start metrics updater
-        let metrics_clone = processor.metrics.clone();
-        let start_time = processor.start_time;
-        
-        tokio::spawn(async move {
-            Self::update_metrics(metrics_clone, start_time).await;
-        });
+fn default_bulk_batch_size() -> usize {
+    500
+}
 
-        processor
-    }
+fn default_bulk_retries() -> u32 {
+    5
+}
 
-    pub async fn submit_job(&self, mut job: ProcessingJob) -> Result<String, String> {
-        job.id = Uuid::new_v4().to_string();
-        job.status = JobStatus::Pending;
-        job.created_at = Utc::now();
-        
-        let job_id = job.id.clone();
-        
-        // Store job
-        {
-            let mut jobs = self.jobs.write().await;
-            jobs.insert(job_id.clone(), job.clone());
-        }
-        
-        // Send to processor
-        self.job_sender.send(job).map_err(|e| e.to_string())?;
-        
-        println!("Job submitted: {}", job_id);
-        Ok(job_id)
-    }
+const OUTPUT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_OUTPUT_RETRY_BACKOFF: Duration = Duration::from_secs(30);
 
-    pub async fn get_job_status(&self, job_id: &str) -> Option<ProcessingJob> {
-        let jobs = self.jobs.read().await;
-        jobs.get(job_id).cloned()
-    }
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum KafkaEncoding {
+    #[default]
+    Json,
+    /// Avro binary values; registered under `<topic>-value` and framed in the
+    /// Confluent wire format when `schema_registry` is set
+    Avro {
+        #[serde(default)]
+        schema: Option<Value>,
+        #[serde(default)]
+        schema_registry: Option<String>,
+    },
+}
 
-    pub async fn list_jobs(&self) -> Vec<ProcessingJob> {
-        let jobs = self.jobs.read().await;
-        jobs.values().cloned().collect()
-    }
+/// Records per produce request
+const KAFKA_PRODUCE_BATCH: usize = 500;
+/// Records serialized per chunk of a streamed request body
+const STREAM_CHUNK_RECORDS: usize = 1_000;
 
-    pub async fn cancel_job(&self, job_id: &str) -> Result<(), String> {
-        let mut jobs = self.jobs.write().await;
-        if let Some(job) = jobs.get_mut(job_id) {
-            if matches!(job.status, JobStatus::Pending | JobStatus::Running) {
-                job.status = JobStatus::Cancelled;
-                println!("Job cancelled: {}", job_id);
-                Ok(())
-            } else {
-                Err("Job cannot be cancelled in current status".to_string())
-            }
-        } else {
-            Err("Job not found".to_string())
+fn default_row_group_size() -> usize {
+    100_000
+}
+
+fn default_insert_batch_size() -> usize {
+    1_000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SqlDialect {
+    Postgres,
+    MySql,
+    Sqlite,
+}
+
+impl SqlDialect {
+    fn from_connection_string(connection_string: &str) -> Result<Self, String> {
+        let scheme = connection_string.split(':').next().unwrap_or_default();
+        match scheme {
+            "postgres" | "postgresql" => Ok(SqlDialect::Postgres),
+            "mysql" | "mariadb" => Ok(SqlDialect::MySql),
+            "sqlite" => Ok(SqlDialect::Sqlite),
+            _ => Err(format!("Unsupported database scheme: {}", scheme)),
         }
     }
 
-    pub async fn load_data_from_file(&self, source_id: &str, file_path: &str) -> Result<usize, String> {
-        let path = Path::new(file_path);
-        if !path.exists() {
-            return Err("File not found".to_string());
+    fn quote_identifier(&self, name: &str) -> String {
+        match self {
+            SqlDialect::MySql => format!("`{}`", name.replace('`', "``")),
+            _ => format!("\"{}\"", name.replace('"', "\"\"")),
         }
+    }
 
-        let mut records = Vec::new();
-        
-        if file_path.ends_with(".csv") {
-            // Load CSV data
-            let file = File::open(path).map_err(|e| e.to_string())?;
-            let mut reader = ReaderBuilder::new().has_headers(true).from_reader(file);
-            
-            for result in reader.records() {
-                let record = result.map_err(|e| e.to_string())?;
-                let mut data_map = serde_json::Map::new();
-                
-                for (i, field) in record.iter().enumerate() {
-                    data_map.insert(format!("field_{}", i), Value::String(field.to_string()));
-                }
-                
-                records.push(DataRecord {
-                    id: Uuid::new_v4().to_string(),
-                    timestamp: Utc::now(),
-                    data: Value::Object(data_map),
-                    source: source_id.to_string(),
-                    processed: false,
-                    metadata: HashMap::new(),
-                });
-            }
-        } else if file_path.ends_with(".json") {
-            // Load JSON data
-            let file = File::open(path).map_err(|e| e.to_string())?;
-            let reader = BufReader::new(file);
-            
-            for line in reader.lines() {
-                let line = line.map_err(|e| e.to_string())?;
-                if let Ok(data) = serde_json::from_str::<Value>(&line) {
-                    records.push(DataRecord {
-                        id: Uuid::new_v4().to_string(),
-                        timestamp: Utc::now(),
-                        data,
-                        source: source_id.to_string(),
-                        processed: false,
-                        metadata: HashMap::new(),
-                    });
-                }
-            }
+    fn placeholder(&self, index: usize) -> String {
+        match self {
+            SqlDialect::Postgres => format!("${}", index),
+            _ => "?".to_string(),
         }
+    }
 
-        let count = records.len();
-        
-        // Store data
-        {
-            let mut data_store = self.data_store.write().await;
-            data_store.insert(source_id.to_string(), records);
+    fn column_type(&self, field_type: FieldType) -> Cow<'static, str> {
+        match (self, field_type) {
+            (_, FieldType::Boolean) => "BOOLEAN".into(),
+            (_, FieldType::Integer) => "BIGINT".into(),
+            (SqlDialect::Postgres, FieldType::Float) => "DOUBLE PRECISION".into(),
+            (SqlDialect::MySql, FieldType::Float) => "DOUBLE".into(),
+            (SqlDialect::Sqlite, FieldType::Float) => "REAL".into(),
+            (_, FieldType::String | FieldType::Json) => "TEXT".into(),
+            (SqlDialect::MySql, FieldType::Decimal(d)) => format!("DECIMAL({}, {})", d.precision, d.scale).into(),
+            (_, FieldType::Decimal(d)) => format!("NUMERIC({}, {})", d.precision, d.scale).into(),
         }
-
-        println!("Loaded {} records from {}", count, file_path);
-        Ok(count)
     }
 
-    pub async fn load_data_from_api(&self, source_id: &str, endpoint: &str) -> Result<usize, String> {
-        let client = Client::new();
-        let response = client.get(endpoint).send().await.map_err(|e| e.to_string())?;
-        
-        if !response.status().is_success() {
-            return Err(format!("API request failed: {}", response.status()));
+    /// Column type for a primary key column; MySQL cannot index TEXT without a prefix length.
+    fn key_column_type(&self, field_type: FieldType) -> Cow<'static, str> {
+        match (self, field_type) {
+            (SqlDialect::MySql, FieldType::String | FieldType::Json) => "VARCHAR(255)".into(),
+            _ => self.column_type(field_type),
         }
+    }
 
-        let data: Value = response.json().await.map_err(|e| e.to_string())?;
-        let mut records = Vec::new();
+    /// Placeholder for a value of `field_type`. Decimals are bound as their exact text and
+    /// cast by the database, since the drivers only bind them as doubles.
+    fn value_placeholder(&self, index: usize, field_type: FieldType) -> String {
+        match field_type {
+            FieldType::Decimal(_) => format!("CAST({} AS {})", self.placeholder(index), self.column_type(field_type)),
+            _ => self.placeholder(index),
+        }
+    }
 
-        // Handle different API response formats
-        match data {
-            Value::Array(items) => {
-                for item in items {
-                    records.push(DataRecord {
-                        id: Uuid::new_v4().to_string(),
-                        timestamp: Utc::now(),
-                        data: item,
-                        source: source_id.to_string(),
-                        processed: false,
-                        metadata: HashMap::new(),
-                    });
-                }
+    /// Clause appended to an INSERT so rows matching the quoted `keys` are updated or kept.
+    fn conflict_clause(&self, keys: &[String], columns: &[String], action: ConflictAction) -> String {
+        let updated: Vec<&String> = columns.iter().filter(|column| !keys.contains(column)).collect();
+        match (self, action) {
+            (_, ConflictAction::Fail) => String::new(),
+            (SqlDialect::MySql, ConflictAction::Update) if !updated.is_empty() => {
+                let assignments: Vec<String> = updated.iter().map(|column| format!("{0} = VALUES({0})", column)).collect();
+                format!(" ON DUPLICATE KEY UPDATE {}", assignments.join(", "))
             },
-            Value::Object(obj) => {
-                if let Some(items) = obj.get("data").and_then(|v| v.as_array()) {
-                    for item in items {
-                        records.push(DataRecord {
-                            id: Uuid::new_v4().to_string(),
-                            timestamp: Utc::now(),
-                            data: item.clone(),
-                            source: source_id.to_string(),
-                            processed: false,
-                            metadata: HashMap::new(),
-                        });
-                    }
-                } else {
-                    records.push(DataRecord {
-                        id: Uuid::new_v4().to_string(),
-                        timestamp: Utc::now(),
-                        data: Value::Object(obj),
-                        source: source_id.to_string(),
-                        processed: false,
-                        metadata: HashMap::new(),
-                    });
-                }
+            // A no-op update rather than INSERT IGNORE, which also swallows unrelated errors
+            (SqlDialect::MySql, _) => format!(" ON DUPLICATE KEY UPDATE {0} = {0}", keys[0]),
+            (_, ConflictAction::Update) if !updated.is_empty() => {
+                let assignments: Vec<String> = updated.iter().map(|column| format!("{0} = EXCLUDED.{0}", column)).collect();
+                format!(" ON CONFLICT ({}) DO UPDATE SET {}", keys.join(", "), assignments.join(", "))
             },
-            _ => {
-                records.push(DataRecord {
-                    id: Uuid::new_v4().to_string(),
-                    timestamp: Utc::now(),
-                    data,
-                    source: source_id.to_string(),
-                    processed: false,
-                    metadata: HashMap::new(),
-                });
-            }
+            _ => format!(" ON CONFLICT ({}) DO NOTHING", keys.join(", ")),
         }
+    }
+}
 
-        let count = records.len();
-        
-        // Store data
-        {
-            let mut data_store = self.data_store.write().await;
-            data_store.insert(source_id.to_string(), records);
-        }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParquetCompression {
+    None,
+    #[default]
+    Snappy,
+    Zstd,
+}
 
-        println!("Loaded {} records from API {}", count, endpoint);
-        Ok(count)
-    }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AvroCodec {
+    #[default]
+    Null,
+    Deflate,
+    Snappy,
+    Zstd,
+}
 
-    pub async fn get_metrics(&self) -> SystemMetrics {
-        self.metrics.read().await.clone()
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldType {
+    Boolean,
+    Integer,
+    Float,
+    String,
+    Json,
+    /// Exact fixed-point number, written as Parquet DECIMAL and database NUMERIC
+    Decimal(DecimalPrecision),
+}
 
-    async fn job_processor(
-        mut receiver: mpsc::UnboundedReceiver<ProcessingJob>,
-        jobs: Arc<RwLock<HashMap<String, ProcessingJob>>>,
-        metrics: Arc<RwLock<SystemMetrics>>,
-        data_store: Arc<RwLock<HashMap<String, Vec<DataRecord>>>>,
-    ) {
-        while let Some(mut job) = receiver.recv().await {
-            println!("Processing job: {}", job.id);
-            
-            // Update job status
-            job.status = JobStatus::Running;
-            job.started_at = Some(Utc::now());
-            
-            {
-                let mut jobs_map = jobs.write().await;
-                jobs_map.insert(job.id.clone(), job.clone());
-            }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaField {
+    pub name: String,
+    pub field_type: FieldType,
+}
 
-            // Process job
-            let start_time = Instant::now();
-            let result = Self::execute_processing_job(&job, &data_store).await;
-            let execution_time = start_time.elapsed();
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessingResult {
+    pub operation: String,
+    pub records_processed: usize,
+    pub execution_time_ms: u128,
+    pub memory_used_bytes: usize,
+    pub errors: Vec<ProcessingError>,
+    pub metadata: HashMap<String, Value>,
+}
 
-            // Update job with results
-            match result {
-                Ok(results) => {
-                    job.status = JobStatus::Completed;
-                    job.completed_at = Some(Utc::now());
-                    job.results = results;
-                    job.processed_count = job.input_count; // Simplified
-                    println!("Job completed: {} in {:?}", job.id, execution_time);
-                },
-                Err(error) => {
-                    job.status = JobStatus::Failed;
-                    job.completed_at = Some(Utc::now());
-                    job.error_count += 1;
-                    println!("Job failed: {} - {}", job.id, error);
-                }
-            }
+/// What one stage of a job's operations produced: a run of record-wise operations or a
+/// single operation that needs the whole dataset.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StageOutput {
+    records: Vec<DataRecord>,
+    /// One result per operation of the stage, named by the caller
+    operations: Vec<ProcessingResult>,
+    /// Records for the dead letter output, with the stage position of the operation that
+    /// rejected them
+    rejected: Vec<(usize, DataRecord, String)>,
+}
 
-            // Update stored job
-            {
-                let mut jobs_map = jobs.write().await;
-                jobs_map.insert(job.id.clone(), job);
-            }
+impl StageOutput {
+    fn single(records: Vec<DataRecord>, metadata: HashMap<String, Value>, elapsed: Duration) -> Self {
+        let result = ProcessingResult {
+            operation: String::new(),
+            records_processed: records.len(),
+            execution_time_ms: elapsed.as_millis(),
+            memory_used_bytes: 0,
+            errors: Vec::new(),
+            metadata,
+        };
+        Self { records, operations: vec![result], rejected: Vec::new() }
+    }
 
-            // Update metrics
-            {
-                let mut metrics_guard = metrics.write().await;
-                metrics_guard.total_records_processed += job.processed_count as u64;
-                metrics_guard.average_processing_time_ms = execution_time.as_millis() as f64;
+    /// Adds what a later segment of the same record-wise stage produced.
+    fn absorb(&mut self, segment: StageOutput) {
+        self.records.extend(segment.records);
+        self.rejected.extend(segment.rejected);
+        if self.operations.is_empty() {
+            self.operations = segment.operations;
+            return;
+        }
+        for (total, part) in self.operations.iter_mut().zip(segment.operations) {
+            total.records_processed += part.records_processed;
+            total.execution_time_ms += part.execution_time_ms;
+            let workers = total.metadata.get_mut("workers").and_then(Value::as_array_mut);
+            let part_workers = part.metadata.get("workers").and_then(Value::as_array);
+            let (Some(workers), Some(part_workers)) = (workers, part_workers) else { continue };
+            for (worker, part) in workers.iter_mut().zip(part_workers) {
+                for field in ["records", "execution_time_ms"] {
+                    let sum = worker[field].as_u64().unwrap_or(0) + part[field].as_u64().unwrap_or(0);
+                    worker[field] = json!(sum);
+                }
             }
         }
     }
+}
 
-    async fn execute_processing_job(
-        job: &ProcessingJob,
-        data_store: &Arc<RwLock<HashMap<String, Vec<DataRecord>>>>,
-    ) -> Result<Vec<ProcessingResult>, String> {
-        let mut results = Vec::new();
-        
-        // Get input data (simplified - assumes single source)
-        let data = {
-            let store = data_store.read().await;
-            store.values().next().cloned().unwrap_or_default()
-        };
-
-        if data.is_empty() {
-            return Err("No input data available".to_string());
-        }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessingError {
+    pub error_type: String,
+    pub message: String,
+    pub record_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub context: HashMap<String, Value>,
+}
 
-        let mut current_data = data;
-        
-        // Execute operations sequentially
-        for operation in &job.configuration.operations {
-            let start_time = Instant::now();
-            let operation_name = format!("{:?}", operation);
-            
-            current_data = Self::execute_operation(operation, current_data).await?;
-            
-            let execution_time = start_time.elapsed();
-            
-            results.push(ProcessingResult {
-                operation: operation_name,
-                records_processed: current_data.len(),
-                execution_time_ms: execution_time.as_millis(),
-                memory_used_bytes: std::mem::size_of_val(&current_data),
-                errors: Vec::new(),
-                metadata: HashMap::new(),
-            });
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemMetrics {
+    pub cpu_usage: f64,
+    pub memory_usage: f64,
+    pub disk_usage: f64,
+    pub active_jobs: usize,
+    pub total_records_processed: u64,
+    pub average_processing_time_ms: f64,
+    pub error_rate: f64,
+    pub uptime_seconds: u64,
+    /// Missed schedule runs and watched files still waiting to be replayed
+    pub catch_up_pending: usize,
+    pub catch_up_completed: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordWriteResult {
+    pub index: usize,
+    pub accepted: bool,
+    pub record_id: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordWriteResponse {
+    pub source_id: String,
+    pub version: u64,
+    pub accepted: usize,
+    pub rejected: usize,
+    pub results: Vec<RecordWriteResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordAuditAction {
+    Patched,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordAuditEntry {
+    pub source_id: String,
+    pub record_id: String,
+    pub action: RecordAuditAction,
+    pub before: Value,
+    pub after: Option<Value>,
+    pub source_version: u64,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedQuery {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub source_id: String,
+    /// Field equality conditions a record must satisfy to be returned
+    #[serde(default)]
+    pub filter: HashMap<String, Value>,
+    /// Data fields to return; all fields when omitted
+    #[serde(default)]
+    pub fields: Option<Vec<String>>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryResults {
+    pub query_id: String,
+    pub name: String,
+    pub source_id: String,
+    pub source_version: u64,
+    pub count: usize,
+    pub offset: usize,
+    /// More matching records exist past this page
+    pub truncated: bool,
+    pub next_offset: Option<usize>,
+    pub records: Vec<Value>,
+}
+
+/// An operation list run inline against one source by `POST /query`, outside the job queue.
+/// Nothing is written: no sinks, no job directory, no checkpoints.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdHocQuery {
+    pub source_id: String,
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+    /// Input records read from the start of the source; at most `MAX_QUERY_INPUT_RECORDS`
+    #[serde(default)]
+    pub max_input: Option<usize>,
+    /// Longest the query may run; at most `MAX_QUERY_TIMEOUT_SECS`
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    #[serde(default)]
+    pub locale: Option<Locale>,
+    #[serde(default)]
+    pub null_semantics: NullSemantics,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdHocQueryResults {
+    pub source_id: String,
+    pub source_version: u64,
+    pub input_records: usize,
+    /// The source holds more records than the query read
+    pub input_truncated: bool,
+    /// Records the operations produced, before paging
+    pub total: usize,
+    pub count: usize,
+    pub offset: usize,
+    pub truncated: bool,
+    pub next_offset: Option<usize>,
+    pub elapsed_ms: u128,
+    pub results: Vec<ProcessingResult>,
+    pub records: Vec<DataRecord>,
+}
+
+/// Input records an ad-hoc query reads when the request does not say, and the most it may ask for
+const DEFAULT_QUERY_INPUT_RECORDS: usize = 10_000;
+const MAX_QUERY_INPUT_RECORDS: usize = 100_000;
+const DEFAULT_QUERY_TIMEOUT_SECS: u64 = 10;
+const MAX_QUERY_TIMEOUT_SECS: u64 = 60;
+
+/// Rows returned by a record-listing endpoint when the caller gives no `limit`.
+const DEFAULT_RESULT_ROWS: usize = 1000;
+/// Default for `--max-result-rows`, the most rows one response may carry.
+const DEFAULT_MAX_RESULT_ROWS: usize = 10_000;
+
+/// Paging parameters for endpoints that return records. `all=true` asks for the complete
+/// result set, which is refused rather than truncated when it exceeds the server's cap.
+#[derive(Debug, Default, Deserialize)]
+pub struct ResultWindow {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+impl ResultWindow {
+    /// Returns the offset and page size to serve, rejecting requests beyond `max_rows`.
+    fn resolve(&self, max_rows: usize) -> Result<(usize, usize), String> {
+        let offset = self.offset.unwrap_or(0);
+        match (self.all, self.limit) {
+            (true, Some(_)) => Err("limit and all cannot be combined".to_string()),
+            (true, None) => Ok((offset, max_rows)),
+            (false, Some(0)) => Err("limit must be at least 1".to_string()),
+            (false, Some(limit)) if limit > max_rows => {
+                Err(format!("limit {} exceeds the maximum of {} rows per response", limit, max_rows))
+            },
+            (false, Some(limit)) => Ok((offset, limit)),
+            (false, None) => Ok((offset, DEFAULT_RESULT_ROWS.min(max_rows))),
         }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub trigger: ScheduleTrigger,
+    /// Job definition submitted on every run
+    pub job: ProcessingJob,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub next_run_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_run_at: Option<DateTime<Utc>>,
+    /// Run owed from a deferring maintenance window, fired once the window closes
+    #[serde(default)]
+    pub deferred_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub skipped_runs: Vec<SkippedRun>,
+    /// Upper bound of the random delay added to each run, spreading load across instances
+    #[serde(default)]
+    pub jitter_seconds: u64,
+    #[serde(default)]
+    pub misfire_policy: MisfirePolicy,
+    /// Runs whose jobs are still pending or running block new ones beyond this limit
+    #[serde(default)]
+    pub max_concurrent_runs: Option<usize>,
+    /// Jittered moment the run for `next_run_at` actually starts
+    #[serde(default)]
+    pub fire_at: Option<DateTime<Utc>>,
+    /// Job ids of runs that have not finished yet
+    #[serde(default)]
+    pub active_runs: Vec<String>,
+    /// Records loaded into a `SourceLoad` trigger's source since its last run
+    #[serde(default)]
+    pub pending_records: usize,
+    /// Logical dates each source of a `SourceSet` trigger has delivered but not yet run for
+    #[serde(default)]
+    pub pending_dates: HashMap<String, BTreeSet<NaiveDate>>,
+    #[serde(default)]
+    pub last_logical_date: Option<NaiveDate>,
+    /// Missed fire times queued by `RunAll`, replayed at the throttled catch-up rate
+    #[serde(default)]
+    pub catch_up: VecDeque<DateTime<Utc>>,
+}
+
+/// What to do with fire times that passed while the scheduler was paused or lagging. Schedules
+/// and their run state live in memory only, so nothing is caught up for the time a server was
+/// down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MisfirePolicy {
+    /// Collapse all missed fire times into a single run
+    #[default]
+    RunOnce,
+    /// Replay missed fire times at the catch-up rate; beyond MAX_CATCH_UP_RUNS the oldest are
+    /// recorded as skipped instead
+    RunAll,
+    /// Drop missed fire times and only run when a fire time is on time
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkipReason {
+    MaintenanceWindow,
+    Misfire,
+    ConcurrencyLimit,
+    /// Missed under `RunAll` but older than the newest MAX_CATCH_UP_RUNS
+    CatchUpLimit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ScheduleTrigger {
+    Interval { seconds: u64 },
+    /// Fires on the minutes a cron expression such as `30 2 * * MON-FRI` matches, in UTC
+    Cron { expression: CronExpression },
+    /// Fires at `time` (UTC) on the days of `calendar_id` selected by `rule`
+    Calendar { calendar_id: String, rule: CalendarRule, time: NaiveTime },
+    /// Fires once `source_id` has received at least `min_records` new records since the last run
+    SourceLoad {
+        source_id: String,
+        #[serde(default = "default_min_records")]
+        min_records: usize,
+    },
+    /// Fires when all (or any) of `source_ids` have loaded data for the same logical date
+    SourceSet {
+        source_ids: Vec<String>,
+        #[serde(default)]
+        mode: SourceSetMode,
+        /// Record field holding the logical date; defaults to the day the records were loaded
+        #[serde(default)]
+        date_field: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SourceSetMode {
+    #[default]
+    AllOf,
+    AnyOf,
+}
+
+/// Logical dates tracked per source while waiting for the rest of an all-of set
+const MAX_PENDING_DATES: usize = 90;
+
+fn default_min_records() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalendarRule {
+    EveryBusinessDay,
+    FirstBusinessDayOfMonth,
+    LastBusinessDayOfMonth,
+    NthBusinessDayOfMonth { n: u32 },
+}
 
-        // Output results based on configuration
-        Self::output_results(&current_data, &job.configuration.output_format).await?;
+fn default_business_days() -> Vec<Weekday> {
+    vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusinessCalendar {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default = "default_business_days")]
+    pub business_days: Vec<Weekday>,
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+}
 
-        Ok(results)
+impl BusinessCalendar {
+    pub fn is_business_day(&self, date: NaiveDate) -> bool {
+        self.business_days.contains(&date.weekday()) && !self.holidays.contains(&date)
     }
 
-    async fn execute_operation(
-        operation: &Operation,
-        mut data: Vec<DataRecord>,
-    ) -> Result<Vec<DataRecord>, String> {
-        match operation {
-            Operation::Filter { condition } => {
-                // Simplified filter implementation
-                data.retain(|record| {
-                    // In a real implementation, you'd parse and evaluate the condition
-                    true // Placeholder
-                });
-                Ok(data)
-            },
-            Operation::Transform { field, expression } => {
-                // Parallel transformation using rayon
-                data.par_iter_mut().for_each(|record| {
-                    // In a real implementation, you'd parse and evaluate the expression
-                    if let Some(field_value) = record.data.get_mut(field) {
-                        // Placeholder transformation
-                        *field_value = json!(format!("transformed_{}", field_value));
-                    }
-                });
-                Ok(data)
+    pub fn matches(&self, rule: CalendarRule, date: NaiveDate) -> bool {
+        if !self.is_business_day(date) {
+            return false;
+        }
+        let month_days = || {
+            let first = date.with_day(1).expect("day 1 exists in every month");
+            first.iter_days().take_while(move |d| d.month() == first.month())
+        };
+        match rule {
+            CalendarRule::EveryBusinessDay => true,
+            CalendarRule::FirstBusinessDayOfMonth => !month_days()
+                .take_while(|d| *d < date)
+                .any(|d| self.is_business_day(d)),
+            CalendarRule::LastBusinessDayOfMonth => !month_days()
+                .filter(|d| *d > date)
+                .any(|d| self.is_business_day(d)),
+            CalendarRule::NthBusinessDayOfMonth { n } => {
+                let position = month_days()
+                    .take_while(|d| *d <= date)
+                    .filter(|d| self.is_business_day(*d))
+                    .count();
+                position == n as usize
             },
-            Operation::Sort { fields, ascending } => {
-                data.sort_by(|a, b| {
-                    // Simplified sorting by first field
-                    if let Some(field) = fields.first() {
-                        let a_val = a.data.get(field).unwrap_or(&Value::Null);
-                        let b_val = b.data.get(field).unwrap_or(&Value::Null);
-                        
-                        if *ascending {
-                            a_val.to_string().cmp(&b_val.to_string())
-                        } else {
-                            b_val.to_string().cmp(&a_val.to_string())
-                        }
-                    } else {
-                        std::cmp::Ordering::Equal
-                    }
-                });
-                Ok(data)
+        }
+    }
+}
+
+impl ScheduleTrigger {
+    /// Computes the first fire time strictly after `now`, keeping interval cadence from `previous`.
+    fn next_fire(
+        &self,
+        previous: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+        calendars: &HashMap<String, BusinessCalendar>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            ScheduleTrigger::Interval { seconds } => {
+                let step = chrono::Duration::seconds(*seconds as i64);
+                let next = previous.map_or(now + step, |previous| previous + step);
+                Some(if next <= now { now + step } else { next })
             },
-            Operation::Deduplicate { fields } => {
-                let mut seen = std::collections::HashSet::new();
-                data.retain(|record| {
-                    let key: Vec<String> = fields.iter()
-                        .map(|field| record.data.get(field).unwrap_or(&Value::Null).to_string())
-                        .collect();
-                    seen.insert(key)
-                });
-                Ok(data)
+            ScheduleTrigger::Cron { expression } => expression.next_after(now),
+            ScheduleTrigger::Calendar { calendar_id, rule, time } => {
+                let calendar = calendars.get(calendar_id)?;
+                // Two years is enough to find any satisfiable monthly rule
+                now.date_naive()
+                    .iter_days()
+                    .take(731)
+                    .filter(|date| calendar.matches(*rule, *date))
+                    .map(|date| date.and_time(*time).and_utc())
+                    .find(|fire_at| *fire_at > now)
             },
-            Operation::Validate { rules } => {
-                for record in &mut data {
-                    for rule in rules {
-                        if let Err(error) = Self::validate_record(record, rule) {
-                            // In a real implementation, you'd collect validation errors
-                            println!("Validation error for record {}: {}", record.id, error);
-                        }
-                    }
-                }
-                Ok(data)
+            // Source loads arm the schedule directly, see notify_source_load
+            ScheduleTrigger::SourceLoad { .. } | ScheduleTrigger::SourceSet { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedRun {
+    pub scheduled_for: DateTime<Utc>,
+    pub reason: SkipReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_id: Option<String>,
+    pub deferred: bool,
+}
+
+/// Most fire times one upcoming-runs request lists
+const MAX_UPCOMING_RUNS: usize = 100;
+
+/// Fire times later than this count as missed and go through the misfire policy
+const MISFIRE_GRACE_SECS: i64 = 60;
+const MAX_CATCH_UP_RUNS: usize = 100;
+/// Skipped runs a schedule keeps; older ones are forgotten
+const MAX_SKIPPED_RUNS: usize = 1000;
+
+impl Schedule {
+    fn jittered(&self, fire_at: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        let jitter = match self.jitter_seconds {
+            0 => 0,
+            seconds => rand::random::<u64>() % (seconds + 1),
+        };
+        fire_at.map(|fire_at| fire_at + chrono::Duration::seconds(jitter as i64))
+    }
+
+    fn has_free_slot(&self, claimed: usize) -> bool {
+        self.active_runs.len() + claimed < self.max_concurrent_runs.unwrap_or(usize::MAX)
+    }
+
+    fn record_skipped(&mut self, run: SkippedRun) {
+        self.skipped_runs.push(run);
+        // Trimmed in batches so recording stays cheap when a long outage skips many runs
+        if self.skipped_runs.len() >= 2 * MAX_SKIPPED_RUNS {
+            self.skipped_runs.drain(..MAX_SKIPPED_RUNS);
+        }
+    }
+
+    /// Claims a concurrency slot for a run, recording it as skipped when none is free.
+    fn claim_run(&mut self, scheduled_for: DateTime<Utc>, claimed: &mut usize) -> bool {
+        if !self.has_free_slot(*claimed) {
+            self.record_skipped(SkippedRun {
+                scheduled_for,
+                reason: SkipReason::ConcurrencyLimit,
+                window_id: None,
+                deferred: false,
+            });
+            println!("Schedule {} skipped a run: {} runs still active", self.name, self.active_runs.len());
+            return false;
+        }
+        *claimed += 1;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    /// Schedules affected by the window; all schedules when empty
+    #[serde(default)]
+    pub schedule_ids: Vec<String>,
+    /// Queue one run for when the window ends instead of dropping suppressed runs
+    #[serde(default)]
+    pub defer: bool,
+}
+
+impl MaintenanceWindow {
+    fn applies(&self, schedule_id: &str, at: DateTime<Utc>) -> bool {
+        self.starts_at <= at
+            && at < self.ends_at
+            && (self.schedule_ids.is_empty() || self.schedule_ids.iter().any(|id| id == schedule_id))
+    }
+}
+
+/// One run's side of a run comparison.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub job_id: String,
+    pub status: JobStatus,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<i64>,
+    pub input_count: usize,
+    /// Input records per second over the whole run
+    pub throughput: Option<f64>,
+}
+
+impl From<&ProcessingJob> for RunSummary {
+    fn from(job: &ProcessingJob) -> Self {
+        let duration_ms = job.started_at.zip(job.completed_at).map(|(start, end)| (end - start).num_milliseconds());
+        RunSummary {
+            job_id: job.id.clone(),
+            status: job.status.clone(),
+            started_at: job.started_at,
+            completed_at: job.completed_at,
+            duration_ms,
+            input_count: job.input_count,
+            throughput: duration_ms.filter(|ms| *ms > 0).map(|ms| job.input_count as f64 * 1000.0 / ms as f64),
+        }
+    }
+}
+
+/// A configuration value that differs between two runs; `None` where a run lacks it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    pub path: String,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CountDelta {
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+    pub delta: i64,
+}
+
+impl CountDelta {
+    fn new(a: Option<usize>, b: Option<usize>) -> Self {
+        CountDelta { a, b, delta: b.unwrap_or(0) as i64 - a.unwrap_or(0) as i64 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionChange {
+    pub a: Option<u64>,
+    pub b: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepTiming {
+    pub operation: String,
+    pub records_processed: usize,
+    pub execution_time_ms: u128,
+}
+
+/// Result entries at the same position in both runs: operations, then dead letters and sinks.
+#[derive(Debug, Clone, Serialize)]
+pub struct StepComparison {
+    pub index: usize,
+    pub a: Option<StepTiming>,
+    pub b: Option<StepTiming>,
+}
+
+/// A job and every job connected to it through `depends_on`, dependencies before dependents.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobDag {
+    pub job_id: String,
+    /// Failed, TimedOut or Cancelled once any job is, Running while any job runs, Completed
+    /// once all have; otherwise Waiting or Pending
+    pub status: JobStatus,
+    pub jobs: Vec<DagJob>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DagJob {
+    pub id: String,
+    pub name: String,
+    pub status: JobStatus,
+    pub depends_on: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// What changed from run `a` to run `b` of a pipeline; deltas are `b - a` and ratios `b / a`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunComparison {
+    pub pipeline_id: String,
+    pub a: RunSummary,
+    pub b: RunSummary,
+    pub config_changes: Vec<ConfigChange>,
+    /// Version of every source either run read
+    pub input_versions: BTreeMap<String, VersionChange>,
+    pub input_count_delta: i64,
+    pub duration_ms_delta: Option<i64>,
+    pub duration_ratio: Option<f64>,
+    pub throughput_delta: Option<f64>,
+    /// Records written per sink, by result label
+    pub outputs: BTreeMap<String, CountDelta>,
+    pub steps: Vec<StepComparison>,
+}
+
+impl RunComparison {
+    fn between(pipeline_id: &str, a: &ProcessingJob, b: &ProcessingJob) -> Self {
+        let mut config_changes = Vec::new();
+        let (config_a, config_b) = (json!(a.configuration), json!(b.configuration));
+        diff_values(String::new(), Some(&config_a), Some(&config_b), &mut config_changes);
+
+        let sources: BTreeSet<&String> = a.input_versions.keys().chain(b.input_versions.keys()).collect();
+        let input_versions = sources.into_iter()
+            .map(|source| (source.clone(), VersionChange {
+                a: a.input_versions.get(source).copied(),
+                b: b.input_versions.get(source).copied(),
+            }))
+            .collect();
+
+        let output_counts = |job: &ProcessingJob| -> BTreeMap<String, usize> {
+            let labels: HashSet<String> = job.configuration.sinks().into_iter().map(|(label, _)| label)
+                .chain(std::iter::once("Dead letter".to_string()))
+                .collect();
+            job.results.iter()
+                .filter(|result| labels.contains(&result.operation))
+                .map(|result| (result.operation.clone(), result.records_processed))
+                .collect()
+        };
+        let (outputs_a, outputs_b) = (output_counts(a), output_counts(b));
+        let labels: BTreeSet<&String> = outputs_a.keys().chain(outputs_b.keys()).collect();
+        let outputs = labels.into_iter()
+            .map(|label| (label.clone(), CountDelta::new(outputs_a.get(label).copied(), outputs_b.get(label).copied())))
+            .collect();
+
+        let timing = |job: &ProcessingJob, index: usize| job.results.get(index).map(|result| StepTiming {
+            operation: result.operation.clone(),
+            records_processed: result.records_processed,
+            execution_time_ms: result.execution_time_ms,
+        });
+        let steps = (0..a.results.len().max(b.results.len()))
+            .map(|index| StepComparison { index, a: timing(a, index), b: timing(b, index) })
+            .collect();
+
+        let (summary_a, summary_b) = (RunSummary::from(a), RunSummary::from(b));
+        RunComparison {
+            pipeline_id: pipeline_id.to_string(),
+            config_changes,
+            input_versions,
+            input_count_delta: b.input_count as i64 - a.input_count as i64,
+            duration_ms_delta: summary_a.duration_ms.zip(summary_b.duration_ms).map(|(a, b)| b - a),
+            duration_ratio: summary_a.duration_ms.zip(summary_b.duration_ms)
+                .filter(|(a, _)| *a > 0)
+                .map(|(a, b)| b as f64 / a as f64),
+            throughput_delta: summary_a.throughput.zip(summary_b.throughput).map(|(a, b)| b - a),
+            outputs,
+            steps,
+            a: summary_a,
+            b: summary_b,
+        }
+    }
+}
+
+/// Collects the leaves that differ between two JSON values, addressed like `operations[0].Filter`.
+fn diff_values(path: String, a: Option<&Value>, b: Option<&Value>, changes: &mut Vec<ConfigChange>) {
+    match (a, b) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff_values(child, a.get(key), b.get(key), changes);
+            }
+        },
+        (Some(Value::Array(a)), Some(Value::Array(b))) => {
+            for index in 0..a.len().max(b.len()) {
+                diff_values(format!("{}[{}]", path, index), a.get(index), b.get(index), changes);
+            }
+        },
+        (a, b) if a != b => changes.push(ConfigChange { path, a: a.cloned(), b: b.cloned() }),
+        _ => {},
+    }
+}
+
+/// Avro schema for a set of output records and how their columns map onto it.
+struct AvroLayout {
+    json: Value,
+    schema: AvroSchema,
+    /// Output column, Avro field name, and whether non-string values are stringified
+    columns: Vec<(String, String, bool)>,
+    decimals: BTreeMap<String, DecimalPrecision>,
+}
+
+impl AvroLayout {
+    fn new(data: &[DataRecord], schema: Option<&Value>, decimals: &BTreeMap<String, DecimalPrecision>) -> Result<Self, String> {
+        let (json, columns) = match schema {
+            Some(schema) => {
+                let fields = schema.get("fields")
+                    .and_then(|fields| fields.as_array())
+                    .ok_or("Avro schema must be a record with fields")?;
+                let columns = fields.iter()
+                    .filter_map(|field| field.get("name").and_then(|n| n.as_str()))
+                    .map(|name| (name.to_string(), name.to_string(), false))
+                    .collect();
+                (schema.clone(), columns)
             },
-            _ => {
-                // Placeholder for other operations
-                Ok(data)
+            None => {
+                let inferred = DataProcessor::infer_output_schema(data, decimals);
+                let fields: Vec<Value> = inferred.iter().map(|field| {
+                    let avro_type = match field.field_type {
+                        FieldType::Boolean => json!("boolean"),
+                        FieldType::Integer => json!("long"),
+                        FieldType::Float => json!("double"),
+                        FieldType::String | FieldType::Json => json!("string"),
+                        FieldType::Decimal(DecimalPrecision { precision, scale }) => json!({
+                            "type": "bytes",
+                            "logicalType": "decimal",
+                            "precision": precision,
+                            "scale": scale
+                        }),
+                    };
+                    json!({
+                        "name": DataProcessor::avro_field_name(&field.name),
+                        "type": ["null", avro_type],
+                        "default": null
+                    })
+                }).collect();
+                let columns = inferred.iter()
+                    .map(|field| (
+                        field.name.clone(),
+                        DataProcessor::avro_field_name(&field.name),
+                        matches!(field.field_type, FieldType::String | FieldType::Json),
+                    ))
+                    .collect();
+                (json!({ "type": "record", "name": "DataRecord", "fields": fields }), columns)
             }
+        };
+        // A given schema already says how each field is encoded
+        let decimals = if schema.is_some() { BTreeMap::new() } else { decimals.clone() };
+        let schema = AvroSchema::parse(&json).map_err(|e| e.to_string())?;
+        Ok(AvroLayout { json, schema, columns, decimals })
+    }
+
+    fn record_value(&self, record: &DataRecord) -> Result<AvroValue, String> {
+        let fields = self.columns.iter().map(|(column, avro_name, stringify)| {
+            let value = match DataProcessor::output_field_value(record, column) {
+                None => AvroValue::Null,
+                Some(value) => match value.as_ref() {
+                    Value::Null => AvroValue::Null,
+                    other if self.decimals.contains_key(column) => {
+                        let precision = self.decimals[column];
+                        let unscaled = precision.unscaled(other).map_err(|e| format!("Field {}: {}", column, e))?;
+                        let bytes = unscaled.to_be_bytes()[16 - precision.byte_width()..].to_vec();
+                        AvroValue::Decimal(AvroDecimal::from(bytes))
+                    },
+                    Value::String(text) => AvroValue::String(text.clone()),
+                    other if *stringify => AvroValue::String(other.to_string()),
+                    other => AvroValue::try_from(other.clone()).map_err(|e| e.to_string())?,
+                },
+            };
+            Ok((avro_name.clone(), value))
+        }).collect::<Result<Vec<_>, String>>()?;
+
+        AvroValue::Record(fields).resolve(&self.schema).map_err(|e| {
+            format!("Record {} does not match Avro schema: {}", record.id, e)
+        })
+    }
+}
+
+/// Kafka's default (murmur2) partitioner hash, so keyed records land where Java producers put them.
+fn kafka_murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if let Some(first) = tail.first() {
+        h ^= *first as u32;
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+/// Where a job's file output goes, rendered from the configured `output_path` template.
+pub struct OutputTarget<'a> {
+    pub template: Option<&'a str>,
+    pub job_id: &'a str,
+    pub source: &'a str,
+    pub partition: Option<&'a str>,
+    pub compression: OutputCompression,
+    /// Key sealing every file written to this target
+    pub encryption_key: Option<[u8; 32]>,
+    /// The job's work directory, where files are written before being moved into place
+    pub staging_dir: &'a Path,
+    /// Directory the rendered path is resolved under and may not leave
+    pub output_root: &'a Path,
+}
+
+/// A file output being written in the work directory, published to its destination once complete.
+struct StagedOutput {
+    path: String,
+    destination: String,
+}
+
+impl StagedOutput {
+    /// Moves the finished file into place, copying when the work directory is on another
+    /// filesystem; returns the destination path.
+    fn publish(self) -> Result<String, String> {
+        if std::fs::rename(&self.path, &self.destination).is_err() {
+            std::fs::copy(&self.path, &self.destination).map_err(|e| e.to_string())?;
+            std::fs::remove_file(&self.path).map_err(|e| e.to_string())?;
+        }
+        Ok(self.destination)
+    }
+}
+
+impl OutputTarget<'_> {
+    /// Renders the output path (defaulting to `output.<extension>`) and creates its parent directory.
+    ///
+    /// For partitioned output the rendered path minus its extension becomes the root directory,
+    /// e.g. `out/{job_id}.parquet` writes `out/<job id>/country=US/part-000.parquet`.
+    fn path(&self, extension: &str) -> Result<String, String> {
+        let extension = match self.encryption_key {
+            Some(_) => format!("{}.enc", extension),
+            None => extension.to_string(),
+        };
+        let template = match self.template {
+            Some(template) => template.to_string(),
+            None => format!("output.{}", extension),
+        };
+        // Keep source ids from escaping the configured directory
+        let source = self.source.replace(['/', '\\'], "_").replace("..", "_");
+        let path = template
+            .replace("{job_id}", self.job_id)
+            .replace("{date}", &Utc::now().format("%Y-%m-%d").to_string())
+            .replace("{source}", &source);
+        let path = match self.partition {
+            Some(partition) => Path::new(&path)
+                .with_extension("")
+                .join(partition)
+                .join(format!("part-000.{}", extension))
+                .to_string_lossy()
+                .into_owned(),
+            None => path,
+        };
+        // Templates come from job configs, so only relative paths below the root are written
+        if !Path::new(&path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(format!("Output path {} must be relative and stay within the output directory", path));
+        }
+        let path = self.output_root.join(&path).to_string_lossy().into_owned();
+
+        if let Some(parent) = Path::new(&path).parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
         }
+        Ok(path)
+    }
+
+    /// Renders the destination path and a scratch path for it in the staging directory, so
+    /// readers of the destination never see a partially written file.
+    fn stage(&self, extension: &str) -> Result<StagedOutput, String> {
+        let destination = self.path(extension)?;
+        let file_name = Path::new(&destination).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let path = self.staging_dir.join(format!("{}-{}", Uuid::new_v4().simple(), file_name));
+        Ok(StagedOutput { path: path.to_string_lossy().into_owned(), destination })
+    }
+}
+
+/// Named secret referenced from connector settings as `${credential:<name>}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credential {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub secret: String,
+    pub version: u64,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+}
+
+/// Credential metadata returned by the API; secrets never leave the server.
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub version: u64,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+}
+
+impl From<&Credential> for CredentialInfo {
+    fn from(credential: &Credential) -> Self {
+        CredentialInfo {
+            name: credential.name.clone(),
+            description: credential.description.clone(),
+            version: credential.version,
+            created_at: credential.created_at,
+            rotated_at: credential.rotated_at,
+        }
+    }
+}
+
+const CREDENTIAL_REF_PREFIX: &str = "${credential:";
+
+/// Marks a credential file sealed with a salted key; older files hold only a nonce and are
+/// sealed with the SHA-256 of the passphrase.
+const CREDENTIAL_FILE_MAGIC: &[u8; 4] = b"DPV2";
+const CREDENTIAL_SALT_LEN: usize = 16;
+/// PBKDF2-HMAC-SHA256 rounds deriving the credential key from the passphrase
+const CREDENTIAL_KDF_ITERATIONS: u32 = 600_000;
+
+/// AES-256-GCM encrypted file holding the credential store; the file is a magic number, the
+/// key derivation salt and the nonce, followed by the sealed JSON list of credentials.
+pub struct CredentialVault {
+    path: PathBuf,
+    salt: [u8; CREDENTIAL_SALT_LEN],
+    key: [u8; 32],
+    /// Key of a store still in the unsalted format, re-sealed with `key` once loaded
+    legacy_key: Option<[u8; 32]>,
+}
+
+impl CredentialVault {
+    /// Opens the vault configured by `DATA_PROCESSOR_CREDENTIAL_KEY` (any passphrase) and
+    /// `DATA_PROCESSOR_CREDENTIAL_FILE`, if a key is set.
+    fn from_env() -> Option<Self> {
+        let passphrase = std::env::var("DATA_PROCESSOR_CREDENTIAL_KEY").ok()?;
+        let path = PathBuf::from(std::env::var("DATA_PROCESSOR_CREDENTIAL_FILE").unwrap_or_else(|_| "credentials.enc".to_string()));
+        // An existing store keeps its salt, so the key is derived once
+        let existing = std::fs::read(&path).ok();
+        let salt: [u8; CREDENTIAL_SALT_LEN] = match existing.as_deref().and_then(|sealed| sealed.strip_prefix(CREDENTIAL_FILE_MAGIC)) {
+            Some(header) if header.len() >= CREDENTIAL_SALT_LEN => header[..CREDENTIAL_SALT_LEN].try_into().expect("salt length"),
+            _ => rand::random(),
+        };
+        let legacy_key = existing
+            .filter(|sealed| !sealed.starts_with(CREDENTIAL_FILE_MAGIC))
+            .map(|_| Sha256::digest(passphrase.as_bytes()).into());
+        let mut key = [0u8; 32];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            std::num::NonZeroU32::new(CREDENTIAL_KDF_ITERATIONS).expect("iterations are non-zero"),
+            &salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        Some(CredentialVault { path, salt, key, legacy_key })
+    }
+
+    /// Fails when the directory the credential file is saved in cannot be reached.
+    fn probe(&self) -> Result<(), String> {
+        let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        match std::fs::metadata(dir) {
+            Ok(metadata) if metadata.is_dir() => Ok(()),
+            Ok(_) => Err(format!("Credential directory {} is not a directory", dir.display())),
+            Err(e) => Err(format!("Credential directory {} is not reachable: {}", dir.display(), e)),
+        }
+    }
+
+    fn cipher(key: &[u8; 32]) -> Aes256Gcm {
+        // Qualified so the trait does not clash with `Mac::new_from_slice` in scope
+        <Aes256Gcm as aes_gcm::KeyInit>::new(&(*key).into())
+    }
+
+    fn load(&self) -> Result<HashMap<String, Credential>, String> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        let sealed = std::fs::read(&self.path).map_err(|e| e.to_string())?;
+        let (key, sealed) = match (sealed.strip_prefix(CREDENTIAL_FILE_MAGIC), &self.legacy_key) {
+            (Some(sealed), _) if sealed.len() >= CREDENTIAL_SALT_LEN => (&self.key, &sealed[CREDENTIAL_SALT_LEN..]),
+            (None, Some(legacy_key)) => (legacy_key, sealed.as_slice()),
+            _ => return Err("Credential file is truncated".to_string()),
+        };
+        if sealed.len() < 12 {
+            return Err("Credential file is truncated".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let plaintext = Self::cipher(key)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Credential file cannot be decrypted with the configured key".to_string())?;
+        let credentials: Vec<Credential> = serde_json::from_slice(&plaintext).map_err(|e| e.to_string())?;
+        let credentials: HashMap<String, Credential> = credentials.into_iter().map(|c| (c.name.clone(), c)).collect();
+
+        if self.legacy_key.is_some() {
+            self.save(&credentials)?;
+            println!("Credential store re-encrypted with a salted key");
+        }
+        Ok(credentials)
+    }
+
+    fn save(&self, credentials: &HashMap<String, Credential>) -> Result<(), String> {
+        let plaintext = serde_json::to_vec(&credentials.values().collect::<Vec<_>>()).map_err(|e| e.to_string())?;
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = Self::cipher(&self.key)
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|e| e.to_string())?;
+
+        // Write then rename so a crash never leaves a half-written store; the temporary file
+        // is created fresh so it is never readable by others, whatever a leftover one allowed
+        let temp_path = self.path.with_extension("tmp");
+        let _ = std::fs::remove_file(&temp_path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temp_path).map_err(|e| e.to_string())?;
+        file.write_all(&[CREDENTIAL_FILE_MAGIC.as_slice(), &self.salt, &nonce, &ciphertext].concat())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| e.to_string())?;
+        std::fs::rename(&temp_path, &self.path).map_err(|e| e.to_string())
+    }
+}
+
+/// Redirects an outbound HTTP client follows, as many as reqwest's default policy does
+const MAX_REDIRECTS: usize = 10;
+
+/// Outbound destinations connectors may reach. Rules are host names (`api.example.com`),
+/// wildcard domains (`*.example.com`), IPs or CIDRs; with no rules egress is unrestricted.
+#[derive(Debug, Clone, Default)]
+pub struct EgressPolicy {
+    hosts: Vec<String>,
+    networks: Vec<IpNet>,
+}
+
+impl EgressPolicy {
+    pub fn parse(rules: &[String]) -> Result<Self, String> {
+        let mut policy = EgressPolicy::default();
+        for rule in rules {
+            if let Ok(network) = rule.parse::<IpNet>() {
+                policy.networks.push(network);
+            } else if let Ok(ip) = rule.parse::<IpAddr>() {
+                policy.networks.push(IpNet::from(ip));
+            } else if !rule.is_empty() && !rule.contains(['/', ':']) {
+                policy.hosts.push(rule.to_ascii_lowercase());
+            } else {
+                return Err(format!("Invalid egress rule: {}", rule));
+            }
+        }
+        Ok(policy)
+    }
+
+    fn is_unrestricted(&self) -> bool {
+        self.hosts.is_empty() && self.networks.is_empty()
+    }
+
+    fn allows_host_name(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.hosts.iter().any(|rule| match rule.strip_prefix("*.") {
+            Some(domain) => host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.')),
+            None => *rule == host,
+        })
+    }
+
+    /// Whether `host` is allowed without resolving it: a listed name, or an IP in an allowed network.
+    fn allows_literal(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match host.parse::<IpAddr>() {
+            Ok(ip) => self.networks.iter().any(|network| network.contains(&ip)),
+            Err(_) => self.allows_host_name(host),
+        }
+    }
+
+    /// Allows listed host names and IPs outright; any other name must resolve only into allowed
+    /// networks, and the addresses it resolved to are returned so a client can be held to them.
+    async fn resolve_allowed(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+        if self.is_unrestricted() || self.allows_literal(host) {
+            return Ok(Vec::new());
+        }
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.parse::<IpAddr>().is_ok() {
+            return Err(format!("Egress to {} is not allowed by policy", host));
+        }
+
+        let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await
+            .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+            .collect();
+        let allowed = !addresses.is_empty()
+            && addresses.iter().all(|address| self.networks.iter().any(|network| network.contains(&address.ip())));
+        if allowed {
+            Ok(addresses)
+        } else {
+            Err(format!("Egress to {} is not allowed by policy", host))
+        }
+    }
+
+    async fn check_host(&self, host: &str, port: u16) -> Result<(), String> {
+        self.resolve_allowed(host, port).await.map(|_| ())
+    }
+
+    /// An HTTP client for requests to `url`, which is checked first. The client connects only
+    /// to the addresses the check resolved, so the name cannot be re-resolved somewhere else,
+    /// and follows redirects only back to `url`'s host or to destinations the policy allows
+    /// without resolving them.
+    async fn client(&self, url: &str, builder: reqwest::ClientBuilder) -> Result<Client, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+        let host = parsed.host_str().unwrap_or_default().to_string();
+        let mut builder = builder;
+        if !host.is_empty() {
+            let addresses = self.resolve_allowed(&host, parsed.port_or_known_default().unwrap_or(0)).await?;
+            if !addresses.is_empty() {
+                builder = builder.resolve_to_addrs(&host, &addresses);
+            }
+        }
+        let policy = self.clone();
+        let redirects = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            let next = attempt.url().host_str().unwrap_or_default().to_string();
+            match policy.is_unrestricted() || next == host || policy.allows_literal(&next) {
+                true => attempt.follow(),
+                false => attempt.error(format!("Redirect to {} is not allowed by egress policy", next)),
+            }
+        });
+        builder.redirect(redirects).build().map_err(|e| e.to_string())
+    }
+
+    async fn check_url(&self, url: &str) -> Result<(), String> {
+        let url = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+        match url.host_str() {
+            Some(host) => self.check_host(host, url.port_or_known_default().unwrap_or(0)).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the endpoint an object store URL reaches; plain paths are local.
+    async fn check_object_store(&self, uri: &str, storage_options: &HashMap<String, String>) -> Result<(), String> {
+        let Ok(url) = reqwest::Url::parse(uri) else {
+            return Ok(());
+        };
+        // A file:// store would read or write anywhere on this host
+        if url.scheme() == "file" {
+            return Err(format!("Object store URI {} must not use the file scheme", uri));
+        }
+        if url.scheme() != "s3" {
+            return Ok(());
+        }
+        let option = |names: &[&str]| names.iter().find_map(|name| storage_options.get(*name));
+        match option(&["aws_endpoint", "aws_endpoint_url", "endpoint"]) {
+            Some(endpoint) => self.check_url(endpoint).await,
+            None => {
+                let region = option(&["aws_region", "region"]).cloned()
+                    .or_else(|| std::env::var("AWS_REGION").ok())
+                    .unwrap_or_else(|| "us-east-1".to_string());
+                self.check_host(&format!("s3.{}.amazonaws.com", region), 443).await
+            },
+        }
+    }
+
+    /// Checks the network destination a source definition reads from.
+    async fn check_source(&self, definition: &SourceDefinition) -> Result<(), String> {
+        match definition {
+            SourceDefinition::File { .. } => Ok(()),
+            SourceDefinition::Api { url, .. } => self.check_url(url).await,
+            SourceDefinition::S3 { url, options } => self.check_object_store(url, options).await,
+            SourceDefinition::Database { connection_string, .. } if connection_string.starts_with("sqlite:") => Ok(()),
+            SourceDefinition::Database { connection_string, .. } => self.check_url(connection_string).await,
+        }
+    }
+
+    /// Checks every network destination an output connects to.
+    async fn check_output(&self, output_format: &OutputFormat) -> Result<(), String> {
+        match output_format {
+            OutputFormat::Api { endpoint, .. } => self.check_url(endpoint).await,
+            OutputFormat::Elasticsearch { url, .. } => self.check_url(url).await,
+            OutputFormat::Database { connection_string, .. } => {
+                // SQLite is a local file; network databases are URLs
+                if connection_string.starts_with("sqlite:") {
+                    return Ok(());
+                }
+                self.check_url(connection_string).await
+            },
+            OutputFormat::Kafka { brokers, encoding, .. } => {
+                for broker in brokers {
+                    let (host, port) = broker.rsplit_once(':').unwrap_or((broker, "9092"));
+                    let port = port.parse().map_err(|_| format!("Invalid Kafka broker: {}", broker))?;
+                    self.check_host(host, port).await?;
+                }
+                match encoding {
+                    KafkaEncoding::Avro { schema_registry: Some(registry), .. } => self.check_url(registry).await,
+                    _ => Ok(()),
+                }
+            },
+            OutputFormat::MongoDb { connection_string, .. } => {
+                let options = mongodb::options::ClientOptions::parse(connection_string).await
+                    .map_err(|e| format!("Invalid MongoDB connection string: {}", e))?;
+                for host in &options.hosts {
+                    if let mongodb::options::ServerAddress::Tcp { host, port } = host {
+                        self.check_host(host, port.unwrap_or(27017)).await?;
+                    }
+                }
+                Ok(())
+            },
+            OutputFormat::DeltaLake { table_uri, storage_options, .. } => self.check_object_store(table_uri, storage_options).await,
+            OutputFormat::Redis { url, .. } => {
+                let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+                match parsed.host_str() {
+                    Some(host) => self.check_host(host, parsed.port().unwrap_or(6379)).await,
+                    None => Ok(()),
+                }
+            },
+            OutputFormat::Json
+                | OutputFormat::JsonLines { .. }
+                | OutputFormat::Csv { .. }
+                | OutputFormat::Parquet { .. }
+                | OutputFormat::Avro { .. } => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobCostEstimate {
+    pub source_id: Option<String>,
+    pub input_records: usize,
+    pub input_bytes: u64,
+    pub projected_memory_bytes: u64,
+    /// Work directory space the job's file outputs are staged in
+    pub projected_disk_bytes: u64,
+}
+
+/// Input records a dry run processes when the request does not say
+const DEFAULT_DRY_RUN_RECORDS: usize = 100;
+
+/// What a dry run found, and the output of the operations on the first input records.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunReport {
+    /// Whether the job would be accepted and could run; warnings do not count against it
+    pub valid: bool,
+    pub errors: Vec<DryRunIssue>,
+    pub warnings: Vec<DryRunIssue>,
+    pub deprecations: Vec<DeprecationWarning>,
+    pub estimate: JobCostEstimate,
+    pub sample_records: usize,
+    /// Per operation, as a real run reports them; empty when the job is not valid
+    pub results: Vec<ProcessingResult>,
+    pub preview: Vec<DataRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunIssue {
+    /// Where in the job the problem is, such as `operations[2].condition`
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdmissionResource {
+    Memory,
+    Disk,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdmissionRejection {
+    pub resource: AdmissionResource,
+    pub estimate: JobCostEstimate,
+    pub available_bytes: u64,
+    /// Absent when the job exceeds the memory limit outright
+    pub retry_after_seconds: Option<u64>,
+}
+
+/// Records serialized to measure a source's average record size
+const COST_SAMPLE_RECORDS: usize = 1_000;
+/// Heap size of parsed records relative to their JSON encoding
+const IN_MEMORY_OVERHEAD: u64 = 3;
+const DEFAULT_ADMISSION_RETRY_SECS: u64 = 30;
+/// Size of compressed file output relative to the uncompressed encoding
+const COMPRESSED_OUTPUT_RATIO: u64 = 4;
+
+/// Memory the host can still hand out (`MemAvailable`), where the platform reports it.
+fn available_system_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Default for `--work-dir-min-free-mb`
+const DEFAULT_WORK_DIR_MIN_FREE_MB: u64 = 512;
+
+/// Root for scratch files. Each job gets `jobs/<job id>` for staged outputs and other
+/// temporary state, removed when the job ends.
+#[derive(Debug, Clone)]
+pub struct WorkDir {
+    root: PathBuf,
+    min_free_bytes: u64,
+    /// Where file outputs are published; empty for the current directory
+    output_root: PathBuf,
+}
+
+impl Default for WorkDir {
+    fn default() -> Self {
+        WorkDir {
+            root: std::env::temp_dir().join("data-processor"),
+            min_free_bytes: DEFAULT_WORK_DIR_MIN_FREE_MB * 1024 * 1024,
+            output_root: PathBuf::new(),
+        }
+    }
+}
+
+impl WorkDir {
+    pub fn new(root: PathBuf, min_free_bytes: u64) -> Result<Self, String> {
+        let work_dir = WorkDir { root, min_free_bytes, output_root: PathBuf::new() };
+        let jobs = work_dir.root.join("jobs");
+        std::fs::create_dir_all(&jobs).map_err(|e| format!("Could not create work directory {}: {}", jobs.display(), e))?;
+        Ok(work_dir)
+    }
+
+    /// Removes job directories left behind by a previous server that crashed mid-job, and
+    /// result exports and spilled sources, which do not outlive the server; only safe while no
+    /// other process shares the work directory.
+    pub fn remove_stale_job_dirs(&self) {
+        let stale: Vec<PathBuf> = ["jobs", "exports", "spill"].iter()
+            .filter_map(|dir| std::fs::read_dir(self.root.join(dir)).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        for dir in &stale {
+            let removed = match dir.is_dir() {
+                true => std::fs::remove_dir_all(dir),
+                false => std::fs::remove_file(dir),
+            };
+            if let Err(e) = removed {
+                println!("Warning: Could not remove work directory {}: {}", dir.display(), e);
+            }
+        }
+        if !stale.is_empty() {
+            println!("Removed {} stale job directories from {}", stale.len(), self.root.display());
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Publishes file outputs under `output_root`, which job output paths cannot leave.
+    pub fn with_output_root(mut self, output_root: PathBuf) -> Self {
+        self.output_root = output_root;
+        self
+    }
+
+    pub fn output_root(&self) -> &Path {
+        &self.output_root
+    }
+
+    /// Fails when files cannot be written under the root or its free space is below the reserve.
+    fn probe(&self) -> Result<(), String> {
+        let probe = self.root.join(".ready-probe");
+        std::fs::write(&probe, b"ok")
+            .and_then(|_| std::fs::remove_file(&probe))
+            .map_err(|e| format!("Work directory {} is not writable: {}", self.root.display(), e))?;
+        match self.free_bytes() {
+            Some(free) if free < self.min_free_bytes => Err(format!(
+                "Work directory {} has {} MiB free, below the {} MiB reserve",
+                self.root.display(), free / (1024 * 1024), self.min_free_bytes / (1024 * 1024),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn free_bytes(&self) -> Option<u64> {
+        fs2::available_space(&self.root).ok()
+    }
+
+    /// Free space left for jobs once the configured reserve is kept back.
+    fn usable_bytes(&self) -> Option<u64> {
+        self.free_bytes().map(|free| free.saturating_sub(self.min_free_bytes))
+    }
+
+    /// Creates the job's scratch directory, refusing when the work directory cannot hold the
+    /// `needed_bytes` the job is projected to stage on top of the reserve.
+    fn create_job_dir(&self, job_id: &str, needed_bytes: u64) -> Result<PathBuf, String> {
+        if let Some(free) = self.free_bytes().filter(|free| *free < self.min_free_bytes.saturating_add(needed_bytes)) {
+            return Err(format!(
+                "Work directory {} has {} MiB free; the job needs an estimated {} MiB on top of the {} MiB reserve",
+                self.root.display(), free / (1024 * 1024), needed_bytes.div_ceil(1024 * 1024), self.min_free_bytes / (1024 * 1024),
+            ));
+        }
+        let dir = self.root.join("jobs").join(job_id);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        Ok(dir)
+    }
+
+    fn remove_job_dir(&self, job_id: &str) {
+        remove_work_dir(&self.root.join("jobs").join(job_id));
+    }
+
+    fn checkpoint_dir(&self, job_id: &str) -> PathBuf {
+        self.root.join("checkpoints").join(job_id)
+    }
+
+    fn has_checkpoint(&self, job_id: &str) -> bool {
+        self.checkpoint_dir(job_id).join("state.json").exists()
+    }
+
+    /// Writes the job and its checkpoint, replacing the previous checkpoint only once the new
+    /// one is complete.
+    fn save_checkpoint(&self, job: &ProcessingJob, checkpoint: &JobCheckpoint) -> Result<(), String> {
+        let dir = self.checkpoint_dir(&job.id);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        std::fs::write(dir.join("job.json"), serde_json::to_vec(job).map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
+        let partial = dir.join("state.json.partial");
+        let mut writer = BufWriter::new(File::create(&partial).map_err(|e| e.to_string())?);
+        serde_json::to_writer(&mut writer, checkpoint).map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())?;
+        std::fs::rename(&partial, dir.join("state.json")).map_err(|e| e.to_string())
+    }
+
+    fn load_checkpoint(&self, job_id: &str) -> Option<JobCheckpoint<'static>> {
+        let path = self.checkpoint_dir(job_id).join("state.json");
+        let file = File::open(&path).ok()?;
+        match serde_json::from_reader(BufReader::new(file)) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(e) => {
+                println!("Warning: Ignoring unreadable checkpoint {}: {}", path.display(), e);
+                None
+            },
+        }
+    }
+
+    fn remove_checkpoint(&self, job_id: &str) {
+        remove_work_dir(&self.checkpoint_dir(job_id));
+    }
+
+    /// Jobs a previous server left checkpoints for.
+    fn checkpointed_jobs(&self) -> Vec<ProcessingJob> {
+        let Ok(entries) = std::fs::read_dir(self.root.join("checkpoints")) else { return Vec::new() };
+        entries.filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join("state.json").exists())
+            .filter_map(|entry| {
+                let path = entry.path().join("job.json");
+                let job = std::fs::read(&path).map_err(|e| e.to_string())
+                    .and_then(|contents| serde_json::from_slice(&contents).map_err(|e| e.to_string()));
+                job.map_err(|e| println!("Warning: Ignoring unreadable checkpoint {}: {}", path.display(), e)).ok()
+            })
+            .collect()
+    }
+}
+
+fn remove_work_dir(dir: &Path) {
+    if let Err(e) = std::fs::remove_dir_all(dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            println!("Warning: Could not remove work directory {}: {}", dir.display(), e);
+        }
+    }
+}
+
+/// Batches each worker takes through a record-wise stage between checkpoints
+const CHECKPOINT_SEGMENT_BATCHES: usize = 8;
+
+/// How far a run has got, kept in `checkpoints/<job id>` with a copy of the job until the job
+/// ends. Operations are only ever resumed at a stage boundary or between segments of a
+/// record-wise stage, so the records either side of `offset` are all that is needed.
+#[derive(Serialize, Deserialize)]
+struct JobCheckpoint<'a> {
+    written_at: DateTime<Utc>,
+    /// First operation of the stage the run was in
+    operation: usize,
+    /// Records of that stage's input already through it
+    offset: usize,
+    source_id: Cow<'a, str>,
+    /// The stage's input from `offset` on
+    records: Cow<'a, [DataRecord]>,
+    /// What the stage made of the records before `offset`
+    staged: Option<Cow<'a, StageOutput>>,
+    results: Cow<'a, [ProcessingResult]>,
+    dead_letters: Cow<'a, [DataRecord]>,
+    baselines: Cow<'a, [(Invariant, Baseline)]>,
+}
+
+/// Writes a running job's checkpoints, skipping any that come sooner than `interval` after
+/// the last one.
+struct Checkpointer<'a> {
+    job: &'a ProcessingJob,
+    logs: &'a JobLogs,
+    work_dir: WorkDir,
+    interval: Duration,
+    last: std::sync::Mutex<Instant>,
+}
+
+impl Checkpointer<'_> {
+    fn save(&self, checkpoint: JobCheckpoint) {
+        let mut last = self.last.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if last.elapsed() < self.interval {
+            return;
+        }
+        match self.work_dir.save_checkpoint(self.job, &checkpoint) {
+            Ok(()) => self.logs.info(&self.job.id, format!(
+                "Checkpointed job {} at operation {} after {} records", self.job.id, checkpoint.operation, checkpoint.offset,
+            )),
+            Err(e) => self.logs.warn(&self.job.id, format!("Warning: Could not checkpoint job {}: {}", self.job.id, e)),
+        }
+        *last = Instant::now();
+    }
+}
+
+/// Local CSV and NDJSON files at least this large are memory-mapped and parsed in parallel
+const MMAP_MIN_FILE_BYTES: u64 = 16 * 1024 * 1024;
+/// Smallest chunk worth handing to its own parser thread
+const MIN_PARSE_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    pub directory: PathBuf,
+    pub pattern: glob::Pattern,
+    pub job_template: Option<ProcessingJob>,
+    /// Ingest files already in the directory at startup, paced by the catch-up throttle
+    pub catch_up: bool,
+}
+
+/// Catch-up operations (missed runs or backlog files) released per minute by default
+const DEFAULT_CATCH_UP_RATE: f64 = 60.0;
+
+/// Paces work replayed after downtime so a backlog does not hit sources and sinks all at once.
+pub struct CatchUpThrottle {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+    /// Backlog files queued by directory watchers; schedule backlogs live on the schedules
+    pending_files: AtomicUsize,
+    completed: AtomicU64,
+}
+
+impl CatchUpThrottle {
+    pub fn new(per_minute: f64) -> Result<Self, String> {
+        let interval = Some(per_minute)
+            .filter(|per_minute| per_minute.is_finite() && *per_minute > 0.0)
+            .and_then(|per_minute| Duration::try_from_secs_f64(60.0 / per_minute).ok())
+            .ok_or("catch-up rate must be a positive number of operations per minute")?;
+        Ok(CatchUpThrottle {
+            interval,
+            next_slot: Mutex::new(Instant::now()),
+            pending_files: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+        })
+    }
+
+    /// Takes the next slot if it is already open, without waiting.
+    async fn try_acquire(&self) -> bool {
+        let mut next_slot = self.next_slot.lock().await;
+        let now = Instant::now();
+        if *next_slot > now {
+            return false;
+        }
+        *next_slot = now + self.interval;
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Reserves the next slot and waits until it opens.
+    async fn acquire(&self) {
+        let wait = {
+            let mut next_slot = self.next_slot.lock().await;
+            let now = Instant::now();
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + self.interval;
+            slot - now
+        };
+        tokio::time::sleep(wait).await;
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Job workers run at once when `--job-workers` is not given.
+const DEFAULT_JOB_WORKERS: usize = 2;
+/// Jobs the queue holds when `--max-queued-jobs` is not given.
+const DEFAULT_MAX_QUEUED_JOBS: usize = 10_000;
+/// Error a submission is refused with while the queue is full
+const QUEUE_FULL: &str = "Job queue full";
+/// Error prefix for the jobs of an all-or-nothing batch that were refused for another's failure
+const BATCH_REFUSED: &str = "Batch refused";
+/// Start of the error a submission over its tenant's quota is refused with
+const QUOTA_EXCEEDED: &str = "Quota exceeded";
+/// Error submissions are refused with while the server drains its queue
+const DRAINING: &str = "Server is draining and not accepting new jobs";
+
+/// Limits on one tenant's jobs; a limit left out does not apply.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobQuota {
+    /// Jobs running at once; more wait in the queue while other tenants' jobs go ahead
+    #[serde(default)]
+    pub max_concurrent_jobs: Option<usize>,
+    /// Input records a job may be submitted with
+    #[serde(default)]
+    pub max_records_per_job: Option<usize>,
+    /// Jobs waiting to run or for their dependencies, beyond which submissions are refused
+    #[serde(default)]
+    pub max_queued_jobs: Option<usize>,
+}
+
+/// The `--quotas` file. Requests name their tenant with `X-Api-Key`; requests without one
+/// count as one anonymous tenant.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QuotaConfig {
+    /// Applies to every tenant without its own entry, the anonymous one included
+    #[serde(default)]
+    pub default: JobQuota,
+    #[serde(default)]
+    pub tenants: HashMap<String, JobQuota>,
+    /// API key to tenant
+    #[serde(default)]
+    pub api_keys: HashMap<String, String>,
+}
+
+impl QuotaConfig {
+    fn quota(&self, tenant: Option<&str>) -> &JobQuota {
+        tenant.and_then(|tenant| self.tenants.get(tenant)).unwrap_or(&self.default)
+    }
+
+    /// The tenant an `X-Api-Key` belongs to; keys the file does not list are refused.
+    fn tenant_for_key(&self, api_key: Option<&str>) -> Result<Option<String>, String> {
+        match api_key {
+            Some(api_key) => self.api_keys.get(api_key).cloned().map(Some).ok_or_else(|| "Unknown API key".to_string()),
+            None => Ok(None),
+        }
+    }
+}
+
+fn tenant_label(tenant: Option<&str>) -> &str {
+    tenant.unwrap_or("anonymous")
+}
+/// Jobs projected to need more memory than this count as long when workers are shared out;
+/// the projection grows with the input the way running time does.
+const LONG_JOB_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Jobs waiting for a worker, popped by priority and then in the order they were pushed.
+/// Clones share one queue.
+#[derive(Clone)]
+pub struct JobQueue {
+    inner: Arc<JobQueueInner>,
+}
+
+struct JobQueueInner {
+    state: std::sync::Mutex<QueueState>,
+    /// New submissions are refused beyond this; retries and released dependents always fit
+    capacity: usize,
+    sequence: AtomicU64,
+    /// Workers taking jobs from the queue
+    workers: AtomicUsize,
+    changed: tokio::sync::Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    jobs: std::collections::BinaryHeap<QueuedJob>,
+    /// Workers busy with a long job
+    running_long: usize,
+    /// Workers busy with each tenant's jobs
+    running_by_tenant: HashMap<Option<String>, usize>,
+    quotas: Arc<QuotaConfig>,
+}
+
+impl QueueState {
+    fn tenant_has_room(&self, tenant: &Option<String>) -> bool {
+        match self.quotas.quota(tenant.as_deref()).max_concurrent_jobs {
+            Some(limit) => self.running_by_tenant.get(tenant).copied().unwrap_or(0) < limit,
+            None => true,
+        }
+    }
+}
+
+struct QueuedJob {
+    priority: i32,
+    sequence: u64,
+    long: bool,
+    job: ProcessingJob,
+}
+
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // The heap pops its greatest entry, so earlier sequences rank higher
+        self.priority.cmp(&other.priority).then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for QueuedJob {}
+
+/// A worker's claim on the job it took from the queue. The job counts among its tenant's
+/// running jobs, and a long job among the running long jobs, until its slot is dropped.
+pub struct JobSlot {
+    long: bool,
+    tenant: Option<String>,
+    queue: JobQueue,
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        if self.long {
+            state.running_long -= 1;
+        }
+        if let Some(running) = state.running_by_tenant.get_mut(&self.tenant) {
+            *running -= 1;
+            if *running == 0 {
+                state.running_by_tenant.remove(&self.tenant);
+            }
+        }
+        drop(state);
+        // A job held back for want of a worker or by its tenant's quota may be able to start now
+        self.queue.inner.changed.notify_waiters();
+    }
+}
+
+impl JobQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(JobQueueInner {
+                state: std::sync::Mutex::new(QueueState::default()),
+                capacity,
+                sequence: AtomicU64::new(0),
+                workers: AtomicUsize::new(0),
+                changed: tokio::sync::Notify::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.inner.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn push(&self, job: ProcessingJob) {
+        let mut state = self.lock();
+        self.push_locked(&mut state, job);
+    }
+
+    /// Queues a new submission unless the queue is full, in which case the depth is returned.
+    pub fn try_push(&self, job: ProcessingJob) -> Result<(), usize> {
+        let mut state = self.lock();
+        if state.jobs.len() >= self.inner.capacity {
+            return Err(state.jobs.len());
+        }
+        self.push_locked(&mut state, job);
+        Ok(())
+    }
+
+    /// Queues every job of a batch, or none of them when they do not all fit.
+    pub fn try_push_all(&self, jobs: Vec<ProcessingJob>) -> Result<(), usize> {
+        let mut state = self.lock();
+        if state.jobs.len() + jobs.len() > self.inner.capacity {
+            return Err(state.jobs.len());
+        }
+        for job in jobs {
+            self.push_locked(&mut state, job);
+        }
+        Ok(())
+    }
+
+    fn push_locked(&self, state: &mut QueueState, job: ProcessingJob) {
+        let sequence = self.inner.sequence.fetch_add(1, Ordering::Relaxed);
+        let long = job.estimated_memory_bytes.is_some_and(|bytes| bytes > LONG_JOB_BYTES);
+        state.jobs.push(QueuedJob { priority: job.priority, sequence, long, job });
+        self.inner.changed.notify_waiters();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.lock().jobs.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    fn set_quotas(&self, quotas: Arc<QuotaConfig>) {
+        self.lock().quotas = quotas;
+    }
+
+    fn set_workers(&self, workers: usize) {
+        self.inner.workers.store(workers, Ordering::Relaxed);
+    }
+
+    /// Waits for the next job a worker may take. With more than one worker, long jobs never
+    /// occupy all of them: a long job stays queued while it would take the last worker free of
+    /// long jobs, and the best short job goes ahead of it, so short jobs keep moving. Likewise
+    /// a job whose tenant already runs as many jobs as its quota allows waits for one to end.
+    pub async fn pop(&self) -> (ProcessingJob, JobSlot) {
+        loop {
+            let changed = self.inner.changed.notified();
+            tokio::pin!(changed);
+            // Registered before looking, so a push in between still wakes this worker
+            changed.as_mut().enable();
+            if let Some(popped) = self.take() {
+                return popped;
+            }
+            changed.await;
+        }
+    }
+
+    fn take(&self) -> Option<(ProcessingJob, JobSlot)> {
+        let mut state = self.lock();
+        let workers = self.inner.workers.load(Ordering::Relaxed).max(1);
+        let long_allowed = workers == 1 || state.running_long + 1 < workers;
+        let eligible = |state: &QueueState, queued: &QueuedJob| {
+            (long_allowed || !queued.long) && state.tenant_has_room(&queued.job.tenant)
+        };
+        let queued = match state.jobs.peek()? {
+            queued if eligible(&state, queued) => state.jobs.pop()?,
+            // Rebuilding the heap is cheap next to running a job
+            _ => {
+                let mut jobs = std::mem::take(&mut state.jobs).into_vec();
+                let best = jobs.iter().enumerate()
+                    .filter(|(_, queued)| eligible(&state, queued))
+                    .max_by(|(_, a), (_, b)| a.cmp(b))
+                    .map(|(index, _)| index);
+                let queued = best.map(|index| jobs.swap_remove(index));
+                state.jobs = jobs.into();
+                queued?
+            },
+        };
+        if queued.long {
+            state.running_long += 1;
+        }
+        let tenant = queued.job.tenant.clone();
+        *state.running_by_tenant.entry(tenant.clone()).or_default() += 1;
+        Some((queued.job, JobSlot { long: queued.long, tenant, queue: self.clone() }))
+    }
+}
+
+/// Sources with fewer records than this are not worth dictionary-encoding
+const MIN_DICTIONARY_RECORDS: usize = 1_000;
+/// Code of a record whose value for an encoded field is missing or kept inline
+const ABSENT_CODE: u32 = u32::MAX;
+/// Top-level fields a source keeps distinct-count sketches for, in the order they first appear
+const MAX_STATS_FIELDS: usize = 64;
+/// HyperLogLog precision of the per-field sketches: 4 KiB each, about 1.6% error
+const STATS_PRECISION: u8 = 12;
+/// Rough bytes a join's hash table spends per distinct key, on the key text and its entry
+const JOIN_KEY_ENTRY_BYTES: usize = 64;
+
+/// Records of one source. With dictionary encoding, top-level string fields that take few
+/// distinct values are moved out of the records into code columns, so each record costs four
+/// bytes for the field instead of a key and a value allocation. Reads restore the fields;
+/// restored fields follow the record's other fields.
+#[derive(Debug, Default)]
+pub struct StoredSource {
+    records: Vec<DataRecord>,
+    columns: BTreeMap<String, DictionaryColumn>,
+    /// When records last landed, by a load or an append
+    loaded_at: Option<DateTime<Utc>>,
+    /// Set while the records and columns are on disk rather than in memory
+    offload: Option<Offload>,
+    /// The storage backend holds exactly these records, so they can be dropped from memory
+    persisted: bool,
+    /// The source version the records are
+    version: u64,
+    /// `ACCESS_CLOCK` when the records were last read, to spill the coldest source first
+    last_used: AtomicU64,
+    /// Secondary indexes by field, over record positions; kept in memory while the records
+    /// are spilled, as spilling keeps their order
+    indexes: BTreeMap<String, Arc<index::FieldIndex>>,
+    /// Row and distinct counts; kept while spilled, and gathered when a source restored from
+    /// the storage backend is first read back
+    stats: Option<SourceStats>,
+}
+
+/// Cardinality of a source, kept as its records load and change so joins can choose their
+/// build side without counting. Distinct counts are HyperLogLog estimates of a field's
+/// non-null values, keyed the way joins key them.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceStats {
+    pub rows: usize,
+    pub distinct_values: BTreeMap<String, u64>,
+    #[serde(skip)]
+    sketches: BTreeMap<String, HyperLogLog>,
+}
+
+impl SourceStats {
+    fn of(records: &[DataRecord]) -> Self {
+        let mut stats = Self::default();
+        stats.add(records);
+        stats
+    }
+
+    /// Counts appended records; removed or edited ones need the stats gathered again.
+    fn add(&mut self, records: &[DataRecord]) {
+        self.rows += records.len();
+        for record in records {
+            let Value::Object(fields) = &record.data else {
+                continue;
+            };
+            for (name, value) in fields.iter().filter(|(_, value)| !value.is_null()) {
+                if !self.sketches.contains_key(name) {
+                    if self.sketches.len() >= MAX_STATS_FIELDS {
+                        continue;
+                    }
+                    self.sketches.insert(name.clone(), HyperLogLog::new(STATS_PRECISION));
+                }
+                if let Some(sketch) = self.sketches.get_mut(name) {
+                    sketch.insert(&value.to_string());
+                }
+            }
+        }
+        self.distinct_values = self.sketches.iter()
+            .map(|(field, sketch)| (field.clone(), sketch.estimate().min(self.rows as u64)))
+            .collect();
+    }
+
+    pub fn distinct(&self, field: &str) -> Option<u64> {
+        self.distinct_values.get(field).copied()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DictionaryColumn {
+    values: Vec<String>,
+    /// Rebuilt from `values` when a spilled source is read back
+    #[serde(skip)]
+    lookup: HashMap<String, u32>,
+    /// One code per record; non-string values stay inline with `ABSENT_CODE`
+    codes: Vec<u32>,
+}
+
+impl DictionaryColumn {
+    fn code(&mut self, value: String) -> u32 {
+        if let Some(code) = self.lookup.get(&value) {
+            return *code;
+        }
+        let code = self.values.len() as u32;
+        self.lookup.insert(value.clone(), code);
+        self.values.push(value);
+        code
+    }
+}
+
+/// Ticks once per source read; `StoredSource::last_used` records the reading
+static ACCESS_CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// A spilled source's file, removed when the source is read back, replaced or deleted.
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            println!("Warning: Could not remove spill file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Where a source's records are while they are not in memory.
+#[derive(Debug)]
+enum Offload {
+    Spilled(SpillFile),
+    /// Only in the storage backend, which is read again for them
+    Stored { source_id: String, len: usize, storage: Arc<dyn storage::SourceStorage> },
+}
+
+/// What a spill file holds: the stored records and columns as they were in memory.
+#[derive(Serialize, Deserialize)]
+struct SpilledRecords {
+    records: Vec<DataRecord>,
+    columns: BTreeMap<String, DictionaryColumn>,
+}
+
+impl SpilledRecords {
+    fn read(spill: &SpillFile) -> Result<Self, String> {
+        let file = File::open(&spill.path).map_err(|e| format!("Could not open spill file {}: {}", spill.path.display(), e))?;
+        let mut spilled: SpilledRecords = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("Could not read spill file {}: {}", spill.path.display(), e))?;
+        for column in spilled.columns.values_mut() {
+            column.lookup = column.values.iter().enumerate().map(|(code, value)| (value.clone(), code as u32)).collect();
+        }
+        Ok(spilled)
+    }
+}
+
+impl StoredSource {
+    pub fn new(records: Vec<DataRecord>, max_values: Option<usize>) -> Self {
+        let stats = SourceStats::of(&records);
+        let mut source = Self { records, loaded_at: Some(Utc::now()), stats: Some(stats), ..Self::default() };
+        if let Some(max_values) = max_values {
+            source.encode(max_values);
+        }
+        source
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.offload {
+            Some(Offload::Spilled(spill)) => spill.len,
+            Some(Offload::Stored { len, .. }) => *len,
+            None => self.records.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> Option<&SourceStats> {
+        self.stats.as_ref()
+    }
+
+    /// Gathers the stats again from every record, after records were edited or removed.
+    fn refresh_stats(&mut self) {
+        self.stats = Some(SourceStats::of(&self.records()));
+    }
+
+    /// The records are on disk, in a spill file or the storage backend, rather than in memory.
+    pub fn is_spilled(&self) -> bool {
+        self.offload.is_some()
+    }
+
+    fn touch(&self) {
+        self.last_used.store(ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Rough memory the records take, scaled up from a sample's serialized size.
+    fn resident_bytes(&self) -> u64 {
+        if self.is_spilled() || self.records.is_empty() {
+            return 0;
+        }
+        let sample = &self.records[..self.records.len().min(COST_SAMPLE_RECORDS)];
+        let sample_bytes: usize = sample.iter().map(|record| serde_json::to_vec(record).map_or(0, |bytes| bytes.len())).sum();
+        let records = sample_bytes as f64 / sample.len() as f64 * self.records.len() as f64;
+        let columns: usize = self.columns.values()
+            .map(|column| column.values.iter().map(String::len).sum::<usize>() + column.codes.len() * 4)
+            .sum();
+        records as u64 * IN_MEMORY_OVERHEAD + columns as u64
+    }
+
+    /// Moves the records and columns to a file at `path`, leaving them in memory if it cannot
+    /// be written.
+    fn spill_to(&mut self, path: PathBuf) -> Result<(), String> {
+        let spilled = SpilledRecords { records: std::mem::take(&mut self.records), columns: std::mem::take(&mut self.columns) };
+        let written = File::create(&path).map_err(|e| e.to_string()).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &spilled).map_err(|e| e.to_string())?;
+            writer.flush().map_err(|e| e.to_string())
+        });
+        match written {
+            Ok(()) => {
+                self.offload = Some(Offload::Spilled(SpillFile { path, len: spilled.records.len() }));
+                Ok(())
+            },
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                self.records = spilled.records;
+                self.columns = spilled.columns;
+                Err(format!("Could not write spill file {}: {}", path.display(), e))
+            },
+        }
+    }
+
+    /// Drops the records from memory, as the storage backend holds them.
+    fn release(&mut self, source_id: &str, storage: Arc<dyn storage::SourceStorage>) {
+        let len = self.records.len();
+        self.records = Vec::new();
+        self.columns.clear();
+        self.offload = Some(Offload::Stored { source_id: source_id.to_string(), len, storage });
+    }
+
+    /// The records and columns of a source that is not in memory, without keeping them.
+    fn read_offloaded(offload: &Offload) -> Result<SpilledRecords, String> {
+        match offload {
+            Offload::Spilled(spill) => SpilledRecords::read(spill),
+            Offload::Stored { source_id, storage, .. } => {
+                let records = storage.load(source_id).map_err(|e| format!("Could not read source {} from storage: {}", source_id, e))?;
+                Ok(SpilledRecords { records, columns: BTreeMap::new() })
+            },
+        }
+    }
+
+    /// Reads the records back into memory, removing their spill file.
+    fn unspill(&mut self) -> Result<(), String> {
+        let Some(offload) = &self.offload else {
+            return Ok(());
+        };
+        let spilled = Self::read_offloaded(offload)?;
+        self.records = spilled.records;
+        self.columns = spilled.columns;
+        self.persisted = matches!(self.offload.take(), Some(Offload::Stored { .. }));
+        if self.stats.is_none() {
+            self.refresh_stats();
+        }
+        Ok(())
+    }
+
+    /// Picks the fields whose string values fit the dictionary and moves them into columns.
+    fn encode(&mut self, max_values: usize) {
+        if self.records.len() < MIN_DICTIONARY_RECORDS || !self.columns.is_empty() {
+            return;
+        }
+
+        let mut candidates: HashMap<&str, HashSet<&str>> = HashMap::new();
+        let mut rejected: HashSet<&str> = HashSet::new();
+        for record in &self.records {
+            let Value::Object(fields) = &record.data else {
+                continue;
+            };
+            for (name, value) in fields {
+                let Value::String(text) = value else {
+                    continue;
+                };
+                if rejected.contains(name.as_str()) {
+                    continue;
+                }
+                let distinct = candidates.entry(name).or_default();
+                distinct.insert(text);
+                if distinct.len() > max_values {
+                    candidates.remove(name.as_str());
+                    rejected.insert(name);
+                }
+            }
+        }
+        let fields: Vec<String> = candidates.into_keys().map(str::to_string).collect();
+        if fields.is_empty() {
+            return;
+        }
+
+        for field in fields {
+            self.columns.insert(field, DictionaryColumn::default());
+        }
+        let records = std::mem::take(&mut self.records);
+        self.push_encoded(records);
+    }
+
+    fn push_encoded(&mut self, records: Vec<DataRecord>) {
+        for mut record in records {
+            for (field, column) in &mut self.columns {
+                let code = match &mut record.data {
+                    Value::Object(fields) if matches!(fields.get(field), Some(Value::String(_))) => {
+                        match fields.remove(field) {
+                            Some(Value::String(text)) => column.code(text),
+                            _ => ABSENT_CODE,
+                        }
+                    },
+                    _ => ABSENT_CODE,
+                };
+                column.codes.push(code);
+            }
+            self.records.push(record);
+        }
+    }
+
+    /// Appends records, encoding the fields that already have a dictionary.
+    pub fn append(&mut self, records: Vec<DataRecord>) {
+        self.loaded_at = Some(Utc::now());
+        if let Some(stats) = &mut self.stats {
+            stats.add(&records);
+        }
+        let start = self.records.len();
+        if self.columns.is_empty() {
+            self.records.extend(records);
+        } else {
+            self.push_encoded(records);
+        }
+        let fields: Vec<String> = self.indexes.keys().cloned().collect();
+        for field in fields {
+            let keys: Vec<index::Key> = (start..self.records.len()).map(|position| self.key_at(position, &field)).collect();
+            if let Some(index) = self.indexes.get_mut(&field) {
+                Arc::make_mut(index).extend(keys);
+            }
+        }
+    }
+
+    /// A field's value in the record at `position`, as an index keys it.
+    fn key_at(&self, position: usize, field: &str) -> index::Key {
+        let encoded = self.columns.get(field).and_then(|column| column.values.get(column.codes[position] as usize));
+        match encoded {
+            Some(value) => index::Key::of_text(value),
+            None => index::Key::of(self.records[position].data.get(field)),
+        }
+    }
+
+    /// Builds an index, replacing any on the same field. The records must be in memory.
+    fn build_index(&mut self, spec: &index::IndexSpec) {
+        let built = index::FieldIndex::build(spec.kind, (0..self.records.len()).map(|position| self.key_at(position, &spec.field)));
+        self.indexes.insert(spec.field.clone(), Arc::new(built));
+    }
+
+    /// Rebuilds every index after records were removed or reordered.
+    fn reindex(&mut self) {
+        let specs: Vec<index::IndexSpec> = self.indexes.iter()
+            .map(|(field, index)| index::IndexSpec { field: field.clone(), kind: index.kind() })
+            .collect();
+        for spec in &specs {
+            self.build_index(spec);
+        }
+    }
+
+    /// Re-keys the record at `position` in every index after its data changed from `before`.
+    fn update_indexes(&mut self, position: usize, before: &Value, after: &Value) {
+        for (field, index) in &mut self.indexes {
+            Arc::make_mut(index).update(position, index::Key::of(before.get(field)), index::Key::of(after.get(field)));
+        }
+    }
+
+    pub fn index(&self, field: &str) -> Option<&Arc<index::FieldIndex>> {
+        self.indexes.get(field)
+    }
+
+    pub fn index_infos(&self) -> Vec<index::IndexInfo> {
+        self.indexes.iter()
+            .map(|(field, index)| index::IndexInfo {
+                field: field.clone(),
+                kind: index.kind(),
+                distinct_values: index.distinct_values(),
+                records: index.records(),
+            })
+            .collect()
+    }
+
+    /// Positions of the records that could satisfy all of `comparisons`, from whichever indexed
+    /// comparison narrows them most, with the field it is on. `None` when no index applies.
+    pub fn index_candidates(&self, comparisons: &[expression::Comparison]) -> Option<(&str, Vec<usize>)> {
+        comparisons.iter()
+            .filter_map(|comparison| {
+                let (field, index) = self.indexes.get_key_value(&comparison.field)?;
+                Some((field.as_str(), index.candidates(comparison)?))
+            })
+            .min_by_key(|(_, positions)| positions.len())
+    }
+
+    /// Positions of the records that could match a business key, when `key_field` is indexed.
+    fn lookup(&self, key: &str, key_field: Option<&str>) -> Option<Vec<usize>> {
+        Some(self.indexes.get(key_field?)?.lookup(key))
+    }
+
+    /// The records at `positions`, with encoded fields restored.
+    pub fn pick(&self, positions: &[usize]) -> Vec<DataRecord> {
+        if self.offload.is_some() {
+            let records = self.records();
+            return positions.iter().filter_map(|position| records.get(*position).cloned()).collect();
+        }
+        self.touch();
+        positions.iter()
+            .map(|position| match self.columns.is_empty() {
+                true => self.records[*position].clone(),
+                false => self.decode(*position),
+            })
+            .collect()
+    }
+
+    fn decode(&self, index: usize) -> DataRecord {
+        let mut record = self.records[index].clone();
+        if let Value::Object(fields) = &mut record.data {
+            for (field, column) in &self.columns {
+                if let Some(value) = column.values.get(column.codes[index] as usize) {
+                    fields.insert(field.clone(), Value::String(value.clone()));
+                }
+            }
+        }
+        record
+    }
+
+    /// Records in `range` with encoded fields restored; borrowed when nothing is encoded.
+    pub fn slice(&self, range: std::ops::Range<usize>) -> Cow<'_, [DataRecord]> {
+        if let Some(offload) = &self.offload {
+            // Readers go through `DataStore::read_loaded`, which reads sources back first; this
+            // only keeps a reader that did not from seeing a source as empty
+            return match Self::read_offloaded(offload) {
+                Ok(spilled) => {
+                    let source = StoredSource { records: spilled.records, columns: spilled.columns, ..Self::default() };
+                    Cow::Owned(source.slice(range).into_owned())
+                },
+                Err(e) => {
+                    println!("Warning: {}", e);
+                    Cow::Owned(Vec::new())
+                },
+            };
+        }
+        self.touch();
+        if self.columns.is_empty() {
+            Cow::Borrowed(&self.records[range])
+        } else {
+            Cow::Owned(range.map(|index| self.decode(index)).collect())
+        }
+    }
+
+    pub fn records(&self) -> Cow<'_, [DataRecord]> {
+        self.slice(0..self.records.len())
+    }
+
+    /// Restores every encoded field in place so the records can be edited directly. The
+    /// dictionary is rebuilt by `encode` afterwards.
+    fn decode_in_place(&mut self) -> &mut Vec<DataRecord> {
+        if !self.columns.is_empty() {
+            self.records = (0..self.records.len()).map(|index| self.decode(index)).collect();
+            self.columns.clear();
+        }
+        &mut self.records
+    }
+}
+
+/// Every source's records, kept in memory within an optional budget. Past the budget the least
+/// recently read sources are spilled to files under the work directory's `spill` directory, and
+/// read back the next time a job, query or edit needs them. With a storage backend every change
+/// is written through to it, and a source it holds is dropped from memory instead of spilled.
+#[derive(Debug)]
+pub struct DataStore {
+    sources: HashMap<String, StoredSource>,
+    /// Bytes in-memory sources may take before the coldest are spilled; unbounded when `None`
+    memory_budget: Option<u64>,
+    spill_dir: PathBuf,
+    storage: Option<Arc<dyn storage::SourceStorage>>,
+    /// Dictionary encoding for sources read back from the storage backend
+    dictionary_max_values: Option<usize>,
+    /// Indexes each source keeps, built again whenever its records are replaced
+    index_specs: HashMap<String, Vec<index::IndexSpec>>,
+}
+
+impl Default for DataStore {
+    fn default() -> Self {
+        DataStore::new(None)
+    }
+}
+
+impl DataStore {
+    pub fn new(memory_budget: Option<u64>) -> Self {
+        DataStore {
+            sources: HashMap::new(),
+            memory_budget,
+            spill_dir: WorkDir::default().root().join("spill"),
+            storage: None,
+            dictionary_max_values: None,
+            index_specs: HashMap::new(),
+        }
+    }
+
+    /// Persists sources to `storage` from now on, and lists the sources it already holds, with
+    /// their versions; they are read from it when first used. The backend does not record the
+    /// version of a source, so it is taken to be the one after its latest retained version.
+    pub fn attach_storage(
+        &mut self,
+        storage: Arc<dyn storage::SourceStorage>,
+        dictionary_max_values: Option<usize>,
+    ) -> Result<Vec<(String, u64)>, String> {
+        let entries = storage.list()?;
+        for entry in &entries {
+            let offload = Offload::Stored { source_id: entry.source_id.clone(), len: entry.len, storage: storage.clone() };
+            let version = entry.source_id.rsplit_once('/').and_then(|(_, version)| version.parse().ok()).unwrap_or(0);
+            let source = StoredSource { loaded_at: entry.loaded_at, offload: Some(offload), persisted: true, version, ..StoredSource::default() };
+            self.sources.insert(entry.source_id.clone(), source);
+        }
+        self.storage = Some(storage);
+        self.dictionary_max_values = dictionary_max_values;
+
+        let source_ids: Vec<String> = self.iter().map(|(source_id, _)| source_id.clone()).collect();
+        let mut restored = Vec::with_capacity(source_ids.len());
+        for source_id in source_ids {
+            let version = self.versions(&source_id).last().map_or(0, |(version, _)| *version) + 1;
+            self.set_version(&source_id, version);
+            restored.push((source_id, version));
+        }
+        Ok(restored)
+    }
+
+    /// Key a retained version of a source is kept under. Source ids cannot contain `/`, so it
+    /// never names a source.
+    fn version_key(source_id: &str, version: u64) -> String {
+        format!("{}/{}", source_id, version)
+    }
+
+    pub fn set_version(&mut self, source_id: &str, version: u64) {
+        if let Some(source) = self.sources.get_mut(source_id) {
+            source.version = version;
+        }
+    }
+
+    /// The earlier versions kept of a source, oldest first.
+    pub fn versions(&self, source_id: &str) -> Vec<(u64, &StoredSource)> {
+        let prefix = format!("{}/", source_id);
+        let mut versions: Vec<(u64, &StoredSource)> = self.sources.iter()
+            .filter_map(|(key, source)| Some((key.strip_prefix(&prefix)?.parse().ok()?, source)))
+            .collect();
+        versions.sort_by_key(|(version, _)| *version);
+        versions
+    }
+
+    /// The key to read a source at `version` under: the source itself when it is at that
+    /// version (or none is asked for), otherwise the retained version.
+    pub fn version_of(&self, source_id: &str, version: Option<u64>) -> Result<String, String> {
+        match version {
+            Some(version) if self.sources.get(source_id).is_none_or(|source| source.version != version) => {
+                let key = Self::version_key(source_id, version);
+                match self.sources.contains_key(&key) {
+                    true => Ok(key),
+                    false => Err(format!("Version {} of source {} is not retained", version, source_id)),
+                }
+            },
+            _ => Ok(source_id.to_string()),
+        }
+    }
+
+    /// A source at `version`, or at its current version when none is given.
+    pub fn get_at(&self, source_id: &str, version: Option<u64>) -> Result<Option<&StoredSource>, String> {
+        Ok(self.sources.get(&self.version_of(source_id, version)?))
+    }
+
+    /// Keeps a source's records as a retained version before new ones replace them.
+    pub fn retain_current(&mut self, source_id: &str) {
+        let Some(current) = self.sources.remove(source_id).filter(|current| current.version > 0) else {
+            return;
+        };
+        let key = Self::version_key(source_id, current.version);
+        self.sources.insert(key.clone(), current);
+        if self.storage.is_some() {
+            // The backend holds the records under the source's id, which the new records take
+            // over, so they are read back and written under the version's key
+            if let Err(e) = self.load(std::slice::from_ref(&key)) {
+                println!("Warning: Version {} not retained: {}", key, e);
+                self.sources.remove(&key);
+                return;
+            }
+            self.persist(&key);
+        }
+    }
+
+    /// Retains a copy of a source's current records, returning their version.
+    pub fn snapshot(&mut self, source_id: &str, dictionary_max_values: Option<usize>) -> Result<u64, String> {
+        let source = self.get_mut(source_id)?.ok_or("Source not found")?;
+        let mut copy = StoredSource::new(source.records().into_owned(), dictionary_max_values);
+        copy.loaded_at = source.loaded_at;
+        copy.version = source.version;
+        copy.indexes = source.indexes.clone();
+        let key = Self::version_key(source_id, copy.version);
+        self.sources.insert(key.clone(), copy);
+        self.persist(&key);
+        self.fit_budget(&[source_id.to_string(), key]);
+        Ok(self.sources[source_id].version)
+    }
+
+    pub fn remove_version(&mut self, source_id: &str, version: u64) -> Option<StoredSource> {
+        self.remove(&Self::version_key(source_id, version))
+    }
+
+    /// Writes a source's records through to the storage backend, replacing what it held. A
+    /// source that cannot be written stays in memory (or spills) as it would without a backend.
+    pub fn persist(&mut self, source_id: &str) {
+        let (Some(storage), Some(source)) = (&self.storage, self.sources.get_mut(source_id)) else {
+            return;
+        };
+        source.persisted = match storage.save(source_id, &source.records(), source.loaded_at) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Warning: Source {} not persisted: {}", source_id, e);
+                false
+            },
+        };
+    }
+
+    /// Writes a source's last `count` records through to the storage backend, or all of them
+    /// when the backend did not hold the rest.
+    pub fn persist_appended(&mut self, source_id: &str, count: usize) {
+        let (Some(storage), Some(source)) = (&self.storage, self.sources.get_mut(source_id)) else {
+            return;
+        };
+        let written = match source.persisted {
+            true => storage.append(source_id, &source.slice(source.len() - count..source.len()), source.loaded_at),
+            false => storage.save(source_id, &source.records(), source.loaded_at),
+        };
+        source.persisted = match written {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Warning: Source {} not persisted: {}", source_id, e);
+                false
+            },
+        };
+    }
+
+    pub fn set_spill_dir(&mut self, spill_dir: PathBuf) {
+        self.spill_dir = spill_dir;
+    }
+
+    pub fn get(&self, source_id: &str) -> Option<&StoredSource> {
+        self.sources.get(source_id)
+    }
+
+    /// Every source at its current version.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &StoredSource)> {
+        self.sources.iter().filter(|(source_id, _)| !source_id.contains('/'))
+    }
+
+    /// A source for editing, read back from disk first if it was spilled. Changes reach the
+    /// storage backend once the caller passes them to `persist` or `persist_appended`.
+    pub fn get_mut(&mut self, source_id: &str) -> Result<Option<&mut StoredSource>, String> {
+        if self.sources.get(source_id).is_some_and(StoredSource::is_spilled) {
+            self.load(&[source_id.to_string()])?;
+        }
+        Ok(self.sources.get_mut(source_id).inspect(|source| source.touch()))
+    }
+
+    /// Like `get_mut`, creating an empty source when there is none.
+    pub fn get_or_create(&mut self, source_id: &str) -> Result<&mut StoredSource, String> {
+        if !self.sources.contains_key(source_id) {
+            self.sources.insert(source_id.to_string(), StoredSource::default());
+        }
+        self.get_mut(source_id).map(|source| source.expect("source was just inserted"))
+    }
+
+    /// Stores a source, replacing any under the same id, and spills others to make room.
+    pub fn insert(&mut self, source_id: String, mut source: StoredSource) {
+        for spec in self.index_specs.get(&source_id).into_iter().flatten() {
+            source.build_index(spec);
+        }
+        source.touch();
+        self.sources.insert(source_id.clone(), source);
+        self.persist(&source_id);
+        self.fit_budget(&[source_id]);
+    }
+
+    pub fn remove(&mut self, source_id: &str) -> Option<StoredSource> {
+        let removed = self.sources.remove(source_id);
+        self.index_specs.remove(source_id);
+        if let Some(storage) = self.storage.as_ref().filter(|_| removed.is_some()) {
+            if let Err(e) = storage.remove(source_id) {
+                println!("Warning: Could not remove source {} from storage: {}", source_id, e);
+            }
+        }
+        removed
+    }
+
+    /// Builds an index on a source and keeps it on the records that replace them.
+    pub fn create_index(&mut self, source_id: &str, spec: index::IndexSpec) -> Result<index::IndexInfo, String> {
+        spec.validate()?;
+        let source = self.get_mut(source_id)?.ok_or("Source not found")?;
+        source.build_index(&spec);
+        let info = source.index_infos().into_iter().find(|info| info.field == spec.field).expect("index was just built");
+        let specs = self.index_specs.entry(source_id.to_string()).or_default();
+        specs.retain(|existing| existing.field != spec.field);
+        specs.push(spec);
+        Ok(info)
+    }
+
+    /// Drops a source's index on `field`. Retained versions keep theirs.
+    pub fn drop_index(&mut self, source_id: &str, field: &str) -> bool {
+        if let Some(specs) = self.index_specs.get_mut(source_id) {
+            specs.retain(|spec| spec.field != field);
+        }
+        self.sources.get_mut(source_id).and_then(|source| source.indexes.remove(field)).is_some()
+    }
+
+    /// Reads spilled sources back into memory, spilling others to stay within the budget.
+    fn load(&mut self, source_ids: &[String]) -> Result<(), String> {
+        for source_id in source_ids {
+            if let Some(source) = self.sources.get_mut(source_id).filter(|source| source.is_spilled()) {
+                let stored = matches!(source.offload, Some(Offload::Stored { .. }));
+                source.unspill()?;
+                source.touch();
+                // Only the backend's copy lacks the dictionary
+                if let Some(max_values) = self.dictionary_max_values.filter(|_| stored) {
+                    source.encode(max_values);
+                }
+                println!("Read source {} back from {}", source_id, if stored { "storage" } else { "disk" });
+            }
+        }
+        self.fit_budget(source_ids);
+        Ok(())
+    }
+
+    /// Spills the least recently read sources, other than `keep`, until the rest fit the
+    /// budget; those the storage backend holds are just dropped from memory. Sources in `keep`
+    /// stay in memory even when they alone are over it.
+    pub fn fit_budget(&mut self, keep: &[String]) {
+        let Some(budget) = self.memory_budget else {
+            return;
+        };
+        let mut sizes: Vec<(u64, String, u64)> = self.sources.iter()
+            .map(|(source_id, source)| (source.last_used.load(Ordering::Relaxed), source_id.clone(), source.resident_bytes()))
+            .collect();
+        let mut resident: u64 = sizes.iter().map(|(_, _, bytes)| bytes).sum();
+        if resident <= budget {
+            return;
+        }
+        sizes.retain(|(_, source_id, bytes)| *bytes > 0 && !keep.contains(source_id));
+        sizes.sort();
+        for (_, source_id, bytes) in sizes {
+            if resident <= budget {
+                break;
+            }
+            let Some(source) = self.sources.get_mut(&source_id) else {
+                continue;
+            };
+            if let Some(storage) = self.storage.as_ref().filter(|_| source.persisted) {
+                source.release(&source_id, storage.clone());
+                resident -= bytes;
+                continue;
+            }
+            let spilled = std::fs::create_dir_all(&self.spill_dir)
+                .map_err(|e| format!("Could not create spill directory {}: {}", self.spill_dir.display(), e))
+                .and_then(|_| source.spill_to(self.spill_dir.join(format!("{}.json", Uuid::new_v4()))));
+            match spilled {
+                Ok(()) => {
+                    resident -= bytes;
+                    println!("Spilled source {} (~{} MiB) to disk", source_id, bytes / (1024 * 1024));
+                },
+                Err(e) => println!("Warning: Could not spill source {}: {}", source_id, e),
+            }
+        }
+        if resident > budget {
+            println!(
+                "Warning: Sources in use take ~{} MiB, over the {} MiB memory budget",
+                resident / (1024 * 1024), budget / (1024 * 1024),
+            );
+        }
+    }
+
+    /// A read lock under which every source `wanted` names is in memory. Spilled ones are read
+    /// back under the write lock, which is then downgraded so none is spilled again before the
+    /// guard is dropped. `wanted` may be called twice, as the store can change in between.
+    pub async fn read_loaded(
+        lock: &RwLock<DataStore>,
+        wanted: impl Fn(&DataStore) -> Vec<String>,
+    ) -> Result<RwLockReadGuard<'_, DataStore>, String> {
+        let store = lock.read().await;
+        if wanted(&store).iter().all(|source_id| !store.get(source_id).is_some_and(StoredSource::is_spilled)) {
+            return Ok(store);
+        }
+        drop(store);
+        let mut store = lock.write().await;
+        let source_ids = wanted(&store);
+        store.load(&source_ids)?;
+        Ok(store.downgrade())
+    }
+}
+
+/// Records sampled to infer the schema of a source restored from storage, which was not
+/// inferred as it loaded
+const SOURCE_SCHEMA_SAMPLE: usize = 1_000;
+
+/// Where `POST /sources` loads a source's records from. Secrets in URLs, headers, options and
+/// connection strings should be `${credential:<name>}` references: definitions are stored and
+/// returned as given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SourceDefinition {
+    /// CSV, JSON lines or Parquet file on the server, by extension
+    File { path: String },
+    /// JSON array, or an object whose `data` field is the array, fetched with GET
+    Api {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// One CSV, JSON lines or Parquet object such as `s3://bucket/path/data.parquet`;
+    /// `options` are object_store settings like `aws_region` or `aws_access_key_id`
+    S3 {
+        url: String,
+        #[serde(default)]
+        options: HashMap<String, String>,
+    },
+    /// Rows of a query, one record per row keyed by column name
+    Database {
+        connection_string: String,
+        query: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SourceRequest {
+    pub id: String,
+    pub definition: SourceDefinition,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceInfo {
+    pub id: String,
+    pub record_count: usize,
+    pub version: u64,
+    pub loaded_at: Option<DateTime<Utc>>,
+    /// The records are on disk, spilled or in the storage backend, until something reads them
+    pub spilled: bool,
+    /// How the source was created through `POST /sources`; absent for uploaded or pushed sources
+    pub definition: Option<SourceDefinition>,
+    /// Data fields, their types and nullability, inferred as the records loaded; only for a
+    /// single source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Vec<schema::Column>>,
+    /// How records deviated from the source's expected schema, when one is registered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_drift: Option<schema::SchemaDrift>,
+    /// Row count and estimated distinct values per field, which joins plan with; only for a
+    /// single source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<SourceStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceVersion {
+    pub version: u64,
+    pub record_count: usize,
+    pub loaded_at: Option<DateTime<Utc>>,
+    pub current: bool,
+    pub spilled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionDiffQuery {
+    pub from: u64,
+    /// Defaults to the current version
+    pub to: Option<u64>,
+    /// Data field identifying records across versions
+    pub key_field: Option<String>,
+}
+
+/// Records of each kind a version diff lists; the counts cover all of them
+const MAX_DIFF_SAMPLES: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordChange {
+    pub before: Value,
+    pub after: Value,
+}
+
+/// How a source's records changed between two versions. The record lists hold the data of
+/// the first `MAX_DIFF_SAMPLES` records of each kind.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VersionDiff {
+    pub source_id: String,
+    pub from: u64,
+    pub to: u64,
+    pub key_field: Option<String>,
+    pub added: usize,
+    pub removed: usize,
+    /// Records whose key is in both versions with different data; only with a key field
+    pub changed: usize,
+    pub unchanged: usize,
+    pub added_records: Vec<Value>,
+    pub removed_records: Vec<Value>,
+    pub changed_records: Vec<RecordChange>,
+}
+
+impl VersionDiff {
+    fn push_added(&mut self, data: Value) {
+        self.added += 1;
+        if self.added_records.len() < MAX_DIFF_SAMPLES {
+            self.added_records.push(data);
+        }
+    }
+
+    fn push_removed(&mut self, data: Value) {
+        self.removed += 1;
+        if self.removed_records.len() < MAX_DIFF_SAMPLES {
+            self.removed_records.push(data);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourcePage {
+    pub source_id: String,
+    pub version: u64,
+    pub total: usize,
+    pub count: usize,
+    pub offset: usize,
+    pub truncated: bool,
+    pub next_offset: Option<usize>,
+    pub records: Vec<DataRecord>,
+}
+
+/// Delay before the first restart of a failed background task; doubles on every further failure
+const SUPERVISOR_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const SUPERVISOR_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A task that ran this long before failing restarts with the initial backoff again
+const SUPERVISOR_HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+/// Delay before the first rerun of a failed job; doubles with every further attempt
+const JOB_RETRY_INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const JOB_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Backoff before rerunning a job that has failed `failures` times, with jitter of up to half
+/// the delay so jobs that failed together do not all retry at once.
+fn job_retry_delay(failures: u32) -> Duration {
+    let backoff = JOB_RETRY_INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(JOB_RETRY_MAX_BACKOFF);
+    backoff.mul_f64(0.5 + rand::random::<f64>() * 0.5)
+}
+
+/// A child of `cancel` that is also cancelled once `timeout_seconds` pass (never when 0). The
+/// deadline stops an attempt the same way a cancellation does, between batches; cancelling it
+/// when the attempt ends releases the timer.
+async fn job_deadline(cancel: &CancellationToken, timeout_seconds: u64) -> CancellationToken {
+    let deadline = cancel.child_token();
+    if timeout_seconds > 0 {
+        let (timer, limit) = (deadline.clone(), Duration::from_secs(timeout_seconds));
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(limit) => timer.cancel(),
+                _ = timer.cancelled() => {},
+            }
+        });
+        // The timer is queued on this worker, which the job may then keep busy; yielding lets it
+        // start its sleep first
+        tokio::task::yield_now().await;
+    }
+    deadline
+}
+
+/// How often a running job's `progress` and `processed_count` are brought up to date.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Log lines kept per job; past this the oldest go first.
+const MAX_JOB_LOG_LINES: usize = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobLogLine {
+    pub timestamp: DateTime<Utc>,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+/// The recent log lines of every job. Lines still go to stdout as they are written, labelled
+/// with the request and trace of a job submitted through the API.
+#[derive(Clone, Default)]
+pub struct JobLogs {
+    lines: Arc<std::sync::Mutex<HashMap<String, VecDeque<JobLogLine>>>>,
+    labels: Arc<std::sync::Mutex<HashMap<String, String>>>,
+}
+
+impl JobLogs {
+    fn write(&self, job_id: &str, level: LogLevel, message: String) {
+        match self.labels.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(job_id) {
+            Some(label) => println!("{} [{}]", message, label),
+            None => println!("{}", message),
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let job_lines = lines.entry(job_id.to_string()).or_default();
+        if job_lines.len() == MAX_JOB_LOG_LINES {
+            job_lines.pop_front();
+        }
+        job_lines.push_back(JobLogLine { timestamp: Utc::now(), level, message });
+    }
+
+    fn info(&self, job_id: &str, message: String) {
+        self.write(job_id, LogLevel::Info, message);
+    }
+
+    fn warn(&self, job_id: &str, message: String) {
+        self.write(job_id, LogLevel::Warn, message);
+    }
+
+    fn error(&self, job_id: &str, message: String) {
+        self.write(job_id, LogLevel::Error, message);
+    }
+
+    /// The job's lines at `level` or above written after `since`, oldest first, keeping the
+    /// last `tail` of them.
+    fn lines(&self, job_id: &str, level: LogLevel, since: Option<DateTime<Utc>>, tail: Option<usize>) -> Vec<JobLogLine> {
+        let lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut selected: Vec<JobLogLine> = lines.get(job_id).into_iter().flatten()
+            .filter(|line| line.level >= level && since.is_none_or(|since| line.timestamp > since))
+            .cloned()
+            .collect();
+        if let Some(tail) = tail {
+            selected.drain(..selected.len().saturating_sub(tail));
+        }
+        selected
+    }
+
+    /// Labels the job's stdout lines with its request and trace, when it has them.
+    fn correlate(&self, job: &ProcessingJob) {
+        if let Some(trace) = &job.trace {
+            self.labels.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(job.id.clone(), trace.log_label());
+        }
+    }
+
+    fn remove(&self, job_id: &str) {
+        self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(job_id);
+        self.labels.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(job_id);
+    }
+}
+
+/// Where a running attempt is. The job reports each stage as it starts and each batch as it
+/// finishes; the publisher reads it from another task.
+struct ProgressTracker {
+    started: Instant,
+    /// Units the attempt is made of: one per operation and one for the output
+    units: f64,
+    completed: AtomicUsize,
+    stage: std::sync::Mutex<StageProgress>,
+}
+
+#[derive(Default)]
+struct StageProgress {
+    name: String,
+    total: usize,
+    /// Units done before the stage and units the stage covers
+    at: f64,
+    width: f64,
+}
+
+impl ProgressTracker {
+    fn new(operations: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            units: operations as f64 + 1.0,
+            completed: AtomicUsize::new(0),
+            stage: std::sync::Mutex::new(StageProgress::default()),
+        }
+    }
+
+    fn start_stage(&self, name: &str, at: f64, width: f64, total: usize) {
+        let mut stage = self.stage.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *stage = StageProgress { name: name.to_string(), total, at, width };
+        self.completed.store(0, Ordering::Relaxed);
+    }
+
+    fn advance(&self, records: usize) {
+        self.completed.fetch_add(records, Ordering::Relaxed);
+    }
+
+    /// Marks the whole stage done; stages that do not run in batches only report this.
+    fn finish_stage(&self) {
+        let stage = self.stage.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.completed.store(stage.total, Ordering::Relaxed);
+    }
+
+    /// The ETA assumes the rest of the attempt goes at the average pace so far.
+    fn snapshot(&self) -> JobProgress {
+        let stage = self.stage.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let completed = self.completed.load(Ordering::Relaxed).min(stage.total);
+        let stage_fraction = match stage.total {
+            0 => 0.0,
+            total => completed as f64 / total as f64,
+        };
+        let fraction = ((stage.at + stage.width * stage_fraction) / self.units).clamp(0.0, 1.0);
+        let elapsed = self.started.elapsed().as_secs_f64();
+        JobProgress {
+            stage: stage.name.clone(),
+            completed,
+            total: stage.total,
+            fraction,
+            eta_seconds: (fraction > 0.0).then(|| (elapsed * (1.0 - fraction) / fraction).round() as u64),
+        }
+    }
+}
+
+/// Copies `progress` onto the stored job every `PROGRESS_INTERVAL`, announcing each change of
+/// stage or record count, until `done` is cancelled at the end of the attempt.
+async fn publish_progress(
+    job_id: String,
+    progress: Arc<ProgressTracker>,
+    jobs: Arc<RwLock<HashMap<String, ProcessingJob>>>,
+    job_updates: broadcast::Sender<ProcessingJob>,
+    done: CancellationToken,
+) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(PROGRESS_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {},
+                _ = done.cancelled() => return,
+            }
+            let snapshot = progress.snapshot();
+            let mut jobs_map = jobs.write().await;
+            let Some(job) = jobs_map.get_mut(&job_id).filter(|job| matches!(job.status, JobStatus::Running)) else {
+                continue;
+            };
+            // The ETA alone changes every tick, so it is only announced along with real progress
+            let moved = job.progress.as_ref()
+                .is_none_or(|last| last.stage != snapshot.stage || last.completed != snapshot.completed);
+            job.processed_count = snapshot.completed;
+            job.progress = Some(snapshot);
+            if moved {
+                let _ = job_updates.send(job.clone());
+            }
+        }
+    });
+    // Like the deadline timer, the publisher has to start before the job occupies this worker
+    tokio::task::yield_now().await;
+}
+
+/// The message a panic was raised with, when it carries one.
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Outcome of one of the checks readiness is decided by.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadinessCheck {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<(), String>> for ReadinessCheck {
+    fn from(result: Result<(), String>) -> Self {
+        ReadinessCheck { ok: result.is_ok(), error: result.err() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: BTreeMap<&'static str, ReadinessCheck>,
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub tasks: BTreeMap<String, TaskHealth>,
+}
+
+/// What operators can change through `/admin`, and the queue they act on.
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminStatus {
+    pub scheduler_paused: bool,
+    pub draining: bool,
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub waiting_jobs: usize,
+    pub pending_jobs: usize,
+    pub running_jobs: usize,
+}
+
+/// State of one supervised background task, as reported by `/readyz`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskHealth {
+    pub running: bool,
+    pub restarts: u32,
+    pub last_failure: Option<String>,
+    pub last_failure_at: Option<DateTime<Utc>>,
+}
+
+/// Runs long-lived background tasks and restarts any that panic or return, with
+/// exponential backoff, so the service never keeps accepting work nobody will do.
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    tasks: Arc<RwLock<BTreeMap<String, TaskHealth>>>,
+}
+
+impl Supervisor {
+    /// Spawns the future built by `start` and builds a fresh one whenever it stops.
+    fn supervise<F, Fut>(&self, name: &str, start: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let tasks = self.tasks.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let mut backoff = SUPERVISOR_INITIAL_BACKOFF;
+            loop {
+                tasks.write().await
+                    .entry(name.clone())
+                    .or_insert(TaskHealth { running: false, restarts: 0, last_failure: None, last_failure_at: None })
+                    .running = true;
+
+                let started = Instant::now();
+                let failure = match tokio::spawn(start()).await {
+                    Ok(()) => "exited".to_string(),
+                    Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic().as_ref())),
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() >= SUPERVISOR_HEALTHY_RUN {
+                    backoff = SUPERVISOR_INITIAL_BACKOFF;
+                }
+                println!("Background task {} {}; restarting in {:?}", name, failure, backoff);
+                if let Some(health) = tasks.write().await.get_mut(&name) {
+                    health.running = false;
+                    health.restarts += 1;
+                    health.last_failure = Some(failure);
+                    health.last_failure_at = Some(Utc::now());
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(SUPERVISOR_MAX_BACKOFF);
+            }
+        });
+    }
+
+    pub async fn health(&self) -> BTreeMap<String, TaskHealth> {
+        self.tasks.read().await.clone()
+    }
+}
+
+/// Jobs whose processed records stay available from `GET /jobs/{id}/results`
+const MAX_RETAINED_JOB_RESULTS: usize = 100;
+/// Records kept across all retained job results; the oldest jobs' records are dropped first
+const MAX_RETAINED_RESULT_RECORDS: usize = 5_000_000;
+
+/// Processed records of recently completed jobs, in completion order.
+#[derive(Default)]
+pub struct RetainedResults {
+    order: VecDeque<String>,
+    records: HashMap<String, Arc<Vec<DataRecord>>>,
+    total_records: usize,
+}
+
+impl RetainedResults {
+    /// Keeps a job's records, evicting older jobs past the retention limits. The newest job is
+    /// always kept, however large.
+    fn insert(&mut self, job_id: String, records: Vec<DataRecord>) {
+        self.total_records += records.len();
+        if let Some(previous) = self.records.insert(job_id.clone(), Arc::new(records)) {
+            self.total_records -= previous.len();
+            self.order.retain(|id| *id != job_id);
+        }
+        self.order.push_back(job_id);
+
+        while self.order.len() > 1
+            && (self.order.len() > MAX_RETAINED_JOB_RESULTS || self.total_records > MAX_RETAINED_RESULT_RECORDS)
+        {
+            if let Some(evicted) = self.order.pop_front().and_then(|id| self.records.remove(&id)) {
+                self.total_records -= evicted.len();
+            }
+        }
+    }
+
+    fn get(&self, job_id: &str) -> Option<Arc<Vec<DataRecord>>> {
+        self.records.get(job_id).cloned()
+    }
+
+    fn remove(&mut self, job_id: &str) {
+        if let Some(removed) = self.records.remove(job_id) {
+            self.total_records -= removed.len();
+            self.order.retain(|id| id != job_id);
+        }
+    }
+}
+
+/// Finished jobs kept when `--retain-jobs` is not given.
+const DEFAULT_RETAINED_JOBS: usize = 10_000;
+/// How often finished jobs past the retention policy are deleted
+const JOB_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long finished jobs are kept. Jobs that an unfinished job depends on are kept regardless.
+#[derive(Debug, Clone, Default)]
+pub struct JobRetention {
+    /// Finished jobs kept, newest first
+    pub max_jobs: Option<usize>,
+    /// Finished jobs are deleted once they have been finished this long
+    pub max_age: Option<chrono::Duration>,
+}
+
+/// Earlier versions kept per source when `--retain-source-versions` is not given.
+const DEFAULT_RETAINED_VERSIONS: usize = 3;
+
+/// How long earlier versions of a source are kept once a load or rollback replaces them.
+/// Versions an unfinished job is pinned to are kept regardless.
+#[derive(Debug, Clone, Default)]
+pub struct VersionRetention {
+    /// Versions kept per source, newest first; `Some(0)` keeps none
+    pub max_versions: Option<usize>,
+    /// Versions are deleted once their records landed this long ago
+    pub max_age: Option<chrono::Duration>,
+}
+
+/// Error an operation stops with once its job is cancelled
+const JOB_CANCELLED: &str = "Job cancelled";
+
+/// Default lifetime of a result export's download link
+const DEFAULT_EXPORT_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Deserialize)]
+pub struct ExportRequest {
+    #[serde(deserialize_with = "OutputFormat::deserialize_compat")]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub output_compression: OutputCompression,
+    #[serde(default)]
+    pub output_columns: Option<Vec<OutputColumn>>,
+    /// How long the download link stays valid, e.g. `2h`; defaults to a day
+    pub expires_in: Option<String>,
+    /// Asked of whoever downloads the export, as the Basic auth password or `X-Export-Password`
+    pub password: Option<String>,
+}
+
+/// One-off file of a job's results, downloadable through a signed link until it expires.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultExport {
+    pub id: String,
+    pub job_id: String,
+    pub file_name: String,
+    pub content_type: &'static str,
+    pub records: usize,
+    pub size_bytes: u64,
+    pub password_protected: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(skip)]
+    path: PathBuf,
+    /// Salt and HMAC-SHA256 of the password under it
+    #[serde(skip)]
+    password_hash: Option<([u8; 16], Vec<u8>)>,
+}
+
+fn export_content_type(format: &OutputFormat, compression: OutputCompression) -> &'static str {
+    match (compression, format) {
+        (OutputCompression::Gzip, _) => "application/gzip",
+        (OutputCompression::Zstd, _) => "application/zstd",
+        (OutputCompression::None, OutputFormat::Json) => "application/json",
+        (OutputCompression::None, OutputFormat::JsonLines { .. }) => "application/x-ndjson",
+        (OutputCompression::None, OutputFormat::Csv { .. }) => "text/csv",
+        (OutputCompression::None, _) => "application/octet-stream",
+    }
+}
+
+impl ResultExport {
+    fn remove_files(&self) {
+        if let Some(dir) = self.path.parent() {
+            if let Err(e) = std::fs::remove_dir_all(dir) {
+                println!("Warning: Could not remove export {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+pub struct DataProcessor {
+    jobs: Arc<RwLock<HashMap<String, ProcessingJob>>>,
+    job_results: Arc<RwLock<RetainedResults>>,
+    /// Cancellation token of every pending or running job
+    cancellations: Arc<RwLock<HashMap<String, CancellationToken>>>,
+    exports: Arc<RwLock<HashMap<String, ResultExport>>>,
+    data_store: Arc<RwLock<DataStore>>,
+    source_versions: Arc<RwLock<HashMap<String, u64>>>,
+    /// Definitions of the sources created through `POST /sources`
+    source_definitions: Arc<RwLock<HashMap<String, SourceDefinition>>>,
+    /// Locale each source's records are normalized with as they are loaded or appended
+    source_locales: Arc<RwLock<HashMap<String, Locale>>>,
+    /// Schema inferred as each source was loaded and widened by appends
+    source_schemas: Arc<RwLock<HashMap<String, Vec<schema::Column>>>>,
+    /// Expected schemas registered for sources, with their drift reports and quarantined records
+    source_registrations: Arc<RwLock<HashMap<String, schema::Registration>>>,
+    record_audit: Arc<RwLock<Vec<RecordAuditEntry>>>,
+    saved_queries: Arc<RwLock<HashMap<String, SavedQuery>>>,
+    schedules: Arc<RwLock<HashMap<String, Schedule>>>,
+    calendars: Arc<RwLock<HashMap<String, BusinessCalendar>>>,
+    maintenance_windows: Arc<RwLock<Vec<MaintenanceWindow>>>,
+    share_secret: Vec<u8>,
+    credentials: Arc<RwLock<HashMap<String, Credential>>>,
+    credential_vault: Option<CredentialVault>,
+    egress_policy: Arc<RwLock<EgressPolicy>>,
+    work_dir: Arc<RwLock<WorkDir>>,
+    memory_limit: Option<u64>,
+    /// Distinct values up to which source string fields are dictionary-encoded; off when `None`
+    dictionary_max_values: Option<usize>,
+    /// Most records a single API response may carry
+    max_result_rows: usize,
+    job_retention: JobRetention,
+    version_retention: VersionRetention,
+    /// Notified of every job's lifecycle events
+    job_webhooks: Vec<JobCallback>,
+    /// Swapped whole when `reload_quotas` reads the quotas file again
+    quotas: std::sync::RwLock<Arc<QuotaConfig>>,
+    webhook_secret: Option<Vec<u8>>,
+    metrics: Arc<RwLock<SystemMetrics>>,
+    catch_up: Arc<CatchUpThrottle>,
+    job_queue: JobQueue,
+    supervisor: Supervisor,
+    job_updates: broadcast::Sender<ProcessingJob>,
+    job_logs: JobLogs,
+    start_time: Instant,
+    /// Schedules do not fire while set; see `pause_scheduler`
+    scheduler_paused: AtomicBool,
+    /// New submissions are refused while set; see `set_draining`
+    draining: AtomicBool,
+}
+
+impl DataProcessor {
+    pub fn new() -> Self {
+        let (job_updates, _) = broadcast::channel(1024);
+
+        // Without a key credentials are kept in memory only; a store that fails to
+        // decrypt is left untouched rather than overwritten
+        let mut credential_vault = CredentialVault::from_env();
+        let credentials = match credential_vault.as_ref().map(CredentialVault::load) {
+            Some(Ok(credentials)) => credentials,
+            Some(Err(e)) => {
+                println!("Warning: Credential store not loaded, changes will not be persisted: {}", e);
+                credential_vault = None;
+                HashMap::new()
+            },
+            None => HashMap::new(),
+        };
+        
+        let processor = Self {
+            jobs: Arc::new(RwLock::new(HashMap::new())),
+            job_results: Arc::new(RwLock::new(RetainedResults::default())),
+            cancellations: Arc::new(RwLock::new(HashMap::new())),
+            exports: Arc::new(RwLock::new(HashMap::new())),
+            data_store: Arc::new(RwLock::new(DataStore::default())),
+            source_versions: Arc::new(RwLock::new(HashMap::new())),
+            source_definitions: Arc::new(RwLock::new(HashMap::new())),
+            source_locales: Arc::new(RwLock::new(HashMap::new())),
+            source_schemas: Arc::new(RwLock::new(HashMap::new())),
+            source_registrations: Arc::new(RwLock::new(HashMap::new())),
+            record_audit: Arc::new(RwLock::new(Vec::new())),
+            saved_queries: Arc::new(RwLock::new(HashMap::new())),
+            schedules: Arc::new(RwLock::new(HashMap::new())),
+            calendars: Arc::new(RwLock::new(HashMap::new())),
+            maintenance_windows: Arc::new(RwLock::new(Vec::new())),
+            // Without a configured secret, share tokens only survive until restart
+            share_secret: std::env::var("DATA_PROCESSOR_SHARE_SECRET")
+                .map(String::into_bytes)
+                .unwrap_or_else(|_| rand::random::<[u8; 32]>().to_vec()),
+            credentials: Arc::new(RwLock::new(credentials)),
+            credential_vault,
+            egress_policy: Arc::new(RwLock::new(EgressPolicy::default())),
+            work_dir: Arc::new(RwLock::new(WorkDir::default())),
+            memory_limit: None,
+            dictionary_max_values: None,
+            max_result_rows: DEFAULT_MAX_RESULT_ROWS,
+            job_retention: JobRetention { max_jobs: Some(DEFAULT_RETAINED_JOBS), max_age: None },
+            version_retention: VersionRetention { max_versions: Some(DEFAULT_RETAINED_VERSIONS), max_age: None },
+            job_webhooks: Vec::new(),
+            quotas: std::sync::RwLock::new(Arc::new(QuotaConfig::default())),
+            webhook_secret: std::env::var("DATA_PROCESSOR_WEBHOOK_SECRET").ok().map(String::into_bytes),
+            metrics: Arc::new(RwLock::new(SystemMetrics {
+                cpu_usage: 0.0,
+                memory_usage: 0.0,
+                disk_usage: 0.0,
+                active_jobs: 0,
+                total_records_processed: 0,
+                average_processing_time_ms: 0.0,
+                error_rate: 0.0,
+                uptime_seconds: 0,
+                catch_up_pending: 0,
+                catch_up_completed: 0,
+            })),
+            catch_up: Arc::new(CatchUpThrottle::new(DEFAULT_CATCH_UP_RATE).expect("default catch-up rate is valid")),
+            job_queue: JobQueue::new(DEFAULT_MAX_QUEUED_JOBS),
+            supervisor: Supervisor::default(),
+            job_updates,
+            job_logs: JobLogs::default(),
+            start_time: Instant::now(),
+            scheduler_paused: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+        };
+
+        // Start metrics updater
+        let metrics_clone = processor.metrics.clone();
+        let start_time = processor.start_time;
+
+        processor.supervisor.supervise("metrics", move || Self::update_metrics(metrics_clone.clone(), start_time));
+
+        processor
+    }
+
+    /// Sets how fast catch-up operations are released after downtime.
+    pub fn with_catch_up_throttle(mut self, throttle: CatchUpThrottle) -> Self {
+        self.catch_up = Arc::new(throttle);
+        self
+    }
+
+    /// Caps how many submitted jobs may wait for a worker; takes effect before the workers start.
+    pub fn with_max_queued_jobs(mut self, max_jobs: usize) -> Self {
+        self.job_queue = JobQueue::new(max_jobs);
+        self.job_queue.set_quotas(self.quotas());
+        self
+    }
+
+    pub fn with_quotas(self, quotas: QuotaConfig) -> Self {
+        self.reload_quotas(quotas);
+        self
+    }
+
+    fn quotas(&self) -> Arc<QuotaConfig> {
+        self.quotas.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replaces the tenant quotas. Jobs already queued or running keep going; the new limits
+    /// apply to submissions and to which queued job a free worker takes next.
+    pub fn reload_quotas(&self, quotas: QuotaConfig) {
+        let quotas = Arc::new(quotas);
+        *self.quotas.write().unwrap_or_else(|e| e.into_inner()) = quotas.clone();
+        self.job_queue.set_quotas(quotas);
+    }
+
+    pub fn tenant_for_key(&self, api_key: Option<&str>) -> Result<Option<String>, String> {
+        self.quotas().tenant_for_key(api_key)
+    }
+
+    /// Refuses a submission that would take its tenant past the job's input or queued limit.
+    fn check_quota(&self, job: &ProcessingJob, jobs: &HashMap<String, ProcessingJob>) -> Result<(), String> {
+        let quotas = self.quotas();
+        let quota = quotas.quota(job.tenant.as_deref());
+        let tenant = tenant_label(job.tenant.as_deref());
+        if let Some(limit) = quota.max_records_per_job.filter(|limit| job.input_count > *limit) {
+            return Err(format!(
+                "{}: the job reads {} records and tenant {} may submit at most {} per job",
+                QUOTA_EXCEEDED, job.input_count, tenant, limit,
+            ));
+        }
+        if let Some(limit) = quota.max_queued_jobs {
+            let queued = jobs.values()
+                .filter(|queued| queued.tenant == job.tenant && matches!(queued.status, JobStatus::Waiting | JobStatus::Pending))
+                .count();
+            if queued >= limit {
+                return Err(format!("{}: tenant {} already has {} queued jobs, its limit", QUOTA_EXCEEDED, tenant, queued));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn queue_depth(&self) -> (usize, usize) {
+        (self.job_queue.depth(), self.job_queue.capacity())
+    }
+
+    /// Caps the memory admitted jobs may be projected to use together.
+    pub fn with_memory_limit(mut self, limit_bytes: Option<u64>) -> Self {
+        self.memory_limit = limit_bytes;
+        self
+    }
+
+    /// Caps the memory sources' records may take before the least recently read are spilled
+    /// to disk; takes effect before any source is loaded.
+    pub fn with_source_memory_budget(mut self, budget_bytes: Option<u64>) -> Self {
+        self.data_store = Arc::new(RwLock::new(DataStore::new(budget_bytes)));
+        self
+    }
+
+    /// Dictionary-encodes source string fields with at most `max_values` distinct values.
+    pub fn with_dictionary_encoding(mut self, max_values: Option<usize>) -> Self {
+        self.dictionary_max_values = max_values;
+        self
+    }
+
+    pub fn with_max_result_rows(mut self, max_rows: usize) -> Self {
+        self.max_result_rows = max_rows.max(1);
+        self
+    }
+
+    pub fn with_job_retention(mut self, retention: JobRetention) -> Self {
+        self.job_retention = retention;
+        self
+    }
+
+    pub fn with_version_retention(mut self, retention: VersionRetention) -> Self {
+        self.version_retention = retention;
+        self
+    }
+
+    pub fn with_job_webhooks(mut self, urls: Vec<String>) -> Self {
+        self.job_webhooks = urls.into_iter().map(|url| JobCallback { url, events: Vec::new(), secret: None }).collect();
+        self
+    }
+
+    /// Callbacks need an HTTP(S) URL and a key to sign with.
+    fn validate_callbacks(&self, callbacks: &[JobCallback]) -> Result<(), String> {
+        if callbacks.len() > MAX_JOB_CALLBACKS {
+            return Err(format!("A job may have at most {} callbacks", MAX_JOB_CALLBACKS));
+        }
+        for callback in callbacks {
+            let url = reqwest::Url::parse(&callback.url).map_err(|e| format!("Invalid callback URL {}: {}", callback.url, e))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(format!("Callback URL {} must be http or https", callback.url));
+            }
+            if callback.secret.is_none() && self.webhook_secret.is_none() {
+                return Err(format!(
+                    "Callback {} needs a secret, as the server has no DATA_PROCESSOR_WEBHOOK_SECRET", callback.url,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn submit_job(&self, job: ProcessingJob) -> Result<String, String> {
+        let mut job = self.prepare_submission(job).await?;
+        let job_id = job.id.clone();
+        let mut jobs = self.jobs.write().await;
+        self.check_submission(&mut job, &jobs)?;
+        self.register_submission(&job).await;
+        // Send to processor while the job list is held, so no worker sees the job before it is
+        // stored; waiting jobs are queued once their dependencies complete
+        if matches!(job.status, JobStatus::Pending) && self.job_queue.try_push(job.clone()).is_err() {
+            self.unregister_submission(&job_id).await;
+            return Err(QUEUE_FULL.to_string());
+        }
+        jobs.insert(job_id.clone(), job.clone());
+        drop(jobs);
+        let _ = self.job_updates.send(job);
+        Ok(job_id)
+    }
+
+    /// Submits a batch of jobs. With `atomic` either every job is filed or none is, and a refusal
+    /// of one is reported against all of them; otherwise each job is submitted on its own.
+    pub async fn submit_jobs(&self, batch: Vec<ProcessingJob>, atomic: bool) -> Vec<Result<String, String>> {
+        if !atomic {
+            let mut submitted = Vec::with_capacity(batch.len());
+            for job in batch {
+                submitted.push(self.submit_job(job).await);
+            }
+            return submitted;
+        }
+
+        let mut prepared = Vec::with_capacity(batch.len());
+        for job in batch {
+            prepared.push(self.prepare_submission(job).await);
+        }
+        if let Some(index) = prepared.iter().position(Result::is_err) {
+            return Self::refuse_batch(prepared.into_iter().map(|job| job.map(|job| job.id)).collect(), index);
+        }
+        let mut prepared: Vec<ProcessingJob> = prepared.into_iter().flatten().collect();
+
+        let mut jobs = self.jobs.write().await;
+        // Each job is filed as it passes, so quotas count the batch's earlier jobs; nobody else
+        // sees them before the write lock is released
+        let mut checked = Vec::with_capacity(prepared.len());
+        for job in &mut prepared {
+            match self.check_submission(job, &jobs) {
+                Ok(()) => {
+                    jobs.insert(job.id.clone(), job.clone());
+                    checked.push(Ok(job.id.clone()));
+                },
+                Err(error) => checked.push(Err(error)),
+            }
+        }
+        if let Some(index) = checked.iter().position(Result::is_err) {
+            for job in &prepared {
+                jobs.remove(&job.id);
+            }
+            return Self::refuse_batch(checked, index);
+        }
+        for job in &prepared {
+            self.register_submission(job).await;
+        }
+        let pending: Vec<ProcessingJob> = prepared.iter().filter(|job| matches!(job.status, JobStatus::Pending)).cloned().collect();
+        if self.job_queue.try_push_all(pending).is_err() {
+            for job in &prepared {
+                jobs.remove(&job.id);
+                self.unregister_submission(&job.id).await;
+            }
+            return prepared.iter().map(|_| Err(QUEUE_FULL.to_string())).collect();
+        }
+        drop(jobs);
+        prepared.into_iter()
+            .map(|job| {
+                let job_id = job.id.clone();
+                let _ = self.job_updates.send(job);
+                Ok(job_id)
+            })
+            .collect()
+    }
+
+    /// The outcome of an all-or-nothing batch whose job `failed` was the first refused: the
+    /// refused jobs keep their errors and the others are reported as refused with the batch.
+    fn refuse_batch(outcomes: Vec<Result<String, String>>, failed: usize) -> Vec<Result<String, String>> {
+        outcomes.into_iter()
+            .map(|outcome| match outcome {
+                Err(error) => Err(error),
+                Ok(_) => Err(format!("{}: job {} of the batch was refused", BATCH_REFUSED, failed)),
+            })
+            .collect()
+    }
+
+    /// Validates a job and stamps it as a new submission: everything `submit_job` does before
+    /// it takes the job list.
+    async fn prepare_submission(&self, mut job: ProcessingJob) -> Result<ProcessingJob, String> {
+        if self.is_draining() {
+            return Err(DRAINING.to_string());
+        }
+        job.configuration.validate()?;
+        validate_tags(&job.tags)?;
+        self.validate_callbacks(&job.callbacks)?;
+        job.warnings = job.configuration.deprecation_warnings();
+        job.attempts.clear();
+        job.retry_at = None;
+        job.dead_lettered_at = None;
+        job.id = Uuid::new_v4().to_string();
+        job.status = JobStatus::Pending;
+        job.created_at = Utc::now();
+        job.error = None;
+        let estimate = self.estimate_job(&job).await;
+        job.estimated_memory_bytes = Some(estimate.projected_memory_bytes);
+        job.estimated_disk_bytes = Some(estimate.projected_disk_bytes);
+        job.input_count = estimate.input_records;
+        job.depends_on.sort();
+        job.depends_on.dedup();
+        Ok(job)
+    }
+
+    /// Checks a prepared job against the held job list, its tenant's quota and its dependencies,
+    /// and sets it waiting when a dependency has not completed.
+    fn check_submission(&self, job: &mut ProcessingJob, jobs: &HashMap<String, ProcessingJob>) -> Result<(), String> {
+        self.check_quota(job, jobs)?;
+        for dependency in &job.depends_on {
+            let status = &jobs.get(dependency).ok_or_else(|| format!("Dependency {} not found", dependency))?.status;
+            if status.is_finished() && !matches!(status, JobStatus::Completed) {
+                return Err(format!("Dependency {} is {:?}", dependency, status));
+            }
+        }
+        if job.depends_on.iter().any(|dependency| !matches!(jobs[dependency].status, JobStatus::Completed)) {
+            job.status = JobStatus::Waiting;
+        }
+        Ok(())
+    }
+
+    async fn register_submission(&self, job: &ProcessingJob) {
+        self.job_logs.correlate(job);
+        // Logged first so the log does not start with a worker picking the job up
+        self.job_logs.info(&job.id, format!("Job submitted: {}", job.id));
+        for warning in &job.warnings {
+            self.job_logs.warn(&job.id, format!("Deprecated at {}: {}", warning.path, warning.message));
+        }
+        self.cancellations.write().await.insert(job.id.clone(), CancellationToken::new());
+    }
+
+    async fn unregister_submission(&self, job_id: &str) {
+        self.cancellations.write().await.remove(job_id);
+        self.job_logs.remove(job_id);
+    }
+
+    pub async fn job_dag(&self, job_id: &str) -> Result<JobDag, String> {
+        let jobs = self.jobs.read().await;
+        if !jobs.contains_key(job_id) {
+            return Err("Job not found".to_string());
+        }
+        let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+        for job in jobs.values() {
+            for dependency in &job.depends_on {
+                dependents.entry(dependency.as_str()).or_default().push(job.id.as_str());
+            }
+        }
+
+        let mut connected: HashSet<&str> = HashSet::from([job_id]);
+        let mut frontier = vec![job_id];
+        while let Some(id) = frontier.pop() {
+            let upstream = jobs.get(id).into_iter().flat_map(|job| job.depends_on.iter().map(String::as_str));
+            let downstream = dependents.get(id).into_iter().flatten().copied();
+            for neighbour in upstream.chain(downstream).collect::<Vec<_>>() {
+                if jobs.contains_key(neighbour) && connected.insert(neighbour) {
+                    frontier.push(neighbour);
+                }
+            }
+        }
+
+        // Dependencies always exist before their dependents, so creation order is topological
+        let mut members: Vec<&ProcessingJob> = connected.iter().map(|id| &jobs[*id]).collect();
+        members.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        let status = members.iter().map(|job| &job.status)
+            .min_by_key(|status| match status {
+                JobStatus::Failed => 0,
+                JobStatus::TimedOut => 1,
+                JobStatus::Cancelled => 2,
+                JobStatus::Running => 3,
+                JobStatus::Waiting => 4,
+                JobStatus::Pending => 5,
+                JobStatus::Completed => 6,
+            })
+            .cloned()
+            .unwrap_or(JobStatus::Completed);
+        Ok(JobDag {
+            job_id: job_id.to_string(),
+            status,
+            jobs: members.into_iter()
+                .map(|job| DagJob {
+                    id: job.id.clone(),
+                    name: job.name.clone(),
+                    status: job.status.clone(),
+                    depends_on: job.depends_on.clone(),
+                    error: job.error.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    pub async fn get_job_status(&self, job_id: &str) -> Option<ProcessingJob> {
+        let jobs = self.jobs.read().await;
+        jobs.get(job_id).cloned()
+    }
+
+    /// The processed records of a completed job, while they are still retained.
+    pub async fn get_job_results(&self, job_id: &str) -> Result<Arc<Vec<DataRecord>>, String> {
+        let status = self.jobs.read().await.get(job_id).map(|job| job.status.clone()).ok_or("Job not found")?;
+        if !matches!(status, JobStatus::Completed) {
+            return Err(format!("Job {} has not completed", job_id));
+        }
+        self.job_results.read().await.get(job_id)
+            .ok_or_else(|| format!("Results for job {} are no longer retained", job_id))
+    }
+
+    /// Writes a completed job's retained results to a one-off file and returns it with a
+    /// signed download token that is valid until the export expires.
+    pub async fn create_export(&self, job_id: &str, request: ExportRequest) -> Result<(ResultExport, String), String> {
+        let extension = request.format.file_extension().ok_or("Exports must use a file format")?;
+        let expires_in = match request.expires_in.as_deref() {
+            Some(expires_in) => parse_duration(expires_in).ok_or("Invalid expiry duration")?,
+            None => DEFAULT_EXPORT_EXPIRY,
+        };
+        let expires_at = Utc::now() + chrono::Duration::from_std(expires_in).map_err(|e| e.to_string())?;
+        let records = self.get_job_results(job_id).await?;
+        let job = self.get_job_status(job_id).await.ok_or("Job not found")?;
+        self.remove_expired_exports().await;
+
+        let id = Uuid::new_v4().to_string();
+        let dir = self.work_dir.read().await.root().join("exports").join(&id);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let file_name = format!("{}.{}", job_id, request.output_compression.extension(extension));
+        let path = dir.join(&file_name);
+        let sink = OutputSpec {
+            name: None,
+            format: request.format,
+            output_path: Some(file_name.clone()),
+            partition_by: None,
+            output_compression: request.output_compression,
+            output_encryption: None,
+            output_columns: request.output_columns,
+        };
+        // Written on its own task: the writer future is too deep to poll inside the request's
+        let (credentials, egress_policy) = (self.credentials.clone(), self.egress_policy.clone());
+        let (staging_dir, spec, data) = (dir.clone(), sink.clone(), records.clone());
+        let written = tokio::spawn(async move {
+            Self::write_output(&job, &spec, &data, "", &credentials, &egress_policy, &staging_dir, &staging_dir).await
+        }).await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            .and_then(|failures| match failures.first() {
+                Some(failure) => Err(failure.message.clone()),
+                None => std::fs::metadata(&path).map_err(|e| e.to_string()),
+            });
+        let size_bytes = match written {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(format!("Export failed: {}", e));
+            },
+        };
+
+        let password_hash = request.password.map(|password| {
+            let salt: [u8; 16] = rand::random();
+            let mut mac = Hmac::<Sha256>::new_from_slice(&salt).expect("HMAC accepts any key length");
+            mac.update(password.as_bytes());
+            (salt, mac.finalize().into_bytes().to_vec())
+        });
+        let export = ResultExport {
+            id: id.clone(),
+            job_id: job_id.to_string(),
+            file_name,
+            content_type: export_content_type(&sink.format, sink.output_compression),
+            records: records.len(),
+            size_bytes,
+            password_protected: password_hash.is_some(),
+            created_at: Utc::now(),
+            expires_at,
+            path,
+            password_hash,
+        };
+        // Scoped so a saved query share token never doubles as an export token
+        let signature = self.sign_share(&format!("export/{}", id), expires_at.timestamp());
+        let token = format!("{}.{}.{}", id, expires_at.timestamp(), signature);
+        self.exports.write().await.insert(id, export.clone());
+        Ok((export, token))
+    }
+
+    /// Resolves a download token to its export, checking the password when the export has one.
+    pub async fn open_export(&self, token: &str, password: Option<&str>) -> Result<ResultExport, String> {
+        let mut parts = token.splitn(3, '.');
+        let (Some(export_id), Some(expires_at), Some(signature)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err("Invalid export token".to_string());
+        };
+        let expires_at: i64 = expires_at.parse().map_err(|_| "Invalid export token")?;
+        let signature = hex::decode(signature).map_err(|_| "Invalid export token")?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.share_secret).expect("HMAC accepts any key length");
+        mac.update(format!("export/{}.{}", export_id, expires_at).as_bytes());
+        mac.verify_slice(&signature).map_err(|_| "Invalid export token")?;
+
+        if Utc::now().timestamp() > expires_at {
+            return Err("Export expired".to_string());
+        }
+        self.remove_expired_exports().await;
+        let export = self.exports.read().await.get(export_id).cloned().ok_or("Export not found")?;
+
+        if let Some((salt, hash)) = &export.password_hash {
+            let password = password.ok_or("Export password required")?;
+            let mut mac = Hmac::<Sha256>::new_from_slice(salt).expect("HMAC accepts any key length");
+            mac.update(password.as_bytes());
+            mac.verify_slice(hash).map_err(|_| "Invalid export password")?;
+        }
+        Ok(export)
+    }
+
+    /// Drops expired exports and deletes their files.
+    async fn remove_expired_exports(&self) {
+        let now = Utc::now();
+        let expired: Vec<ResultExport> = {
+            let mut exports = self.exports.write().await;
+            let ids: Vec<String> = exports.values().filter(|export| export.expires_at < now).map(|export| export.id.clone()).collect();
+            ids.iter().filter_map(|id| exports.remove(id)).collect()
+        };
+        for export in expired {
+            export.remove_files();
+        }
+    }
+
+    /// Waits until the job's status changes or the timeout elapses, returning its latest state.
+    pub async fn wait_for_job_change(&self, job_id: &str, timeout: Duration) -> Option<ProcessingJob> {
+        // Subscribe before reading so no transition is missed in between
+        let mut updates = self.job_updates.subscribe();
+        let initial_status = self.get_job_status(job_id).await?.status;
+
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                update = updates.recv() => match update {
+                    Ok(job) if job.id == job_id && job.status != initial_status => return Some(job),
+                    Ok(_) => {},
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        let job = self.get_job_status(job_id).await?;
+                        if job.status != initial_status {
+                            return Some(job);
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+
+        self.get_job_status(job_id).await
+    }
+
+    pub async fn list_jobs(&self, tags: &TagFilter) -> Vec<ProcessingJob> {
+        let jobs = self.jobs.read().await;
+        jobs.values().filter(|job| tags.matches(job)).cloned().collect()
+    }
+
+    /// Builds a new job from an existing one: its name, configuration, priority and tags, with
+    /// `overrides` applied. The copy does not depend on anything unless the overrides say so.
+    pub async fn rerun_request(&self, job_id: &str, overrides: JobRerunOverrides) -> Result<ProcessingJob, String> {
+        let original = self.jobs.read().await.get(job_id).cloned().ok_or_else(|| "Job not found".to_string())?;
+        let configuration = match &overrides.configuration {
+            Some(patch) => {
+                let mut configuration = serde_json::to_value(&original.configuration).map_err(|e| e.to_string())?;
+                merge_patch(&mut configuration, patch);
+                serde_json::from_value(configuration).map_err(|e| format!("Invalid configuration override: {}", e))?
+            },
+            None => original.configuration.clone(),
+        };
+        Ok(ProcessingJob {
+            id: String::new(),
+            name: overrides.name.unwrap_or_else(|| original.name.clone()),
+            status: JobStatus::Pending,
+            created_at: Utc::now(),
+            started_at: None,
+            completed_at: None,
+            input_count: 0,
+            processed_count: 0,
+            error_count: 0,
+            configuration,
+            results: Vec::new(),
+            comments: Vec::new(),
+            estimated_memory_bytes: None,
+            estimated_disk_bytes: None,
+            error: None,
+            schedule_id: None,
+            input_versions: BTreeMap::new(),
+            engine_version: None,
+            warnings: Vec::new(),
+            attempts: Vec::new(),
+            retry_at: None,
+            priority: overrides.priority.unwrap_or(original.priority),
+            tags: overrides.tags.unwrap_or_else(|| original.tags.clone()),
+            callbacks: original.callbacks.clone(),
+            tenant: original.tenant.clone(),
+            imported_at: None,
+            rerun_of: Some(original.id),
+            depends_on: overrides.depends_on.unwrap_or_default(),
+            progress: None,
+            dead_lettered_at: None,
+            trace: None,
+        })
+    }
+
+    /// Job totals over the jobs `tags` selects, overall and per value of tag `group_by`.
+    pub async fn job_counts(&self, tags: &TagFilter, group_by: Option<&str>) -> (JobCounts, Option<Vec<TagGroup<JobCounts>>>) {
+        let jobs = self.jobs.read().await;
+        let selected: Vec<&ProcessingJob> = jobs.values().filter(|job| tags.matches(job)).collect();
+        let groups = group_by.map(|name| {
+            group_by_tag(selected.iter().copied(), name).into_iter()
+                .map(|(value, jobs)| TagGroup { value, content: JobCounts::of(jobs) })
+                .collect()
+        });
+        (JobCounts::of(selected), groups)
+    }
+
+    /// Deletes the finished jobs `purge` picks, with their retained results, and returns their
+    /// ids. Jobs an unfinished job still depends on stay.
+    pub async fn purge_jobs(&self, purge: impl Fn(&ProcessingJob) -> bool) -> Vec<String> {
+        let mut jobs = self.jobs.write().await;
+        let needed: HashSet<&String> = jobs.values()
+            .filter(|job| !job.status.is_finished())
+            .flat_map(|job| &job.depends_on)
+            .collect();
+        let purged: Vec<String> = jobs.values()
+            .filter(|job| job.status.is_finished() && !needed.contains(&job.id) && purge(job))
+            .map(|job| job.id.clone())
+            .collect();
+        let mut job_results = self.job_results.write().await;
+        for job_id in &purged {
+            jobs.remove(job_id);
+            job_results.remove(job_id);
+            self.job_logs.remove(job_id);
+        }
+        purged
+    }
+
+    /// Archives finished jobs with whatever of their results are still retained: the jobs in
+    /// `ids`, every one of which must exist and be finished, or all finished jobs when it is
+    /// empty, narrowed by `tags` either way.
+    pub async fn export_jobs(&self, ids: &[String], tags: &TagFilter) -> Result<JobArchive, String> {
+        let jobs = self.jobs.read().await;
+        let mut selected: Vec<&ProcessingJob> = match ids.is_empty() {
+            true => jobs.values().filter(|job| job.status.is_finished()).collect(),
+            false => ids.iter()
+                .map(|id| match jobs.get(id) {
+                    Some(job) if job.status.is_finished() => Ok(job),
+                    Some(_) => Err(format!("Job {} has not finished", id)),
+                    None => Err(format!("Job {} not found", id)),
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        selected.retain(|job| tags.matches(job));
+        selected.sort_by_key(|job| job.created_at);
+
+        let job_results = self.job_results.read().await;
+        let jobs = selected.into_iter()
+            .map(|job| ArchivedJob {
+                job: job.clone(),
+                results: job_results.get(&job.id).map(|records| records.as_ref().clone()),
+            })
+            .collect();
+        Ok(JobArchive {
+            archive_version: JOB_ARCHIVE_VERSION,
+            engine_version: ENGINE_VERSION,
+            exported_at: Utc::now(),
+            jobs,
+        })
+    }
+
+    /// Adds an archive's jobs, and the results it carries, as they are: nothing is run again
+    /// and no webhooks fire. Jobs whose id is already taken are skipped, so importing the same
+    /// archive twice is harmless.
+    pub async fn import_jobs(&self, archive: JobArchive) -> Result<JobImport, String> {
+        if let Some(archived) = archive.jobs.iter().find(|archived| !archived.job.status.is_finished()) {
+            return Err(format!("Job {} has not finished; only finished jobs can be imported", archived.job.id));
+        }
+        let now = Utc::now();
+        let mut import = JobImport { imported: Vec::new(), skipped: Vec::new() };
+        let mut jobs = self.jobs.write().await;
+        let mut job_results = self.job_results.write().await;
+        for ArchivedJob { mut job, results } in archive.jobs {
+            if jobs.contains_key(&job.id) {
+                import.skipped.push(job.id);
+                continue;
+            }
+            job.imported_at = Some(now);
+            job.progress = None;
+            if let Some(results) = results {
+                job_results.insert(job.id.clone(), results);
+            }
+            import.imported.push(job.id.clone());
+            jobs.insert(job.id.clone(), job);
+        }
+        Ok(import)
+    }
+
+    /// Applies the retention policy once.
+    async fn purge_expired_jobs(&self) {
+        let JobRetention { max_jobs, max_age } = self.job_retention.clone();
+        let cutoff = max_age.map(|age| Utc::now() - age);
+        // Imported jobs are kept as if they had finished when they arrived
+        let finished_at = |job: &ProcessingJob| job.completed_at.max(job.imported_at);
+        // Finished jobs past the newest `max_jobs`, in completion order
+        let overflow: HashSet<String> = match max_jobs {
+            Some(max_jobs) => {
+                let jobs = self.jobs.read().await;
+                let mut finished: Vec<&ProcessingJob> = jobs.values().filter(|job| job.status.is_finished()).collect();
+                finished.sort_by_key(|job| std::cmp::Reverse(finished_at(job)));
+                finished.into_iter().skip(max_jobs).map(|job| job.id.clone()).collect()
+            },
+            None => HashSet::new(),
+        };
+        // Dead letters stay until someone re-drives or deletes them
+        let purged = self.purge_jobs(|job| {
+            job.dead_lettered_at.is_none()
+                && (overflow.contains(&job.id) || cutoff.is_some_and(|cutoff| finished_at(job).is_some_and(|at| at < cutoff)))
+        }).await;
+        if !purged.is_empty() {
+            println!("Deleted {} finished jobs past retention", purged.len());
+        }
+    }
+
+    pub fn start_job_cleanup(self: &Arc<Self>) {
+        let processor = self.clone();
+        self.supervisor.supervise("job_cleanup", move || {
+            let processor = processor.clone();
+            async move {
+                let mut interval = tokio::time::interval(JOB_CLEANUP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    processor.purge_expired_jobs().await;
+                    processor.purge_expired_versions().await;
+                }
+            }
+        });
+    }
+
+    pub async fn add_job_comment(&self, job_id: &str, mut comment: JobComment) -> Result<JobComment, String> {
+        if comment.text.trim().is_empty() {
+            return Err("Comment text is required".to_string());
+        }
+        comment.id = Uuid::new_v4().to_string();
+        comment.created_at = Utc::now();
+
+        let mut jobs = self.jobs.write().await;
+        let job = jobs.get_mut(job_id).ok_or("Job not found")?;
+        job.comments.push(comment.clone());
+        let _ = self.job_updates.send(job.clone());
+        println!("Comment added to job {} by {}", job_id, comment.author);
+        Ok(comment)
+    }
+
+    pub async fn get_job_logs(&self, job_id: &str, query: &JobLogQuery) -> Option<Vec<JobLogLine>> {
+        self.jobs.read().await.get(job_id)?;
+        Some(self.job_logs.lines(job_id, query.level.unwrap_or(LogLevel::Info), query.since, query.tail))
+    }
+
+    pub async fn get_job_comments(&self, job_id: &str) -> Option<Vec<JobComment>> {
+        let jobs = self.jobs.read().await;
+        jobs.get(job_id).map(|job| job.comments.clone())
+    }
+
+    /// Cancels a pending job before it starts, or stops a running one at its next
+    /// cancellation point.
+    pub async fn cancel_job(&self, job_id: &str) -> Result<(), String> {
+        let mut jobs = self.jobs.write().await;
+        if let Some(job) = jobs.get_mut(job_id) {
+            if matches!(job.status, JobStatus::Waiting | JobStatus::Pending | JobStatus::Running) {
+                if let Some(cancel) = self.cancellations.read().await.get(job_id) {
+                    cancel.cancel();
+                }
+                let running = matches!(job.status, JobStatus::Running);
+                job.status = JobStatus::Cancelled;
+                let _ = self.job_updates.send(job.clone());
+                self.job_logs.info(job_id, format!("Job cancelled: {}", job_id));
+                // A running job releases its dependents when the processor stops it
+                if !running {
+                    let failed = Self::release_dependents(&mut jobs, job_id, &self.job_queue, &self.job_updates, &self.job_logs);
+                    let mut cancellations = self.cancellations.write().await;
+                    for job_id in failed {
+                        cancellations.remove(&job_id);
+                    }
+                }
+                Ok(())
+            } else {
+                Err("Job cannot be cancelled in current status".to_string())
+            }
+        } else {
+            Err("Job not found".to_string())
+        }
+    }
+
+    /// Deletes a job and its retained results and logs, and with `artifacts` its result exports too,
+    /// returning how many exports went. A job that has not finished is only deleted with
+    /// `force`, which cancels it first; if it is running it stops in the background.
+    pub async fn delete_job(&self, job_id: &str, force: bool, artifacts: bool) -> Result<usize, String> {
+        let status = self.jobs.read().await.get(job_id).map(|job| job.status.clone()).ok_or("Job not found")?;
+        if !status.is_finished() {
+            if !force {
+                return Err(format!("Job is {:?}; cancel it first or delete it with force=true", status));
+            }
+            // It may have finished in the meantime, which is just as good
+            if let Err(error) = self.cancel_job(job_id).await {
+                let finished = self.jobs.read().await.get(job_id).is_none_or(|job| job.status.is_finished());
+                if !finished {
+                    return Err(error);
+                }
+            }
+        }
+
+        self.jobs.write().await.remove(job_id);
+        self.job_results.write().await.remove(job_id);
+        self.job_logs.remove(job_id);
+        let removed: Vec<ResultExport> = match artifacts {
+            true => {
+                let mut exports = self.exports.write().await;
+                let ids: Vec<String> = exports.values().filter(|export| export.job_id == job_id).map(|export| export.id.clone()).collect();
+                ids.iter().filter_map(|id| exports.remove(id)).collect()
+            },
+            false => Vec::new(),
+        };
+        for export in &removed {
+            export.remove_files();
+        }
+        println!("Job deleted: {}", job_id);
+        Ok(removed.len())
+    }
+
+    /// The dead-letter queue, oldest first.
+    pub async fn list_dead_letters(&self) -> Vec<DeadLetteredJob> {
+        let jobs = self.jobs.read().await;
+        let mut dead_letters: Vec<DeadLetteredJob> = jobs.values()
+            .filter_map(|job| Some(DeadLetteredJob {
+                job_id: job.id.clone(),
+                name: job.name.clone(),
+                schedule_id: job.schedule_id.clone(),
+                dead_lettered_at: job.dead_lettered_at?,
+                error: job.error.clone(),
+                attempts: job.attempts.clone(),
+            }))
+            .collect();
+        dead_letters.sort_by(|a, b| a.dead_lettered_at.cmp(&b.dead_lettered_at).then_with(|| a.job_id.cmp(&b.job_id)));
+        dead_letters
+    }
+
+    pub async fn get_dead_letter(&self, job_id: &str) -> Option<ProcessingJob> {
+        self.jobs.read().await.get(job_id).filter(|job| job.dead_lettered_at.is_some()).cloned()
+    }
+
+    /// Queues dead-lettered jobs to run again under their own ids, keeping their attempt
+    /// history. Their retries were used up, so a re-driven job that fails goes straight back to
+    /// the queue. Returns the jobs re-driven before the job queue filled up.
+    pub async fn redrive_dead_letters(&self, job_ids: &[String]) -> Result<Vec<String>, String> {
+        let mut jobs = self.jobs.write().await;
+        if let Some(missing) = job_ids.iter().find(|id| jobs.get(*id).is_none_or(|job| job.dead_lettered_at.is_none())) {
+            return Err(format!("Dead-lettered job {} not found", missing));
+        }
+        let mut redriven = Vec::new();
+        for job_id in job_ids {
+            let Some(job) = jobs.get_mut(job_id) else { continue };
+            let mut rerun = job.clone();
+            rerun.status = JobStatus::Pending;
+            rerun.completed_at = None;
+            rerun.dead_lettered_at = None;
+            rerun.error = None;
+            self.cancellations.write().await.insert(job_id.clone(), CancellationToken::new());
+            if self.job_queue.try_push(rerun.clone()).is_err() {
+                self.cancellations.write().await.remove(job_id);
+                break;
+            }
+            *job = rerun;
+            let _ = self.job_updates.send(job.clone());
+            self.job_logs.info(job_id, format!("Job re-driven: {}", job_id));
+            redriven.push(job_id.clone());
+        }
+        match redriven.is_empty() && !job_ids.is_empty() {
+            true => Err(QUEUE_FULL.to_string()),
+            false => Ok(redriven),
+        }
+    }
+
+    /// Replaces a source's records, coerced to the schema inferred from them, returning the
+    /// new source version.
+    async fn store_source(&self, source_id: &str, mut records: Vec<DataRecord>) -> u64 {
+        self.localize_records(source_id, &mut records).await;
+        self.enforce_schema(source_id, &mut records, true).await;
+        let schema = schema::infer(&records);
+        schema::coerce_records(&mut records, &schema);
+        let pinned = self.pinned_versions().await.remove(source_id).unwrap_or_default();
+        // Triggers are armed under the store lock so a run cannot start before the records land
+        let mut data_store = self.data_store.write().await;
+        self.notify_source_load(source_id, &records).await;
+        self.retain_current(&mut data_store, source_id, &pinned);
+        data_store.insert(source_id.to_string(), StoredSource::new(records, self.dictionary_max_values));
+        self.source_schemas.write().await.insert(source_id.to_string(), schema);
+        let version = self.bump_source_version(&mut data_store, source_id).await;
+        self.prune_versions(&mut data_store, source_id, &pinned);
+        version
+    }
+
+    /// Versions of each source that unfinished jobs are pinned to.
+    async fn pinned_versions(&self) -> HashMap<String, HashSet<u64>> {
+        let mut pinned: HashMap<String, HashSet<u64>> = HashMap::new();
+        for job in self.jobs.read().await.values().filter(|job| !job.status.is_finished()) {
+            for (source_id, version) in &job.configuration.pinned_versions {
+                pinned.entry(source_id.clone()).or_default().insert(*version);
+            }
+        }
+        pinned
+    }
+
+    /// Retains a source's records before they are replaced, unless the retention policy keeps
+    /// no versions and no unfinished job is pinned to them.
+    fn retain_current(&self, data_store: &mut DataStore, source_id: &str, pinned: &HashSet<u64>) {
+        let current = data_store.get(source_id).map(StoredSource::version);
+        if self.version_retention.max_versions != Some(0) || current.is_some_and(|version| pinned.contains(&version)) {
+            data_store.retain_current(source_id);
+        }
+    }
+
+    /// Deletes a source's versions past the retention policy, other than `pinned` ones.
+    fn prune_versions(&self, data_store: &mut DataStore, source_id: &str, pinned: &HashSet<u64>) {
+        let VersionRetention { max_versions, max_age } = self.version_retention.clone();
+        let cutoff = max_age.map(|age| Utc::now() - age);
+        let retained = data_store.versions(source_id);
+        let overflow = max_versions.map_or(0, |max_versions| retained.len().saturating_sub(max_versions));
+        let expired: Vec<u64> = retained.iter().enumerate()
+            .filter(|(index, (version, source))| {
+                !pinned.contains(version)
+                    && (*index < overflow || cutoff.is_some_and(|cutoff| source.loaded_at.is_some_and(|at| at < cutoff)))
+            })
+            .map(|(_, (version, _))| *version)
+            .collect();
+        for version in &expired {
+            data_store.remove_version(source_id, *version);
+        }
+        if !expired.is_empty() {
+            println!("Deleted {} versions of source {} past retention", expired.len(), source_id);
+        }
+    }
+
+    /// Applies the version retention policy's age limit to every source.
+    async fn purge_expired_versions(&self) {
+        if self.version_retention.max_age.is_none() {
+            return;
+        }
+        let pinned = self.pinned_versions().await;
+        let mut data_store = self.data_store.write().await;
+        let source_ids: Vec<String> = data_store.iter().map(|(source_id, _)| source_id.clone()).collect();
+        for source_id in source_ids {
+            self.prune_versions(&mut data_store, &source_id, pinned.get(&source_id).unwrap_or(&HashSet::new()));
+        }
+    }
+
+    /// The retained versions of a source, oldest first, then its current version.
+    pub async fn list_source_versions(&self, source_id: &str) -> Option<Vec<SourceVersion>> {
+        let data_store = self.data_store.read().await;
+        let current = data_store.get(source_id)?;
+        let describe = |version: u64, source: &StoredSource, current: bool| SourceVersion {
+            version,
+            record_count: source.len(),
+            loaded_at: source.loaded_at,
+            current,
+            spilled: source.is_spilled(),
+        };
+        let mut versions: Vec<SourceVersion> = data_store.versions(source_id).into_iter()
+            .map(|(version, source)| describe(version, source, false))
+            .collect();
+        versions.push(describe(current.version(), current, true));
+        Some(versions)
+    }
+
+    /// Retains a copy of a source's current records, returning their version.
+    pub async fn snapshot_source(&self, source_id: &str) -> Result<u64, String> {
+        let pinned = self.pinned_versions().await.remove(source_id).unwrap_or_default();
+        let mut data_store = self.data_store.write().await;
+        let version = data_store.snapshot(source_id, self.dictionary_max_values)?;
+        self.prune_versions(&mut data_store, source_id, &pinned);
+        println!("Retained version {} of source {}", version, source_id);
+        Ok(version)
+    }
+
+    pub async fn delete_source_version(&self, source_id: &str, version: u64) -> Result<(), String> {
+        if self.pinned_versions().await.get(source_id).is_some_and(|pinned| pinned.contains(&version)) {
+            return Err(format!("Version {} of source {} is pinned by an unfinished job", version, source_id));
+        }
+        match self.data_store.write().await.remove_version(source_id, version) {
+            Some(_) => Ok(()),
+            None => Err(format!("Version {} of source {} is not retained", version, source_id)),
+        }
+    }
+
+    /// Makes a retained version's records the source's records again, as a new version. The
+    /// records they replace are retained in turn.
+    pub async fn rollback_source(&self, source_id: &str, version: u64) -> Result<u64, String> {
+        let pinned = self.pinned_versions().await.remove(source_id).unwrap_or_default();
+        let mut data_store = self.data_store.write().await;
+        let current = data_store.get(source_id).ok_or("Source not found")?.version();
+        if current == version {
+            return Err(format!("Source {} is at version {} already", source_id, version));
+        }
+        let key = data_store.version_of(source_id, Some(version))?;
+        let records = data_store.get_mut(&key)?.ok_or("Source not found")?.records().into_owned();
+
+        self.notify_source_load(source_id, &records).await;
+        let schema = schema::infer(&records);
+        self.retain_current(&mut data_store, source_id, &pinned);
+        data_store.insert(source_id.to_string(), StoredSource::new(records, self.dictionary_max_values));
+        self.source_schemas.write().await.insert(source_id.to_string(), schema);
+        let rolled_back = self.bump_source_version(&mut data_store, source_id).await;
+        self.prune_versions(&mut data_store, source_id, &pinned);
+        println!("Rolled source {} back to version {} (version {})", source_id, version, rolled_back);
+        Ok(rolled_back)
+    }
+
+    /// How a source's records changed between two versions, `to` defaulting to the current
+    /// one. Records are matched by the value of `key_field`, or else by their whole data,
+    /// since every load gives records new ids.
+    pub async fn diff_source_versions(&self, source_id: &str, query: &VersionDiffQuery) -> Result<VersionDiff, String> {
+        let data_store = DataStore::read_loaded(&self.data_store, |store| {
+            [Some(query.from), query.to].iter().filter_map(|version| store.version_of(source_id, *version).ok()).collect()
+        }).await?;
+        let to = match query.to {
+            Some(to) => to,
+            None => data_store.get(source_id).ok_or("Source not found")?.version(),
+        };
+        let before = data_store.get_at(source_id, Some(query.from))?.ok_or("Source not found")?.records();
+        let after = data_store.get_at(source_id, Some(to))?.ok_or("Source not found")?.records();
+
+        let mut diff = VersionDiff {
+            source_id: source_id.to_string(),
+            from: query.from,
+            to,
+            key_field: query.key_field.clone(),
+            ..VersionDiff::default()
+        };
+        match &query.key_field {
+            Some(field) => {
+                let keyed = |records: &[DataRecord]| -> HashMap<String, Value> {
+                    records.iter()
+                        .filter_map(|record| Some((record.data.get(field)?.to_string(), record.data.clone())))
+                        .collect()
+                };
+                let (before, mut after) = (keyed(&before), keyed(&after));
+                for (key, data) in before {
+                    match after.remove(&key) {
+                        Some(now) if now == data => diff.unchanged += 1,
+                        Some(now) => {
+                            diff.changed += 1;
+                            if diff.changed_records.len() < MAX_DIFF_SAMPLES {
+                                diff.changed_records.push(RecordChange { before: data, after: now });
+                            }
+                        },
+                        None => diff.push_removed(data),
+                    }
+                }
+                after.into_values().for_each(|data| diff.push_added(data));
+            },
+            None => {
+                let mut remaining: HashMap<String, usize> = HashMap::new();
+                for record in before.iter() {
+                    *remaining.entry(record.data.to_string()).or_default() += 1;
+                }
+                for record in after.iter() {
+                    match remaining.get_mut(&record.data.to_string()).filter(|count| **count > 0) {
+                        Some(count) => {
+                            *count -= 1;
+                            diff.unchanged += 1;
+                        },
+                        None => diff.push_added(record.data.clone()),
+                    }
+                }
+                for record in before.iter() {
+                    if let Some(count) = remaining.get_mut(&record.data.to_string()).filter(|count| **count > 0) {
+                        *count -= 1;
+                        diff.push_removed(record.data.clone());
+                    }
+                }
+            },
+        }
+        Ok(diff)
+    }
+
+    pub async fn list_indexes(&self, source_id: &str) -> Option<Vec<index::IndexInfo>> {
+        self.data_store.read().await.get(source_id).map(StoredSource::index_infos)
+    }
+
+    /// Builds an index on one of a source's fields, used from then on by Filter comparisons
+    /// against literals, Joins on the field and record edits keyed by it.
+    pub async fn create_index(&self, source_id: &str, spec: index::IndexSpec) -> Result<index::IndexInfo, String> {
+        let start = Instant::now();
+        let info = self.data_store.write().await.create_index(source_id, spec)?;
+        println!(
+            "Built {:?} index on {} of source {} ({} distinct values) in {:?}",
+            info.kind, info.field, source_id, info.distinct_values, start.elapsed(),
+        );
+        Ok(info)
+    }
+
+    pub async fn drop_index(&self, source_id: &str, field: &str) -> Result<(), String> {
+        match self.data_store.write().await.drop_index(source_id, field) {
+            true => Ok(()),
+            false => Err(format!("Source {} has no index on {}", source_id, field)),
+        }
+    }
+
+    /// Drops the records that do not conform to the source's expected schema, if it has one,
+    /// returning why each record given was refused.
+    async fn enforce_schema(&self, source_id: &str, records: &mut Vec<DataRecord>, replace: bool) -> Vec<Option<String>> {
+        let mut registrations = self.source_registrations.write().await;
+        let Some(registration) = registrations.get_mut(source_id) else {
+            return vec![None; records.len()];
+        };
+        let refusals = registration.enforce(records, replace);
+        let refused = refusals.iter().filter(|refusal| refusal.is_some()).count();
+        if refused > 0 {
+            println!("Warning: {} records for source {} do not conform to its schema", refused, source_id);
+        }
+        refusals
+    }
+
+    pub async fn set_expected_schema(&self, source_id: &str, expected: Option<schema::ExpectedSchema>) -> Result<(), String> {
+        let mut registrations = self.source_registrations.write().await;
+        match expected {
+            Some(expected) => {
+                expected.validate()?;
+                registrations.insert(source_id.to_string(), schema::Registration::new(expected));
+            },
+            None => {
+                registrations.remove(source_id);
+            },
+        }
+        Ok(())
+    }
+
+    pub async fn get_expected_schema(&self, source_id: &str) -> Option<schema::ExpectedSchema> {
+        self.source_registrations.read().await.get(source_id).map(|registration| registration.expected.clone())
+    }
+
+    /// Records quarantined for not conforming to the source's schema, oldest first.
+    pub async fn get_quarantine(&self, source_id: &str) -> Option<Vec<schema::QuarantinedRecord>> {
+        self.source_registrations.read().await.get(source_id).map(|registration| registration.quarantine.iter().cloned().collect())
+    }
+
+    /// Discards a source's quarantined records, returning how many there were.
+    pub async fn clear_quarantine(&self, source_id: &str) -> Option<usize> {
+        let mut registrations = self.source_registrations.write().await;
+        registrations.get_mut(source_id).map(|registration| registration.quarantine.drain(..).count())
+    }
+
+    /// Normalizes `records` with the source's locale, if it has one.
+    async fn localize_records(&self, source_id: &str, records: &mut [DataRecord]) {
+        if let Some(locale) = self.source_locales.read().await.get(source_id) {
+            records.par_iter_mut().for_each(|record| locale.normalize(&mut record.data));
+        }
+    }
+
+    pub async fn set_source_locale(&self, source_id: &str, locale: Option<Locale>) -> Result<(), String> {
+        let mut locales = self.source_locales.write().await;
+        match locale {
+            Some(locale) => {
+                locale.validate()?;
+                locales.insert(source_id.to_string(), locale);
+            },
+            None => {
+                locales.remove(source_id);
+            },
+        }
+        Ok(())
+    }
+
+    pub async fn get_source_locale(&self, source_id: &str) -> Option<Locale> {
+        self.source_locales.read().await.get(source_id).cloned()
+    }
+
+    /// Arms the source-triggered schedules watching `source_id` for a load of `records`.
+    async fn notify_source_load(&self, source_id: &str, records: &[DataRecord]) {
+        if records.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        let mut schedules = self.schedules.write().await;
+        for schedule in schedules.values_mut().filter(|s| s.enabled) {
+            let armed = schedule.next_run_at.is_some();
+            let fire = match &schedule.trigger {
+                // A run already armed picks up these records as well
+                ScheduleTrigger::SourceLoad { source_id: watched, min_records } if watched == source_id && !armed => {
+                    schedule.pending_records += records.len();
+                    let fire = schedule.pending_records >= *min_records;
+                    if fire {
+                        schedule.pending_records = 0;
+                    }
+                    fire
+                },
+                ScheduleTrigger::SourceSet { source_ids, mode, date_field } if source_ids.iter().any(|id| id == source_id) => {
+                    let dates = schedule.pending_dates.entry(source_id.to_string()).or_default();
+                    dates.extend(records.iter().filter_map(|record| Self::logical_date(record, date_field.as_deref())));
+                    while dates.len() > MAX_PENDING_DATES {
+                        dates.pop_first();
+                    }
+
+                    match mode {
+                        SourceSetMode::AnyOf => {
+                            let latest = schedule.pending_dates.remove(source_id).and_then(|dates| dates.last().copied());
+                            schedule.last_logical_date = latest.or(schedule.last_logical_date);
+                            !armed
+                        },
+                        SourceSetMode::AllOf => {
+                            let complete: Vec<NaiveDate> = schedule.pending_dates[source_id].iter()
+                                .copied()
+                                .filter(|date| source_ids.iter().all(|id| {
+                                    schedule.pending_dates.get(id).is_some_and(|dates| dates.contains(date))
+                                }))
+                                .collect();
+                            for dates in schedule.pending_dates.values_mut() {
+                                dates.retain(|date| !complete.contains(date));
+                            }
+                            match complete.last() {
+                                Some(date) => {
+                                    schedule.last_logical_date = Some(*date);
+                                    !armed
+                                },
+                                None => false,
+                            }
+                        },
+                    }
+                },
+                _ => false,
+            };
+
+            if fire {
+                schedule.next_run_at = Some(now);
+                schedule.fire_at = schedule.jittered(Some(now));
+                println!("Schedule {} triggered by load into source {}", schedule.name, source_id);
+            }
+        }
+    }
+
+    /// Logical date of a record: the date prefix of `date_field`, or the day it was loaded.
+    fn logical_date(record: &DataRecord, date_field: Option<&str>) -> Option<NaiveDate> {
+        match date_field {
+            Some(field) => {
+                let value = record.data.get(field)?.as_str()?;
+                NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok()
+            },
+            None => Some(record.timestamp.date_naive()),
+        }
+    }
+
+    async fn bump_source_version(&self, data_store: &mut DataStore, source_id: &str) -> u64 {
+        let mut versions = self.source_versions.write().await;
+        let version = versions.entry(source_id.to_string()).or_insert(0);
+        *version += 1;
+        data_store.set_version(source_id, *version);
+        *version
+    }
+
+    /// Appends pushed records to a source (creating it if needed), returning the new source version.
+    pub async fn append_records(&self, source_id: &str, items: Vec<Result<Value, String>>) -> RecordWriteResponse {
+        let mut results = Vec::with_capacity(items.len());
+        let mut records = Vec::new();
+
+        for (index, item) in items.into_iter().enumerate() {
+            match item {
+                Ok(data @ Value::Object(_)) => {
+                    let record = DataRecord {
+                        id: Uuid::new_v4().to_string(),
+                        timestamp: Utc::now(),
+                        data,
+                        source: source_id.to_string(),
+                        processed: false,
+                        metadata: HashMap::new(),
+                    };
+                    results.push(RecordWriteResult {
+                        index,
+                        accepted: true,
+                        record_id: Some(record.id.clone()),
+                        error: None,
+                    });
+                    records.push(record);
+                },
+                Ok(_) => results.push(RecordWriteResult {
+                    index,
+                    accepted: false,
+                    record_id: None,
+                    error: Some("Record must be a JSON object".to_string()),
+                }),
+                Err(error) => results.push(RecordWriteResult {
+                    index,
+                    accepted: false,
+                    record_id: None,
+                    error: Some(error),
+                }),
+            }
+        }
+
+        self.localize_records(source_id, &mut records).await;
+        let refusals = self.enforce_schema(source_id, &mut records, false).await;
+        for (result, refusal) in results.iter_mut().filter(|result| result.accepted).zip(refusals) {
+            if let Some(refusal) = refusal {
+                result.accepted = false;
+                result.record_id = None;
+                result.error = Some(refusal);
+            }
+        }
+        let mut accepted = records.len();
+        let appended = schema::infer(&records);
+
+        // Hold the store lock across the version bump so concurrent writers see ordered versions
+        let mut data_store = self.data_store.write().await;
+        let source = match accepted {
+            0 => Ok(None),
+            _ => data_store.get_or_create(source_id).map(Some),
+        };
+        let version = match source {
+            Ok(Some(source)) => {
+                let below_threshold = source.len() < MIN_DICTIONARY_RECORDS;
+                let mut schemas = self.source_schemas.write().await;
+                // A source restored from storage has no schema until `get_source` samples one
+                let schema = match schemas.get(source_id) {
+                    Some(existing) if !source.is_empty() => schema::merge(existing, &appended),
+                    _ => appended,
+                };
+                schema::coerce_records(&mut records, &schema);
+                if schemas.contains_key(source_id) || source.is_empty() {
+                    schemas.insert(source_id.to_string(), schema);
+                }
+                drop(schemas);
+                self.notify_source_load(source_id, &records).await;
+                source.append(records);
+                // A source that grows past the threshold through appends gets its dictionary then
+                if let Some(max_values) = self.dictionary_max_values.filter(|_| below_threshold) {
+                    source.encode(max_values);
+                }
+                data_store.persist_appended(source_id, accepted);
+                data_store.fit_budget(&[source_id.to_string()]);
+                self.bump_source_version(&mut data_store, source_id).await
+            },
+            // The source was spilled and could not be read back, so nothing is appended
+            Err(error) => {
+                for result in results.iter_mut().filter(|result| result.accepted) {
+                    result.accepted = false;
+                    result.record_id = None;
+                    result.error = Some(error.clone());
+                }
+                accepted = 0;
+                self.source_versions.read().await.get(source_id).copied().unwrap_or(0)
+            },
+            Ok(None) => self.source_versions.read().await.get(source_id).copied().unwrap_or(0),
+        };
+        drop(data_store);
+        let rejected = results.len() - accepted;
+
+        println!("Appended {} records to source {} (version {})", accepted, source_id, version);
+
+        RecordWriteResponse {
+            source_id: source_id.to_string(),
+            version,
+            accepted,
+            rejected,
+            results,
+        }
+    }
+
+    /// Matches records by id, or by the value of `key_field` when a business key is given.
+    fn record_matches(record: &DataRecord, key: &str, key_field: Option<&str>) -> bool {
+        match key_field {
+            Some(field) => match record.data.get(field) {
+                Some(Value::String(value)) => value == key,
+                Some(value) => serde_json::from_str::<Value>(key).is_ok_and(|key| &key == value),
+                None => false,
+            },
+            None => record.id == key,
+        }
+    }
+
+    /// Applies a JSON merge patch (RFC 7396) to every matching record of a source.
+    pub async fn patch_records(
+        &self,
+        source_id: &str,
+        key: &str,
+        key_field: Option<&str>,
+        patch: &Value,
+    ) -> Result<(Vec<DataRecord>, u64), String> {
+        if !patch.is_object() {
+            return Err("Patch must be a JSON object".to_string());
+        }
+        let mut patch = patch.clone();
+        if let Some(locale) = self.source_locales.read().await.get(source_id) {
+            locale.normalize(&mut patch);
+        }
+
+        let mut data_store = self.data_store.write().await;
+        let source = data_store.get_mut(source_id)?.ok_or("Source not found")?;
+        let candidates = source.lookup(key, key_field);
+        let records = source.decode_in_place();
+
+        let positions: Box<dyn Iterator<Item = usize>> = match candidates {
+            Some(candidates) => Box::new(candidates.into_iter()),
+            None => Box::new(0..records.len()),
+        };
+        let mut changes = Vec::new();
+        for position in positions {
+            let record = &mut records[position];
+            if !Self::record_matches(record, key, key_field) {
+                continue;
+            }
+            let before = record.data.clone();
+            merge_patch(&mut record.data, &patch);
+            changes.push((position, before, record.clone()));
+        }
+        for (position, before, record) in &changes {
+            source.update_indexes(*position, before, &record.data);
+        }
+        if !changes.is_empty() {
+            source.refresh_stats();
+        }
+        if let Some(max_values) = self.dictionary_max_values {
+            source.encode(max_values);
+        }
+        if changes.is_empty() {
+            return Err("Record not found".to_string());
+        }
+        data_store.persist(source_id);
+
+        let version = self.bump_source_version(&mut data_store, source_id).await;
+        drop(data_store);
+
+        let mut audit = self.record_audit.write().await;
+        let mut updated = Vec::with_capacity(changes.len());
+        for (_, before, record) in changes {
+            println!("Record patched: {}/{} (version {})", source_id, record.id, version);
+            audit.push(RecordAuditEntry {
+                source_id: source_id.to_string(),
+                record_id: record.id.clone(),
+                action: RecordAuditAction::Patched,
+                before,
+                after: Some(record.data.clone()),
+                source_version: version,
+                timestamp: Utc::now(),
+            });
+            updated.push(record);
+        }
+
+        Ok((updated, version))
+    }
+
+    /// Removes every matching record from a source, returning how many were deleted.
+    pub async fn delete_records(&self, source_id: &str, key: &str, key_field: Option<&str>) -> Result<(usize, u64), String> {
+        let mut data_store = self.data_store.write().await;
+        let source = data_store.get_mut(source_id)?.ok_or("Source not found")?;
+        let candidates = source.lookup(key, key_field);
+        let records = source.decode_in_place();
+
+        let mut position = 0;
+        let (removed, kept): (Vec<DataRecord>, Vec<DataRecord>) = std::mem::take(records)
+            .into_iter()
+            .partition(|r| {
+                let candidate = candidates.as_ref().is_none_or(|candidates| candidates.binary_search(&position).is_ok());
+                position += 1;
+                candidate && Self::record_matches(r, key, key_field)
+            });
+        *records = kept;
+        if !removed.is_empty() {
+            source.reindex();
+            source.refresh_stats();
+        }
+        if let Some(max_values) = self.dictionary_max_values {
+            source.encode(max_values);
+        }
+        if removed.is_empty() {
+            return Err("Record not found".to_string());
+        }
+        data_store.persist(source_id);
+
+        let version = self.bump_source_version(&mut data_store, source_id).await;
+        drop(data_store);
+
+        let mut audit = self.record_audit.write().await;
+        for record in &removed {
+            println!("Record deleted: {}/{} (version {})", source_id, record.id, version);
+            audit.push(RecordAuditEntry {
+                source_id: source_id.to_string(),
+                record_id: record.id.clone(),
+                action: RecordAuditAction::Deleted,
+                before: record.data.clone(),
+                after: None,
+                source_version: version,
+                timestamp: Utc::now(),
+            });
+        }
+
+        Ok((removed.len(), version))
+    }
+
+    pub async fn get_record_audit(&self, source_id: &str) -> Vec<RecordAuditEntry> {
+        let audit = self.record_audit.read().await;
+        audit.iter().filter(|entry| entry.source_id == source_id).cloned().collect()
+    }
+
+    pub async fn save_query(&self, mut query: SavedQuery) -> Result<SavedQuery, String> {
+        if query.name.trim().is_empty() {
+            return Err("Query name is required".to_string());
+        }
+        query.id = Uuid::new_v4().to_string();
+        query.created_at = Utc::now();
+
+        let mut queries = self.saved_queries.write().await;
+        queries.insert(query.id.clone(), query.clone());
+        println!("Query saved: {} ({})", query.name, query.id);
+        Ok(query)
+    }
+
+    pub async fn list_queries(&self) -> Vec<SavedQuery> {
+        let queries = self.saved_queries.read().await;
+        queries.values().cloned().collect()
+    }
+
+    pub async fn delete_query(&self, query_id: &str) -> Result<(), String> {
+        let mut queries = self.saved_queries.write().await;
+        queries.remove(query_id).map(|_| ()).ok_or("Query not found".to_string())
+    }
+
+    /// Evaluates a saved query against the current contents of its source, returning the
+    /// requested page of its results.
+    pub async fn run_saved_query(&self, query_id: &str, window: &ResultWindow) -> Result<QueryResults, String> {
+        let (offset, page_size) = window.resolve(self.max_result_rows)?;
+        let query = {
+            let queries = self.saved_queries.read().await;
+            queries.get(query_id).cloned().ok_or("Query not found")?
+        };
+
+        let data_store = DataStore::read_loaded(&self.data_store, |_| vec![query.source_id.clone()]).await?;
+        let records = data_store.get(&query.source_id).ok_or("Source not found")?.records();
+        let source_version = self.source_versions.read().await.get(&query.source_id).copied().unwrap_or(0);
+
+        // One record past the page tells whether the results were cut off
+        let mut results: Vec<Value> = records.iter()
+            .filter(|record| query.filter.iter().all(|(field, expected)| record.data.get(field) == Some(expected)))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .skip(offset)
+            .take(page_size.saturating_add(1))
+            .map(|record| match &query.fields {
+                Some(fields) => {
+                    let projected: serde_json::Map<String, Value> = fields.iter()
+                        .filter_map(|field| record.data.get(field).map(|value| (field.clone(), value.clone())))
+                        .collect();
+                    Value::Object(projected)
+                },
+                None => record.data.clone(),
+            })
+            .collect();
+
+        let truncated = results.len() > page_size;
+        if truncated && window.all {
+            return Err(format!(
+                "Result set exceeds the maximum of {} rows per response; page with limit and offset",
+                self.max_result_rows,
+            ));
+        }
+        results.truncate(page_size);
+
+        Ok(QueryResults {
+            query_id: query.id,
+            name: query.name,
+            source_id: query.source_id,
+            source_version,
+            count: results.len(),
+            offset,
+            truncated,
+            next_offset: truncated.then_some(offset + page_size),
+            records: results,
+        })
+    }
+
+    /// Runs an ad-hoc operation list over the start of a source and returns the requested page
+    /// of its output, without creating a job.
+    pub async fn run_query(&self, query: AdHocQuery) -> Result<AdHocQueryResults, String> {
+        let window = ResultWindow { limit: query.limit, offset: query.offset, all: false };
+        let (offset, page_size) = window.resolve(self.max_result_rows)?;
+        let max_input = query.max_input.unwrap_or(DEFAULT_QUERY_INPUT_RECORDS);
+        if max_input == 0 || max_input > MAX_QUERY_INPUT_RECORDS {
+            return Err(format!("max_input must be between 1 and {}", MAX_QUERY_INPUT_RECORDS));
+        }
+        let timeout_seconds = query.timeout_seconds.unwrap_or(DEFAULT_QUERY_TIMEOUT_SECS);
+        if timeout_seconds == 0 || timeout_seconds > MAX_QUERY_TIMEOUT_SECS {
+            return Err(format!("timeout_seconds must be between 1 and {}", MAX_QUERY_TIMEOUT_SECS));
+        }
+        let source_len = self.data_store.read().await.get(&query.source_id).map(StoredSource::len).ok_or("Source not found")?;
+        let source_version = self.source_versions.read().await.get(&query.source_id).copied().unwrap_or(0);
+
+        let configuration = ProcessingConfig {
+            input_sources: vec![query.source_id.clone()],
+            operations: query.operations,
+            batch_size: DEFAULT_QUERY_INPUT_RECORDS,
+            parallel_workers: 0,
+            timeout_seconds,
+            retry_attempts: 0,
+            output_format: OutputFormat::Json,
+            output_path: None,
+            partition_by: None,
+            output_compression: OutputCompression::default(),
+            output_encryption: None,
+            output_columns: None,
+            outputs: Vec::new(),
+            dead_letter: None,
+            engine_version: None,
+            locale: query.locale,
+            decimal_fields: BTreeMap::new(),
+            null_semantics: query.null_semantics,
+            invariants: Vec::new(),
+            checkpoint_interval_secs: None,
+            pinned_versions: BTreeMap::new(),
+        };
+        configuration.validate()?;
+        let input_records = source_len.min(max_input);
+        let job = ProcessingJob {
+            id: Uuid::new_v4().to_string(),
+            name: "query".to_string(),
+            status: JobStatus::Running,
+            created_at: Utc::now(),
+            started_at: Some(Utc::now()),
+            completed_at: None,
+            input_count: input_records,
+            processed_count: 0,
+            error_count: 0,
+            configuration,
+            results: Vec::new(),
+            comments: Vec::new(),
+            estimated_memory_bytes: None,
+            estimated_disk_bytes: None,
+            error: None,
+            schedule_id: None,
+            input_versions: BTreeMap::new(),
+            engine_version: Some(ENGINE_VERSION),
+            warnings: Vec::new(),
+            attempts: Vec::new(),
+            retry_at: None,
+            priority: 0,
+            tags: BTreeMap::new(),
+            rerun_of: None,
+            callbacks: Vec::new(),
+            tenant: None,
+            imported_at: None,
+            depends_on: Vec::new(),
+            progress: None,
+            dead_lettered_at: None,
+            trace: None,
+        };
+
+        let started = Instant::now();
+        let deadline = job_deadline(&CancellationToken::new(), timeout_seconds).await;
+        // A sample run writes no sinks, job directory or checkpoint
+        let outcome = Box::pin(Self::execute_processing_job(
+            &job, &self.data_store, &self.credentials, &self.egress_policy, &self.work_dir, Some(max_input),
+            &ProgressTracker::new(job.configuration.operations.len()), &deadline, &JobLogs::default(),
+        )).await;
+        let timed_out = deadline.is_cancelled();
+        deadline.cancel();
+        if timed_out {
+            return Err(format!("Query timed out after {}s", timeout_seconds));
+        }
+        let (results, records) = outcome?;
+
+        let total = records.len();
+        let offset = offset.min(total);
+        let records: Vec<DataRecord> = records.into_iter().skip(offset).take(page_size).collect();
+        let truncated = offset + records.len() < total;
+        Ok(AdHocQueryResults {
+            source_id: query.source_id,
+            source_version,
+            input_records,
+            input_truncated: source_len > input_records,
+            total,
+            count: records.len(),
+            offset,
+            truncated,
+            next_offset: truncated.then_some(offset + records.len()),
+            elapsed_ms: started.elapsed().as_millis(),
+            results,
+            records,
+        })
+    }
+
+    fn sign_share(&self, query_id: &str, expires_at: i64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.share_secret).expect("HMAC accepts any key length");
+        mac.update(format!("{}.{}", query_id, expires_at).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Issues a signed token granting read access to a saved query until it expires.
+    pub async fn create_share_token(&self, query_id: &str, expires_in: Duration) -> Result<(String, DateTime<Utc>), String> {
+        if !self.saved_queries.read().await.contains_key(query_id) {
+            return Err("Query not found".to_string());
+        }
+        let expires_at = Utc::now() + chrono::Duration::from_std(expires_in).map_err(|e| e.to_string())?;
+        let signature = self.sign_share(query_id, expires_at.timestamp());
+        Ok((format!("{}.{}.{}", query_id, expires_at.timestamp(), signature), expires_at))
+    }
+
+    /// Verifies a share token and returns the query id it grants access to.
+    pub fn verify_share_token(&self, token: &str) -> Result<String, String> {
+        let mut parts = token.splitn(3, '.');
+        let (Some(query_id), Some(expires_at), Some(signature)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err("Invalid share token".to_string());
+        };
+        let expires_at: i64 = expires_at.parse().map_err(|_| "Invalid share token")?;
+        let signature = hex::decode(signature).map_err(|_| "Invalid share token")?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.share_secret).expect("HMAC accepts any key length");
+        mac.update(format!("{}.{}", query_id, expires_at).as_bytes());
+        mac.verify_slice(&signature).map_err(|_| "Invalid share token")?;
+
+        if Utc::now().timestamp() > expires_at {
+            return Err("Share token expired".to_string());
+        }
+        Ok(query_id.to_string())
+    }
+
+    pub async fn load_data_from_file(&self, source_id: &str, file_path: &str) -> Result<usize, String> {
+        let records = Self::read_file_records(source_id, file_path)?;
+        let count = records.len();
+        
+        // Store data
+        self.store_source(source_id, records).await;
+
+        println!("Loaded {} records from {}", count, file_path);
+        Ok(count)
+    }
+
+    fn read_file_records(source_id: &str, file_path: &str) -> Result<Vec<DataRecord>, String> {
+        let path = Path::new(file_path);
+        if !path.exists() {
+            return Err("File not found".to_string());
+        }
+
+        let csv = file_path.ends_with(".csv");
+        if csv || [".json", ".jsonl", ".ndjson"].iter().any(|extension| file_path.ends_with(extension)) {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            let size = file.metadata().map_err(|e| e.to_string())?.len();
+            if size >= MMAP_MIN_FILE_BYTES {
+                Self::read_mapped_records(source_id, &file, csv)
+            } else if csv {
+                Self::read_csv_records(source_id, file)
+            } else {
+                Self::read_ndjson_records(source_id, BufReader::new(file))
+            }
+        } else if file_path.ends_with(".parquet") {
+            let contents = std::fs::read(path).map_err(|e| e.to_string())?;
+            Self::read_parquet_records(source_id, Bytes::from(contents))
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Parses a large file straight from a read-only mapping, splitting it into line-aligned
+    /// chunks that are parsed in parallel and concatenated in file order.
+    fn read_mapped_records(source_id: &str, file: &File, csv: bool) -> Result<Vec<DataRecord>, String> {
+        // Safety: the mapping is read-only and dropped before returning. Like any mmap reader,
+        // a file truncated by another process while it is being parsed can fault.
+        let map = unsafe { memmap2::Mmap::map(file) }.map_err(|e| e.to_string())?;
+        let chunks = Self::split_line_chunks(&map, csv);
+
+        let parsed: Vec<Result<Vec<DataRecord>, String>> = chunks.par_iter()
+            .enumerate()
+            .map(|(index, chunk)| if csv {
+                // Only the first chunk carries the header row
+                Self::csv_reader_records(source_id, ReaderBuilder::new().has_headers(index == 0).from_reader(*chunk))
+            } else {
+                Self::read_ndjson_records(source_id, *chunk)
+            })
+            .collect();
+
+        let mut records = Vec::new();
+        for chunk in parsed {
+            records.extend(chunk?);
+        }
+        Ok(records)
+    }
+
+    /// Splits at newlines, one chunk per rayon thread. CSV boundaries skip newlines inside
+    /// quoted fields, which takes one pass over the bytes tracking quote state.
+    fn split_line_chunks(bytes: &[u8], quoted: bool) -> Vec<&[u8]> {
+        let parts = rayon::current_num_threads().min(bytes.len() / MIN_PARSE_CHUNK_BYTES).max(1);
+        let target = bytes.len() / parts;
+        let mut chunks = Vec::with_capacity(parts);
+        let (mut start, mut position, mut in_quotes) = (0, 0, false);
+
+        while chunks.len() + 1 < parts {
+            let goal = (start + target).min(bytes.len());
+            if quoted {
+                in_quotes ^= bytes[position..goal].iter().filter(|&&byte| byte == b'"').count() % 2 == 1;
+            }
+            position = goal;
+            while position < bytes.len() && (bytes[position] != b'\n' || in_quotes) {
+                if quoted && bytes[position] == b'"' {
+                    in_quotes = !in_quotes;
+                }
+                position += 1;
+            }
+            if position >= bytes.len() {
+                break;
+            }
+            position += 1;
+            chunks.push(&bytes[start..position]);
+            start = position;
+        }
+
+        chunks.push(&bytes[start..]);
+        chunks
+    }
+
+    fn read_csv_records<R: Read>(source_id: &str, input: R) -> Result<Vec<DataRecord>, String> {
+        Self::csv_reader_records(source_id, ReaderBuilder::new().has_headers(true).from_reader(input))
+    }
+
+    fn csv_reader_records<R: Read>(source_id: &str, mut reader: csv::Reader<R>) -> Result<Vec<DataRecord>, String> {
+        let mut records = Vec::new();
+        
+        for result in reader.records() {
+            let record = result.map_err(|e| e.to_string())?;
+            let mut data_map = serde_json::Map::new();
+            
+            for (i, field) in record.iter().enumerate() {
+                data_map.insert(format!("field_{}", i), Value::String(field.to_string()));
+            }
+            
+            records.push(DataRecord {
+                id: Uuid::new_v4().to_string(),
+                timestamp: Utc::now(),
+                data: Value::Object(data_map),
+                source: source_id.to_string(),
+                processed: false,
+                metadata: HashMap::new(),
+            });
+        }
+
+        Ok(records)
+    }
+
+    fn read_ndjson_records<R: BufRead>(source_id: &str, input: R) -> Result<Vec<DataRecord>, String> {
+        Self::read_ndjson_lines(source_id, input).map(|(records, _)| records)
+    }
+
+    /// Parses one record per line, returning the lines that are not JSON alongside the records
+    /// rather than failing on them; blank lines are skipped.
+    fn read_ndjson_lines<R: BufRead>(source_id: &str, input: R) -> Result<(Vec<DataRecord>, Vec<ProcessingError>), String> {
+        let mut records = Vec::new();
+        let mut rejected = Vec::new();
+
+        for (index, line) in input.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Value>(&line) {
+                Ok(data) => records.push(DataRecord {
+                    id: Uuid::new_v4().to_string(),
+                    timestamp: Utc::now(),
+                    data,
+                    source: source_id.to_string(),
+                    processed: false,
+                    metadata: HashMap::new(),
+                }),
+                Err(e) => rejected.push(ProcessingError {
+                    error_type: "ParseFailed".to_string(),
+                    message: e.to_string(),
+                    record_id: None,
+                    timestamp: Utc::now(),
+                    context: HashMap::from([("line".to_string(), json!(index + 1))]),
+                }),
+            }
+        }
+
+        Ok((records, rejected))
+    }
+
+    /// Parses a JSON document holding an array of records or a single record.
+    fn read_json_records(source_id: &str, contents: &[u8]) -> Result<Vec<DataRecord>, String> {
+        let items = match serde_json::from_slice::<Value>(contents) {
+            Ok(Value::Array(items)) => items,
+            Ok(object @ Value::Object(_)) => vec![object],
+            Ok(_) => return Err("JSON uploads must hold an array of records or a single record".to_string()),
+            Err(e) => return Err(format!("Invalid JSON (upload one record per line as .ndjson): {}", e)),
+        };
+        Ok(items.into_iter()
+            .map(|data| DataRecord {
+                id: Uuid::new_v4().to_string(),
+                timestamp: Utc::now(),
+                data,
+                source: source_id.to_string(),
+                processed: false,
+                metadata: HashMap::new(),
+            })
+            .collect())
+    }
+
+    fn read_parquet_records(source_id: &str, contents: Bytes) -> Result<Vec<DataRecord>, String> {
+        let reader = SerializedFileReader::new(contents).map_err(|e| e.to_string())?;
+        let rows = reader.get_row_iter(None).map_err(|e| e.to_string())?;
+        let mut records = Vec::new();
+
+        for row in rows {
+            let row = row.map_err(|e| e.to_string())?;
+            let mut data = row.to_json_value();
+            // DECIMAL columns come back as text; they are exact numbers again here
+            for (name, field) in row.get_column_iter() {
+                if let (ParquetField::Decimal(_), Some(value)) = (field, data.get_mut(name)) {
+                    if let Some(decimal) = to_decimal(value) {
+                        *value = decimal_value(&decimal);
+                    }
+                }
+            }
+            records.push(DataRecord {
+                id: Uuid::new_v4().to_string(),
+                timestamp: Utc::now(),
+                data,
+                source: source_id.to_string(),
+                processed: false,
+                metadata: HashMap::new(),
+            });
+        }
+
+        Ok(records)
+    }
+
+    /// Parses an uploaded file by extension (falling back to its content type), along with the
+    /// NDJSON lines it rejected.
+    fn read_uploaded_records(
+        source_id: &str,
+        file_name: &str,
+        content_type: Option<&str>,
+        contents: Bytes,
+    ) -> Result<(Vec<DataRecord>, Vec<ProcessingError>), String> {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        let format = match (extension.as_deref(), content_type) {
+            (Some(extension @ ("csv" | "ndjson" | "jsonl" | "json" | "parquet")), _) => extension,
+            (_, Some("text/csv")) => "csv",
+            (_, Some("application/x-ndjson")) => "ndjson",
+            (_, Some("application/json")) => "json",
+            (_, Some("application/vnd.apache.parquet")) => "parquet",
+            _ => return Err(format!("Unsupported upload format: {}", file_name)),
+        };
+        let (mut records, mut rejected) = match format {
+            "csv" => (Self::read_csv_records(source_id, contents.reader())?, Vec::new()),
+            "json" => (Self::read_json_records(source_id, &contents)?, Vec::new()),
+            "parquet" => (Self::read_parquet_records(source_id, contents)?, Vec::new()),
+            _ => Self::read_ndjson_lines(source_id, contents.reader())?,
+        };
+
+        for record in &mut records {
+            record.metadata.insert("source_file".to_string(), json!(file_name));
+        }
+        for error in &mut rejected {
+            error.context.insert("source_file".to_string(), json!(file_name));
+        }
+        Ok((records, rejected))
+    }
+
+    /// Replaces a source with the records parsed from uploaded files, returning the new version
+    /// and the lines that could not be parsed.
+    pub async fn upload_source(
+        &self,
+        source_id: &str,
+        files: Vec<(String, Option<String>, Bytes)>,
+    ) -> Result<(usize, u64, Vec<ProcessingError>), String> {
+        let mut records = Vec::new();
+        let mut rejected = Vec::new();
+        for (file_name, content_type, contents) in files {
+            let (parsed, errors) = Self::read_uploaded_records(source_id, &file_name, content_type.as_deref(), contents)?;
+            records.extend(parsed);
+            rejected.extend(errors);
+        }
+
+        let count = records.len();
+        let version = self.store_source(source_id, records).await;
+        println!("Uploaded {} records to source {}", count, source_id);
+        if !rejected.is_empty() {
+            println!("Warning: Upload to source {} rejected {} lines that are not JSON", source_id, rejected.len());
+        }
+        Ok((count, version, rejected))
+    }
+
+    /// Runs a pipeline synchronously over records read from stdin, bypassing the job queue.
+    pub async fn run_stdin_pipeline(
+        &self,
+        configuration: ProcessingConfig,
+        format: StdinFormat,
+    ) -> Result<ProcessingJob, String> {
+        configuration.validate()?;
+        // A file output would otherwise land as output.<ext> in whatever directory the command ran in
+        if let Some((label, _)) = configuration.sinks().iter().find(|(_, sink)| sink.format.is_file_output() && sink.output_path.is_none()) {
+            return Err(format!("{} needs an output_path when run from the command line", label));
+        }
+        let warnings = configuration.deprecation_warnings();
+        let stdin = std::io::stdin();
+        let records = match format {
+            StdinFormat::Csv => Self::read_csv_records("stdin", stdin.lock())?,
+            StdinFormat::Ndjson => Self::read_ndjson_records("stdin", stdin.lock())?,
+        };
+        let input_count = records.len();
+
+        self.store_source("stdin", records).await;
+
+        let mut job = ProcessingJob {
+            id: Uuid::new_v4().to_string(),
+            name: "stdin".to_string(),
+            status: JobStatus::Running,
+            created_at: Utc::now(),
+            started_at: Some(Utc::now()),
+            completed_at: None,
+            input_count,
+            processed_count: 0,
+            error_count: 0,
+            configuration,
+            results: Vec::new(),
+            comments: Vec::new(),
+            estimated_memory_bytes: None,
+            estimated_disk_bytes: None,
+            error: None,
+            schedule_id: None,
+            input_versions: BTreeMap::new(),
+            engine_version: Some(ENGINE_VERSION),
+            warnings,
+            attempts: Vec::new(),
+            retry_at: None,
+            priority: 0,
+            tags: BTreeMap::new(),
+            rerun_of: None,
+            callbacks: Vec::new(),
+            tenant: None,
+            imported_at: None,
+            depends_on: Vec::new(),
+            progress: None,
+            dead_lettered_at: None,
+            trace: None,
+        };
+
+        let deadline = job_deadline(&CancellationToken::new(), job.configuration.timeout_seconds).await;
+        let result = Self::execute_processing_job(
+            &job, &self.data_store, &self.credentials, &self.egress_policy, &self.work_dir, None,
+            &ProgressTracker::new(job.configuration.operations.len()), &deadline, &self.job_logs,
+        ).await;
+        let timed_out = deadline.is_cancelled();
+        deadline.cancel();
+        self.work_dir.read().await.remove_job_dir(&job.id);
+        self.work_dir.read().await.remove_checkpoint(&job.id);
+        if timed_out {
+            return Err(format!("Job timed out after {}s", job.configuration.timeout_seconds));
+        }
+        let (results, records) = result?;
+        if let Some(error) = Self::invariant_failure(&results) {
+            return Err(error);
+        }
+        job.results = results;
+        job.status = JobStatus::Completed;
+        job.completed_at = Some(Utc::now());
+        job.processed_count = records.len();
+        Ok(job)
+    }
+
+    pub async fn remove_stale_job_dirs(&self) {
+        self.work_dir.read().await.remove_stale_job_dirs();
+    }
+
+    /// Queues the jobs a previous server left checkpoints for, to resume where they stopped.
+    /// Only safe while no other process shares the work directory.
+    pub async fn resume_checkpointed_jobs(&self) {
+        let checkpointed = self.work_dir.read().await.checkpointed_jobs();
+        for mut job in checkpointed {
+            job.status = JobStatus::Pending;
+            job.retry_at = None;
+            job.progress = None;
+            let mut jobs = self.jobs.write().await;
+            self.cancellations.write().await.insert(job.id.clone(), CancellationToken::new());
+            if self.job_queue.try_push(job.clone()).is_err() {
+                self.cancellations.write().await.remove(&job.id);
+                self.job_logs.warn(&job.id, format!("Warning: Job queue full, not resuming checkpointed job {}", job.id));
+                continue;
+            }
+            self.job_logs.correlate(&job);
+            self.job_logs.info(&job.id, format!("Resuming checkpointed job: {}", job.id));
+            jobs.insert(job.id.clone(), job);
+        }
+    }
+
+    pub async fn set_work_dir(&self, work_dir: WorkDir) {
+        println!("Work directory: {}", work_dir.root().display());
+        self.data_store.write().await.set_spill_dir(work_dir.root().join("spill"));
+        *self.work_dir.write().await = work_dir;
+    }
+
+    /// Persists sources to `storage`, restoring those it already holds. Returns how many were
+    /// restored; they are read from the backend when first used.
+    pub async fn attach_storage(&self, storage: Arc<dyn storage::SourceStorage>) -> Result<usize, String> {
+        let restored = self.data_store.write().await.attach_storage(storage, self.dictionary_max_values)?;
+        let mut versions = self.source_versions.write().await;
+        for (source_id, version) in &restored {
+            versions.insert(source_id.clone(), *version);
+        }
+        Ok(restored.len())
+    }
+
+    pub async fn set_egress_policy(&self, policy: EgressPolicy) {
+        if !policy.is_unrestricted() {
+            println!("Egress restricted to {} hosts and {} networks", policy.hosts.len(), policy.networks.len());
+        }
+        *self.egress_policy.write().await = policy;
+    }
+
+    /// The sources a job reads, in order. Empty only when the job names none and the store
+    /// holds none.
+    fn select_inputs<'a>(
+        store: &'a DataStore,
+        config: &'a ProcessingConfig,
+    ) -> Result<Vec<(&'a String, &'a StoredSource)>, String> {
+        let source_ids: Vec<&String> = match config.input_sources.is_empty() {
+            true => store.iter().map(|(source_id, _)| source_id).min().into_iter().collect(),
+            false => config.input_sources.iter().collect(),
+        };
+        source_ids.into_iter()
+            .map(|source_id| {
+                let source = store.get_at(source_id, config.pinned_versions.get(source_id).copied())?;
+                source.map(|source| (source_id, source)).ok_or_else(|| format!("Input source {} not found", source_id))
+            })
+            .collect()
+    }
+
+    /// Store keys of every source a job reads: its inputs, as `select_inputs` picks them, and
+    /// the sources it joins, at their pinned versions.
+    fn job_sources(store: &DataStore, config: &ProcessingConfig) -> Vec<String> {
+        let inputs = match config.input_sources.is_empty() {
+            true => store.iter().map(|(source_id, _)| source_id).min().cloned().into_iter().collect(),
+            false => config.input_sources.clone(),
+        };
+        let joined = config.operations.iter().filter_map(|operation| match operation {
+            Operation::Join { source, .. } => Some(source.clone()),
+            _ => None,
+        });
+        inputs.into_iter().chain(joined)
+            .map(|source_id| store.version_of(&source_id, config.pinned_versions.get(&source_id).copied()).unwrap_or(source_id))
+            .collect()
+    }
+
+    /// How outputs name the job's input: its source ids joined by `+`.
+    fn input_label(inputs: &[(&String, &StoredSource)]) -> String {
+        inputs.iter().map(|(source_id, _)| source_id.as_str()).collect::<Vec<_>>().join("+")
+    }
+
+    /// Projects a job's peak memory: the working copy of its input plus the copy an
+    /// operation produces alongside it. Sinks stream, so they add no full copies.
+    pub async fn estimate_job(&self, job: &ProcessingJob) -> JobCostEstimate {
+        let config = &job.configuration;
+        let store = match DataStore::read_loaded(&self.data_store, |store| Self::job_sources(store, config)).await {
+            Ok(store) => store,
+            // Spilled inputs are then sampled straight from their files
+            Err(_) => self.data_store.read().await,
+        };
+        let inputs = Self::select_inputs(&store, &job.configuration).unwrap_or_default();
+        if inputs.is_empty() {
+            return JobCostEstimate {
+                source_id: None,
+                input_records: 0,
+                input_bytes: 0,
+                projected_memory_bytes: 0,
+                projected_disk_bytes: 0,
+            };
+        }
+
+        let input_bytes = inputs.iter()
+            .map(|(_, records)| {
+                let sample = records.slice(0..records.len().min(COST_SAMPLE_RECORDS));
+                let sample_bytes: usize = sample.iter()
+                    .map(|record| serde_json::to_vec(record).map_or(0, |bytes| bytes.len()))
+                    .sum();
+                (sample_bytes as f64 / sample.len().max(1) as f64 * records.len() as f64) as u64
+            })
+            .sum();
+
+        let working_copies = if job.configuration.operations.is_empty() { 1 } else { 2 };
+        let projected_memory_bytes = input_bytes * IN_MEMORY_OVERHEAD * working_copies;
+
+        // Every local file sink is staged at about the size of the input
+        let projected_disk_bytes = job.configuration.sinks().iter()
+            .filter(|(_, sink)| sink.format.is_file_output())
+            .map(|(_, sink)| match sink.output_compression {
+                OutputCompression::None => input_bytes,
+                OutputCompression::Gzip | OutputCompression::Zstd => input_bytes / COMPRESSED_OUTPUT_RATIO,
+            })
+            .sum();
+
+        JobCostEstimate {
+            source_id: Some(Self::input_label(&inputs)),
+            input_records: inputs.iter().map(|(_, records)| records.len()).sum(),
+            input_bytes,
+            projected_memory_bytes,
+            projected_disk_bytes,
+        }
+    }
+
+    /// Checks a job without submitting it and runs its operations on the first `sample` input
+    /// records. Nothing is written to any sink or dead-letter output.
+    pub async fn dry_run(&self, job: &ProcessingJob, sample: usize) -> DryRunReport {
+        let config = &job.configuration;
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+        let issue = |path: String, message: String| DryRunIssue { path, message };
+        if let Err(error) = config.validate() {
+            errors.push(issue("configuration".to_string(), error));
+        }
+        if let Err(error) = validate_tags(&job.tags) {
+            errors.push(issue("tags".to_string(), error));
+        }
+        if let Err(error) = self.validate_callbacks(&job.callbacks) {
+            errors.push(issue("callbacks".to_string(), error));
+        }
+
+        // Fields seen in the input, and those each operation adds, tell misspelt names apart
+        let (input_fields, mut join_fields) = {
+            let store = match DataStore::read_loaded(&self.data_store, |store| Self::job_sources(store, config)).await {
+                Ok(store) => store,
+                Err(error) => {
+                    errors.push(issue("input".to_string(), error));
+                    self.data_store.read().await
+                },
+            };
+            let sample_fields = |source: &StoredSource| -> HashSet<String> {
+                source.slice(0..source.len().min(sample)).iter()
+                    .filter_map(|record| record.data.as_object())
+                    .flat_map(|fields| fields.keys().cloned())
+                    .collect()
+            };
+            let input_fields = match Self::select_inputs(&store, config) {
+                Ok(inputs) if inputs.is_empty() => {
+                    errors.push(issue("input".to_string(), "No input data available".to_string()));
+                    None
+                },
+                Ok(inputs) => Some(inputs.into_iter().flat_map(|(_, source)| sample_fields(source)).collect::<HashSet<_>>()),
+                Err(error) => {
+                    errors.push(issue("configuration.input_sources".to_string(), error));
+                    None
+                },
+            };
+            let mut join_fields = HashMap::new();
+            for (index, operation) in config.operations.iter().enumerate() {
+                if let Operation::Join { source, .. } = operation {
+                    match store.get_at(source, config.pinned_versions.get(source).copied()) {
+                        Ok(Some(records)) => { join_fields.insert(index, sample_fields(records)); },
+                        Ok(None) => errors.push(issue(format!("operations[{}].source", index), format!("Source {} not found", source))),
+                        Err(e) => errors.push(issue(format!("operations[{}].source", index), e)),
+                    }
+                }
+            }
+            (input_fields, join_fields)
+        };
+
+        // Unknown once an Aggregate replaces the records with its groups
+        let mut known = input_fields;
+        let mut interner = FieldInterner::default();
+        for (index, operation) in config.operations.iter().enumerate() {
+            let path = format!("operations[{}]", index);
+            let expression = match operation {
+                Operation::Filter { condition } => Some(("condition", condition)),
+                Operation::Transform { expression, .. } => Some(("expression", expression)),
+                _ => None,
+            };
+            // (part of the operation, field) for every field the operation reads
+            let mut referenced: Vec<(String, String)> = Vec::new();
+            if let Some((part, source)) = expression {
+                match CompiledExpression::compile(source, &mut interner) {
+                    Ok(compiled) => referenced.extend(compiled.fields().iter().map(|field| (part.to_string(), field.clone()))),
+                    Err(error) => errors.push(issue(format!("{}.{}", path, part), error)),
+                }
+            }
+            match operation {
+                Operation::Sort { fields, .. } | Operation::Deduplicate { fields } => {
+                    referenced.extend(fields.iter().map(|field| ("fields".to_string(), field.clone())));
+                },
+                Operation::Join { on, .. } => referenced.push(("on".to_string(), on.clone())),
+                Operation::Aggregate { group_by, functions } => {
+                    referenced.extend(group_by.iter().map(|field| ("group_by".to_string(), field.clone())));
+                    for (function_index, function) in functions.iter().enumerate() {
+                        let field = match function {
+                            AggregateFunction::Count | AggregateFunction::Custom { .. } => continue,
+                            AggregateFunction::Sum { field } | AggregateFunction::Average { field }
+                            | AggregateFunction::Min { field } | AggregateFunction::Max { field }
+                            | AggregateFunction::ApproxDistinct { field, .. }
+                            | AggregateFunction::ApproxQuantiles { field, .. }
+                            | AggregateFunction::TopK { field, .. } => field,
+                        };
+                        referenced.push((format!("functions[{}].field", function_index), field.clone()));
+                    }
+                },
+                Operation::Validate { rules } => {
+                    for (rule_index, rule) in rules.iter().enumerate() {
+                        if let ValidationType::Pattern { regex } = &rule.rule_type {
+                            if let Err(error) = regex_lite::Regex::new(regex) {
+                                errors.push(issue(format!("{}.rules[{}].regex", path, rule_index), format!("Invalid regex: {}", error)));
+                            }
+                        }
+                    }
+                },
+                _ => {},
+            }
+            if let Some(known) = &known {
+                for (part, field) in referenced {
+                    let top = field.split('.').next().unwrap_or(&field);
+                    if !known.contains(&field) && !known.contains(top) {
+                        warnings.push(issue(format!("{}.{}", path, part), format!("Field {} is not in the input", field)));
+                    }
+                }
+            }
+            match operation {
+                Operation::Transform { field, .. } => { known.get_or_insert_with(HashSet::new).insert(field.clone()); },
+                Operation::Join { .. } => {
+                    let joined = join_fields.remove(&index).unwrap_or_default();
+                    known = known.map(|known| known.into_iter().chain(joined).collect());
+                },
+                Operation::Aggregate { .. } => known = None,
+                _ => {},
+            }
+        }
+
+        let estimate = self.estimate_job(job).await;
+        let (results, preview) = match errors.is_empty() {
+            true => {
+                let deadline = job_deadline(&CancellationToken::new(), config.timeout_seconds).await;
+                let progress = ProgressTracker::new(config.operations.len());
+                // The job's future is large; on the heap it stays off the request task's stack
+                let outcome = Box::pin(Self::execute_processing_job(
+                    job, &self.data_store, &self.credentials, &self.egress_policy, &self.work_dir, Some(sample),
+                    // A dry run's lines are not kept
+                    &progress, &deadline, &JobLogs::default(),
+                )).await;
+                deadline.cancel();
+                match outcome {
+                    Ok(outcome) => outcome,
+                    Err(error) => {
+                        errors.push(issue("run".to_string(), error));
+                        (Vec::new(), Vec::new())
+                    },
+                }
+            },
+            false => (Vec::new(), Vec::new()),
+        };
+
+        DryRunReport {
+            valid: errors.is_empty(),
+            sample_records: sample.min(estimate.input_records),
+            errors,
+            warnings,
+            deprecations: config.deprecation_warnings(),
+            estimate,
+            results,
+            preview,
+        }
+    }
+
+    /// Admits a job when its projected memory and work directory space fit the headroom left
+    /// by running jobs and the host.
+    pub async fn check_admission(&self, job: &ProcessingJob) -> Result<JobCostEstimate, AdmissionRejection> {
+        let estimate = self.estimate_job(job).await;
+        let (running, reserved, reserved_disk): (usize, u64, u64) = {
+            let jobs = self.jobs.read().await;
+            let running: Vec<&ProcessingJob> = jobs.values().filter(|j| j.status == JobStatus::Running).collect();
+            (
+                running.len(),
+                running.iter().filter_map(|j| j.estimated_memory_bytes).sum(),
+                running.iter().filter_map(|j| j.estimated_disk_bytes).sum(),
+            )
+        };
+        let retry_after = || async {
+            if running > 0 {
+                let average_ms = self.metrics.read().await.average_processing_time_ms;
+                ((average_ms / 1000.0).ceil() as u64).max(1)
+            } else {
+                DEFAULT_ADMISSION_RETRY_SECS
+            }
+        };
+
+        let limit_headroom = self.memory_limit.map(|limit| limit.saturating_sub(reserved));
+        let available_memory = match (limit_headroom, available_system_memory()) {
+            (Some(limit), Some(system)) => Some(limit.min(system)),
+            (limit, system) => limit.or(system),
+        };
+        if let Some(available_bytes) = available_memory.filter(|available| estimate.projected_memory_bytes > *available) {
+            // A job larger than the configured limit never fits, so there is nothing to retry
+            let never_fits = self.memory_limit.is_some_and(|limit| estimate.projected_memory_bytes > limit);
+            let retry_after_seconds = match never_fits {
+                true => None,
+                false => Some(retry_after().await),
+            };
+            return Err(AdmissionRejection { resource: AdmissionResource::Memory, estimate, available_bytes, retry_after_seconds });
+        }
+
+        // Running jobs are still staging their outputs, so their projections are held back too
+        if let Some(usable) = self.work_dir.read().await.usable_bytes() {
+            let available_bytes = usable.saturating_sub(reserved_disk);
+            if estimate.projected_disk_bytes > available_bytes {
+                let never_fits = estimate.projected_disk_bytes > usable;
+                let retry_after_seconds = match never_fits {
+                    true => None,
+                    false => Some(retry_after().await),
+                };
+                return Err(AdmissionRejection { resource: AdmissionResource::Disk, estimate, available_bytes, retry_after_seconds });
+            }
+        }
+
+        Ok(estimate)
+    }
+
+    pub async fn load_data_from_api(&self, source_id: &str, endpoint: &str) -> Result<usize, String> {
+        let client = self.egress_policy.read().await.client(endpoint, Client::builder()).await?;
+        let records = Self::fetch_api_records(&client, source_id, endpoint, &HashMap::new()).await?;
+        let count = records.len();
+        
+        // Store data
+        self.store_source(source_id, records).await;
+
+        println!("Loaded {} records from API {}", count, endpoint);
+        Ok(count)
+    }
+
+    async fn fetch_api_records(
+        client: &Client,
+        source_id: &str,
+        endpoint: &str,
+        headers: &HashMap<String, String>,
+    ) -> Result<Vec<DataRecord>, String> {
+        let mut request = client.get(endpoint);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        
+        if !response.status().is_success() {
+            return Err(format!("API request failed: {}", response.status()));
+        }
+
+        let data: Value = response.json().await.map_err(|e| e.to_string())?;
+
+        // Handle different API response formats
+        let items = match data {
+            Value::Array(items) => items,
+            Value::Object(mut obj) => match obj.remove("data") {
+                Some(Value::Array(items)) => items,
+                Some(data) => {
+                    obj.insert("data".to_string(), data);
+                    vec![Value::Object(obj)]
+                },
+                None => vec![Value::Object(obj)],
+            },
+            data => vec![data],
+        };
+        Ok(items.into_iter().map(|data| DataRecord {
+            id: Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            data,
+            source: source_id.to_string(),
+            processed: false,
+            metadata: HashMap::new(),
+        }).collect())
+    }
+
+    /// Reads one object from an object store, parsed by its extension like an upload.
+    async fn fetch_object_records(source_id: &str, url: &str, options: &HashMap<String, String>) -> Result<Vec<DataRecord>, String> {
+        use object_store::ObjectStore;
+
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+        let (store, path) = object_store::parse_url_opts(&parsed, options).map_err(|e| e.to_string())?;
+        let contents = store.get(&path).await.map_err(|e| e.to_string())?
+            .bytes().await.map_err(|e| e.to_string())?;
+        let file_name = path.filename().unwrap_or_default().to_string();
+        let (records, rejected) = Self::read_uploaded_records(source_id, &file_name, None, contents)?;
+        if !rejected.is_empty() {
+            println!("Warning: Skipped {} lines of {} that are not JSON", rejected.len(), url);
+        }
+        Ok(records)
+    }
+
+    /// Runs a query, making each row a record keyed by column name. Columns are read as the
+    /// first of integer, float, boolean or text that their type decodes to.
+    async fn query_database_records(source_id: &str, connection_string: &str, query: &str) -> Result<Vec<DataRecord>, String> {
+        use sqlx::{Column, Row};
+
+        sqlx::any::install_default_drivers();
+        let mut connection = AnyConnection::connect(connection_string).await.map_err(|e| e.to_string())?;
+        let rows = sqlx::query(query).fetch_all(&mut connection).await.map_err(|e| e.to_string())?;
+        let _ = connection.close().await;
+
+        Ok(rows.iter().map(|row| {
+            let fields: serde_json::Map<String, Value> = row.columns().iter()
+                .map(|column| {
+                    let index = column.ordinal();
+                    let value = row.try_get::<Option<i64>, _>(index).map(|value| json!(value))
+                        .or_else(|_| row.try_get::<Option<f64>, _>(index).map(|value| json!(value)))
+                        .or_else(|_| row.try_get::<Option<bool>, _>(index).map(|value| json!(value)))
+                        .or_else(|_| row.try_get::<Option<String>, _>(index).map(|value| json!(value)))
+                        .unwrap_or(Value::Null);
+                    (column.name().to_string(), value)
+                })
+                .collect();
+            DataRecord {
+                id: Uuid::new_v4().to_string(),
+                timestamp: Utc::now(),
+                data: Value::Object(fields),
+                source: source_id.to_string(),
+                processed: false,
+                metadata: HashMap::new(),
+            }
+        }).collect())
+    }
+
+    /// Loads a source from its definition, replacing any records it had, and keeps the
+    /// definition for `GET /sources`.
+    pub async fn create_source(&self, request: SourceRequest) -> Result<SourceInfo, String> {
+        let SourceRequest { id, definition } = request;
+        if id.is_empty() || id.contains('/') {
+            return Err("Source id must be non-empty and contain no '/'".to_string());
+        }
+        let mut resolved = serde_json::to_value(&definition).map_err(|e| e.to_string())?;
+        Self::substitute_credentials(&mut resolved, &*self.credentials.read().await, &mut BTreeMap::new())?;
+        let resolved: SourceDefinition = serde_json::from_value(resolved).map_err(|e| e.to_string())?;
+        self.egress_policy.read().await.check_source(&resolved).await?;
+
+        let records = match &resolved {
+            SourceDefinition::File { path } => Self::read_file_records(&id, path)?,
+            SourceDefinition::Api { url, headers } => {
+                let client = self.egress_policy.read().await.client(url, Client::builder()).await?;
+                Self::fetch_api_records(&client, &id, url, headers).await?
+            },
+            SourceDefinition::S3 { url, options } => Self::fetch_object_records(&id, url, options).await?,
+            SourceDefinition::Database { connection_string, query } => {
+                Self::query_database_records(&id, connection_string, query).await?
+            },
+        };
+        let count = records.len();
+        self.source_definitions.write().await.insert(id.clone(), definition);
+        let version = self.store_source(&id, records).await;
+        println!("Created source {} with {} records (version {})", id, count, version);
+        self.get_source(&id).await.ok_or_else(|| "Source not found".to_string())
+    }
+
+    pub async fn list_sources(&self) -> Vec<SourceInfo> {
+        let data_store = self.data_store.read().await;
+        let mut sources: Vec<(&String, &StoredSource)> = data_store.iter().collect();
+        sources.sort_by_key(|(id, _)| *id);
+        let versions = self.source_versions.read().await;
+        let definitions = self.source_definitions.read().await;
+        let registrations = self.source_registrations.read().await;
+        sources.into_iter().map(|(id, source)| SourceInfo {
+            id: id.clone(),
+            record_count: source.len(),
+            version: versions.get(id).copied().unwrap_or(0),
+            loaded_at: source.loaded_at,
+            spilled: source.is_spilled(),
+            definition: definitions.get(id).cloned(),
+            schema: None,
+            schema_drift: registrations.get(id).map(|registration| registration.drift.clone()),
+            stats: None,
+        }).collect()
+    }
+
+    pub async fn get_source(&self, source_id: &str) -> Option<SourceInfo> {
+        let data_store = DataStore::read_loaded(&self.data_store, |_| vec![source_id.to_string()]).await.ok()?;
+        let source = data_store.get(source_id)?;
+        let schema = match self.source_schemas.read().await.get(source_id) {
+            Some(schema) => schema.clone(),
+            None => schema::infer(&source.slice(0..source.len().min(SOURCE_SCHEMA_SAMPLE))),
+        };
+        Some(SourceInfo {
+            id: source_id.to_string(),
+            record_count: source.len(),
+            version: self.source_versions.read().await.get(source_id).copied().unwrap_or(0),
+            loaded_at: source.loaded_at,
+            spilled: source.is_spilled(),
+            definition: self.source_definitions.read().await.get(source_id).cloned(),
+            schema: Some(schema),
+            schema_drift: self.source_registrations.read().await.get(source_id).map(|registration| registration.drift.clone()),
+            stats: source.stats().cloned(),
+        })
+    }
+
+    /// A page of a source's records in load order, with `data` cut down to `fields` when given.
+    pub async fn preview_source(
+        &self,
+        source_id: &str,
+        window: &ResultWindow,
+        fields: Option<&[String]>,
+    ) -> Result<SourcePage, String> {
+        let (offset, page_size) = window.resolve(self.max_result_rows)?;
+        let data_store = DataStore::read_loaded(&self.data_store, |_| vec![source_id.to_string()]).await?;
+        let source = data_store.get(source_id).ok_or("Source not found")?;
+        let total = source.len();
+        let offset = offset.min(total);
+        let mut records = source.slice(offset..(offset + page_size).min(total)).into_owned();
+        drop(data_store);
+        if let Some(fields) = fields {
+            for record in &mut records {
+                record.data = fields.iter()
+                    .filter_map(|field| record.data.get(field).map(|value| (field.clone(), value.clone())))
+                    .collect::<serde_json::Map<String, Value>>()
+                    .into();
+            }
+        }
+        let truncated = offset + records.len() < total;
+        Ok(SourcePage {
+            source_id: source_id.to_string(),
+            version: self.source_versions.read().await.get(source_id).copied().unwrap_or(0),
+            total,
+            count: records.len(),
+            offset,
+            truncated,
+            next_offset: truncated.then_some(offset + records.len()),
+            records,
+        })
+    }
+
+    /// Drops a source's records, retained versions and definition. Refused while a pending,
+    /// waiting or running job reads from it. The version counter is kept, so a source created again under the same id
+    /// continues its versions.
+    pub async fn delete_source(&self, source_id: &str) -> Result<(), String> {
+        let configs: Vec<ProcessingConfig> = self.jobs.read().await.values()
+            .filter(|job| !job.status.is_finished())
+            .map(|job| job.configuration.clone())
+            .collect();
+        let users = {
+            let data_store = self.data_store.read().await;
+            configs.iter()
+                .filter(|config| {
+                    let pinned = config.pinned_versions.get(source_id).map(|version| DataStore::version_key(source_id, *version));
+                    Self::job_sources(&data_store, config).iter().any(|id| id == source_id || pinned.as_ref() == Some(id))
+                })
+                .count()
+        };
+        if users > 0 {
+            return Err(format!("Source {} is read by {} unfinished jobs", source_id, users));
+        }
+        let removed = {
+            let mut data_store = self.data_store.write().await;
+            let versions: Vec<u64> = data_store.versions(source_id).into_iter().map(|(version, _)| version).collect();
+            for version in versions {
+                data_store.remove_version(source_id, version);
+            }
+            data_store.remove(source_id)
+        };
+        self.source_definitions.write().await.remove(source_id);
+        self.source_schemas.write().await.remove(source_id);
+        self.source_locales.write().await.remove(source_id);
+        self.source_registrations.write().await.remove(source_id);
+        match removed {
+            Some(_) => {
+                println!("Deleted source {}", source_id);
+                Ok(())
+            },
+            None => Err("Source not found".to_string()),
+        }
+    }
+
+    /// Ingests files that arrived while the watcher was down, oldest first, one per catch-up slot.
+    async fn catch_up_directory(&self, config: &WatchConfig, seen: &mut HashMap<PathBuf, (u64, Option<SystemTime>)>) {
+        let entries = match std::fs::read_dir(&config.directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("Could not list {} for catch-up: {}", config.directory.display(), e);
+                return;
+            }
+        };
+        let mut backlog: Vec<(Option<SystemTime>, PathBuf, String)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_str()?.to_string();
+                let file_meta = entry.metadata().ok()?;
+                let matches = config.pattern.matches(&file_name) && file_meta.is_file() && file_meta.len() > 0;
+                matches.then(|| (file_meta.modified().ok(), entry.path(), file_name))
+            })
+            .collect();
+        backlog.sort();
+
+        println!("Catching up on {} files in {}", backlog.len(), config.directory.display());
+        self.catch_up.pending_files.fetch_add(backlog.len(), Ordering::Relaxed);
+        for (modified, path, file_name) in backlog {
+            self.catch_up.acquire().await;
+            self.catch_up.pending_files.fetch_sub(1, Ordering::Relaxed);
+            if let Ok(file_meta) = std::fs::metadata(&path) {
+                seen.insert(path.clone(), (file_meta.len(), modified));
+            }
+            if let Err(e) = self.ingest_watched_file(&path, &file_name, config.job_template.as_ref()).await {
+                println!("Failed to ingest watched file {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    pub fn watch_directory(self: &Arc<Self>, config: WatchConfig) -> Result<(), String> {
+        if !config.directory.is_dir() {
+            return Err(format!("Watch directory not found: {}", config.directory.display()));
+        }
+
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let _ = event_sender.send(event);
+        }).map_err(|e| e.to_string())?;
+        watcher.watch(&config.directory, RecursiveMode::NonRecursive)
+            .map_err(|e| e.to_string())?;
+
+        println!("Watching {} for files matching {}", config.directory.display(), config.pattern);
+
+        let processor = self.clone();
+        tokio::spawn(async move {
+            // The watcher stops delivering events once dropped, so it lives in this task
+            let _watcher = watcher;
+            let mut seen: HashMap<PathBuf, (u64, Option<SystemTime>)> = HashMap::new();
+
+            if config.catch_up {
+                processor.catch_up_directory(&config, &mut seen).await;
+            }
+
+            while let Some(event) = event_receiver.recv().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        println!("Watch error: {}", e);
+                        continue;
+                    }
+                };
+
+                // Forget files that are gone, so a file of the same name is ingested again and
+                // the map stays as large as the directory; after lost events, check them all
+                if event.need_rescan() {
+                    seen.retain(|path, _| path.exists());
+                }
+                if matches!(event.kind, EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From))) {
+                    for path in &event.paths {
+                        seen.remove(path);
+                    }
+                    continue;
+                }
+
+                // Ingest once a file is fully written or moved into place; creation alone would
+                // catch it mid-write and then again when it is closed
+                let ready = matches!(
+                    event.kind,
+                    EventKind::Access(AccessKind::Close(AccessMode::Write))
+                        | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+                );
+                if !ready {
+                    continue;
+                }
+
+                for path in event.paths {
+                    let Some(file_name) = path.file_name().and_then(|n| n.to_str()).map(str::to_string) else {
+                        continue;
+                    };
+                    if !config.pattern.matches(&file_name) {
+                        continue;
+                    }
+
+                    // Skip files that are still empty or were already ingested unchanged
+                    let Ok(file_meta) = std::fs::metadata(&path) else {
+                        continue;
+                    };
+                    if !file_meta.is_file() || file_meta.len() == 0 {
+                        continue;
+                    }
+                    let signature = (file_meta.len(), file_meta.modified().ok());
+                    if seen.get(&path) == Some(&signature) {
+                        continue;
+                    }
+                    seen.insert(path.clone(), signature);
+
+                    if let Err(e) = processor.ingest_watched_file(&path, &file_name, config.job_template.as_ref()).await {
+                        println!("Failed to ingest watched file {}: {}", path.display(), e);
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn ingest_watched_file(
+        &self,
+        path: &Path,
+        file_name: &str,
+        job_template: Option<&ProcessingJob>,
+    ) -> Result<(), String> {
+        let file_path = path.to_str().ok_or("Invalid file path")?;
+        let mut records = Self::read_file_records(file_name, file_path)?;
+
+        for record in &mut records {
+            record.metadata.insert("source_file".to_string(), json!(file_name));
+        }
+
+        let count = records.len();
+        self.store_source(file_name, records).await;
+        println!("Ingested {} records from watched file {}", count, file_path);
+
+        if let Some(template) = job_template {
+            let mut job = template.clone();
+            if job.configuration.input_sources.is_empty() {
+                job.configuration.input_sources = vec![file_name.to_string()];
+            }
+            job.input_count = count;
+            self.submit_job(job).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_schedule(&self, mut schedule: Schedule) -> Result<Schedule, String> {
+        let calendars = self.calendars.read().await;
+        match &schedule.trigger {
+            ScheduleTrigger::Interval { seconds: 0 } => {
+                return Err("Schedule interval must be positive".to_string());
+            },
+            ScheduleTrigger::Calendar { calendar_id, .. } if !calendars.contains_key(calendar_id) => {
+                return Err("Calendar not found".to_string());
+            },
+            ScheduleTrigger::SourceLoad { min_records: 0, .. } => {
+                return Err("Source trigger min_records must be positive".to_string());
+            },
+            ScheduleTrigger::SourceSet { source_ids, .. } if source_ids.is_empty() => {
+                return Err("Source set trigger needs at least one source".to_string());
+            },
+            _ => {},
+        }
+        schedule.id = Uuid::new_v4().to_string();
+        schedule.next_run_at = schedule.trigger.next_fire(None, Utc::now(), &calendars);
+        schedule.fire_at = schedule.jittered(schedule.next_run_at);
+        let event_driven = matches!(schedule.trigger, ScheduleTrigger::SourceLoad { .. } | ScheduleTrigger::SourceSet { .. });
+        if schedule.next_run_at.is_none() && !event_driven {
+            return Err("Schedule never fires".to_string());
+        }
+        drop(calendars);
+        schedule.last_run_at = None;
+        schedule.deferred_until = None;
+        schedule.skipped_runs.clear();
+        schedule.active_runs.clear();
+        schedule.pending_records = 0;
+        schedule.pending_dates.clear();
+        schedule.last_logical_date = None;
+        schedule.catch_up.clear();
+
+        let mut schedules = self.schedules.write().await;
+        schedules.insert(schedule.id.clone(), schedule.clone());
+        println!("Schedule created: {} ({})", schedule.name, schedule.id);
+        Ok(schedule)
+    }
+
+    pub async fn list_schedules(&self) -> Vec<Schedule> {
+        let schedules = self.schedules.read().await;
+        schedules.values().cloned().collect()
+    }
+
+    pub async fn get_schedule(&self, schedule_id: &str) -> Result<Schedule, String> {
+        self.schedules.read().await.get(schedule_id).cloned().ok_or_else(|| "Schedule not found".to_string())
+    }
+
+    /// Pauses or resumes a schedule. A disabled schedule has no next run; enabling it picks the
+    /// cadence up from now, so runs missed while it was off are not caught up.
+    pub async fn set_schedule_enabled(&self, schedule_id: &str, enabled: bool) -> Result<Schedule, String> {
+        let calendars = self.calendars.read().await;
+        let mut schedules = self.schedules.write().await;
+        let schedule = schedules.get_mut(schedule_id).ok_or("Schedule not found")?;
+        if schedule.enabled == enabled {
+            return Ok(schedule.clone());
+        }
+        schedule.enabled = enabled;
+        schedule.next_run_at = enabled.then(|| schedule.trigger.next_fire(None, Utc::now(), &calendars)).flatten();
+        schedule.fire_at = schedule.jittered(schedule.next_run_at);
+        schedule.deferred_until = None;
+        schedule.catch_up.clear();
+        println!("Schedule {} {}", schedule.name, if enabled { "enabled" } else { "disabled" });
+        Ok(schedule.clone())
+    }
+
+    /// The next `count` fire times of an enabled time-based schedule, before jitter.
+    pub async fn upcoming_runs(&self, schedule_id: &str, count: usize) -> Result<Vec<DateTime<Utc>>, String> {
+        let calendars = self.calendars.read().await;
+        let schedules = self.schedules.read().await;
+        let schedule = schedules.get(schedule_id).ok_or("Schedule not found")?;
+        let mut upcoming: Vec<DateTime<Utc>> = schedule.next_run_at.into_iter().collect();
+        while let Some(&last) = upcoming.last().filter(|_| upcoming.len() < count.min(MAX_UPCOMING_RUNS)) {
+            match schedule.trigger.next_fire(Some(last), last, &calendars) {
+                Some(next) => upcoming.push(next),
+                None => break,
+            }
+        }
+        upcoming.truncate(count);
+        Ok(upcoming)
+    }
+
+    /// Compares two runs of a pipeline, the jobs a schedule submitted. Without `a` and `b` the
+    /// latest finished run is compared with the one before it.
+    pub async fn compare_runs(&self, pipeline_id: &str, a: Option<&str>, b: Option<&str>) -> Result<RunComparison, String> {
+        if !self.schedules.read().await.contains_key(pipeline_id) {
+            return Err("Pipeline not found".to_string());
+        }
+        let jobs = self.jobs.read().await;
+        let run = |job_id: &str| -> Result<&ProcessingJob, String> {
+            let job = jobs.get(job_id).ok_or_else(|| format!("Run {} not found", job_id))?;
+            if job.schedule_id.as_deref() != Some(pipeline_id) {
+                return Err(format!("Job {} is not a run of pipeline {}", job_id, pipeline_id));
+            }
+            Ok(job)
+        };
+
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (run(a)?, run(b)?),
+            (None, None) => {
+                let mut finished: Vec<&ProcessingJob> = jobs.values()
+                    .filter(|job| job.schedule_id.as_deref() == Some(pipeline_id) && job.completed_at.is_some())
+                    .collect();
+                finished.sort_by_key(|job| job.created_at);
+                match finished.as_slice() {
+                    [.., a, b] => (*a, *b),
+                    _ => return Err(format!("Pipeline {} has fewer than two finished runs", pipeline_id)),
+                }
+            },
+            _ => return Err("Pass both a and b, or neither".to_string()),
+        };
+        Ok(RunComparison::between(pipeline_id, a, b))
+    }
+
+    pub async fn create_calendar(&self, mut calendar: BusinessCalendar) -> Result<BusinessCalendar, String> {
+        if calendar.business_days.is_empty() {
+            return Err("Calendar needs at least one business day".to_string());
+        }
+        calendar.id = Uuid::new_v4().to_string();
+
+        let mut calendars = self.calendars.write().await;
+        calendars.insert(calendar.id.clone(), calendar.clone());
+        println!("Calendar created: {} ({})", calendar.name, calendar.id);
+        Ok(calendar)
+    }
+
+    /// Lists calendars, optionally only those belonging to one tenant.
+    pub async fn list_calendars(&self, tenant: Option<&str>) -> Vec<BusinessCalendar> {
+        let calendars = self.calendars.read().await;
+        calendars.values()
+            .filter(|calendar| tenant.is_none() || calendar.tenant.as_deref() == tenant)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn add_maintenance_window(&self, mut window: MaintenanceWindow) -> Result<MaintenanceWindow, String> {
+        if window.ends_at <= window.starts_at {
+            return Err("Maintenance window must end after it starts".to_string());
+        }
+        window.id = Uuid::new_v4().to_string();
+
+        let mut windows = self.maintenance_windows.write().await;
+        windows.push(window.clone());
+        println!("Maintenance window added: {} ({} - {})", window.name, window.starts_at, window.ends_at);
+        Ok(window)
+    }
+
+    pub async fn list_maintenance_windows(&self) -> Vec<MaintenanceWindow> {
+        self.maintenance_windows.read().await.clone()
+    }
+
+    pub async fn delete_maintenance_window(&self, window_id: &str) -> Result<(), String> {
+        let mut windows = self.maintenance_windows.write().await;
+        let before = windows.len();
+        windows.retain(|window| window.id != window_id);
+        if windows.len() == before {
+            return Err("Maintenance window not found".to_string());
+        }
+        Ok(())
+    }
+
+    pub async fn add_credential(&self, request: CredentialRequest) -> Result<CredentialInfo, String> {
+        let valid_name = !request.name.is_empty()
+            && request.name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if !valid_name {
+            return Err("Credential names may only contain letters, digits, '_', '-' and '.'".to_string());
+        }
+
+        let mut credentials = self.credentials.write().await;
+        if credentials.contains_key(&request.name) {
+            return Err("Credential already exists, rotate it instead".to_string());
+        }
+        let credential = Credential {
+            name: request.name,
+            description: request.description,
+            secret: request.secret,
+            version: 1,
+            created_at: Utc::now(),
+            rotated_at: None,
+        };
+        credentials.insert(credential.name.clone(), credential.clone());
+        self.persist_credentials(&credentials)?;
+        println!("Credential added: {}", credential.name);
+        Ok(CredentialInfo::from(&credential))
+    }
+
+    /// Replaces a credential's secret; jobs pick it up on their next (re)connection.
+    pub async fn rotate_credential(&self, name: &str, secret: String) -> Result<CredentialInfo, String> {
+        let mut credentials = self.credentials.write().await;
+        let credential = credentials.get_mut(name).ok_or("Credential not found")?;
+        credential.secret = secret;
+        credential.version += 1;
+        credential.rotated_at = Some(Utc::now());
+        let info = CredentialInfo::from(&*credential);
+        self.persist_credentials(&credentials)?;
+        println!("Credential rotated: {} (version {})", info.name, info.version);
+        Ok(info)
+    }
+
+    pub async fn list_credentials(&self) -> Vec<CredentialInfo> {
+        let credentials = self.credentials.read().await;
+        let mut infos: Vec<CredentialInfo> = credentials.values().map(CredentialInfo::from).collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+
+    pub async fn delete_credential(&self, name: &str) -> Result<(), String> {
+        let mut credentials = self.credentials.write().await;
+        if credentials.remove(name).is_none() {
+            return Err("Credential not found".to_string());
+        }
+        self.persist_credentials(&credentials)
+    }
+
+    fn persist_credentials(&self, credentials: &HashMap<String, Credential>) -> Result<(), String> {
+        match &self.credential_vault {
+            Some(vault) => vault.save(credentials),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts `workers` job workers, each running one job at a time from the queue.
+    pub fn start_job_workers(&self, workers: usize) {
+        self.job_queue.set_workers(workers);
+        for worker in 0..workers {
+            let (queue, jobs, job_results, cancellations) =
+                (self.job_queue.clone(), self.jobs.clone(), self.job_results.clone(), self.cancellations.clone());
+            let (metrics, data_store, source_versions, credentials) =
+                (self.metrics.clone(), self.data_store.clone(), self.source_versions.clone(), self.credentials.clone());
+            let (egress_policy, work_dir, job_updates, job_logs) =
+                (self.egress_policy.clone(), self.work_dir.clone(), self.job_updates.clone(), self.job_logs.clone());
+            // Survives restarts, so a restarted worker knows which job it was cut off from
+            let current = Arc::new(std::sync::Mutex::new(None));
+            self.supervisor.supervise(&format!("job_worker_{}", worker), move || {
+                // A restarted worker picks up the queue where the failed one left it
+                Self::job_processor(
+                    queue.clone(),
+                    current.clone(),
+                    jobs.clone(),
+                    job_results.clone(),
+                    cancellations.clone(),
+                    metrics.clone(),
+                    data_store.clone(),
+                    source_versions.clone(),
+                    credentials.clone(),
+                    egress_policy.clone(),
+                    work_dir.clone(),
+                    job_updates.clone(),
+                    job_logs.clone(),
+                )
+            });
+        }
+    }
+
+    pub fn start_scheduler(self: &Arc<Self>) {
+        let processor = self.clone();
+        self.supervisor.supervise("scheduler", move || {
+            let processor = processor.clone();
+            async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    if !processor.scheduler_paused.load(Ordering::Relaxed) {
+                        processor.run_due_schedules().await;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Posts job lifecycle events to the server's webhooks and the job's own callbacks.
+    pub fn start_job_webhooks(self: &Arc<Self>) {
+        let processor = self.clone();
+        self.supervisor.supervise("job_webhooks", move || processor.clone().dispatch_job_events());
+    }
+
+    async fn dispatch_job_events(self: Arc<Self>) {
+        let mut updates = self.job_updates.subscribe();
+        // Status each job was last seen in, so progress, comments and repeats raise nothing
+        let mut last_status: HashMap<String, JobStatus> = HashMap::new();
+        loop {
+            let job = match updates.recv().await {
+                Ok(job) => job,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    println!("Warning: Job webhooks missed {} job updates", missed);
+                    continue;
+                },
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            if last_status.insert(job.id.clone(), job.status.clone()).as_ref() == Some(&job.status) {
+                continue;
+            }
+            if last_status.len() > self.job_retention.max_jobs.unwrap_or(DEFAULT_RETAINED_JOBS) * 2 {
+                let jobs = self.jobs.read().await;
+                last_status.retain(|job_id, _| jobs.contains_key(job_id));
+            }
+            let Some(event) = JobEvent::for_status(&job.status) else { continue };
+            let callbacks = self.job_webhooks.iter().chain(&job.callbacks)
+                .filter(|callback| callback.events.is_empty() || callback.events.contains(&event));
+            let summary = JobEventSummary::from(&job);
+            for callback in callbacks.cloned() {
+                let (processor, summary) = (self.clone(), summary.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = processor.deliver_job_event(&callback, event, &summary).await {
+                        println!("Webhook {} for job {} failed: {}", callback.url, summary.id, e);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Posts one event to one callback, retrying connection errors, 429s and 5xx with backoff.
+    async fn deliver_job_event(&self, callback: &JobCallback, event: JobEvent, job: &JobEventSummary) -> Result<(), String> {
+        let key = match &callback.secret {
+            Some(secret) => {
+                let mut value = Value::String(secret.clone());
+                Self::substitute_credentials(&mut value, &*self.credentials.read().await, &mut BTreeMap::new())?;
+                value.as_str().unwrap_or_default().as_bytes().to_vec()
+            },
+            None => self.webhook_secret.clone().ok_or("No webhook secret configured")?,
+        };
+        let client = self.egress_policy.read().await.client(&callback.url, Client::builder().timeout(WEBHOOK_TIMEOUT)).await?;
+
+        let body = serde_json::to_vec(&json!({ "event": event, "job": job })).map_err(|e| e.to_string())?;
+        let event_name = serde_json::to_value(event).ok().and_then(|name| name.as_str().map(str::to_string)).unwrap_or_default();
+        let delivery_id = Uuid::new_v4().to_string();
+        let mut backoff = OUTPUT_RETRY_BACKOFF;
+        for attempt in 1..=WEBHOOK_DELIVERY_ATTEMPTS {
+            let timestamp = Utc::now().timestamp().to_string();
+            let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts any key length");
+            mac.update(timestamp.as_bytes());
+            mac.update(b".");
+            mac.update(&body);
+            let mut request = client.post(&callback.url)
+                .header("content-type", "application/json")
+                .header("x-webhook-event", &event_name)
+                .header("x-webhook-delivery", &delivery_id)
+                .header("x-webhook-timestamp", &timestamp)
+                .header("x-webhook-signature", format!("sha256={}", hex::encode(mac.finalize().into_bytes())))
+                .body(body.clone());
+            if let Some(traceparent) = &job.traceparent {
+                request = request.header("traceparent", traceparent);
+            }
+            let (error, retryable) = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    (format!("{} responded {}", callback.url, status), status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error())
+                },
+                Err(e) => (e.to_string(), true),
+            };
+            if !retryable || attempt == WEBHOOK_DELIVERY_ATTEMPTS {
+                return Err(format!("{} after {} attempts", error, attempt));
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_OUTPUT_RETRY_BACKOFF);
+        }
+        Ok(())
+    }
+
+    /// Stops schedules firing until `resume_scheduler`. Runs that fall due meanwhile are
+    /// handled by each schedule's misfire policy on resuming, as after downtime. Returns whether
+    /// the scheduler was running.
+    pub fn pause_scheduler(&self) -> bool {
+        !self.scheduler_paused.swap(true, Ordering::Relaxed)
+    }
+
+    /// Returns whether the scheduler was paused.
+    pub fn resume_scheduler(&self) -> bool {
+        self.scheduler_paused.swap(false, Ordering::Relaxed)
+    }
+
+    /// While draining new submissions are refused, and readiness fails so traffic moves
+    /// elsewhere, but queued and running jobs go on to finish. Returns the previous setting.
+    pub fn set_draining(&self, draining: bool) -> bool {
+        self.draining.swap(draining, Ordering::Relaxed)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    pub async fn admin_status(&self) -> AdminStatus {
+        let (queue_depth, queue_capacity) = self.queue_depth();
+        let jobs = self.jobs.read().await;
+        let count = |status: JobStatus| jobs.values().filter(|job| job.status == status).count();
+        AdminStatus {
+            scheduler_paused: self.scheduler_paused.load(Ordering::Relaxed),
+            draining: self.is_draining(),
+            queue_depth,
+            queue_capacity,
+            waiting_jobs: count(JobStatus::Waiting),
+            pending_jobs: count(JobStatus::Pending),
+            running_jobs: count(JobStatus::Running),
+        }
+    }
+
+    pub async fn background_tasks(&self) -> BTreeMap<String, TaskHealth> {
+        self.supervisor.health().await
+    }
+
+    /// Whether the instance can take work: it is not draining, its work directory and credential
+    /// store are usable, the job queue has room, and every supervised background task is running.
+    pub async fn readiness(&self) -> Readiness {
+        let mut checks = BTreeMap::new();
+        checks.insert("accepting_jobs", ReadinessCheck::from(match self.is_draining() {
+            true => Err("Draining: new jobs are refused".to_string()),
+            false => Ok(()),
+        }));
+        checks.insert("work_dir", ReadinessCheck::from(self.work_dir.read().await.probe()));
+        if let Some(vault) = &self.credential_vault {
+            checks.insert("credential_store", ReadinessCheck::from(vault.probe()));
+        }
+        let (queue_depth, queue_capacity) = self.queue_depth();
+        checks.insert("job_queue", ReadinessCheck::from(match queue_depth >= queue_capacity {
+            true => Err(format!("Job queue is full at {} jobs", queue_capacity)),
+            false => Ok(()),
+        }));
+        let tasks = self.background_tasks().await;
+        let stopped: Vec<&str> = tasks.iter().filter(|(_, task)| !task.running).map(|(name, _)| name.as_str()).collect();
+        checks.insert("background_tasks", ReadinessCheck::from(match stopped.is_empty() {
+            true => Ok(()),
+            false => Err(format!("Background tasks not running: {}", stopped.join(", "))),
+        }));
+        Readiness {
+            ready: checks.values().all(|check| check.ok),
+            checks,
+            queue_depth,
+            queue_capacity,
+            tasks,
+        }
+    }
+
+    async fn run_due_schedules(&self) {
+        let now = Utc::now();
+        let windows = self.maintenance_windows.read().await.clone();
+        let calendars = self.calendars.read().await.clone();
+        let unfinished: HashSet<String> = self.jobs.read().await
+            .values()
+            .filter(|job| matches!(job.status, JobStatus::Waiting | JobStatus::Pending | JobStatus::Running))
+            .map(|job| job.id.clone())
+            .collect();
+        let grace = chrono::Duration::seconds(MISFIRE_GRACE_SECS);
+        let mut due_jobs = Vec::new();
+
+        {
+            let mut schedules = self.schedules.write().await;
+            for schedule in schedules.values_mut().filter(|s| s.enabled) {
+                schedule.active_runs.retain(|job_id| unfinished.contains(job_id));
+                let active_window = windows.iter().find(|window| window.applies(&schedule.id, now));
+                let mut claimed = 0;
+
+                // A deferred run fires as soon as no window holds it back
+                if let Some(deferred_until) = schedule.deferred_until {
+                    if deferred_until <= now && active_window.is_none() {
+                        schedule.deferred_until = None;
+                        if schedule.claim_run(deferred_until, &mut claimed) {
+                            schedule.last_run_at = Some(now);
+                            due_jobs.push((schedule.id.clone(), schedule.job.clone()));
+                        }
+                    }
+                }
+
+                // Missed runs wait in the backlog until a catch-up slot and a run slot are free
+                while active_window.is_none() && !schedule.catch_up.is_empty() && schedule.has_free_slot(claimed) {
+                    if !self.catch_up.try_acquire().await {
+                        break;
+                    }
+                    if let Some(scheduled_for) = schedule.catch_up.pop_front() {
+                        schedule.claim_run(scheduled_for, &mut claimed);
+                        schedule.last_run_at = Some(now);
+                        due_jobs.push((schedule.id.clone(), schedule.job.clone()));
+                    }
+                }
+
+                let Some(next_run_at) = schedule.next_run_at else {
+                    continue;
+                };
+                if schedule.fire_at.unwrap_or(next_run_at) > now {
+                    continue;
+                }
+
+                // Walk every fire time that has passed, keeping the newest; older ones are
+                // recorded as skipped unless the policy collapses them into one run anyway
+                let overflow_reason = match schedule.misfire_policy {
+                    MisfirePolicy::RunAll => Some(SkipReason::CatchUpLimit),
+                    MisfirePolicy::Skip => Some(SkipReason::Misfire),
+                    MisfirePolicy::RunOnce => None,
+                };
+                let mut fire_times = VecDeque::from([next_run_at]);
+                let mut overflowed = 0;
+                let mut next = schedule.trigger.next_fire(Some(next_run_at), next_run_at, &calendars);
+                while let Some(fire_at) = next.filter(|fire_at| *fire_at <= now) {
+                    if fire_times.len() == MAX_CATCH_UP_RUNS {
+                        let scheduled_for = fire_times.pop_front().expect("fire times are not empty");
+                        if let Some(reason) = overflow_reason {
+                            schedule.record_skipped(SkippedRun { scheduled_for, reason, window_id: None, deferred: false });
+                            overflowed += 1;
+                        }
+                    }
+                    fire_times.push_back(fire_at);
+                    next = schedule.trigger.next_fire(Some(fire_at), fire_at, &calendars);
+                }
+                if overflowed > 0 {
+                    println!("Schedule {} skipped its {} oldest missed runs", schedule.name, overflowed);
+                }
+                schedule.next_run_at = next;
+                schedule.fire_at = schedule.jittered(next);
+
+                let (missed, on_time): (Vec<_>, Vec<_>) = fire_times.into_iter()
+                    .partition(|fire_at| now - *fire_at > grace);
+                let runs: Vec<_> = match schedule.misfire_policy {
+                    MisfirePolicy::RunAll => {
+                        schedule.catch_up.extend(missed);
+                        // A backlog still replaying gives way to the newer missed runs
+                        let excess = schedule.catch_up.len().saturating_sub(MAX_CATCH_UP_RUNS);
+                        let dropped: Vec<_> = schedule.catch_up.drain(..excess).collect();
+                        if !dropped.is_empty() {
+                            println!("Schedule {} skipped {} missed runs beyond the catch-up limit", schedule.name, dropped.len());
+                        }
+                        for scheduled_for in dropped {
+                            schedule.record_skipped(SkippedRun {
+                                scheduled_for,
+                                reason: SkipReason::CatchUpLimit,
+                                window_id: None,
+                                deferred: false,
+                            });
+                        }
+                        on_time
+                    },
+                    MisfirePolicy::RunOnce => missed.into_iter().chain(on_time).last().into_iter().collect(),
+                    MisfirePolicy::Skip => {
+                        for scheduled_for in missed {
+                            schedule.record_skipped(SkippedRun {
+                                scheduled_for,
+                                reason: SkipReason::Misfire,
+                                window_id: None,
+                                deferred: false,
+                            });
+                        }
+                        on_time.into_iter().last().into_iter().collect()
+                    },
+                };
+
+                for scheduled_for in runs {
+                    match active_window {
+                        Some(window) => {
+                            if window.defer {
+                                let until = schedule.deferred_until.map_or(window.ends_at, |d| d.max(window.ends_at));
+                                schedule.deferred_until = Some(until);
+                            }
+                            schedule.record_skipped(SkippedRun {
+                                scheduled_for,
+                                reason: SkipReason::MaintenanceWindow,
+                                window_id: Some(window.id.clone()),
+                                deferred: window.defer,
+                            });
+                            println!("Schedule {} suppressed by maintenance window {}", schedule.name, window.name);
+                        },
+                        None => {
+                            if schedule.claim_run(scheduled_for, &mut claimed) {
+                                schedule.last_run_at = Some(now);
+                                let mut job = schedule.job.clone();
+                                // Source-triggered runs read the sources that triggered them
+                                // unless the job names its own
+                                if job.configuration.input_sources.is_empty() {
+                                    job.configuration.input_sources = match &schedule.trigger {
+                                        ScheduleTrigger::SourceLoad { source_id, .. } => vec![source_id.clone()],
+                                        ScheduleTrigger::SourceSet { source_ids, .. } => source_ids.clone(),
+                                        _ => Vec::new(),
+                                    };
+                                }
+                                due_jobs.push((schedule.id.clone(), job));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for (schedule_id, mut job) in due_jobs {
+            job.schedule_id = Some(schedule_id.clone());
+            match self.submit_job(job).await {
+                Ok(job_id) => {
+                    if let Some(schedule) = self.schedules.write().await.get_mut(&schedule_id) {
+                        schedule.active_runs.push(job_id);
+                    }
+                },
+                Err(e) => println!("Failed to submit scheduled job for {}: {}", schedule_id, e),
+            }
+        }
+    }
+
+    pub async fn get_metrics(&self) -> SystemMetrics {
+        let mut metrics = self.metrics.read().await.clone();
+        let schedule_backlog: usize = self.schedules.read().await.values().map(|s| s.catch_up.len()).sum();
+        metrics.catch_up_pending = schedule_backlog + self.catch_up.pending_files.load(Ordering::Relaxed);
+        metrics.catch_up_completed = self.catch_up.completed.load(Ordering::Relaxed);
+        metrics.active_jobs = self.jobs.read().await.values().filter(|job| job.status == JobStatus::Running).count();
+        metrics
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn job_processor(
+        queue: JobQueue,
+        current: Arc<std::sync::Mutex<Option<String>>>,
+        jobs: Arc<RwLock<HashMap<String, ProcessingJob>>>,
+        job_results: Arc<RwLock<RetainedResults>>,
+        cancellations: Arc<RwLock<HashMap<String, CancellationToken>>>,
+        metrics: Arc<RwLock<SystemMetrics>>,
+        data_store: Arc<RwLock<DataStore>>,
+        source_versions: Arc<RwLock<HashMap<String, u64>>>,
+        credentials: Arc<RwLock<HashMap<String, Credential>>>,
+        egress_policy: Arc<RwLock<EgressPolicy>>,
+        work_dir: Arc<RwLock<WorkDir>>,
+        job_updates: broadcast::Sender<ProcessingJob>,
+        job_logs: JobLogs,
+    ) {
+        let interrupted = current.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(job_id) = interrupted {
+            work_dir.read().await.remove_checkpoint(&job_id);
+            Self::fail_interrupted_job(&jobs, &job_id, &queue, &job_updates, &job_logs).await;
+        }
+
+        loop {
+            let (mut job, slot) = queue.pop().await;
+            let cancel = cancellations.read().await.get(&job.id).cloned().unwrap_or_default();
+            if cancel.is_cancelled() {
+                cancellations.write().await.remove(&job.id);
+                work_dir.read().await.remove_checkpoint(&job.id);
+                job_logs.info(&job.id, format!("Skipping cancelled job: {}", job.id));
+                continue;
+            }
+            job_logs.info(&job.id, format!("Processing job: {} (attempt {})", job.id, job.attempts.len() + 1));
+            *current.lock().unwrap_or_else(|e| e.into_inner()) = Some(job.id.clone());
+            
+            // Update job status
+            job.status = JobStatus::Running;
+            job.started_at = Some(Utc::now());
+            job.retry_at = None;
+            job.engine_version = Some(ENGINE_VERSION);
+            // A resumed run goes on with the inputs its checkpoint was taken from
+            if !work_dir.read().await.has_checkpoint(&job.id) {
+                Self::record_inputs(&mut job, &data_store, &source_versions).await;
+            }
+            
+            {
+                let mut jobs_map = jobs.write().await;
+                Self::keep_comments(&jobs_map, &mut job);
+                jobs_map.insert(job.id.clone(), job.clone());
+            }
+            let _ = job_updates.send(job.clone());
+
+            // Process job
+            let start_time = Instant::now();
+            let deadline = job_deadline(&cancel, job.configuration.timeout_seconds).await;
+            let progress = Arc::new(ProgressTracker::new(job.configuration.operations.len()));
+            publish_progress(job.id.clone(), progress.clone(), jobs.clone(), job_updates.clone(), deadline.clone()).await;
+            // A panic anywhere in the job fails that job instead of taking the processor down
+            let result = std::panic::AssertUnwindSafe(
+                Self::execute_processing_job(
+                    &job, &data_store, &credentials, &egress_policy, &work_dir, None, &progress, &deadline, &job_logs,
+                ),
+            ).catch_unwind().await.unwrap_or_else(|panic| Err(format!("Job panicked: {}", panic_message(panic.as_ref()))));
+            let timed_out = deadline.is_cancelled() && !cancel.is_cancelled();
+            deadline.cancel();
+            work_dir.read().await.remove_job_dir(&job.id);
+            let execution_time = start_time.elapsed();
+            let timeout_error = timed_out.then(|| format!("Job timed out after {}s", job.configuration.timeout_seconds));
+
+            let error = match &result {
+                _ if timed_out => timeout_error.clone(),
+                _ if cancel.is_cancelled() => Some(JOB_CANCELLED.to_string()),
+                Ok((results, _)) => Self::invariant_failure(results),
+                Err(error) => Some(error.clone()),
+            };
+            job.attempts.push(JobAttempt {
+                number: job.attempts.len() as u32 + 1,
+                started_at: job.started_at.unwrap_or_else(Utc::now),
+                completed_at: Utc::now(),
+                error,
+            });
+            let failures = job.attempts.len() as u32;
+            let retry = result.is_err() && !deadline.is_cancelled() && failures <= job.configuration.retry_attempts;
+            // A retry resumes from the last checkpoint
+            if !retry {
+                cancellations.write().await.remove(&job.id);
+                work_dir.read().await.remove_checkpoint(&job.id);
+            }
+
+            // Update job with results
+            match result {
+                // Like a cancellation, but the interrupted stage goes into the error
+                _ if timed_out => {
+                    let mut error = timeout_error.unwrap_or_default();
+                    job.status = JobStatus::TimedOut;
+                    job.completed_at = Some(Utc::now());
+                    job.error_count += 1;
+                    if let Ok((mut results, _)) = result {
+                        if let Some(last) = results.last_mut().filter(|last| last.operation == "Cancelled") {
+                            last.operation = "TimedOut".to_string();
+                            if let Some(stage) = last.metadata.get("interrupted").and_then(|stage| stage.as_str()) {
+                                error = format!("{} during {}", error, stage);
+                            }
+                        }
+                        job.error_count += results.iter().map(|result| result.errors.len()).sum::<usize>();
+                        job.results = results;
+                    }
+                    job_logs.error(&job.id, format!("Job timed out: {} - {}", job.id, error));
+                    job.error = Some(error);
+                },
+                // Whatever ran before the cancellation took effect stays on the job
+                _ if cancel.is_cancelled() => {
+                    job.status = JobStatus::Cancelled;
+                    job.completed_at = Some(Utc::now());
+                    if let Ok((results, _)) = result {
+                        job.error_count = results.iter().map(|result| result.errors.len()).sum();
+                        job.results = results;
+                    }
+                    job_logs.info(&job.id, format!("Job cancelled: {} after {:?}", job.id, execution_time));
+                },
+                // Violations are deterministic, so the job fails without a retry
+                Ok((results, _)) if Self::invariant_failure(&results).is_some() => {
+                    let error = Self::invariant_failure(&results).unwrap_or_default();
+                    job.status = JobStatus::Failed;
+                    job.completed_at = Some(Utc::now());
+                    job.error_count = results.iter().map(|result| result.errors.len()).sum();
+                    job.results = results;
+                    job_logs.error(&job.id, format!("Job failed: {} - {}", job.id, error));
+                    job.error = Some(error);
+                },
+                Ok((results, records)) => {
+                    job.processed_count = records.len();
+                    job_results.write().await.insert(job.id.clone(), records);
+                    job.status = JobStatus::Completed;
+                    job.completed_at = Some(Utc::now());
+                    job.error = None;
+                    job.error_count = results.iter().map(|result| result.errors.len()).sum();
+                    job.results = results;
+                    job_logs.info(&job.id, format!("Job completed: {} in {:?}", job.id, execution_time));
+                },
+                Err(error) if retry => {
+                    let delay = job_retry_delay(failures);
+                    job.status = JobStatus::Pending;
+                    job.retry_at = chrono::Duration::from_std(delay).ok().map(|delay| Utc::now() + delay);
+                    job.error_count += 1;
+                    job_logs.warn(&job.id, format!(
+                        "Job failed: {} - {} (attempt {} of {}, retrying in {:?})",
+                        job.id, error, failures, job.configuration.retry_attempts + 1, delay,
+                    ));
+                    job.error = Some(error);
+
+                    // Cancelling the job while it waits drops the retry
+                    let (queue, rerun, cancellations) = (queue.clone(), job.clone(), cancellations.clone());
+                    tokio::spawn(async move {
+                        tokio::select! {
+                            _ = tokio::time::sleep(delay) => queue.push(rerun),
+                            _ = cancel.cancelled() => { cancellations.write().await.remove(&rerun.id); },
+                        }
+                    });
+                },
+                // Out of retries, so the job waits in the dead-letter queue to be re-driven
+                Err(error) => {
+                    job.status = JobStatus::Failed;
+                    job.completed_at = Some(Utc::now());
+                    job.dead_lettered_at = job.completed_at;
+                    job.error_count += 1;
+                    job_logs.error(&job.id, format!("Job dead-lettered: {} - {}", job.id, error));
+                    job.error = Some(error);
+                }
+            }
+
+            // Update stored job; one deleted while it ran stays deleted, though its dependents
+            // are still released
+            {
+                let mut jobs_map = jobs.write().await;
+                if jobs_map.contains_key(&job.id) {
+                    Self::keep_comments(&jobs_map, &mut job);
+                    jobs_map.insert(job.id.clone(), job.clone());
+                    let _ = job_updates.send(job.clone());
+                } else {
+                    job_results.write().await.remove(&job.id);
+                }
+                if job.status.is_finished() {
+                    let failed = Self::release_dependents(&mut jobs_map, &job.id, &queue, &job_updates, &job_logs);
+                    let mut cancellations = cancellations.write().await;
+                    for job_id in failed {
+                        cancellations.remove(&job_id);
+                    }
+                }
+            }
+
+            *current.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            drop(slot);
+
+            // Update metrics
+            {
+                let mut metrics_guard = metrics.write().await;
+                metrics_guard.total_records_processed += job.processed_count as u64;
+                metrics_guard.average_processing_time_ms = execution_time.as_millis() as f64;
+            }
+        }
+    }
+
+    /// Notes the size and version of the inputs a starting job is about to read.
+    async fn record_inputs(
+        job: &mut ProcessingJob,
+        data_store: &RwLock<DataStore>,
+        source_versions: &RwLock<HashMap<String, u64>>,
+    ) {
+        let store = data_store.read().await;
+        let versions = source_versions.read().await;
+        let inputs = Self::select_inputs(&store, &job.configuration).unwrap_or_default();
+        if inputs.is_empty() {
+            return;
+        }
+        job.input_count = inputs.iter().map(|(_, records)| records.len()).sum();
+
+        let joined = job.configuration.operations.iter().filter_map(|operation| match operation {
+            Operation::Join { source, .. } => Some(source),
+            _ => None,
+        });
+        let pinned = &job.configuration.pinned_versions;
+        job.input_versions = inputs.iter().map(|(source_id, _)| *source_id).chain(joined)
+            .map(|source| (source.clone(), pinned.get(source).or(versions.get(source)).copied().unwrap_or(0)))
+            .collect();
+    }
+
+    /// The job a worker was running when it crashed will never finish.
+    async fn fail_interrupted_job(
+        jobs: &RwLock<HashMap<String, ProcessingJob>>,
+        job_id: &str,
+        queue: &JobQueue,
+        job_updates: &broadcast::Sender<ProcessingJob>,
+        job_logs: &JobLogs,
+    ) {
+        let mut jobs_map = jobs.write().await;
+        let Some(job) = jobs_map.get_mut(job_id).filter(|job| matches!(job.status, JobStatus::Running)) else {
+            return;
+        };
+        job.status = JobStatus::Failed;
+        job.completed_at = Some(Utc::now());
+        job.error_count += 1;
+        job.error = Some("Interrupted by a job worker crash".to_string());
+        job.progress = None;
+        job_logs.error(job_id, format!("Job failed: {} - interrupted by a job worker crash", job.id));
+        let _ = job_updates.send(job.clone());
+        Self::release_dependents(&mut jobs_map, job_id, queue, job_updates, job_logs);
+    }
+
+    /// Moves the jobs waiting on `finished` along: those whose dependencies have all completed
+    /// are queued, and those with a dependency that did not complete fail, which in turn fails
+    /// their own dependents. Returns the jobs failed this way.
+    fn release_dependents(
+        jobs_map: &mut HashMap<String, ProcessingJob>,
+        finished: &str,
+        queue: &JobQueue,
+        job_updates: &broadcast::Sender<ProcessingJob>,
+        job_logs: &JobLogs,
+    ) -> Vec<String> {
+        let mut failed = Vec::new();
+        let mut settled = vec![finished.to_string()];
+        while let Some(finished) = settled.pop() {
+            let waiting: Vec<String> = jobs_map.values()
+                .filter(|job| matches!(job.status, JobStatus::Waiting) && job.depends_on.contains(&finished))
+                .map(|job| job.id.clone())
+                .collect();
+            for job_id in waiting {
+                let blocker = jobs_map[&job_id].depends_on.iter()
+                    .filter_map(|dependency| jobs_map.get(dependency).map(|dependency| (&dependency.id, &dependency.status)))
+                    .find(|(_, status)| status.is_finished() && !matches!(status, JobStatus::Completed))
+                    .map(|(id, status)| format!("Dependency {} is {:?}", id, status));
+                let ready = jobs_map[&job_id].depends_on.iter()
+                    .all(|dependency| jobs_map.get(dependency).is_some_and(|dependency| matches!(dependency.status, JobStatus::Completed)));
+                let Some(job) = jobs_map.get_mut(&job_id) else { continue };
+                if let Some(error) = blocker {
+                    job.status = JobStatus::Failed;
+                    job.completed_at = Some(Utc::now());
+                    job.error_count += 1;
+                    job_logs.error(&job.id, format!("Job failed: {} - {}", job.id, error));
+                    job.error = Some(error);
+                    failed.push(job_id.clone());
+                    settled.push(job_id);
+                } else if ready {
+                    job.status = JobStatus::Pending;
+                    job_logs.info(&job.id, format!("Job released: {}", job.id));
+                    queue.push(job.clone());
+                } else {
+                    continue;
+                }
+                let _ = job_updates.send(job.clone());
+            }
+        }
+        failed
+    }
+
+    /// Comments may be added while the processor works on its own copy of the job.
+    fn keep_comments(jobs_map: &HashMap<String, ProcessingJob>, job: &mut ProcessingJob) {
+        if let Some(stored) = jobs_map.get(&job.id) {
+            job.comments = stored.comments.clone();
+        }
+    }
+
+    /// Runs a job's operations and sinks. `progress` counts the records the running stage of
+    /// operations has finished. Cancellation is honoured between operations, between batches
+    /// of the record-wise operations and between sinks; the results so far are returned
+    /// with a closing `Cancelled` entry naming the step that was interrupted. With a `sample`
+    /// size the operations run on that many input records and nothing is written.
+    #[allow(clippy::too_many_arguments)]
+    async fn execute_processing_job(
+        job: &ProcessingJob,
+        data_store: &Arc<RwLock<DataStore>>,
+        credentials: &Arc<RwLock<HashMap<String, Credential>>>,
+        egress_policy: &Arc<RwLock<EgressPolicy>>,
+        work_dir: &Arc<RwLock<WorkDir>>,
+        sample: Option<usize>,
+        progress: &ProgressTracker,
+        cancel: &CancellationToken,
+        logs: &JobLogs,
+    ) -> Result<(Vec<ProcessingResult>, Vec<DataRecord>), String> {
+        let mut results = Vec::new();
+        // Only sinks stage files, and a dry run has none
+        let job_dir = match sample {
+            Some(_) => PathBuf::new(),
+            None => work_dir.read().await.create_job_dir(&job.id, job.estimated_disk_bytes.unwrap_or(0))?,
+        };
+        let output_root = work_dir.read().await.output_root().to_path_buf();
+        let mut resumed = match sample {
+            Some(_) => None,
+            None => work_dir.read().await.load_checkpoint(&job.id),
+        };
+        let checkpointer = match (sample, job.configuration.checkpoint_interval_secs) {
+            (None, Some(interval)) => Some(Checkpointer {
+                job,
+                logs,
+                work_dir: work_dir.read().await.clone(),
+                interval: Duration::from_secs(interval),
+                last: std::sync::Mutex::new(Instant::now()),
+            }),
+            _ => None,
+        };
+        
+        let expressions = Self::compile_expressions(&job.configuration.operations)?;
+        // Indexes hold the stored values, which a locale would change before the operations run
+        let indexed = job.configuration.locale.is_none();
+        // A leading Filter reads only the input records an index leaves, unless the input is
+        // sampled or invariants need a baseline of all of it
+        let pushdown = match (job.configuration.operations.first(), expressions.first()) {
+            (Some(Operation::Filter { .. }), Some(Some(condition)))
+                if indexed && sample.is_none() && job.configuration.invariants.is_empty() => Some(condition),
+            _ => None,
+        };
+
+        // Get input data (simplified - assumes single source)
+        let (source_id, data, mut join_inputs, join_indexes, join_stats) = {
+            let store = DataStore::read_loaded(data_store, |store| Self::job_sources(store, &job.configuration)).await?;
+            let (source_id, data) = match &mut resumed {
+                Some(checkpoint) => (checkpoint.source_id.to_string(), std::mem::take(&mut checkpoint.records).into_owned()),
+                None => {
+                    let inputs = Self::select_inputs(&store, &job.configuration)?;
+                    // A sample is taken from the front of the concatenated input
+                    let mut remaining = sample.unwrap_or(usize::MAX);
+                    let mut data = Vec::new();
+                    for (input, records) in &inputs {
+                        if let Some((field, positions)) = pushdown.and_then(|condition| records.index_candidates(condition.comparisons())) {
+                            logs.info(&job.id, format!(
+                                "Filter reads {} of {} records of source {} through its index on {}",
+                                positions.len(), records.len(), input, field,
+                            ));
+                            data.extend(records.pick(&positions));
+                            continue;
+                        }
+                        let count = remaining.min(records.len());
+                        data.extend(records.slice(0..count).into_owned());
+                        remaining -= count;
+                    }
+                    (Self::input_label(&inputs), data)
+                },
+            };
+            // Join inputs are read under the same lock so every operation sees one snapshot
+            let mut join_inputs: HashMap<&str, Vec<DataRecord>> = HashMap::new();
+            let mut join_indexes: HashMap<(&str, &str), Arc<index::FieldIndex>> = HashMap::new();
+            let mut join_stats: HashMap<&str, SourceStats> = HashMap::new();
+            for operation in &job.configuration.operations {
+                if let Operation::Join { source, on } = operation {
+                    let records = store.get_at(source, job.configuration.pinned_versions.get(source).copied())?
+                        .ok_or_else(|| format!("Join source {} not found", source))?;
+                    join_inputs.insert(source, records.records().into_owned());
+                    if let Some(stats) = records.stats() {
+                        join_stats.insert(source, stats.clone());
+                    }
+                    if let Some(index) = records.index(on).filter(|_| indexed) {
+                        join_indexes.insert((source.as_str(), on.as_str()), index.clone());
+                    }
+                }
+            }
+            (source_id, data, join_inputs, join_indexes, join_stats)
+        };
+
+        if data.is_empty() && resumed.is_none() {
+            return Err("No input data available".to_string());
+        }
+
+        let pool = Self::worker_pool(&job.id, job.configuration.parallel_workers)?;
+        let mut current_data = data;
+        if let Some(locale) = &job.configuration.locale {
+            pool.install(|| {
+                // Checkpointed records were normalized by the run that wrote them
+                if resumed.is_none() {
+                    current_data.par_iter_mut().for_each(|record| locale.normalize(&mut record.data));
+                }
+                for records in join_inputs.values_mut() {
+                    records.par_iter_mut().for_each(|record| locale.normalize(&mut record.data));
+                }
+            });
+        }
+        let baselines: Vec<(Invariant, Baseline)> = match &mut resumed {
+            Some(checkpoint) => std::mem::take(&mut checkpoint.baselines).into_owned(),
+            None => job.configuration.invariants.iter()
+                .map(|invariant| (invariant.clone(), invariant.baseline(&current_data)))
+                .collect(),
+        };
+        if let Some(dead_letter) = &job.configuration.dead_letter {
+            // The default `output.<ext>` would collide with the job's own output
+            if dead_letter.format.is_file_output() && dead_letter.output_path.is_none() {
+                return Err("Dead letter file output needs an output_path".to_string());
+            }
+        }
+        
+        let mut dead_letters = Vec::new();
+        let mut index = 0;
+        // A checkpoint taken part way through a stage carries what the stage had produced so far
+        let mut staged = None;
+        if let Some(checkpoint) = resumed {
+            logs.info(&job.id, format!(
+                "Resuming job {} at operation {} after {} records, from its checkpoint of {}",
+                job.id, checkpoint.operation, checkpoint.offset, checkpoint.written_at,
+            ));
+            results = checkpoint.results.into_owned();
+            dead_letters = checkpoint.dead_letters.into_owned();
+            index = checkpoint.operation;
+            staged = checkpoint.staged.map(|output| (checkpoint.offset, output.into_owned()));
+        }
+        let checkpoint = |operation: usize, offset: usize, records: &[DataRecord], staged: Option<&StageOutput>,
+                          results: &[ProcessingResult], dead_letters: &[DataRecord]| {
+            let Some(checkpointer) = &checkpointer else { return };
+            checkpointer.save(JobCheckpoint {
+                written_at: Utc::now(),
+                operation,
+                offset,
+                source_id: Cow::Borrowed(&source_id),
+                records: Cow::Borrowed(records),
+                staged: staged.map(Cow::Borrowed),
+                results: Cow::Borrowed(results),
+                dead_letters: Cow::Borrowed(dead_letters),
+                baselines: Cow::Borrowed(&baselines),
+            });
+        };
+        let interrupted = |mut results: Vec<ProcessingResult>, stage: String, data: Vec<DataRecord>| {
+            results.push(ProcessingResult {
+                operation: "Cancelled".to_string(),
+                records_processed: 0,
+                execution_time_ms: 0,
+                memory_used_bytes: 0,
+                errors: Vec::new(),
+                metadata: HashMap::from([("interrupted".to_string(), json!(stage))]),
+            });
+            Ok((results, data))
+        };
+        
+        // Runs of Filter, Transform and Validate go through together batch by batch; every other
+        // operation needs the whole dataset and runs on its own
+        let operations = &job.configuration.operations;
+        let batch_size = job.configuration.batch_size.max(1);
+        while index < operations.len() {
+            let start_time = Instant::now();
+            let stage_len = match operations[index].is_record_wise() {
+                true => operations[index..].iter().take_while(|operation| operation.is_record_wise()).count(),
+                false => 1,
+            };
+            let stage: Vec<(&Operation, Option<&CompiledExpression>)> = operations[index..index + stage_len].iter()
+                .zip(&expressions[index..index + stage_len])
+                .map(|(operation, expression)| (operation, expression.as_ref()))
+                .collect();
+            let kinds: Vec<String> = stage.iter().map(|(operation, _)| operation.kind()).collect();
+            let stage_name = match stage_len {
+                1 => format!("Operation {} ({})", index, kinds[0]),
+                _ => format!("Operations {}-{} ({})", index, index + stage_len - 1, kinds.join(", ")),
+            };
+            if cancel.is_cancelled() {
+                return interrupted(results, stage_name, current_data);
+            }
+            let staged = staged.take();
+            let done = staged.as_ref().map_or(0, |(offset, _)| *offset);
+            progress.start_stage(&stage_name, index as f64, stage_len as f64, done + current_data.len());
+            progress.advance(done);
+            logs.info(&job.id, format!("{} started for job {} on {} records", stage_name, job.id, done + current_data.len()));
+
+            let dead_letter = job.configuration.dead_letter.is_some();
+            let output = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pool.install(|| match stage[0].0 {
+                operation if operation.is_record_wise() && checkpointer.is_some() => Self::execute_segmented_stage(
+                    &stage, current_data, staged, dead_letter, batch_size, progress, cancel,
+                    &|offset, records, output| checkpoint(index, offset, records, Some(output), &results, &dead_letters),
+                ),
+                operation if operation.is_record_wise() => Self::execute_record_stage(
+                    &stage, current_data, dead_letter, batch_size, progress, cancel,
+                ),
+                Operation::Join { source, on } => {
+                    let mut metadata = HashMap::new();
+                    let index = join_indexes.get(&(source.as_str(), on.as_str())).map(Arc::as_ref);
+                    let joined = Self::execute_join(
+                        current_data, &join_inputs[source.as_str()], on, index, join_stats.get(source.as_str()),
+                        job.configuration.null_semantics.join, &mut metadata,
+                    );
+                    Ok(StageOutput::single(joined, metadata, start_time.elapsed()))
+                },
+                operation => Self::execute_operation(operation, current_data, &job.configuration)
+                    .map(|data| StageOutput::single(data, HashMap::new(), start_time.elapsed())),
+            })));
+            let output = match output {
+                Ok(Err(_)) if cancel.is_cancelled() => return interrupted(results, stage_name, Vec::new()),
+                Ok(output) => output?,
+                Err(panic) => return Err(format!("{} panicked: {}", stage_name, panic_message(panic.as_ref()))),
+            };
+            current_data = output.records;
+            progress.finish_stage();
+            logs.info(&job.id, format!(
+                "{} finished for job {} with {} records in {:?}", stage_name, job.id, current_data.len(), start_time.elapsed(),
+            ));
+
+            for (position, ((operation, _), mut result)) in stage.iter().zip(output.operations).enumerate() {
+                if let Some(first) = result.errors.first() {
+                    logs.warn(&job.id, format!(
+                        "Operation {} ({}) reported {} errors for job {}, the first: {}",
+                        index + position, kinds[position], result.errors.len(), job.id, first.message,
+                    ));
+                }
+                let rejected: Vec<_> = output.rejected.iter().filter(|(at, _, _)| *at == position).collect();
+                if !rejected.is_empty() {
+                    result.metadata.insert("dead_lettered".to_string(), json!(rejected.len()));
+                }
+                result.operation = format!("{:?}", operation);
+                result.memory_used_bytes = std::mem::size_of_val(&current_data);
+                results.push(result);
+            }
+            dead_letters.extend(output.rejected.into_iter().map(|(position, mut record, error)| {
+                record.metadata.insert("dead_letter_error".to_string(), json!(error));
+                record.metadata.insert("dead_letter_operation".to_string(), json!(index + position));
+                record.metadata.insert("dead_letter_operation_kind".to_string(), json!(kinds[position]));
+                record
+            }));
+            index += stage_len;
+            checkpoint(index, 0, &current_data, None, &results, &dead_letters);
+        }
+
+        if cancel.is_cancelled() {
+            return interrupted(results, "Output".to_string(), current_data);
+        }
+
+        // A violation ends the run here, so nothing is written
+        if !baselines.is_empty() {
+            let check_start = Instant::now();
+            let (errors, violations) = invariant::check_all(&baselines, &current_data);
+            results.push(ProcessingResult {
+                operation: "Invariants".to_string(),
+                records_processed: current_data.len(),
+                execution_time_ms: check_start.elapsed().as_millis(),
+                memory_used_bytes: 0,
+                errors,
+                metadata: HashMap::from([
+                    ("checked".to_string(), json!(baselines.len())),
+                    ("violations".to_string(), json!(violations)),
+                ]),
+            });
+            if violations > 0 {
+                return Ok((results, current_data));
+            }
+        }
+
+        // Rejected records go out before the results so a failure here loses nothing
+        let dead_letter = job.configuration.dead_letter.as_ref().filter(|_| sample.is_none() && !dead_letters.is_empty());
+        if let Some(dead_letter) = dead_letter {
+            let output_start = Instant::now();
+            let failures = Self::write_output(job, dead_letter, &dead_letters, &source_id, credentials, egress_policy, &job_dir, &output_root)
+                .await
+                .map_err(|e| format!("Dead letter output failed: {}", e))?;
+            if !failures.is_empty() {
+                return Err(format!("Dead letter output rejected {} records", Self::failed_record_count(&failures)));
+            }
+            logs.warn(&job.id, format!("{} records dead-lettered for job {}", dead_letters.len(), job.id));
+            results.push(ProcessingResult {
+                operation: "Dead letter".to_string(),
+                records_processed: dead_letters.len(),
+                execution_time_ms: output_start.elapsed().as_millis(),
+                memory_used_bytes: 0,
+                errors: Vec::new(),
+                metadata: HashMap::new(),
+            });
+        }
+
+        Self::fit_decimals(&mut current_data, &job.configuration.decimal_fields)?;
+        if sample.is_some() {
+            return Ok((results, current_data));
+        }
+
+        // Every sink gets its own result entry; the job only fails when none succeeded
+        let sinks = job.configuration.sinks();
+        let mut sink_errors = Vec::new();
+        let sink_share = 1.0 / sinks.len().max(1) as f64;
+        for (position, (label, sink)) in sinks.iter().enumerate() {
+            if cancel.is_cancelled() {
+                return interrupted(results, label.clone(), current_data);
+            }
+            let at = operations.len() as f64 + position as f64 * sink_share;
+            progress.start_stage(label, at, sink_share, current_data.len());
+            let output_start = Instant::now();
+            let (records_processed, errors) = match Self::write_output(
+                job, sink, &current_data, &source_id, credentials, egress_policy, &job_dir, &output_root,
+            ).await {
+                // Records the sink rejected individually are reported without failing the job
+                Ok(failures) => {
+                    let rejected = Self::failed_record_count(&failures);
+                    logs.info(&job.id, format!("{} wrote {} records for job {}", label, current_data.len().saturating_sub(rejected), job.id));
+                    if rejected > 0 {
+                        logs.warn(&job.id, format!("{} rejected {} records for job {}", label, rejected, job.id));
+                    }
+                    (current_data.len().saturating_sub(rejected), failures)
+                },
+                Err(error) => {
+                    logs.error(&job.id, format!("{} failed for job {}: {}", label, job.id, error));
+                    sink_errors.push(format!("{}: {}", label, error));
+                    (0, vec![ProcessingError {
+                        error_type: "OutputFailed".to_string(),
+                        message: error,
+                        record_id: None,
+                        timestamp: Utc::now(),
+                        context: HashMap::from([("record_count".to_string(), json!(current_data.len()))]),
+                    }])
+                },
+            };
+            results.push(ProcessingResult {
+                operation: label.clone(),
+                records_processed,
+                execution_time_ms: output_start.elapsed().as_millis(),
+                memory_used_bytes: 0,
+                errors,
+                metadata: HashMap::new(),
+            });
+            progress.finish_stage();
+        }
+        if sink_errors.len() == sinks.len() {
+            return Err(sink_errors.join("; "));
+        }
+
+        Ok((results, current_data))
+    }
+
+    /// The failure a run's closing `Invariants` entry reports, if the invariants stopped it.
+    fn invariant_failure(results: &[ProcessingResult]) -> Option<String> {
+        let last = results.last().filter(|last| last.operation == "Invariants")?;
+        let violations = last.metadata.get("violations").and_then(Value::as_u64).filter(|n| *n > 0)?;
+        Some(format!("Invariant violations: {}", violations))
+    }
+
+    /// Threads a job's operations run on. Rayon work started inside `install` stays on them.
+    fn worker_pool(job_id: &str, workers: usize) -> Result<rayon::ThreadPool, String> {
+        let job_id = job_id.to_string();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(workers)
+            .thread_name(move |index| format!("job-{}-worker-{}", job_id, index))
+            .build()
+            .map_err(|e| format!("Could not start {} workers: {}", workers, e))
+    }
+
+    /// Rounds the configured decimal fields to their scale so every sink writes the same digits.
+    fn fit_decimals(data: &mut [DataRecord], decimals: &BTreeMap<String, DecimalPrecision>) -> Result<(), String> {
+        if decimals.is_empty() {
+            return Ok(());
+        }
+        data.par_iter_mut().try_for_each(|record| {
+            let Value::Object(fields) = &mut record.data else { return Ok(()) };
+            for (name, precision) in decimals {
+                if let Some(value) = fields.get_mut(name).filter(|value| !value.is_null()) {
+                    let decimal = precision.fit(value)
+                        .map_err(|e| format!("Record {} field {}: {}", record.id, name, e))?;
+                    *value = decimal_value(&decimal);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Inner hash join on `on`. Whichever side makes the smaller hash table, judged by the
+    /// joined source's stats, is built into one that is shared read-only by the parallel probes
+    /// over the other side, so the large side is never hashed. Output follows the order of `left`; fields from `right` fill in fields the
+    /// left record does not have. The side sizes and the chosen strategy go into `metadata`.
+    /// With an index of `right` on `on`, the left records probe it instead and nothing is built.
+    fn execute_join(
+        left: Vec<DataRecord>,
+        right: &[DataRecord],
+        on: &str,
+        index: Option<&index::FieldIndex>,
+        stats: Option<&SourceStats>,
+        nulls: NullEquality,
+        metadata: &mut HashMap<String, Value>,
+    ) -> Vec<DataRecord> {
+        let key_of = |record: &DataRecord| match record.data.get(on) {
+            None | Some(Value::Null) if nulls == NullEquality::Distinct => None,
+            None => Some(Value::Null.to_string()),
+            Some(value) => Some(value.to_string()),
+        };
+        let build_table = |records: &[DataRecord], keys: usize| {
+            let mut table: HashMap<String, Vec<usize>> = HashMap::with_capacity(keys);
+            for (index, record) in records.iter().enumerate() {
+                if let Some(key) = key_of(record) {
+                    table.entry(key).or_default().push(index);
+                }
+            }
+            table
+        };
+        let merge = |left: &DataRecord, right: &DataRecord| {
+            let mut joined = left.clone();
+            if let (Value::Object(fields), Value::Object(right_fields)) = (&mut joined.data, &right.data) {
+                for (name, value) in right_fields {
+                    if !fields.contains_key(name) {
+                        fields.insert(name.clone(), value.clone());
+                    }
+                }
+            }
+            joined
+        };
+
+        if let Some(index) = index {
+            let key_of = &key_of;
+            let joined = left.par_iter()
+                .flat_map_iter(|record| {
+                    let key = key_of(record);
+                    // The index keys numbers and numeric strings alike, so candidates are checked
+                    let candidates = match &key {
+                        Some(_) => index.get(&index::Key::of(record.data.get(on))),
+                        None => &[],
+                    };
+                    candidates.iter()
+                        .filter(move |right_index| key_of(&right[**right_index]) == key)
+                        .map(move |right_index| merge(record, &right[*right_index]))
+                })
+                .collect();
+            metadata.insert("strategy".to_string(), json!("index"));
+            metadata.insert("left_records".to_string(), json!(left.len()));
+            metadata.insert("right_records".to_string(), json!(right.len()));
+            metadata.insert("index_distinct_values".to_string(), json!(index.distinct_values()));
+            return joined;
+        }
+
+        // The joined source's stats give its key cardinality without a pass over it. The left
+        // side is whatever earlier operations produced, so every left record is taken to have its
+        // own key, which makes the left side the build side only when it is clearly cheaper
+        let right_rows = stats.map_or(right.len(), |stats| stats.rows);
+        let right_keys = stats.and_then(|stats| stats.distinct(on)).map_or(right_rows, |keys| keys as usize);
+        let table_bytes = |rows: usize, keys: usize| rows * std::mem::size_of::<usize>() + keys * JOIN_KEY_ENTRY_BYTES;
+        let build_left = table_bytes(left.len(), left.len()) < table_bytes(right_rows, right_keys);
+        let (table, joined) = if build_left {
+            let table = build_table(&left, left.len());
+            let mut pairs: Vec<(usize, usize)> = right.par_iter()
+                .enumerate()
+                .flat_map_iter(|(right_index, record)| {
+                    let matches = key_of(record).and_then(|key| table.get(&key)).map(Vec::as_slice).unwrap_or_default();
+                    matches.iter().map(move |left_index| (*left_index, right_index))
+                })
+                .collect();
+            pairs.par_sort_unstable();
+            let joined = pairs.par_iter().map(|(l, r)| merge(&left[*l], &right[*r])).collect();
+            (table, joined)
+        } else {
+            let table = build_table(right, right_keys);
+            let joined = left.par_iter()
+                .flat_map_iter(|record| {
+                    let matches = key_of(record).and_then(|key| table.get(&key)).map(Vec::as_slice).unwrap_or_default();
+                    matches.iter().map(move |right_index| merge(record, &right[*right_index]))
+                })
+                .collect();
+            (table, joined)
+        };
+
+        metadata.insert("strategy".to_string(), json!("broadcast_hash"));
+        metadata.insert("build_side".to_string(), json!(if build_left { "left" } else { "right" }));
+        metadata.insert("left_records".to_string(), json!(left.len()));
+        metadata.insert("right_records".to_string(), json!(right.len()));
+        metadata.insert("build_distinct_keys".to_string(), json!(table.len()));
+        if let Some(keys) = stats.and_then(|stats| stats.distinct(on)) {
+            metadata.insert("right_distinct_keys_estimate".to_string(), json!(keys));
+        }
+        joined
+    }
+
+    /// Groups records by the `group_by` fields (in first-seen order) and emits one record per
+    /// group holding the group values and each function's result.
+    fn aggregate(
+        data: Vec<DataRecord>,
+        group_by: &[String],
+        functions: &[AggregateFunction],
+        nulls: NullAggregation,
+    ) -> Result<Vec<DataRecord>, String> {
+        enum State {
+            Count(u64),
+            Sum(ExactSum),
+            Average { total: ExactSum, count: u64 },
+            // The value itself is kept so the output has all of its digits
+            Min(Option<(f64, Value)>),
+            Max(Option<(f64, Value)>),
+            Distinct(HyperLogLog),
+            Quantiles(TDigest),
+            Top(TopK),
+        }
+
+        for function in functions {
+            match function {
+                AggregateFunction::Custom { name, .. } => {
+                    return Err(format!("Custom aggregate {} is not supported", name));
+                },
+                AggregateFunction::ApproxDistinct { precision, .. }
+                    if !(HyperLogLog::MIN_PRECISION..=HyperLogLog::MAX_PRECISION).contains(precision) => {
+                    return Err(format!(
+                        "HyperLogLog precision must be between {} and {}",
+                        HyperLogLog::MIN_PRECISION, HyperLogLog::MAX_PRECISION,
+                    ));
+                },
+                AggregateFunction::ApproxQuantiles { quantiles, .. } if quantiles.iter().any(|q| !(0.0..=1.0).contains(q)) => {
+                    return Err("Quantiles must be between 0.0 and 1.0".to_string());
+                },
+                AggregateFunction::TopK { k, .. } if !(1..=TopK::MAX_K).contains(k) => {
+                    return Err(format!("TopK k must be between 1 and {}", TopK::MAX_K));
+                },
+                _ => {},
+            }
+        }
+        let new_states = || -> Vec<State> {
+            functions.iter().map(|function| match function {
+                AggregateFunction::Count => State::Count(0),
+                AggregateFunction::Sum { .. } => State::Sum(ExactSum::default()),
+                AggregateFunction::Average { .. } => State::Average { total: ExactSum::default(), count: 0 },
+                AggregateFunction::Min { .. } => State::Min(None),
+                AggregateFunction::Max { .. } => State::Max(None),
+                AggregateFunction::ApproxDistinct { precision, .. } => State::Distinct(HyperLogLog::new(*precision)),
+                AggregateFunction::ApproxQuantiles { .. } => State::Quantiles(TDigest::default()),
+                AggregateFunction::TopK { k, .. } => State::Top(TopK::new(*k)),
+                AggregateFunction::Custom { .. } => unreachable!("rejected above"),
+            }).collect()
+        };
+        let numeric = |value: Option<&Value>| match value {
+            Some(Value::Number(n)) => n.as_f64(),
+            Some(Value::String(text)) => text.trim().parse::<f64>().ok(),
+            _ => None,
+        };
+        let text = |value: &Value| match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        // Values equal as doubles are told apart exactly, as large integers often are
+        let extreme = |current: &mut Option<(f64, Value)>, value: Option<&Value>, wanted: std::cmp::Ordering| {
+            let (Some(n), Some(value)) = (numeric(value), value) else { return };
+            let replace = match current {
+                None => true,
+                Some((m, _)) if n != *m => n.partial_cmp(m) == Some(wanted),
+                Some((_, kept)) => to_decimal(value).zip(to_decimal(kept)).is_some_and(|(a, b)| a.cmp(&b) == wanted),
+            };
+            if replace {
+                *current = Some((n, value.clone()));
+            }
+        };
+
+        let zero = json!(0);
+
+        let source = data.first().map(|record| record.source.clone()).unwrap_or_default();
+        let mut group_index: HashMap<String, usize> = HashMap::new();
+        let mut groups: Vec<(Vec<Value>, Vec<State>)> = Vec::new();
+        for record in &data {
+            let key_values: Vec<Value> = group_by.iter()
+                .map(|field| record.data.get(field).cloned().unwrap_or(Value::Null))
+                .collect();
+            let key = serde_json::to_string(&key_values).map_err(|e| e.to_string())?;
+            let index = *group_index.entry(key).or_insert_with(|| {
+                groups.push((key_values, new_states()));
+                groups.len() - 1
+            });
+
+            for (function, state) in functions.iter().zip(&mut groups[index].1) {
+                let field_value = match function {
+                    AggregateFunction::Sum { field }
+                        | AggregateFunction::Average { field }
+                        | AggregateFunction::Min { field }
+                        | AggregateFunction::Max { field } => match record.data.get(field).filter(|v| !v.is_null()) {
+                            None if nulls == NullAggregation::Zero => Some(&zero),
+                            value => value,
+                        },
+                    AggregateFunction::ApproxDistinct { field, .. }
+                        | AggregateFunction::ApproxQuantiles { field, .. }
+                        | AggregateFunction::TopK { field, .. } => record.data.get(field).filter(|v| !v.is_null()),
+                    _ => None,
+                };
+                match state {
+                    State::Count(count) => *count += 1,
+                    State::Sum(total) => if let Some(value) = field_value {
+                        total.add(value);
+                    },
+                    State::Average { total, count } => if field_value.is_some_and(|value| total.add(value)) {
+                        *count += 1;
+                    },
+                    State::Min(min) => extreme(min, field_value, std::cmp::Ordering::Less),
+                    State::Max(max) => extreme(max, field_value, std::cmp::Ordering::Greater),
+                    State::Distinct(hll) => if let Some(value) = field_value {
+                        hll.insert(&text(value));
+                    },
+                    State::Quantiles(digest) => if let Some(n) = numeric(field_value) {
+                        digest.insert(n);
+                    },
+                    State::Top(top) => if let Some(value) = field_value {
+                        top.insert(&text(value));
+                    },
+                }
+            }
+        }
+
+        let kept = |extreme: Option<(f64, Value)>| match extreme {
+            Some((_, value @ Value::Number(_))) => value,
+            Some((_, value)) => to_decimal(&value).map(|d| decimal_value(&d)).unwrap_or(Value::Null),
+            None => Value::Null,
+        };
+        let mut output = Vec::with_capacity(groups.len());
+        for (key_values, states) in groups {
+            let mut fields = serde_json::Map::new();
+            for (field, value) in group_by.iter().zip(key_values) {
+                fields.insert(field.clone(), value);
+            }
+            for (function, state) in functions.iter().zip(states) {
+                let (name, value) = match (function, state) {
+                    (_, State::Count(count)) => ("count".to_string(), json!(count)),
+                    (AggregateFunction::Sum { field }, State::Sum(total)) => (
+                        format!("sum_{}", field),
+                        total.total().map(|total| decimal_value(&total)).unwrap_or(Value::Null),
+                    ),
+                    (AggregateFunction::Average { field }, State::Average { total, count }) => (
+                        format!("avg_{}", field),
+                        match total.total().and_then(|total| total.to_f64()) {
+                            Some(total) if count > 0 => json!(total / count as f64),
+                            _ => Value::Null,
+                        },
+                    ),
+                    (AggregateFunction::Min { field }, State::Min(min)) => (format!("min_{}", field), kept(min)),
+                    (AggregateFunction::Max { field }, State::Max(max)) => (format!("max_{}", field), kept(max)),
+                    (AggregateFunction::ApproxDistinct { field, .. }, State::Distinct(hll)) => (
+                        format!("approx_distinct_{}", field),
+                        json!(hll.estimate()),
+                    ),
+                    (AggregateFunction::ApproxQuantiles { field, quantiles }, State::Quantiles(mut digest)) => (
+                        format!("quantiles_{}", field),
+                        Value::Object(quantiles.iter()
+                            .map(|q| (format!("p{}", (q * 1e4).round() / 1e2), json!(digest.quantile(*q))))
+                            .collect()),
+                    ),
+                    (AggregateFunction::TopK { field, .. }, State::Top(top)) => (
+                        format!("top_{}", field),
+                        top.top().into_iter().map(|(value, count)| json!({"value": value, "count": count})).collect(),
+                    ),
+                    _ => unreachable!("states are built from the same functions"),
+                };
+                fields.insert(name, value);
+            }
+            output.push(DataRecord {
+                id: Uuid::new_v4().to_string(),
+                timestamp: Utc::now(),
+                data: Value::Object(fields),
+                source: source.clone(),
+                processed: false,
+                metadata: HashMap::new(),
+            });
+        }
+
+        Ok(output)
+    }
+
+    /// Compiles every filter condition and transform expression up front, so a bad expression
+    /// fails the job before any operation runs and records are never re-parsed.
+    fn compile_expressions(operations: &[Operation]) -> Result<Vec<Option<CompiledExpression>>, String> {
+        let mut fields = FieldInterner::default();
+        operations.iter()
+            .map(|operation| match operation {
+                Operation::Filter { condition } => CompiledExpression::compile(condition, &mut fields).map(Some),
+                Operation::Transform { expression, .. } => CompiledExpression::compile(expression, &mut fields).map(Some),
+                _ => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Runs consecutive record-wise operations on the current rayon pool. The records are split
+    /// into one contiguous partition per worker, and each worker passes its partition through
+    /// every operation `batch_size` records at a time, so no operation's whole output is held
+    /// next to its input. `progress` advances as each batch finishes and `cancel` is
+    /// checked between batches. Each operation's result has per-worker counts and times under
+    /// `workers`.
+    fn execute_record_stage(
+        stage: &[(&Operation, Option<&CompiledExpression>)],
+        data: Vec<DataRecord>,
+        dead_letter: bool,
+        batch_size: usize,
+        progress: &ProgressTracker,
+        cancel: &CancellationToken,
+    ) -> Result<StageOutput, String> {
+        struct Work {
+            records: Vec<DataRecord>,
+            rejected: Vec<(usize, DataRecord, String)>,
+            passed: Vec<usize>,
+            elapsed: Vec<Duration>,
+        }
+
+        let partition = data.len().div_ceil(rayon::current_num_threads()).max(1);
+        let work: Vec<Work> = data.into_par_iter().chunks(partition)
+            .map(|records| {
+                let mut work = Work {
+                    records: Vec::with_capacity(records.len()),
+                    rejected: Vec::new(),
+                    passed: vec![0; stage.len()],
+                    elapsed: vec![Duration::ZERO; stage.len()],
+                };
+                let mut records = records.into_iter();
+                loop {
+                    if cancel.is_cancelled() {
+                        return Err(JOB_CANCELLED.to_string());
+                    }
+                    let mut batch: Vec<DataRecord> = records.by_ref().take(batch_size).collect();
+                    if batch.is_empty() {
+                        return Ok(work);
+                    }
+                    let taken = batch.len();
+                    for (position, (operation, expression)) in stage.iter().enumerate() {
+                        let start = Instant::now();
+                        let mut rejected = Vec::new();
+                        batch = Self::apply_to_batch(operation, *expression, batch, dead_letter.then_some(&mut rejected));
+                        work.rejected.extend(rejected.into_iter().map(|(record, error)| (position, record, error)));
+                        work.passed[position] += batch.len();
+                        work.elapsed[position] += start.elapsed();
+                    }
+                    work.records.append(&mut batch);
+                    progress.advance(taken);
+                }
+            })
+            .collect::<Result<_, String>>()?;
+
+        let operations = (0..stage.len())
+            .map(|position| {
+                let workers = work.iter().enumerate()
+                    .map(|(worker, work)| json!({
+                        "worker": worker,
+                        "records": work.passed[position],
+                        "execution_time_ms": work.elapsed[position].as_millis(),
+                    }))
+                    .collect();
+                ProcessingResult {
+                    operation: String::new(),
+                    records_processed: work.iter().map(|work| work.passed[position]).sum(),
+                    // Workers run side by side, so the slowest one is the operation's time
+                    execution_time_ms: work.iter().map(|work| work.elapsed[position].as_millis()).max().unwrap_or(0),
+                    memory_used_bytes: 0,
+                    errors: Vec::new(),
+                    metadata: HashMap::from([("workers".to_string(), Value::Array(workers))]),
+                }
+            })
+            .collect();
+        let mut output = StageOutput { records: Vec::new(), operations, rejected: Vec::new() };
+        for work in work {
+            output.records.extend(work.records);
+            output.rejected.extend(work.rejected);
+        }
+        Ok(output)
+    }
+
+    /// Runs a record-wise stage a few batches per worker at a time, handing `checkpoint` the
+    /// count of input records done, the input still to go and the output so far between
+    /// segments. `staged` is that count and output when resuming part way through the stage.
+    #[allow(clippy::too_many_arguments)]
+    fn execute_segmented_stage(
+        stage: &[(&Operation, Option<&CompiledExpression>)],
+        mut data: Vec<DataRecord>,
+        staged: Option<(usize, StageOutput)>,
+        dead_letter: bool,
+        batch_size: usize,
+        progress: &ProgressTracker,
+        cancel: &CancellationToken,
+        checkpoint: &(dyn Fn(usize, &[DataRecord], &StageOutput) + Sync),
+    ) -> Result<StageOutput, String> {
+        let (mut done, mut output) = staged.unwrap_or_default();
+        let segment = batch_size.saturating_mul(rayon::current_num_threads()).saturating_mul(CHECKPOINT_SEGMENT_BATCHES);
+        while !data.is_empty() {
+            let records: Vec<DataRecord> = data.drain(..segment.min(data.len())).collect();
+            done += records.len();
+            output.absorb(Self::execute_record_stage(stage, records, dead_letter, batch_size, progress, cancel)?);
+            if !data.is_empty() {
+                checkpoint(done, &data, &output);
+            }
+        }
+        Ok(output)
+    }
+
+    /// Applies Filter, Transform or Validate to one batch. With `rejected`, records Validate
+    /// fails on are moved there with their error instead of passing through.
+    fn apply_to_batch(
+        operation: &Operation,
+        expression: Option<&CompiledExpression>,
+        mut batch: Vec<DataRecord>,
+        rejected: Option<&mut Vec<(DataRecord, String)>>,
+    ) -> Vec<DataRecord> {
+        match (operation, expression) {
+            (Operation::Filter { .. }, Some(condition)) => {
+                batch.retain(|record| condition.matches(record));
+                batch
+            },
+            (Operation::Transform { field, .. }, Some(expression)) => {
+                for record in &mut batch {
+                    let value = expression.evaluate(record);
+                    if let Value::Object(map) = &mut record.data {
+                        map.insert(field.clone(), value);
+                    }
+                }
+                batch
+            },
+            (Operation::Validate { rules }, _) => {
+                let patterns = Self::compile_patterns(rules);
+                let Some(rejected) = rejected else {
+                    for record in &batch {
+                        for (rule, pattern) in rules.iter().zip(&patterns) {
+                            if let Err(error) = Self::validate_record(record, rule, pattern.as_ref()) {
+                                // In a real implementation, you'd collect validation errors
+                                println!("Validation error for record {}: {}", record.id, error);
+                            }
+                        }
+                    }
+                    return batch;
+                };
+
+                let mut valid = Vec::with_capacity(batch.len());
+                for record in batch {
+                    let errors: Vec<String> = rules.iter().zip(&patterns)
+                        .filter_map(|(rule, pattern)| Self::validate_record(&record, rule, pattern.as_ref()).err())
+                        .collect();
+                    if errors.is_empty() {
+                        valid.push(record);
+                    } else {
+                        rejected.push((record, errors.join("; ")));
+                    }
+                }
+                valid
+            },
+            _ => batch,
+        }
+    }
+
+    /// Applies one operation that needs the whole dataset, on the current rayon pool.
+    fn execute_operation(operation: &Operation, mut data: Vec<DataRecord>, config: &ProcessingConfig) -> Result<Vec<DataRecord>, String> {
+        let nulls = &config.null_semantics;
+        match operation {
+            Operation::Aggregate { group_by, functions } => Self::aggregate(data, group_by, functions, nulls.aggregates),
+            Operation::Sort { fields, ascending } => {
+                let collation = config.locale.as_ref().map(|locale| locale.collation).unwrap_or_default();
+                data.sort_by(|a, b| {
+                    // Simplified sorting by first field
+                    if let Some(field) = fields.first() {
+                        let a_val = a.data.get(field).unwrap_or(&Value::Null);
+                        let b_val = b.data.get(field).unwrap_or(&Value::Null);
+
+                        // Nulls keep their place whichever way the values sort
+                        match (a_val.is_null(), b_val.is_null(), nulls.sort) {
+                            (true, true, _) => std::cmp::Ordering::Equal,
+                            (true, false, NullOrder::First) | (false, true, NullOrder::Last) => std::cmp::Ordering::Less,
+                            (true, false, NullOrder::Last) | (false, true, NullOrder::First) => std::cmp::Ordering::Greater,
+                            _ if *ascending => collation.compare(a_val, b_val),
+                            _ => collation.compare(b_val, a_val),
+                        }
+                    } else {
+                        std::cmp::Ordering::Equal
+                    }
+                });
+                Ok(data)
+            },
+            Operation::Deduplicate { fields } => {
+                let mut seen = std::collections::HashSet::new();
+                data.retain(|record| {
+                    let values: Vec<&Value> = fields.iter()
+                        .map(|field| record.data.get(field).unwrap_or(&Value::Null))
+                        .collect();
+                    // A null is a duplicate of nothing, so the record always stays
+                    if nulls.deduplicate == NullEquality::Distinct && values.iter().any(|value| value.is_null()) {
+                        return true;
+                    }
+                    seen.insert(values.iter().map(|value| value.to_string()).collect::<Vec<_>>())
+                });
+                Ok(data)
+            },
+            _ => {
+                // Placeholder for other operations
+                Ok(data)
+            }
+        }
+    }
+
+    /// The compiled regex of each Pattern rule, `None` for other rules and for a regex that
+    /// does not compile, which `validate_record` then fails every record on.
+    fn compile_patterns(rules: &[ValidationRule]) -> Vec<Option<regex_lite::Regex>> {
+        rules.iter()
+            .map(|rule| match &rule.rule_type {
+                ValidationType::Pattern { regex } => regex_lite::Regex::new(regex).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn validate_record(record: &DataRecord, rule: &ValidationRule, pattern: Option<&regex_lite::Regex>) -> Result<(), String> {
+        let field_value = record.data.get(&rule.field);
+        
+        match &rule.rule_type {
+            ValidationType::Required if field_value.is_none() || field_value == Some(&Value::Null) => {
+                return Err(format!("Field {} is required", rule.field));
+            },
+            ValidationType::DataType { expected_type } => {
+                if let Some(value) = field_value {
+                    let actual_type = match value {
+                        Value::String(_) => "string",
+                        Value::Number(_) => "number",
+                        Value::Bool(_) => "boolean",
+                        Value::Array(_) => "array",
+                        Value::Object(_) => "object",
+                        Value::Null => "null",
+                    };
+                    
+                    if actual_type != expected_type {
+                        return Err(format!("Field {} expected type {}, got {}", 
+                                         rule.field, expected_type, actual_type));
+                    }
+                }
+            },
+            ValidationType::Range { min, max } => {
+                if let Some(Value::Number(num)) = field_value {
+                    if let Some(val) = num.as_f64() {
+                        if val < *min || val > *max {
+                            return Err(format!("Field {} value {} out of range [{}, {}]", 
+                                             rule.field, val, min, max));
+                        }
+                    }
+                }
+            },
+            ValidationType::Length { min_length, max_length } => {
+                let length = match field_value {
+                    Some(Value::String(text)) => Some(text.chars().count()),
+                    Some(Value::Array(items)) => Some(items.len()),
+                    _ => None,
+                };
+                if let Some(length) = length.filter(|length| length < min_length || length > max_length) {
+                    return Err(format!("Field {} length {} out of range [{}, {}]", rule.field, length, min_length, max_length));
+                }
+            },
+            ValidationType::Pattern { regex } => {
+                if let Some(Value::String(text)) = field_value {
+                    match pattern {
+                        Some(pattern) if pattern.is_match(text) => {},
+                        Some(_) => return Err(format!("Field {} does not match pattern {}", rule.field, regex)),
+                        None => return Err(format!("Field {} has an invalid pattern {}", rule.field, regex)),
+                    }
+                }
+            },
+            _ => {
+                // Placeholder for other validation types
+            }
+        }
+        
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn write_output(
+        job: &ProcessingJob,
+        sink: &OutputSpec,
+        data: &[DataRecord],
+        source_id: &str,
+        credentials: &Arc<RwLock<HashMap<String, Credential>>>,
+        egress_policy: &Arc<RwLock<EgressPolicy>>,
+        staging_dir: &Path,
+        output_root: &Path,
+    ) -> Result<Vec<ProcessingError>, String> {
+        let is_file_output = sink.format.is_file_output();
+        let mapped;
+        let data = match &sink.output_columns {
+            Some(columns) => {
+                mapped = Self::map_output_columns(data, columns)?;
+                mapped.as_slice()
+            },
+            None => data,
+        };
+        let retry_attempts = job.configuration.retry_attempts;
+        let decimals = &job.configuration.decimal_fields;
+        let encryption_key = match &sink.output_encryption {
+            Some(_) if !is_file_output => return Err("Output encryption only applies to file outputs".to_string()),
+            Some(encryption) => Some(encryption.key()?),
+            None => None,
+        };
+        let (mut output_format, used_credentials) = Self::resolve_credentials(&sink.format, credentials).await?;
+        // Mapped CSV output carries exactly the mapped columns, in mapping order
+        if let (OutputFormat::Csv { columns: columns @ None }, Some(mapping)) = (&mut output_format, &sink.output_columns) {
+            *columns = Some(mapping.iter().map(|column| column.name.clone()).collect());
+        }
+        // Checked after resolution so credential references cannot smuggle in a destination
+        let egress = egress_policy.read().await.clone();
+        egress.check_output(&output_format).await?;
+
+        match &sink.partition_by {
+            Some(partitioning) if is_file_output => {
+                let mut partitions: BTreeMap<String, Vec<DataRecord>> = BTreeMap::new();
+                for record in data {
+                    partitions.entry(partitioning.partition_path(record)).or_default().push(record.clone());
+                }
+
+                let mut failures = Vec::new();
+                for (partition, records) in &partitions {
+                    let target = OutputTarget {
+                        template: sink.output_path.as_deref(),
+                        job_id: &job.id,
+                        source: source_id,
+                        partition: Some(partition),
+                        compression: sink.output_compression,
+                        encryption_key,
+                        staging_dir,
+                        output_root,
+                    };
+                    failures.extend(Self::output_results(records, &output_format, &target, &egress, retry_attempts, decimals).await?);
+                }
+                Ok(failures)
+            },
+            _ => {
+                let target = OutputTarget {
+                    template: sink.output_path.as_deref(),
+                    job_id: &job.id,
+                    source: source_id,
+                    partition: None,
+                    compression: sink.output_compression,
+                    encryption_key,
+                    staging_dir,
+                    output_root,
+                };
+                match Self::output_results(data, &output_format, &target, &egress, retry_attempts, decimals).await {
+                    // A credential rotated while the job ran: reconnect once with the current secret
+                    Err(e) if Self::credentials_rotated(&used_credentials, credentials).await => {
+                        println!("Output failed after credential rotation, reconnecting: {}", e);
+                        let (output_format, _) = Self::resolve_credentials(&sink.format, credentials).await?;
+                        let egress = egress_policy.read().await.clone();
+                        egress.check_output(&output_format).await?;
+                        Self::output_results(data, &output_format, &target, &egress, retry_attempts, decimals).await
+                    },
+                    result => result,
+                }
+            }
+        }
+    }
+
+    /// Replaces each record's data with the mapped columns; the envelope is kept.
+    fn map_output_columns(data: &[DataRecord], columns: &[OutputColumn]) -> Result<Vec<DataRecord>, String> {
+        let mut names = HashSet::new();
+        for column in columns {
+            if column.from.is_some() && column.value.is_some() {
+                return Err(format!("Output column {} cannot have both from and value", column.name));
+            }
+            if !names.insert(column.name.as_str()) {
+                return Err(format!("Output column {} is listed twice", column.name));
+            }
+        }
+
+        Ok(data.par_iter().map(|record| {
+            let fields: serde_json::Map<String, Value> = columns.iter()
+                .map(|column| (column.name.clone(), column.resolve(record)))
+                .collect();
+            DataRecord {
+                id: record.id.clone(),
+                timestamp: record.timestamp,
+                data: Value::Object(fields),
+                source: record.source.clone(),
+                processed: record.processed,
+                metadata: record.metadata.clone(),
+            }
+        }).collect())
+    }
+
+    /// Substitutes `${credential:<name>}` references in connector settings, returning the
+    /// resolved format and the version of every credential it used.
+    async fn resolve_credentials(
+        output_format: &OutputFormat,
+        credentials: &RwLock<HashMap<String, Credential>>,
+    ) -> Result<(OutputFormat, BTreeMap<String, u64>), String> {
+        let mut value = serde_json::to_value(output_format).map_err(|e| e.to_string())?;
+        let mut used = BTreeMap::new();
+        let credentials = credentials.read().await;
+        Self::substitute_credentials(&mut value, &credentials, &mut used)?;
+        drop(credentials);
+
+        let output_format = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        Ok((output_format, used))
+    }
+
+    fn substitute_credentials(
+        value: &mut Value,
+        credentials: &HashMap<String, Credential>,
+        used: &mut BTreeMap<String, u64>,
+    ) -> Result<(), String> {
+        match value {
+            Value::String(text) if text.contains(CREDENTIAL_REF_PREFIX) => {
+                let mut resolved = String::new();
+                let mut rest = text.as_str();
+                while let Some(start) = rest.find(CREDENTIAL_REF_PREFIX) {
+                    let name_start = start + CREDENTIAL_REF_PREFIX.len();
+                    let end = rest[name_start..].find('}')
+                        .map(|end| name_start + end)
+                        .ok_or("Unterminated credential reference")?;
+                    let name = &rest[name_start..end];
+                    let credential = credentials.get(name)
+                        .ok_or_else(|| format!("Credential {} not found", name))?;
+                    used.insert(name.to_string(), credential.version);
+                    resolved.push_str(&rest[..start]);
+                    resolved.push_str(&credential.secret);
+                    rest = &rest[end + 1..];
+                }
+                resolved.push_str(rest);
+                *text = resolved;
+            },
+            Value::Array(items) => {
+                for item in items {
+                    Self::substitute_credentials(item, credentials, used)?;
+                }
+            },
+            Value::Object(fields) => {
+                for field in fields.values_mut() {
+                    Self::substitute_credentials(field, credentials, used)?;
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    async fn credentials_rotated(used: &BTreeMap<String, u64>, credentials: &RwLock<HashMap<String, Credential>>) -> bool {
+        let credentials = credentials.read().await;
+        used.iter().any(|(name, version)| credentials.get(name).map(|c| c.version) != Some(*version))
+    }
+
+    async fn output_results(
+        data: &[DataRecord],
+        output_format: &OutputFormat,
+        target: &OutputTarget<'_>,
+        egress: &EgressPolicy,
+        retry_attempts: u32,
+        decimals: &BTreeMap<String, DecimalPrecision>,
+    ) -> Result<Vec<ProcessingError>, String> {
+        let mut failures = Vec::new();
+        match output_format {
+            OutputFormat::Json => {
+                let staged = target.stage(&target.compression.extension("json"))?;
+                let mut file = BufWriter::new(OutputWriter::create(&staged.path, target.compression, target.encryption_key.as_ref())?);
+                // Serializes record by record into the buffered writer
+                serde_json::to_writer_pretty(&mut file, data)
+                    .map_err(|e| e.to_string())?;
+                file.into_inner().map_err(|e| e.to_string())?.finish()?;
+                let path = staged.publish()?;
+                
+                println!("Results written to {}", path);
+            },
+            OutputFormat::JsonLines { data_only } => {
+                let staged = target.stage(&target.compression.extension("jsonl"))?;
+                let mut file = BufWriter::new(OutputWriter::create(&staged.path, target.compression, target.encryption_key.as_ref())?);
+                for record in data {
+                    if *data_only {
+                        serde_json::to_writer(&mut file, &record.data)
+                    } else {
+                        serde_json::to_writer(&mut file, record)
+                    }.map_err(|e| e.to_string())?;
+                    file.write_all(b"\n").map_err(|e| e.to_string())?;
+                }
+                file.into_inner().map_err(|e| e.to_string())?.finish()?;
+                let path = staged.publish()?;
+
+                println!("Results written to {}", path);
+            },
+            OutputFormat::Csv { columns } => {
+                let staged = target.stage(&target.compression.extension("csv"))?;
+                let mut wtr = csv::Writer::from_writer(OutputWriter::create(&staged.path, target.compression, target.encryption_key.as_ref())?);
+                let explicit = columns.is_some();
+                let columns = match columns {
+                    Some(columns) => columns.clone(),
+                    None => Self::csv_columns(data),
+                };
+                wtr.write_record(&columns).map_err(|e| e.to_string())?;
+                
+                let mut fields = Vec::new();
+                for record in data {
+                    fields.clear();
+                    Self::flatten_fields(None, &record.data, &mut fields);
+                    let row: HashMap<&str, &Value> = fields.iter().map(|(name, value)| (name.as_str(), *value)).collect();
+                    let cells: Vec<Cow<str>> = columns.iter().map(|column| match column.as_str() {
+                        _ if explicit && row.contains_key(column.as_str()) => match row[column.as_str()] {
+                            Value::String(text) => Cow::Borrowed(text.as_str()),
+                            Value::Null => Cow::Borrowed(""),
+                            value => Cow::Owned(value.to_string()),
+                        },
+                        "id" => Cow::Borrowed(record.id.as_str()),
+                        "timestamp" => Cow::Owned(record.timestamp.to_rfc3339()),
+                        "source" => Cow::Borrowed(record.source.as_str()),
+                        _ => match row.get(column.as_str()) {
+                            Some(Value::String(text)) => Cow::Borrowed(text.as_str()),
+                            Some(Value::Null) | None => Cow::Borrowed(""),
+                            Some(value) => Cow::Owned(value.to_string()),
+                        },
+                    }).collect();
+                    wtr.write_record(cells.iter().map(|cell| cell.as_bytes())).map_err(|e| e.to_string())?;
+                }
+                
+                wtr.into_inner().map_err(|e| e.to_string())?.finish()?;
+                let path = staged.publish()?;
+                println!("Results written to {}", path);
+            },
+            OutputFormat::Api { endpoint, headers, batch_size } => {
+                let client = egress.client(endpoint, Client::builder()).await?;
+                failures = Self::write_webhook(&client, data, endpoint, headers, *batch_size, retry_attempts).await?;
+                println!("Results sent to API endpoint: {} ({} batches failed)", endpoint, failures.len());
+            },
+            OutputFormat::Parquet { schema, row_group_size, compression } => {
+                let schema = match schema {
+                    Some(schema) => schema.clone(),
+                    None => Self::infer_output_schema(data, decimals),
+                };
+                let staged = target.stage("parquet")?;
+                Self::write_parquet(&staged.path, data, &schema, *row_group_size, *compression, target.encryption_key.as_ref())?;
+                let path = staged.publish()?;
+                println!("Results written to {}", path);
+            },
+            OutputFormat::Avro { schema, codec } => {
+                let staged = target.stage("avro")?;
+                Self::write_avro(&staged.path, data, schema.as_ref(), decimals, *codec, target.encryption_key.as_ref())?;
+                let path = staged.publish()?;
+                println!("Results written to {}", path);
+            },
+            OutputFormat::Database { connection_string, table, batch_size, create_table, conflict_keys, on_conflict } => {
+                Self::write_database(
+                    data, connection_string, table, *batch_size, *create_table, conflict_keys, *on_conflict, decimals,
+                ).await?;
+                println!("Results written to database table {}", table);
+            },
+            OutputFormat::Kafka { brokers, topic, key_field, encoding } => {
+                Self::write_kafka(data, brokers, topic, key_field.as_deref(), encoding, decimals, egress).await?;
+                println!("Results published to Kafka topic {}", topic);
+            },
+            OutputFormat::Elasticsearch { url, index, id_field, batch_size, max_retries, headers } => {
+                let client = egress.client(url, Client::builder()).await?;
+                failures = Self::write_elasticsearch(
+                    &client, data, url, index, id_field.as_deref(), *batch_size, *max_retries, headers, target.job_id,
+                ).await?;
+                println!("Results indexed to {} ({} documents failed)", url, failures.len());
+            },
+            OutputFormat::MongoDb { connection_string, database, collection, mode, id_field, batch_size } => {
+                failures = Self::write_mongodb(
+                    data, connection_string, database, collection, *mode, id_field.as_deref(), *batch_size,
+                ).await?;
+                println!("Results written to MongoDB collection {} ({} documents failed)", collection, failures.len());
+            },
+            OutputFormat::DeltaLake { table_uri, storage_options, partition_columns, schema_mode, compression, row_group_size } => {
+                let version = Self::write_delta(
+                    data, table_uri, storage_options, partition_columns, *schema_mode, *compression, *row_group_size, decimals, target.job_id,
+                    target.output_root,
+                ).await?;
+                println!("Results appended to Delta table {} (version {})", table_uri, version);
+            },
+            OutputFormat::Redis { url, key_field, key_prefix, value_type, ttl_seconds, batch_size } => {
+                failures = Self::write_redis(
+                    data, url, key_field, key_prefix, *value_type, *ttl_seconds, *batch_size,
+                ).await?;
+                println!("Results written to Redis ({} records without a key)", failures.len());
+            },
+        }
+        
+        Ok(failures)
+    }
+
+    /// Maps an output column name onto the Avro name grammar (`[A-Za-z_][A-Za-z0-9_]*`).
+    fn avro_field_name(name: &str) -> String {
+        let mut avro_name: String = name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+            .collect();
+        if !avro_name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            avro_name.insert(0, '_');
+        }
+        avro_name
+    }
+
+    fn write_avro(
+        path: &str,
+        data: &[DataRecord],
+        schema: Option<&Value>,
+        decimals: &BTreeMap<String, DecimalPrecision>,
+        codec: AvroCodec,
+        key: Option<&[u8; 32]>,
+    ) -> Result<(), String> {
+        let layout = AvroLayout::new(data, schema, decimals)?;
+        let avro_codec = match codec {
+            AvroCodec::Null => Codec::Null,
+            AvroCodec::Deflate => Codec::Deflate(DeflateSettings::default()),
+            AvroCodec::Snappy => Codec::Snappy,
+            AvroCodec::Zstd => Codec::Zstandard(ZstandardSettings::default()),
+        };
+        let file = OutputFile::create(path, key)?;
+        let mut writer = AvroWriter::with_codec(&layout.schema, file, avro_codec).map_err(|e| e.to_string())?;
+
+        for record in data {
+            writer.append_value(layout.record_value(record)?).map_err(|e| e.to_string())?;
+        }
+
+        writer.into_inner().map_err(|e| e.to_string())?.finish()
+    }
+
+    async fn write_kafka(
+        data: &[DataRecord],
+        brokers: &[String],
+        topic: &str,
+        key_field: Option<&str>,
+        encoding: &KafkaEncoding,
+        decimals: &BTreeMap<String, DecimalPrecision>,
+        egress: &EgressPolicy,
+    ) -> Result<(), String> {
+        // Values are encoded per produce batch so the topic payload is never held in full
+        let avro = match encoding {
+            KafkaEncoding::Json => None,
+            KafkaEncoding::Avro { schema, schema_registry } => {
+                let layout = AvroLayout::new(data, schema.as_ref(), decimals)?;
+                // Confluent framing: magic byte 0 followed by the big-endian schema id
+                let prefix = match schema_registry {
+                    Some(registry) => {
+                        let subject = format!("{}-value", topic);
+                        let client = egress.client(registry, Client::builder()).await?;
+                        let schema_id = Self::register_avro_schema(&client, registry, &subject, &layout.json).await?;
+                        let mut prefix = vec![0u8];
+                        prefix.extend(schema_id.to_be_bytes());
+                        prefix
+                    },
+                    None => Vec::new(),
+                };
+                Some((layout, prefix))
+            },
+        };
+        let datum_writer = avro.as_ref()
+            .map(|(layout, _)| GenericDatumWriter::builder(&layout.schema).build())
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let encode = |record: &DataRecord| -> Result<Vec<u8>, String> {
+            match (&avro, &datum_writer) {
+                (Some((layout, prefix)), Some(datum_writer)) => {
+                    let datum = datum_writer.write_value_to_vec(layout.record_value(record)?)
+                        .map_err(|e| e.to_string())?;
+                    Ok([prefix.as_slice(), &datum].concat())
+                },
+                _ => serde_json::to_vec(record).map_err(|e| e.to_string()),
+            }
+        };
+
+        let client = ClientBuilder::new(brokers.to_vec()).build().await.map_err(|e| e.to_string())?;
+        let partitions: Vec<i32> = client.list_topics().await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .find(|t| t.name == topic)
+            .map(|t| t.partitions.into_iter().collect())
+            .unwrap_or_default();
+        if partitions.is_empty() {
+            return Err(format!("Kafka topic {} not found", topic));
+        }
+
+        // Keyed records follow Kafka's default partitioner so consumers see per-key ordering
+        let key_of = |record: &DataRecord| key_field
+            .and_then(|field| Self::output_field_value(record, field))
+            .and_then(|value| match value.as_ref() {
+                Value::Null => None,
+                Value::String(text) => Some(text.clone().into_bytes()),
+                other => Some(other.to_string().into_bytes()),
+            });
+        let mut batches: BTreeMap<i32, Vec<&DataRecord>> = BTreeMap::new();
+        for (index, record) in data.iter().enumerate() {
+            let slot = match key_of(record) {
+                Some(key) => (kafka_murmur2(&key) & 0x7fff_ffff) as usize % partitions.len(),
+                None => index % partitions.len(),
+            };
+            batches.entry(partitions[slot]).or_default().push(record);
+        }
+
+        for (partition, records) in batches {
+            let partition_client = client.partition_client(topic, partition, UnknownTopicHandling::Error).await
+                .map_err(|e| e.to_string())?;
+            for chunk in records.chunks(KAFKA_PRODUCE_BATCH) {
+                let messages = chunk.iter().map(|record| Ok(KafkaRecord {
+                    key: key_of(record),
+                    value: Some(encode(record)?),
+                    headers: BTreeMap::new(),
+                    timestamp: record.timestamp,
+                })).collect::<Result<Vec<_>, String>>()?;
+                partition_client.produce(messages, KafkaCompression::NoCompression).await
+                    .map_err(|e| format!("Kafka produce to {}/{} failed: {}", topic, partition, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Posts records in batches, retrying 429, 5xx and connection failures with exponential
+    /// backoff; batches that still fail are reported rather than aborting the job.
+    async fn write_webhook(
+        client: &Client,
+        data: &[DataRecord],
+        endpoint: &str,
+        headers: &HashMap<String, String>,
+        batch_size: Option<usize>,
+        retry_attempts: u32,
+    ) -> Result<Vec<ProcessingError>, String> {
+        let mut failures = Vec::new();
+        let batch_size = batch_size.unwrap_or(data.len()).max(1);
+        // An empty result is still posted, as `[]`, so the endpoint hears that the job ran
+        let batches: Vec<&[DataRecord]> = if data.is_empty() { vec![data] } else { data.chunks(batch_size).collect() };
+
+        for (batch, records) in batches.into_iter().enumerate() {
+            let mut attempt = 0;
+            let mut backoff = OUTPUT_RETRY_BACKOFF;
+            let error = loop {
+                let mut request = client.post(endpoint);
+                for (key, value) in headers {
+                    request = request.header(key, value);
+                }
+                let (error, retryable) = match Self::send_json_stream(request, records).await {
+                    Ok(response) if response.status().is_success() => break None,
+                    Ok(response) => {
+                        let status = response.status();
+                        let retryable = status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+                        (format!("API request failed: {}", status), retryable)
+                    },
+                    Err(e) => (e.to_string(), true),
+                };
+                if !retryable || attempt >= retry_attempts {
+                    break Some((error, attempt + 1));
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_OUTPUT_RETRY_BACKOFF);
+                attempt += 1;
+            };
+
+            if let Some((message, attempts)) = error {
+                println!("Batch {} to {} failed after {} attempts: {}", batch, endpoint, attempts, message);
+                failures.push(ProcessingError {
+                    error_type: "BatchFailed".to_string(),
+                    message,
+                    record_id: None,
+                    timestamp: Utc::now(),
+                    context: HashMap::from([
+                        ("batch".to_string(), json!(batch)),
+                        ("record_count".to_string(), json!(records.len())),
+                        ("attempts".to_string(), json!(attempts)),
+                        ("first_record_id".to_string(), json!(records.first().map(|r| &r.id))),
+                        ("last_record_id".to_string(), json!(records.last().map(|r| &r.id))),
+                    ]),
+                });
+            }
+        }
+
+        Ok(failures)
+    }
+
+    /// Sends `records` as a JSON array body, serializing it in chunks while the request streams.
+    async fn send_json_stream(request: reqwest::RequestBuilder, records: &[DataRecord]) -> Result<reqwest::Response, String> {
+        let (chunk_sender, chunk_receiver) = mpsc::channel::<Result<Vec<u8>, std::io::Error>>(4);
+        let body = futures::stream::unfold(chunk_receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        });
+        let send = request
+            .header("Content-Type", "application/json")
+            .body(reqwest::Body::wrap_stream(body))
+            .send();
+
+        let produce = async move {
+            let mut first = true;
+            let _ = chunk_sender.send(Ok(b"[".to_vec())).await;
+            for chunk in records.chunks(STREAM_CHUNK_RECORDS) {
+                let mut bytes = Vec::new();
+                for record in chunk {
+                    if !first {
+                        bytes.push(b',');
+                    }
+                    first = false;
+                    if let Err(e) = serde_json::to_writer(&mut bytes, record) {
+                        let _ = chunk_sender.send(Err(std::io::Error::other(e))).await;
+                        return;
+                    }
+                }
+                // The request has given up on the body, so stop serializing
+                if chunk_sender.send(Ok(bytes)).await.is_err() {
+                    return;
+                }
+            }
+            let _ = chunk_sender.send(Ok(b"]".to_vec())).await;
+        };
+
+        let (response, ()) = tokio::join!(send, produce);
+        response.map_err(|e| e.to_string())
+    }
+
+    /// Records covered by output errors; batch-level errors carry their size in `record_count`.
+    fn failed_record_count(errors: &[ProcessingError]) -> usize {
+        errors.iter()
+            .map(|error| error.context.get("record_count").and_then(|count| count.as_u64()).unwrap_or(1) as usize)
+            .sum()
+    }
+
+    /// Renders an index name template for one record; Elasticsearch requires lowercase names.
+    fn render_index(template: &str, record: &DataRecord, job_id: &str) -> String {
+        let mut index = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+                break;
+            };
+            index.push_str(&rest[..start]);
+            match &rest[start + 1..end] {
+                "date" => index.push_str(&record.timestamp.format("%Y.%m.%d").to_string()),
+                "job_id" => index.push_str(job_id),
+                name => match Self::output_field_value(record, name).as_deref() {
+                    Some(Value::String(text)) => index.push_str(text),
+                    Some(Value::Null) | None => {},
+                    Some(other) => index.push_str(&other.to_string()),
+                },
+            }
+            rest = &rest[end + 1..];
+        }
+        index.push_str(rest);
+        index.to_lowercase()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn write_elasticsearch(
+        client: &Client,
+        data: &[DataRecord],
+        url: &str,
+        index: &str,
+        id_field: Option<&str>,
+        batch_size: usize,
+        max_retries: u32,
+        headers: &HashMap<String, String>,
+        job_id: &str,
+    ) -> Result<Vec<ProcessingError>, String> {
+        let endpoint = format!("{}/_bulk", url.trim_end_matches('/'));
+        let mut failures = Vec::new();
+
+        for chunk in data.chunks(batch_size.max(1)) {
+            let mut pending: Vec<&DataRecord> = chunk.iter().collect();
+            let mut attempt = 0;
+            let mut backoff = OUTPUT_RETRY_BACKOFF;
+
+            while !pending.is_empty() {
+                let mut body = String::new();
+                for record in &pending {
+                    let mut action = json!({ "_index": Self::render_index(index, record, job_id) });
+                    if let Some(id) = id_field.and_then(|field| Self::output_field_value(record, field)) {
+                        action["_id"] = match id.as_ref() {
+                            Value::String(text) => json!(text),
+                            other => json!(other.to_string()),
+                        };
+                    }
+                    body.push_str(&json!({ "index": action }).to_string());
+                    body.push('\n');
+                    body.push_str(&serde_json::to_string(record).map_err(|e| e.to_string())?);
+                    body.push('\n');
+                }
+
+                let mut request = client.post(&endpoint).header("Content-Type", "application/x-ndjson");
+                for (key, value) in headers {
+                    request = request.header(key, value);
+                }
+                let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+
+                let can_retry = attempt < max_retries;
+                let mut retry = Vec::new();
+                if response.status() == StatusCode::TOO_MANY_REQUESTS && can_retry {
+                    retry = pending;
+                } else if !response.status().is_success() {
+                    return Err(format!("Bulk request failed: {}", response.status()));
+                } else {
+                    let result: Value = response.json().await.map_err(|e| e.to_string())?;
+                    let items = result.get("items").and_then(|items| items.as_array()).cloned().unwrap_or_default();
+                    for (position, record) in pending.into_iter().enumerate() {
+                        let item = items.get(position).and_then(|item| item.get("index"));
+                        let status = item.and_then(|item| item.get("status")).and_then(|s| s.as_u64()).unwrap_or(0);
+                        if (200..300).contains(&status) {
+                            continue;
+                        }
+                        if status == 429 && can_retry {
+                            retry.push(record);
+                            continue;
+                        }
+                        let error = item.and_then(|item| item.get("error")).cloned().unwrap_or(Value::Null);
+                        let message = error.get("reason")
+                            .and_then(|reason| reason.as_str())
+                            .map(str::to_string)
+                            .unwrap_or_else(|| format!("Indexing failed with status {}", status));
+                        failures.push(ProcessingError {
+                            error_type: "IndexingFailed".to_string(),
+                            message,
+                            record_id: Some(record.id.clone()),
+                            timestamp: Utc::now(),
+                            context: HashMap::from([
+                                ("status".to_string(), json!(status)),
+                                ("error".to_string(), error),
+                            ]),
+                        });
+                    }
+                }
+
+                if !retry.is_empty() {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_OUTPUT_RETRY_BACKOFF);
+                    attempt += 1;
+                }
+                pending = retry;
+            }
+        }
+
+        Ok(failures)
+    }
+
+    /// Registers an Avro schema with a Confluent-compatible registry, returning its id.
+    async fn register_avro_schema(client: &Client, registry: &str, subject: &str, schema: &Value) -> Result<u32, String> {
+        let url = format!("{}/subjects/{}/versions", registry.trim_end_matches('/'), subject);
+        let response = client
+            .post(&url)
+            .header("Content-Type", "application/vnd.schemaregistry.v1+json")
+            .json(&json!({ "schema": schema.to_string() }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Schema registry request failed: {}", response.status()));
+        }
+        let body: Value = response.json().await.map_err(|e| e.to_string())?;
+        body.get("id")
+            .and_then(|id| id.as_u64())
+            .and_then(|id| u32::try_from(id).ok())
+            .ok_or_else(|| "Schema registry response has no schema id".to_string())
+    }
+
+    /// Inserts records in batches inside one transaction, so a failure leaves the table untouched.
+    /// With conflict keys, records sharing a key are collapsed first, since one statement cannot
+    /// touch the same row twice.
+    #[allow(clippy::too_many_arguments)]
+    async fn write_database(
+        data: &[DataRecord],
+        connection_string: &str,
+        table: &str,
+        batch_size: usize,
+        create_table: bool,
+        conflict_keys: &[String],
+        on_conflict: ConflictAction,
+        decimals: &BTreeMap<String, DecimalPrecision>,
+    ) -> Result<(), String> {
+        let dialect = SqlDialect::from_connection_string(connection_string)?;
+        if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            return Err(format!("Invalid table name: {}", table));
+        }
+        let table_name = table.split('.')
+            .map(|part| dialect.quote_identifier(part))
+            .collect::<Vec<_>>()
+            .join(".");
+
+        let schema = Self::infer_output_schema(data, decimals);
+        let columns = schema.iter()
+            .map(|field| dialect.quote_identifier(&field.name))
+            .collect::<Vec<_>>();
+
+        if conflict_keys.is_empty() && on_conflict != ConflictAction::Fail {
+            return Err(format!("on_conflict {:?} needs conflict_keys", on_conflict));
+        }
+        if let Some(missing) = conflict_keys.iter().find(|key| !schema.iter().any(|field| field.name == **key)) {
+            return Err(format!("Conflict key {} is not an output column", missing));
+        }
+        let key_columns: Vec<String> = conflict_keys.iter().map(|key| dialect.quote_identifier(key)).collect();
+
+        let mut records: Vec<&DataRecord> = Vec::with_capacity(data.len());
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for record in data {
+            if conflict_keys.is_empty() {
+                records.push(record);
+                continue;
+            }
+            let mut key = Vec::with_capacity(conflict_keys.len());
+            for name in conflict_keys {
+                let value = Self::output_field_value(record, name).filter(|value| !value.is_null())
+                    .ok_or_else(|| format!("Record {} has no value for conflict key {}", record.id, name))?;
+                key.push(value);
+            }
+            let key = serde_json::to_string(&key).map_err(|e| e.to_string())?;
+            match (positions.get(&key), on_conflict) {
+                (Some(position), ConflictAction::Update) => records[*position] = record,
+                (Some(_), ConflictAction::Ignore) => {},
+                _ => {
+                    positions.insert(key, records.len());
+                    records.push(record);
+                },
+            }
+        }
+        let conflict_clause = match key_columns.is_empty() {
+            true => String::new(),
+            false => dialect.conflict_clause(&key_columns, &columns, on_conflict),
+        };
+
+        sqlx::any::install_default_drivers();
+        let mut connection = AnyConnection::connect(connection_string).await.map_err(|e| e.to_string())?;
+        let mut transaction = connection.begin().await.map_err(|e| e.to_string())?;
+
+        if create_table {
+            let mut definitions = schema.iter()
+                .zip(&columns)
+                .map(|(field, column)| match conflict_keys.contains(&field.name) {
+                    true => format!("{} {}", column, dialect.key_column_type(field.field_type)),
+                    false => format!("{} {}", column, dialect.column_type(field.field_type)),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            if !key_columns.is_empty() {
+                definitions.push_str(&format!(", PRIMARY KEY ({})", key_columns.join(", ")));
+            }
+            let statement = format!("CREATE TABLE IF NOT EXISTS {} ({})", table_name, definitions);
+            sqlx::query(&statement).execute(&mut *transaction).await.map_err(|e| e.to_string())?;
+        }
+
+        // Stay under the bind parameter limit of the strictest backend
+        let max_rows = (65_535 / columns.len().max(1)).max(1);
+        let rows_per_batch = batch_size.clamp(1, max_rows);
+
+        for batch in records.chunks(rows_per_batch) {
+            let mut placeholder_index = 0;
+            let rows = batch.iter()
+                .map(|_| {
+                    let row = schema.iter()
+                        .map(|field| {
+                            placeholder_index += 1;
+                            dialect.value_placeholder(placeholder_index, field.field_type)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    format!("({})", row)
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let statement = format!("INSERT INTO {} ({}) VALUES {}{}", table_name, columns.join(", "), rows, conflict_clause);
+
+            let mut query = sqlx::query(&statement);
+            for record in batch {
+                for field in &schema {
+                    let value = Self::output_field_value(record, &field.name).filter(|v| !v.is_null());
+                    query = match field.field_type {
+                        FieldType::Boolean => query.bind(value.and_then(|v| v.as_bool())),
+                        FieldType::Integer => query.bind(value.and_then(|v| v.as_i64())),
+                        FieldType::Float => query.bind(value.and_then(|v| v.as_f64())),
+                        FieldType::String | FieldType::Json => query.bind(value.map(|v| match v.as_ref() {
+                            Value::String(text) => text.clone(),
+                            other => other.to_string(),
+                        })),
+                        FieldType::Decimal(precision) => {
+                            let decimal = value.map(|v| precision.fit(&v))
+                                .transpose()
+                                .map_err(|e| format!("Field {}: {}", field.name, e))?;
+                            query.bind(decimal.map(|d| d.to_plain_string()))
+                        },
+                    };
+                }
+            }
+            query.execute(&mut *transaction).await.map_err(|e| e.to_string())?;
+        }
+
+        transaction.commit().await.map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Inserts or upserts records into a MongoDB collection, returning the records that failed.
+    async fn write_mongodb(
+        data: &[DataRecord],
+        connection_string: &str,
+        database: &str,
+        collection: &str,
+        mode: MongoWriteMode,
+        id_field: Option<&str>,
+        batch_size: usize,
+    ) -> Result<Vec<ProcessingError>, String> {
+        let client = mongodb::Client::with_uri_str(connection_string).await.map_err(|e| e.to_string())?;
+        let collection = client.database(database).collection::<mongodb::bson::Document>(collection);
+        let mut failures = Vec::new();
+        let write_failure = |record: &DataRecord, code: i32, message: String| ProcessingError {
+            error_type: "WriteFailed".to_string(),
+            message,
+            record_id: Some(record.id.clone()),
+            timestamp: Utc::now(),
+            context: HashMap::from([("code".to_string(), json!(code))]),
+        };
+
+        let to_document = |record: &DataRecord| -> Result<mongodb::bson::Document, String> {
+            let mut document = match &record.data {
+                Value::Object(_) => mongodb::bson::to_document(&record.data).map_err(|e| e.to_string())?,
+                other => mongodb::bson::doc! { "value": mongodb::bson::to_bson(other).map_err(|e| e.to_string())? },
+            };
+            let id = match id_field {
+                Some(field) => Self::output_field_value(record, field)
+                    .filter(|value| !value.is_null())
+                    .ok_or_else(|| format!("Record {} has no {} value for _id", record.id, field))?
+                    .into_owned(),
+                None if mode == MongoWriteMode::Upsert => json!(record.id),
+                None => return Ok(document),
+            };
+            document.insert("_id", mongodb::bson::to_bson(&id).map_err(|e| e.to_string())?);
+            Ok(document)
+        };
+
+        for batch in data.chunks(batch_size.max(1)) {
+            // Records that cannot be mapped are reported without failing the rest of the batch
+            let mut records = Vec::with_capacity(batch.len());
+            let mut documents = Vec::with_capacity(batch.len());
+            for record in batch {
+                match to_document(record) {
+                    Ok(document) => {
+                        records.push(record);
+                        documents.push(document);
+                    },
+                    Err(message) => failures.push(write_failure(record, 0, message)),
+                }
+            }
+
+            match mode {
+                MongoWriteMode::Insert => {
+                    let options = mongodb::options::InsertManyOptions::builder().ordered(false).build();
+                    let Err(error) = collection.insert_many(&documents, options).await else {
+                        continue;
+                    };
+                    match *error.kind {
+                        mongodb::error::ErrorKind::BulkWrite(mongodb::error::BulkWriteFailure {
+                            write_errors: Some(write_errors),
+                            write_concern_error: None,
+                            ..
+                        }) => {
+                            for write_error in write_errors {
+                                if let Some(record) = records.get(write_error.index) {
+                                    failures.push(write_failure(record, write_error.code, write_error.message));
+                                }
+                            }
+                        },
+                        _ => return Err(error.to_string()),
+                    }
+                },
+                MongoWriteMode::Upsert => {
+                    let mut writes = futures::stream::iter(documents.into_iter().enumerate().map(|(index, document)| {
+                        let collection = collection.clone();
+                        async move {
+                            let filter = mongodb::bson::doc! { "_id": document.get("_id").cloned() };
+                            let options = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+                            (index, collection.replace_one(filter, document, options).await)
+                        }
+                    })).buffer_unordered(MONGO_UPSERT_CONCURRENCY);
+
+                    while let Some((index, result)) = writes.next().await {
+                        let Err(error) = result else {
+                            continue;
+                        };
+                        match *error.kind {
+                            mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(write_error)) => {
+                                failures.push(write_failure(records[index], write_error.code, write_error.message));
+                            },
+                            _ => return Err(error.to_string()),
+                        }
+                    }
+                },
+            }
+        }
+
+        Ok(failures)
+    }
+
+    async fn write_redis(
+        data: &[DataRecord],
+        url: &str,
+        key_field: &str,
+        key_prefix: &str,
+        value_type: RedisValueType,
+        ttl_seconds: Option<u64>,
+        batch_size: usize,
+    ) -> Result<Vec<ProcessingError>, String> {
+        let client = redis::Client::open(url).map_err(|e| e.to_string())?;
+        let mut connection = client.get_multiplexed_async_connection().await.map_err(|e| e.to_string())?;
+        let mut failures = Vec::new();
+
+        for batch in data.chunks(batch_size.max(1)) {
+            // Atomic so readers never see a hash between its delete and its rewrite
+            let mut pipeline = redis::pipe();
+            pipeline.atomic();
+            for record in batch {
+                let key = match Self::output_field_value(record, key_field).filter(|value| !value.is_null()) {
+                    Some(value) => match value.as_ref() {
+                        Value::String(text) => format!("{}{}", key_prefix, text),
+                        other => format!("{}{}", key_prefix, other),
+                    },
+                    None => {
+                        failures.push(ProcessingError {
+                            error_type: "WriteFailed".to_string(),
+                            message: format!("Record has no {} value for the Redis key", key_field),
+                            record_id: Some(record.id.clone()),
+                            timestamp: Utc::now(),
+                            context: HashMap::new(),
+                        });
+                        continue;
+                    },
+                };
+
+                match value_type {
+                    RedisValueType::Json => {
+                        let value = serde_json::to_string(&record.data).map_err(|e| e.to_string())?;
+                        let command = pipeline.cmd("SET").arg(&key).arg(value);
+                        if let Some(ttl) = ttl_seconds {
+                            command.arg("EX").arg(ttl);
+                        }
+                        command.ignore();
+                    },
+                    RedisValueType::Hash => {
+                        let fields: Vec<(String, String)> = match &record.data {
+                            Value::Object(fields) => fields.iter()
+                                .map(|(name, value)| (name.clone(), match value {
+                                    Value::String(text) => text.clone(),
+                                    other => other.to_string(),
+                                }))
+                                .collect(),
+                            other => vec![("value".to_string(), other.to_string())],
+                        };
+                        pipeline.cmd("DEL").arg(&key).ignore();
+                        if !fields.is_empty() {
+                            pipeline.cmd("HSET").arg(&key).arg(fields).ignore();
+                        }
+                        if let Some(ttl) = ttl_seconds {
+                            pipeline.cmd("EXPIRE").arg(&key).arg(ttl).ignore();
+                        }
+                    },
+                }
+            }
+            pipeline.query_async::<_, ()>(&mut connection).await.map_err(|e| e.to_string())?;
+        }
+
+        Ok(failures)
+    }
+
+    /// Looks up an output column: the record envelope fields first, then top-level data fields.
+    fn output_field_value<'a>(record: &'a DataRecord, name: &str) -> Option<Cow<'a, Value>> {
+        match name {
+            "id" => Some(Cow::Owned(json!(record.id))),
+            "timestamp" => Some(Cow::Owned(json!(record.timestamp.to_rfc3339()))),
+            "source" => Some(Cow::Owned(json!(record.source))),
+            _ => record.data.get(name).map(Cow::Borrowed),
+        }
+    }
+
+    /// Derives a columnar schema from the envelope fields plus the union of top-level data fields.
+    /// Wrapper fields first, then flattened data fields in the order they first appear.
+    fn csv_columns(data: &[DataRecord]) -> Vec<String> {
+        let mut columns: Vec<String> = ["id", "timestamp", "source"].iter().map(|name| name.to_string()).collect();
+        let mut seen: HashSet<String> = columns.iter().cloned().collect();
+        let mut fields = Vec::new();
+        for record in data {
+            fields.clear();
+            Self::flatten_fields(None, &record.data, &mut fields);
+            for (name, _) in fields.drain(..) {
+                if seen.insert(name.clone()) {
+                    columns.push(name);
+                }
+            }
+        }
+        columns
+    }
+
+    /// Maps dotted paths to leaf values; arrays are kept whole and written as JSON.
+    fn flatten_fields<'a>(prefix: Option<&str>, value: &'a Value, out: &mut Vec<(String, &'a Value)>) {
+        match value {
+            Value::Object(fields) => {
+                for (name, field) in fields {
+                    let path = match prefix {
+                        Some(prefix) => format!("{}.{}", prefix, name),
+                        None => name.clone(),
+                    };
+                    Self::flatten_fields(Some(&path), field, out);
+                }
+            },
+            leaf => {
+                out.push((prefix.unwrap_or("value").to_string(), leaf));
+            },
+        }
+    }
+
+    /// Column types observed in `data`, with `decimals` overriding the type of fields they name.
+    fn infer_output_schema(data: &[DataRecord], decimals: &BTreeMap<String, DecimalPrecision>) -> Vec<SchemaField> {
+        let mut schema: Vec<SchemaField> = ["id", "timestamp", "source"]
+            .iter()
+            .map(|name| SchemaField { name: name.to_string(), field_type: FieldType::String })
+            .collect();
+        let mut observed: Vec<(String, Option<FieldType>)> = Vec::new();
+
+        for record in data {
+            let Value::Object(fields) = &record.data else {
+                continue;
+            };
+            for (name, value) in fields {
+                let value_type = match value {
+                    Value::Null => None,
+                    Value::Bool(_) => Some(FieldType::Boolean),
+                    Value::Number(n) if n.is_i64() => Some(FieldType::Integer),
+                    Value::Number(_) => Some(FieldType::Float),
+                    Value::String(_) => Some(FieldType::String),
+                    Value::Array(_) | Value::Object(_) => Some(FieldType::Json),
+                };
+                let entry = match observed.iter_mut().find(|(existing, _)| existing == name) {
+                    Some((_, entry)) => entry,
+                    None => {
+                        observed.push((name.clone(), None));
+                        &mut observed.last_mut().unwrap().1
+                    }
+                };
+                *entry = match (*entry, value_type) {
+                    (current, None) => current,
+                    (None, new) => new,
+                    (Some(a), Some(b)) if a == b => Some(a),
+                    (Some(FieldType::Integer), Some(FieldType::Float))
+                    | (Some(FieldType::Float), Some(FieldType::Integer)) => Some(FieldType::Float),
+                    _ => Some(FieldType::String),
+                };
+            }
+        }
+
+        for (name, field_type) in observed {
+            if schema.iter().any(|field| field.name == name) {
+                continue;
+            }
+            let field_type = match decimals.get(&name) {
+                Some(precision) => FieldType::Decimal(*precision),
+                None => field_type.unwrap_or(FieldType::String),
+            };
+            schema.push(SchemaField { name, field_type });
+        }
+        schema
+    }
+
+    fn write_parquet(
+        path: &str,
+        data: &[DataRecord],
+        schema: &[SchemaField],
+        row_group_size: usize,
+        compression: ParquetCompression,
+        key: Option<&[u8; 32]>,
+    ) -> Result<(), String> {
+        let file = OutputFile::create(path, key)?;
+        Self::encode_parquet(file, data, schema, row_group_size, compression)?.finish()
+    }
+
+    /// Appends `data` as new Parquet files and commits them as the next table version.
+    #[allow(clippy::too_many_arguments)]
+    async fn write_delta(
+        data: &[DataRecord],
+        table_uri: &str,
+        storage_options: &HashMap<String, String>,
+        partition_columns: &[String],
+        schema_mode: DeltaSchemaMode,
+        compression: ParquetCompression,
+        row_group_size: usize,
+        decimals: &BTreeMap<String, DecimalPrecision>,
+        job_id: &str,
+        output_root: &Path,
+    ) -> Result<i64, String> {
+        use object_store::path::Path as StorePath;
+        use object_store::{ObjectStore, PutMode, PutPayload};
+
+        let (store, root): (Box<dyn ObjectStore>, StorePath) = match reqwest::Url::parse(table_uri) {
+            Ok(url) if url.scheme() == "file" => {
+                return Err(format!("Delta table {} must be an object store URI or a path relative to the output directory", table_uri));
+            },
+            Ok(url) if url.scheme().len() > 1 => object_store::parse_url_opts(&url, storage_options)
+                .map_err(|e| e.to_string())?,
+            // Plain paths are local directories below the output root, like file outputs
+            _ => {
+                if !Path::new(table_uri).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+                    return Err(format!("Delta table {} must be relative and stay within the output directory", table_uri));
+                }
+                let table_dir = output_root.join(table_uri);
+                std::fs::create_dir_all(&table_dir).map_err(|e| e.to_string())?;
+                let local = object_store::local::LocalFileSystem::new_with_prefix(&table_dir)
+                    .map_err(|e| e.to_string())?;
+                (Box::new(local), StorePath::default())
+            },
+        };
+        let child = |relative: &str| -> Result<StorePath, String> {
+            let full = if root.as_ref().is_empty() { relative.to_string() } else { format!("{}/{}", root, relative) };
+            StorePath::parse(full).map_err(|e| e.to_string())
+        };
+
+        let mut table = Self::load_delta_table(store.as_ref(), &child("_delta_log")?).await?;
+        let batch_schema: Vec<SchemaField> = Self::infer_output_schema(data, decimals).into_iter()
+            .map(|field| match field.field_type {
+                // Delta has no JSON type; nested values are stored as their JSON text
+                FieldType::Json => SchemaField { field_type: FieldType::String, ..field },
+                _ => field,
+            })
+            .collect();
+        let partitions = match &table.metadata {
+            Some(metadata) => serde_json::from_value::<Vec<String>>(metadata["partitionColumns"].clone())
+                .map_err(|e| format!("Invalid Delta partition columns: {}", e))?,
+            None => partition_columns.to_vec(),
+        };
+        if !partition_columns.is_empty() && partition_columns != partitions.as_slice() {
+            return Err(format!("Delta table is partitioned by {:?}, not {:?}", partitions, partition_columns));
+        }
+        let (mut table_fields, write_schema) = Self::merge_delta_schema(&table, &batch_schema, &partitions, schema_mode, data)?;
+
+        // Data files first; they are invisible to readers until a commit references them
+        let mut groups: BTreeMap<Vec<Option<String>>, Vec<DataRecord>> = BTreeMap::new();
+        for record in data {
+            let key = partitions.iter()
+                .map(|column| match Self::output_field_value(record, column).as_deref() {
+                    None | Some(Value::Null) => None,
+                    Some(Value::String(text)) => Some(text.clone()),
+                    Some(other) => Some(other.to_string()),
+                })
+                .collect();
+            groups.entry(key).or_default().push(record.clone());
+        }
+
+        let mut add_actions = Vec::with_capacity(groups.len());
+        for (values, records) in &groups {
+            let directory: Vec<String> = partitions.iter().zip(values)
+                .map(|(column, value)| format!(
+                    "{}={}",
+                    escape_partition_value(column),
+                    value.as_deref().map(escape_partition_value).unwrap_or_else(|| "__HIVE_DEFAULT_PARTITION__".to_string()),
+                ))
+                .collect();
+            let file_name = format!("part-00000-{}-c000.{}.parquet", Uuid::new_v4(), match compression {
+                ParquetCompression::None => "uncompressed",
+                ParquetCompression::Snappy => "snappy",
+                ParquetCompression::Zstd => "zstd",
+            });
+            let relative = directory.iter().cloned().chain([file_name]).collect::<Vec<_>>().join("/");
+
+            let mut contents = Vec::new();
+            Self::encode_parquet(&mut contents, records, &write_schema, row_group_size, compression)?;
+            let size = contents.len();
+            store.put(&child(&relative)?, PutPayload::from(contents)).await.map_err(|e| e.to_string())?;
+
+            let partition_values: serde_json::Map<String, Value> = partitions.iter().zip(values)
+                .map(|(column, value)| (column.clone(), json!(value)))
+                .collect();
+            add_actions.push(json!({"add": {
+                // `path` is a relative URI, so the escapes in partition directories are escaped again
+                "path": relative.replace('%', "%25").replace(' ', "%20"),
+                "partitionValues": partition_values,
+                "size": size,
+                "modificationTime": Utc::now().timestamp_millis(),
+                "dataChange": true,
+                "stats": json!({"numRecords": records.len()}).to_string(),
+            }}));
+        }
+
+        for _ in 0..DELTA_COMMIT_ATTEMPTS {
+            let version = table.version.map_or(0, |version| version + 1);
+            let mut actions = vec![json!({"commitInfo": {
+                "timestamp": Utc::now().timestamp_millis(),
+                "operation": "WRITE",
+                "operationParameters": {"mode": "Append", "partitionBy": json!(partitions).to_string()},
+                "engineInfo": format!("rust-data-processor/{}", env!("CARGO_PKG_VERSION")),
+                "txnId": job_id,
+            }})];
+            let schema_string = json!({"type": "struct", "fields": table_fields}).to_string();
+            match &table.metadata {
+                None => {
+                    actions.push(json!({"protocol": {"minReaderVersion": 1, "minWriterVersion": DELTA_MAX_WRITER_VERSION}}));
+                    actions.push(json!({"metaData": {
+                        "id": Uuid::new_v4().to_string(),
+                        "format": {"provider": "parquet", "options": {}},
+                        "schemaString": schema_string,
+                        "partitionColumns": partitions,
+                        "configuration": {},
+                        "createdTime": Utc::now().timestamp_millis(),
+                    }}));
+                },
+                Some(metadata) if metadata["schemaString"].as_str() != Some(schema_string.as_str()) => {
+                    let mut metadata = metadata.clone();
+                    metadata["schemaString"] = json!(schema_string);
+                    actions.push(json!({"metaData": metadata}));
+                },
+                Some(_) => {},
+            }
+            actions.extend(add_actions.iter().cloned());
+
+            let mut commit = String::new();
+            for action in &actions {
+                commit.push_str(&action.to_string());
+                commit.push('\n');
+            }
+            let path = child(&format!("_delta_log/{:020}.json", version))?;
+            match store.put_opts(&path, PutPayload::from(commit), PutMode::Create.into()).await {
+                Ok(_) => return Ok(version),
+                Err(object_store::Error::AlreadyExists { .. }) => {
+                    // Another writer took this version; appends still apply on top of theirs
+                    table = Self::load_delta_table(store.as_ref(), &child("_delta_log")?).await?;
+                    let (fields, schema) = Self::merge_delta_schema(&table, &batch_schema, &partitions, schema_mode, data)?;
+                    if schema != write_schema {
+                        return Err("Delta table schema changed concurrently in an incompatible way".to_string());
+                    }
+                    table_fields = fields;
+                },
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+
+        Err(format!("Could not commit to Delta table after {} attempts", DELTA_COMMIT_ATTEMPTS))
+    }
+
+    /// Reads the latest protocol and metadata from the JSON commits, falling back to the last
+    /// checkpoint for anything older than the commits still in the log.
+    async fn load_delta_table(store: &dyn object_store::ObjectStore, log: &object_store::path::Path) -> Result<DeltaTable, String> {
+        use futures::TryStreamExt;
+
+        let entries: Vec<object_store::ObjectMeta> = store.list(Some(log)).try_collect().await.map_err(|e| e.to_string())?;
+        let mut commits = BTreeMap::new();
+        let mut checkpoints: BTreeMap<i64, Vec<object_store::path::Path>> = BTreeMap::new();
+        for entry in entries {
+            let Some(name) = entry.location.filename() else {
+                continue;
+            };
+            let Some(version) = name.get(..20).and_then(|digits| digits.parse::<i64>().ok()) else {
+                continue;
+            };
+            if &name[20..] == ".json" {
+                commits.insert(version, entry.location.clone());
+            } else if name[20..].starts_with(".checkpoint") && name.ends_with(".parquet") {
+                checkpoints.entry(version).or_default().push(entry.location.clone());
+            }
+        }
+
+        let mut table = DeltaTable {
+            version: commits.keys().next_back().copied().max(checkpoints.keys().next_back().copied()),
+            ..DeltaTable::default()
+        };
+        let checkpoint = checkpoints.keys().next_back().copied();
+        for (version, location) in commits.iter().rev() {
+            if table.metadata.is_some() && table.protocol.is_some() || checkpoint.is_some_and(|cp| *version <= cp) {
+                break;
+            }
+            let contents = store.get(location).await.map_err(|e| e.to_string())?.bytes().await.map_err(|e| e.to_string())?;
+            for line in contents.split(|byte| *byte == b'\n').filter(|line| !line.is_empty()) {
+                let action: Value = serde_json::from_slice(line).map_err(|e| format!("Invalid Delta commit {}: {}", version, e))?;
+                table.absorb(&action);
+            }
+        }
+        if let Some(version) = checkpoint.filter(|_| table.metadata.is_none() || table.protocol.is_none()) {
+            for location in &checkpoints[&version] {
+                let contents = store.get(location).await.map_err(|e| e.to_string())?.bytes().await.map_err(|e| e.to_string())?;
+                let reader = SerializedFileReader::new(contents).map_err(|e| e.to_string())?;
+                for row in reader.get_row_iter(None).map_err(|e| e.to_string())? {
+                    table.absorb(&row.map_err(|e| e.to_string())?.to_json_value());
+                }
+            }
+        }
+
+        if table.version.is_some() && table.metadata.is_none() {
+            return Err("Delta log has no table metadata".to_string());
+        }
+        if let Some(protocol) = &table.protocol {
+            let writer_version = protocol["minWriterVersion"].as_i64().unwrap_or(1);
+            if writer_version > DELTA_MAX_WRITER_VERSION {
+                return Err(format!("Delta writer protocol version {} is not supported", writer_version));
+            }
+        }
+        Ok(table)
+    }
+
+    /// Reads a Delta `decimal(precision,scale)` type name.
+    fn parse_delta_decimal(type_name: &str) -> Option<DecimalPrecision> {
+        let (precision, scale) = type_name.strip_prefix("decimal(")?.strip_suffix(')')?.split_once(',')?;
+        let precision = DecimalPrecision {
+            precision: precision.trim().parse().ok()?,
+            scale: scale.trim().parse().ok()?,
+        };
+        precision.validate().ok().map(|_| precision)
+    }
+
+    /// Returns the table's fields after adding new output columns, plus the typed columns the
+    /// data files are written with. Partition columns live in directory names, not in files.
+    fn merge_delta_schema(
+        table: &DeltaTable,
+        batch: &[SchemaField],
+        partitions: &[String],
+        mode: DeltaSchemaMode,
+        data: &[DataRecord],
+    ) -> Result<(Vec<Value>, Vec<SchemaField>), String> {
+        let mut fields: Vec<Value> = match &table.metadata {
+            Some(metadata) => {
+                let schema: Value = metadata["schemaString"].as_str()
+                    .and_then(|schema| serde_json::from_str(schema).ok())
+                    .ok_or("Delta table has an invalid schema")?;
+                schema["fields"].as_array().cloned().unwrap_or_default()
+            },
+            None => Vec::new(),
+        };
+        if fields.iter().any(|field| field["metadata"].get("delta.invariants").is_some()) {
+            return Err("Delta tables with column invariants are not supported".to_string());
+        }
+
+        let all_null = |name: &str| data.iter().all(|record| Self::output_field_value(record, name).is_none_or(|v| v.is_null()));
+        let mut write_schema = Vec::new();
+        for field in batch {
+            let existing = fields.iter().find(|existing| existing["name"] == field.name.as_str());
+            let table_type = match existing {
+                Some(existing) => match existing["type"].as_str() {
+                    Some("boolean") => Some(FieldType::Boolean),
+                    Some("long") => Some(FieldType::Integer),
+                    Some("double") => Some(FieldType::Float),
+                    Some("string") => Some(FieldType::String),
+                    Some(other) => Self::parse_delta_decimal(other).map(FieldType::Decimal),
+                    None => None,
+                },
+                None => {
+                    if mode == DeltaSchemaMode::Strict && table.metadata.is_some() {
+                        return Err(format!("Field {} is not in the Delta table schema", field.name));
+                    }
+                    fields.push(json!({
+                        "name": field.name,
+                        "type": match field.field_type {
+                            FieldType::Boolean => "boolean".to_string(),
+                            FieldType::Integer => "long".to_string(),
+                            FieldType::Float => "double".to_string(),
+                            FieldType::String | FieldType::Json => "string".to_string(),
+                            FieldType::Decimal(d) => format!("decimal({},{})", d.precision, d.scale),
+                        },
+                        "nullable": true,
+                        "metadata": {},
+                    }));
+                    Some(field.field_type)
+                },
+            };
+            if partitions.contains(&field.name) {
+                continue;
+            }
+
+            let write_type = match (table_type, field.field_type) {
+                (Some(table_type), batch_type) if table_type == batch_type => table_type,
+                // Anything can be written as text, and integers widen losslessly enough to doubles
+                (Some(FieldType::String), _) => FieldType::String,
+                (Some(FieldType::Float), FieldType::Integer) => FieldType::Float,
+                (Some(FieldType::Decimal(precision)), FieldType::Integer | FieldType::Float) => FieldType::Decimal(precision),
+                // Columns without values are simply left out of the file and read back as null
+                _ if all_null(&field.name) => continue,
+                (Some(table_type), batch_type) => return Err(format!(
+                    "Field {} is {:?} in the Delta table but {:?} in the output", field.name, table_type, batch_type,
+                )),
+                (None, _) => return Err(format!("Field {} has a Delta type this sink cannot write", field.name)),
+            };
+            write_schema.push(SchemaField { name: field.name.clone(), field_type: write_type });
+        }
+
+        Ok((fields, write_schema))
+    }
+
+    /// Writes `data` as a Parquet file into `output` and returns the writer once the footer is written.
+    fn encode_parquet<W: Write + Send>(
+        output: W,
+        data: &[DataRecord],
+        schema: &[SchemaField],
+        row_group_size: usize,
+        compression: ParquetCompression,
+    ) -> Result<W, String> {
+        let fields = schema.iter().map(|field| {
+            let (physical, logical) = match field.field_type {
+                FieldType::Boolean => (PhysicalType::BOOLEAN, None),
+                FieldType::Integer => (PhysicalType::INT64, None),
+                FieldType::Float => (PhysicalType::DOUBLE, None),
+                FieldType::String => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+                FieldType::Json => (PhysicalType::BYTE_ARRAY, Some(LogicalType::Json)),
+                FieldType::Decimal(d) => (
+                    if d.precision <= PARQUET_INT64_DECIMAL_DIGITS { PhysicalType::INT64 } else { PhysicalType::FIXED_LEN_BYTE_ARRAY },
+                    Some(LogicalType::Decimal(DecimalType { scale: d.scale as i32, precision: d.precision as i32 })),
+                ),
+            };
+            let mut builder = ParquetType::primitive_type_builder(&field.name, physical)
+                .with_repetition(Repetition::OPTIONAL)
+                .with_logical_type(logical);
+            if let FieldType::Decimal(d) = field.field_type {
+                builder = builder.with_precision(d.precision as i32).with_scale(d.scale as i32);
+                if physical == PhysicalType::FIXED_LEN_BYTE_ARRAY {
+                    builder = builder.with_length(d.byte_width() as i32);
+                }
+            }
+            builder
+                .build()
+                .map(Arc::new)
+        }).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+        let message = ParquetType::group_type_builder("record")
+            .with_fields(fields)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let codec = match compression {
+            ParquetCompression::None => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        let properties = WriterProperties::builder().set_compression(codec).build();
+
+        let mut writer = SerializedFileWriter::new(output, Arc::new(message), Arc::new(properties))
+            .map_err(|e| e.to_string())?;
+
+        for chunk in data.chunks(row_group_size.max(1)) {
+            let mut row_group = writer.next_row_group().map_err(|e| e.to_string())?;
+
+            for field in schema {
+                let mut column = row_group.next_column()
+                    .map_err(|e| e.to_string())?
+                    .ok_or("Parquet schema has fewer columns than expected")?;
+                let values: Vec<Option<Cow<Value>>> = chunk.iter()
+                    .map(|record| Self::output_field_value(record, &field.name).filter(|v| !v.is_null()))
+                    .collect();
+                let def_levels: Vec<i16> = values.iter().map(|v| i16::from(v.is_some())).collect();
+                let present: Vec<(&DataRecord, &Value)> = chunk.iter().zip(&values)
+                    .filter_map(|(record, value)| value.as_deref().map(|value| (record, value)))
+                    .collect();
+
+                let written = match field.field_type {
+                    FieldType::Boolean => {
+                        let column_values = Self::parquet_values(&present, &field.name, "a boolean", Value::as_bool)?;
+                        column.typed::<BoolType>().write_batch(&column_values, Some(&def_levels), None)
+                    },
+                    FieldType::Integer => {
+                        let column_values = Self::parquet_values(&present, &field.name, "an integer", Value::as_i64)?;
+                        column.typed::<Int64Type>().write_batch(&column_values, Some(&def_levels), None)
+                    },
+                    FieldType::Float => {
+                        let column_values = Self::parquet_values(&present, &field.name, "a number", Value::as_f64)?;
+                        column.typed::<DoubleType>().write_batch(&column_values, Some(&def_levels), None)
+                    },
+                    FieldType::String | FieldType::Json => {
+                        let column_values: Vec<ByteArray> = present.iter().map(|(_, v)| match v {
+                            Value::String(text) => ByteArray::from(text.as_str()),
+                            other => ByteArray::from(other.to_string().as_str()),
+                        }).collect();
+                        column.typed::<ByteArrayType>().write_batch(&column_values, Some(&def_levels), None)
+                    },
+                    FieldType::Decimal(precision) => {
+                        let unscaled = present.iter().map(|(_, v)| precision.unscaled(v))
+                            .collect::<Result<Vec<i128>, String>>()
+                            .map_err(|e| format!("Field {}: {}", field.name, e))?;
+                        if precision.precision <= PARQUET_INT64_DECIMAL_DIGITS {
+                            let column_values: Vec<i64> = unscaled.iter().map(|n| *n as i64).collect();
+                            column.typed::<Int64Type>().write_batch(&column_values, Some(&def_levels), None)
+                        } else {
+                            let width = precision.byte_width();
+                            let column_values: Vec<FixedLenByteArray> = unscaled.iter()
+                                .map(|n| FixedLenByteArray::from(n.to_be_bytes()[16 - width..].to_vec()))
+                                .collect();
+                            column.typed::<FixedLenByteArrayType>().write_batch(&column_values, Some(&def_levels), None)
+                        }
+                    },
+                };
+                written.map_err(|e| e.to_string())?;
+                column.close().map_err(|e| e.to_string())?;
+            }
+
+            row_group.close().map_err(|e| e.to_string())?;
+        }
+
+        writer.into_inner().map_err(|e| e.to_string())
+    }
+
+    /// Converts a column's present values to its schema type, failing on the first record
+    /// whose value does not have that type rather than writing a default in its place.
+    fn parquet_values<T>(
+        present: &[(&DataRecord, &Value)],
+        field: &str,
+        expected: &str,
+        convert: impl Fn(&Value) -> Option<T>,
+    ) -> Result<Vec<T>, String> {
+        present.iter()
+            .map(|(record, value)| convert(value).ok_or_else(|| format!(
+                "Field {} of record {} is {}, not {}", field, record.id, value, expected,
+            )))
+            .collect()
+    }
+
+    async fn update_metrics(metrics: Arc<RwLock<SystemMetrics>>, start_time: Instant) {
+        let mut interval = tokio::time::interval(Duration::from_secs(10));
+        
+        loop {
+            interval.tick().await;
+            Self::sample_metrics(&mut *metrics.write().await, start_time);
+        }
+    }
+
+    fn sample_metrics(metrics: &mut SystemMetrics, start_time: Instant) {
+        metrics.uptime_seconds = start_time.elapsed().as_secs();
+
+        // In a real implementation, you'd collect actual system metrics
+        metrics.cpu_usage = rand::random::<f64>() * 100.0;
+        metrics.memory_usage = rand::random::<f64>() * 100.0;
+        metrics.disk_usage = rand::random::<f64>() * 100.0;
+    }
+
+    /// Samples system metrics now rather than at the updater's next tick.
+    pub async fn refresh_metrics(&self) -> SystemMetrics {
+        Self::sample_metrics(&mut *self.metrics.write().await, self.start_time);
+        self.get_metrics().await
+    }
+}
+
+/// Latest protocol and metadata actions of a Delta table; `version` is `None` for a new table.
+#[derive(Debug, Default)]
+struct DeltaTable {
+    version: Option<i64>,
+    protocol: Option<Value>,
+    metadata: Option<Value>,
+}
+
+impl DeltaTable {
+    /// Keeps the first protocol and metadata seen, so actions must arrive newest first.
+    fn absorb(&mut self, action: &Value) {
+        if self.protocol.is_none() && action["protocol"].is_object() {
+            self.protocol = Some(action["protocol"].clone());
+        }
+        if self.metadata.is_none() && action["metaData"].is_object() {
+            self.metadata = Some(action["metaData"].clone());
+        }
+    }
+}
+
+/// Hive-style escaping of a partition value for use in a directory name.
+fn escape_partition_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b' ') {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch_map) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    if let Value::Object(target_map) = target {
+        for (key, value) in patch_map {
+            if value.is_null() {
+                target_map.remove(key);
+            } else {
+                merge_patch(target_map.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+impl Default for DataProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// REST API handlers
+/// Largest JSON request body the API reads; bulk records go through the streaming routes
+const MAX_JSON_BODY_BYTES: u64 = 16 * 1024 * 1024;
+
+fn json_body<T: serde::de::DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(MAX_JSON_BODY_BYTES).and(warp::body::json())
+}
+
+fn with_processor(
+    processor: Arc<DataProcessor>,
+) -> impl Filter<Extract = (Arc<DataProcessor>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || processor.clone())
+}
+
+pub async fn openapi_handler() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&openapi::document()))
+}
+
+pub async fn docs_handler() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::html(openapi::SWAGGER_UI))
+}
+
+pub async fn health_handler() -> Result<impl Reply, Rejection> {
+    let health = json!({
+        "status": "healthy",
+        "service": "rust-data-processor",
+        "timestamp": Utc::now(),
+        "version": "1.0.0",
+        "engine_version": ENGINE_VERSION
+    });
+    
+    Ok(warp::reply::with_status(
+        warp::reply::json(&health),
+        StatusCode::OK,
+    ))
+}
+
+/// Alive while the server answers at all; an orchestrator restarts the instance otherwise.
+pub async fn liveness_handler() -> Result<impl Reply, Rejection> {
+    let response = json!({
+        "status": "alive",
+        "timestamp": Utc::now()
+    });
+    Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK))
+}
+
+/// Ready while the instance can take work; see `DataProcessor::readiness`. An orchestrator
+/// stops routing requests to it otherwise, without restarting it.
+pub async fn readiness_handler(processor: Arc<DataProcessor>) -> Result<impl Reply, Rejection> {
+    let readiness = processor.readiness().await;
+    let response = json!({
+        "status": if readiness.ready { "ready" } else { "degraded" },
+        "checks": readiness.checks,
+        "queue_depth": readiness.queue_depth,
+        "queue_capacity": readiness.queue_capacity,
+        "tasks": readiness.tasks,
+        "timestamp": Utc::now()
+    });
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE },
+    ))
+}
+
+/// Configuration files `POST /admin/reload` reads again.
+#[derive(Clone)]
+pub struct ReloadSources {
+    pub quotas: Option<PathBuf>,
+    /// Its signing keys are fetched again; the auth file itself is only read at startup
+    pub authenticator: Option<Arc<auth::Authenticator>>,
+}
+
+pub async fn admin_status_handler(processor: Arc<DataProcessor>) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&processor.admin_status().await))
+}
+
+pub async fn pause_scheduler_handler(processor: Arc<DataProcessor>) -> Result<impl Reply, Rejection> {
+    let changed = processor.pause_scheduler();
+    if changed {
+        println!("Scheduler paused");
+    }
+    Ok(warp::reply::json(&json!({"changed": changed, "status": processor.admin_status().await})))
+}
+
+pub async fn resume_scheduler_handler(processor: Arc<DataProcessor>) -> Result<impl Reply, Rejection> {
+    let changed = processor.resume_scheduler();
+    if changed {
+        println!("Scheduler resumed");
+    }
+    Ok(warp::reply::json(&json!({"changed": changed, "status": processor.admin_status().await})))
+}
+
+/// `POST` starts draining and `DELETE` stops it.
+pub async fn drain_handler(method: warp::http::Method, processor: Arc<DataProcessor>) -> Result<impl Reply, Rejection> {
+    let draining = method == warp::http::Method::POST;
+    let changed = processor.set_draining(draining) != draining;
+    if changed {
+        println!("{}", if draining { "Draining: new jobs are refused" } else { "Drain ended: accepting jobs" });
+    }
+    Ok(warp::reply::json(&json!({"changed": changed, "status": processor.admin_status().await})))
+}
+
+/// Reads the quotas file again and re-fetches the auth provider's signing keys. Quotas that do
+/// not load leave the current ones in force.
+pub async fn reload_config_handler(sources: ReloadSources, processor: Arc<DataProcessor>) -> Result<warp::reply::Response, Rejection> {
+    let mut reloaded = serde_json::Map::new();
+    if let Some(path) = &sources.quotas {
+        match load_quotas(path) {
+            Ok(quotas) => {
+                processor.reload_quotas(quotas);
+                reloaded.insert("quotas".to_string(), json!(path));
+            },
+            Err(e) => return Ok(ApiError::invalid(format!("Could not reload quotas from {}: {}", path.display(), e)).into_response()),
+        }
+    }
+    if let Some(authenticator) = &sources.authenticator {
+        match authenticator.refresh().await {
+            Ok(keys) => {
+                reloaded.insert("signing_keys".to_string(), json!(keys));
+            },
+            Err(e) => {
+                return Ok(ApiError::new(ErrorCode::Upstream, format!("Could not refresh signing keys: {}", e))
+                    .with_details(json!({"reloaded": reloaded}))
+                    .into_response());
+            },
+        }
+    }
+    println!("Configuration reloaded: {}", Value::Object(reloaded.clone()));
+    Ok(warp::reply::json(&json!({"reloaded": reloaded})).into_response())
+}
+
+pub async fn refresh_metrics_handler(processor: Arc<DataProcessor>) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&processor.refresh_metrics().await))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobSubmitQuery {
+    /// Check the job and preview its output instead of submitting it
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Input records a dry run processes
+    pub sample: Option<usize>,
+}
+
+pub async fn submit_job_handler(
+    mut job: ProcessingJob,
+    query: JobSubmitQuery,
+    api_key: Option<String>,
+    trace: trace::TraceContext,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    // Only the scheduler files jobs under a pipeline, only reruns name their original and only
+    // imports carry an import time
+    job.schedule_id = None;
+    job.rerun_of = None;
+    job.imported_at = None;
+    job.trace = Some(trace.clone());
+    job.tenant = match processor.tenant_for_key(api_key.as_deref()) {
+        Ok(tenant) => tenant,
+        Err(error) => return Ok(ApiError::new(ErrorCode::Unauthenticated, error).into_response()),
+    };
+    if query.dry_run {
+        let sample = query.sample.unwrap_or(DEFAULT_DRY_RUN_RECORDS).clamp(1, processor.max_result_rows);
+        let report = processor.dry_run(&job, sample).await;
+        return Ok(warp::reply::with_status(warp::reply::json(&report), StatusCode::OK).into_response());
+    }
+    Ok(trace.decorate(Box::pin(submit_reply(job, &processor)).await))
+}
+
+/// Lets a request through when auth is off, its route is public, or its bearer token grants
+/// the role the route needs.
+async fn authorize_request(
+    method: warp::http::Method,
+    path: warp::path::FullPath,
+    authorization: Option<String>,
+    query: Option<String>,
+    authenticator: Option<Arc<auth::Authenticator>>,
+) -> Result<(), Rejection> {
+    let Some(authenticator) = authenticator else {
+        return Ok(());
+    };
+    let query_token = || query.as_deref()?.split('&').find_map(|pair| pair.strip_prefix("access_token="));
+    let token = match authorization.as_deref().and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(token) => Some(token),
+        None if auth::takes_query_token(versioning::unversioned(path.as_str())) => query_token(),
+        None => None,
+    };
+    authenticator.authorize(method.as_str(), versioning::unversioned(path.as_str()), token).await.map_err(warp::reject::custom)
+}
+
+/// Counts a request against its client's rate limit: the API key it sends when the key is a
+/// known tenant's, or else its address, so made-up keys cannot each claim a fresh bucket.
+async fn limit_request(
+    path: warp::path::FullPath,
+    api_key: Option<String>,
+    remote: Option<std::net::SocketAddr>,
+    limiter: Option<Arc<ratelimit::RateLimiter>>,
+    processor: Arc<DataProcessor>,
+) -> Result<(), Rejection> {
+    let Some(limiter) = limiter else {
+        return Ok(());
+    };
+    if ratelimit::exempt(versioning::unversioned(path.as_str())) {
+        return Ok(());
+    }
+    let api_key = api_key.filter(|key| processor.tenant_for_key(Some(key)).is_ok());
+    let client = match (api_key, remote) {
+        (Some(key), _) => format!("key:{}", key),
+        (None, Some(remote)) => format!("ip:{}", remote.ip()),
+        (None, None) => "unknown".to_string(),
+    };
+    limiter.check(&client).map_err(|wait| warp::reject::custom(ratelimit::Limited(wait.as_secs_f64().ceil().max(1.0) as u64)))
+}
+
+/// Refuses a job the server would not accept: an invalid configuration, tags or callbacks, or
+/// one that does not fit the memory and disk headroom.
+async fn check_submittable(job: &ProcessingJob, processor: &DataProcessor) -> Result<(), ApiError> {
+    job.configuration.validate()
+        .and_then(|_| validate_tags(&job.tags))
+        .and_then(|_| processor.validate_callbacks(&job.callbacks))
+        .map_err(ApiError::invalid)?;
+    if let Err(rejection) = processor.check_admission(job).await {
+        let message = match rejection.resource {
+            AdmissionResource::Memory => "Insufficient memory headroom for job",
+            AdmissionResource::Disk => "Insufficient work directory space for job",
+        };
+        let details = json!({"admission": rejection});
+        return Err(match rejection.retry_after_seconds {
+            Some(retry_after) => ApiError::new(ErrorCode::Unavailable, message).with_details(details).retry_after(retry_after),
+            // Bigger than the server could ever admit
+            None => ApiError::invalid(message).with_details(details),
+        });
+    }
+    Ok(())
+}
+
+/// The reply for an error from `submit_job` or `submit_jobs`.
+fn submission_error(error: String, processor: &DataProcessor) -> ApiError {
+    match error {
+        error if error.starts_with(QUOTA_EXCEEDED) => ApiError::new(ErrorCode::QuotaExceeded, error),
+        error if error == QUEUE_FULL => {
+            let (depth, capacity) = processor.queue_depth();
+            ApiError::new(ErrorCode::QueueFull, error)
+                .with_details(json!({"queue_depth": depth, "queue_capacity": capacity}))
+        },
+        error if error.starts_with(BATCH_REFUSED) => ApiError::new(ErrorCode::Conflict, error),
+        error if error == DRAINING => ApiError::new(ErrorCode::Unavailable, error),
+        error if error.starts_with("Dependency ") => ApiError::invalid(error),
+        error => ApiError::new(ErrorCode::Internal, error),
+    }
+}
+
+/// Validates, admits and submits a job, replying as `POST /jobs` does.
+async fn submit_reply(job: ProcessingJob, processor: &DataProcessor) -> warp::reply::Response {
+    if let Err(error) = check_submittable(&job, processor).await {
+        return error.into_response();
+    }
+    let warnings = job.configuration.deprecation_warnings();
+
+    match processor.submit_job(job).await {
+        Ok(job_id) => {
+            let response = json!({
+                "success": true,
+                "job_id": job_id,
+                "message": "Job submitted successfully",
+                "engine_version": ENGINE_VERSION,
+                "warnings": warnings
+            });
+            warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::CREATED,
+            ).into_response()
+        },
+        Err(error) => submission_error(error, processor).into_response(),
+    }
+}
+
+/// Most jobs one `POST /jobs/batch` may submit
+const MAX_BATCH_JOBS: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    /// Every job is submitted or none is
+    #[default]
+    AllOrNothing,
+    /// Each job is submitted on its own; the ones refused are reported
+    BestEffort,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobBatch {
+    pub jobs: Vec<ProcessingJob>,
+    #[serde(default)]
+    pub mode: BatchMode,
+}
+
+/// Submits many jobs in one request, replying with the id or error of each in request order:
+/// 201 when all were submitted, 207 when a best-effort batch was partly refused, and an error
+/// carrying the per-job outcomes when an all-or-nothing batch was refused.
+pub async fn submit_batch_handler(
+    batch: JobBatch,
+    api_key: Option<String>,
+    trace: trace::TraceContext,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let tenant = match processor.tenant_for_key(api_key.as_deref()) {
+        Ok(tenant) => tenant,
+        Err(error) => return Ok(ApiError::new(ErrorCode::Unauthenticated, error).into_response()),
+    };
+    if batch.jobs.is_empty() || batch.jobs.len() > MAX_BATCH_JOBS {
+        return Ok(ApiError::invalid(format!("A batch holds between 1 and {} jobs", MAX_BATCH_JOBS)).into_response());
+    }
+    let atomic = batch.mode == BatchMode::AllOrNothing;
+    let jobs: Vec<ProcessingJob> = batch.jobs.into_iter()
+        .map(|job| ProcessingJob {
+            schedule_id: None,
+            rerun_of: None,
+            imported_at: None,
+            tenant: tenant.clone(),
+            trace: Some(trace.clone()),
+            ..job
+        })
+        .collect();
+
+    let mut outcomes: Vec<Option<Result<String, ApiError>>> = Vec::with_capacity(jobs.len());
+    for job in &jobs {
+        outcomes.push(Box::pin(check_submittable(job, &processor)).await.err().map(Err));
+    }
+    let first_refused = outcomes.iter().position(Option::is_some);
+    match first_refused {
+        Some(failed) if atomic => {
+            for outcome in outcomes.iter_mut().filter(|outcome| outcome.is_none()) {
+                let message = format!("{}: job {} of the batch was refused", BATCH_REFUSED, failed);
+                *outcome = Some(Err(ApiError::new(ErrorCode::Conflict, message)));
+            }
+        },
+        _ => {
+            let (accepted, indexes): (Vec<ProcessingJob>, Vec<usize>) = jobs.into_iter().enumerate()
+                .filter(|(index, _)| outcomes[*index].is_none())
+                .map(|(index, job)| (job, index))
+                .unzip();
+            let submitted = Box::pin(processor.submit_jobs(accepted, atomic)).await;
+            for (index, result) in indexes.into_iter().zip(submitted) {
+                outcomes[index] = Some(result.map_err(|error| submission_error(error, &processor)));
+            }
+        },
+    }
+
+    let outcomes: Vec<Result<String, ApiError>> = outcomes.into_iter().flatten().collect();
+    let submitted = outcomes.iter().filter(|outcome| outcome.is_ok()).count();
+    // The jobs refused only because the batch was are not what it failed on
+    let first_error = outcomes.iter()
+        .filter_map(|outcome| outcome.as_ref().err())
+        .min_by_key(|error| error.message.starts_with(BATCH_REFUSED))
+        .map(|error| error.code);
+    let items: Vec<Value> = outcomes.iter().enumerate()
+        .map(|(index, outcome)| match outcome {
+            Ok(job_id) => json!({"index": index, "job_id": job_id}),
+            Err(error) => json!({"index": index, "error": error}),
+        })
+        .collect();
+    println!("Batch of {} jobs: {} submitted", items.len(), submitted);
+    let response = json!({
+        "success": submitted == items.len(),
+        "submitted": submitted,
+        "refused": items.len() - submitted,
+        "engine_version": ENGINE_VERSION,
+        "jobs": items
+    });
+    Ok(trace.decorate(match first_error {
+        None => warp::reply::with_status(warp::reply::json(&response), StatusCode::CREATED).into_response(),
+        Some(code) if atomic => ApiError::new(code, format!("Batch refused: none of its {} jobs were submitted", items.len()))
+            .with_details(json!({"jobs": items}))
+            .into_response(),
+        Some(_) => warp::reply::with_status(warp::reply::json(&response), StatusCode::MULTI_STATUS).into_response(),
+    }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobRerunOverrides {
+    pub name: Option<String>,
+    /// JSON merge patch applied to the original configuration
+    pub configuration: Option<Value>,
+    pub priority: Option<i32>,
+    /// Replace the original tags
+    pub tags: Option<BTreeMap<String, String>>,
+    pub depends_on: Option<Vec<String>>,
+}
+
+/// Submits a copy of a job under a fresh id. The body is optional; see `JobRerunOverrides`.
+pub async fn rerun_job_handler(
+    job_id: String,
+    body: bytes::Bytes,
+    api_key: Option<String>,
+    trace: trace::TraceContext,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    // The rerun counts against whoever asked for it
+    let tenant = match processor.tenant_for_key(api_key.as_deref()) {
+        Ok(tenant) => tenant,
+        Err(error) => return Ok(ApiError::new(ErrorCode::Unauthenticated, error).into_response()),
+    };
+    let overrides = match body.iter().all(u8::is_ascii_whitespace) {
+        true => Ok(JobRerunOverrides::default()),
+        false => serde_json::from_slice::<JobRerunOverrides>(&body),
+    };
+    let overrides = match overrides {
+        Ok(overrides) => overrides,
+        Err(e) => return Ok(ApiError::new(ErrorCode::InvalidRequest, format!("Invalid overrides: {}", e)).into_response()),
+    };
+    match processor.rerun_request(&job_id, overrides).await {
+        Ok(job) => {
+            let job = ProcessingJob { tenant, trace: Some(trace.clone()), ..job };
+            Ok(trace.decorate(Box::pin(submit_reply(job, &processor)).await))
+        },
+        Err(error) if error == "Job not found" => Ok(ApiError::not_found(error).into_response()),
+        Err(error) => Ok(ApiError::invalid(error).into_response()),
+    }
+}
+
+pub async fn estimate_job_handler(
+    job: ProcessingJob,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let estimate = processor.estimate_job(&job).await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&estimate),
+        StatusCode::OK,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobStatusQuery {
+    pub wait: Option<String>,
+}
+
+const MAX_JOB_WAIT: Duration = Duration::from_secs(60);
+
+/// Parses a duration such as `30s`, `500ms`, `2m`, `12h`, `7d` or a bare number of seconds.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let duration = if let Some(ms) = value.strip_suffix("ms") {
+        Duration::from_millis(ms.parse().ok()?)
+    } else if let Some(secs) = value.strip_suffix('s') {
+        Duration::from_secs(secs.parse().ok()?)
+    } else if let Some(mins) = value.strip_suffix('m') {
+        Duration::from_secs(mins.parse::<u64>().ok()?.checked_mul(60)?)
+    } else if let Some(hours) = value.strip_suffix('h') {
+        Duration::from_secs(hours.parse::<u64>().ok()?.checked_mul(60 * 60)?)
+    } else if let Some(days) = value.strip_suffix('d') {
+        Duration::from_secs(days.parse::<u64>().ok()?.checked_mul(24 * 60 * 60)?)
+    } else {
+        Duration::from_secs(value.parse().ok()?)
+    };
+    Some(duration)
+}
+
+fn parse_wait_duration(value: &str) -> Option<Duration> {
+    parse_duration(value).map(|wait| wait.min(MAX_JOB_WAIT))
+}
+
+fn job_etag(job: &ProcessingJob) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(job).unwrap_or_default().hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+pub async fn job_dag_handler(
+    job_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.job_dag(&job_id).await {
+        Ok(dag) => Ok(warp::reply::with_status(warp::reply::json(&dag), StatusCode::OK).into_response()),
+        Err(error) => Ok(ApiError::not_found(error).into_response()),
+    }
+}
+
+fn sse_event(name: &str, data: &impl Serialize) -> Option<warp::sse::Event> {
+    warp::sse::Event::default().event(name).json_data(data).ok()
+}
+
+/// What a job's event stream has sent so far, so each update only raises what changed.
+struct JobEventFeed {
+    processor: Arc<DataProcessor>,
+    updates: broadcast::Receiver<ProcessingJob>,
+    job_id: String,
+    status: Option<JobStatus>,
+    progress: Option<JobProgress>,
+    /// Time of the last log line sent
+    logged_at: DateTime<Utc>,
+    pending: VecDeque<warp::sse::Event>,
+    finished: bool,
+}
+
+impl JobEventFeed {
+    /// Queues a `warning` or `error` event for each new log line at those levels, a `status`
+    /// event when the status changed and a `progress` event when the running attempt moved on.
+    fn observe(&mut self, job: &ProcessingJob) {
+        // Lines first, so a failure's reason comes before the status that ends the stream
+        for line in self.processor.job_logs.lines(&job.id, LogLevel::Warn, Some(self.logged_at), None) {
+            self.logged_at = line.timestamp;
+            let name = if line.level == LogLevel::Error { "error" } else { "warning" };
+            self.pending.extend(sse_event(name, &line));
+        }
+        if self.status.as_ref() != Some(&job.status) {
+            self.status = Some(job.status.clone());
+            self.pending.extend(sse_event("status", &JobEventSummary::from(job)));
+        }
+        if job.progress.is_some() && job.progress != self.progress {
+            self.progress = job.progress.clone();
+            self.pending.extend(job.progress.as_ref().and_then(|progress| sse_event("progress", progress)));
+        }
+        self.finished = job.status.is_finished();
+    }
+
+    async fn next(mut self) -> Option<(Result<warp::sse::Event, std::convert::Infallible>, Self)> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some((Ok(event), self));
+            }
+            if self.finished {
+                return None;
+            }
+            let job = match self.updates.recv().await {
+                Ok(job) if job.id == self.job_id => job,
+                Ok(_) => continue,
+                // Catch up from the stored job; one deleted meanwhile ends the stream
+                Err(broadcast::error::RecvError::Lagged(_)) => self.processor.get_job_status(&self.job_id).await?,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            self.observe(&job);
+        }
+    }
+}
+
+/// Streams a job's changes as server-sent events until it finishes, starting with its
+/// current status and progress.
+pub async fn job_events_handler(
+    job_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<warp::reply::Response, Rejection> {
+    // Subscribe before reading so no change is missed in between
+    let updates = processor.job_updates.subscribe();
+    let Some(job) = processor.get_job_status(&job_id).await else {
+        return Ok(ApiError::not_found("Job not found").into_response());
+    };
+    let mut feed = JobEventFeed {
+        processor,
+        updates,
+        job_id,
+        status: None,
+        progress: None,
+        logged_at: Utc::now(),
+        pending: VecDeque::new(),
+        finished: false,
+    };
+    feed.observe(&job);
+    let events = futures::stream::unfold(feed, JobEventFeed::next);
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response())
+}
+
+pub async fn get_job_handler(
+    job_id: String,
+    query: JobStatusQuery,
+    if_none_match: Option<String>,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let wait = match query.wait.as_deref().map(parse_wait_duration) {
+        Some(None) => return Ok(ApiError::new(ErrorCode::InvalidRequest, "Invalid wait duration").into_response()),
+        Some(Some(wait)) => Some(wait),
+        None => None,
+    };
+
+    let mut job = processor.get_job_status(&job_id).await;
+
+    // Long-poll only while the caller's view is still current
+    if let (Some(current), Some(wait)) = (&job, wait) {
+        let stale = if_none_match.as_deref().is_some_and(|tag| tag != job_etag(current));
+        if !stale {
+            job = processor.wait_for_job_change(&job_id, wait).await;
+        }
+    }
+
+    match job {
+        Some(job) => {
+            let etag = job_etag(&job);
+            if if_none_match.as_deref() == Some(etag.as_str()) {
+                return Ok(warp::reply::with_header(
+                    StatusCode::NOT_MODIFIED,
+                    "etag",
+                    etag,
+                ).into_response());
+            }
+
+            Ok(warp::reply::with_header(
+                warp::reply::with_status(
+                    warp::reply::json(&job),
+                    StatusCode::OK,
+                ),
+                "etag",
+                etag,
+            ).into_response())
+        },
+        None => Ok(ApiError::not_found("Job not found").into_response()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultsFormat {
+    /// One page of records in a JSON envelope
+    #[default]
+    Json,
+    /// Every record from `offset` on, streamed one JSON object per line without a row cap
+    Ndjson,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct JobResultsQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    #[serde(default)]
+    pub all: bool,
+    #[serde(default)]
+    pub format: ResultsFormat,
+}
+
+/// Records serialized per chunk of a streamed results response
+const RESULTS_STREAM_CHUNK: usize = 1000;
+
+fn job_results_error_reply(error: String) -> warp::reply::Response {
+    let code = match error.as_str() {
+        "Job not found" => ErrorCode::NotFound,
+        e if e.ends_with("has not completed") => ErrorCode::Conflict,
+        e if e.ends_with("no longer retained") => ErrorCode::Gone,
+        e if e.starts_with("Result set exceeds") => ErrorCode::PayloadTooLarge,
+        e if e.starts_with("Query timed out") => ErrorCode::Unavailable,
+        _ => ErrorCode::InvalidRequest,
+    };
+    ApiError::new(code, error).into_response()
+}
+
+pub async fn job_results_handler(
+    job_id: String,
+    query: JobResultsQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let records = match processor.get_job_results(&job_id).await {
+        Ok(records) => records,
+        Err(error) => return Ok(job_results_error_reply(error)),
+    };
+    let offset = query.offset.unwrap_or(0).min(records.len());
+
+    if let ResultsFormat::Ndjson = query.format {
+        let end = query.limit.map_or(records.len(), |limit| offset.saturating_add(limit).min(records.len()));
+        let chunks = futures::stream::iter((offset..end).step_by(RESULTS_STREAM_CHUNK)).map(move |start| {
+            let mut chunk = Vec::new();
+            for record in &records[start..(start + RESULTS_STREAM_CHUNK).min(end)] {
+                serde_json::to_writer(&mut chunk, record).map_err(std::io::Error::other)?;
+                chunk.push(b'\n');
+            }
+            Ok::<_, std::io::Error>(Bytes::from(chunk))
+        });
+        let mut response = warp::reply::Response::new(warp::hyper::Body::wrap_stream(chunks));
+        response.headers_mut().insert(
+            warp::http::header::CONTENT_TYPE,
+            warp::http::HeaderValue::from_static("application/x-ndjson"),
+        );
+        return Ok(response);
+    }
+
+    let window = ResultWindow { limit: query.limit, offset: Some(offset), all: query.all };
+    let (offset, page_size) = match window.resolve(processor.max_result_rows) {
+        Ok(page) => page,
+        Err(error) => return Ok(job_results_error_reply(error)),
+    };
+    let total = records.len();
+    if query.all && total - offset > page_size {
+        return Ok(job_results_error_reply(format!(
+            "Result set exceeds the maximum of {} rows per response; page with limit and offset or use format=ndjson",
+            page_size,
+        )));
+    }
+    let page = &records[offset..(offset + page_size).min(total)];
+    let truncated = offset + page.len() < total;
+    let response = json!({
+        "job_id": job_id,
+        "total": total,
+        "count": page.len(),
+        "offset": offset,
+        "truncated": truncated,
+        "next_offset": truncated.then_some(offset + page.len()),
+        "records": page
+    });
+    Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+}
+
+pub async fn create_export_handler(
+    job_id: String,
+    request: ExportRequest,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.create_export(&job_id, request).await {
+        Ok((export, token)) => {
+            let response = json!({
+                "success": true,
+                "export": export,
+                "token": token,
+                "url": format!("/exports/{}", token)
+            });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::CREATED).into_response())
+        },
+        Err(error) => Ok(job_results_error_reply(error)),
+    }
+}
+
+/// Bytes read per chunk of a streamed export download
+const EXPORT_STREAM_CHUNK: usize = 64 * 1024;
+
+/// The export password from `X-Export-Password`, or the password half of Basic credentials
+/// so that browsers can prompt for it.
+fn export_password(header: Option<String>, authorization: Option<String>) -> Option<String> {
+    header.or_else(|| {
+        let encoded = authorization?.strip_prefix("Basic ")?.trim().to_string();
+        let decoded = String::from_utf8(base64::engine::general_purpose::STANDARD.decode(encoded).ok()?).ok()?;
+        decoded.split_once(':').map(|(_, password)| password.to_string())
+    })
+}
+
+pub async fn download_export_handler(
+    token: String,
+    password_header: Option<String>,
+    authorization: Option<String>,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let password = export_password(password_header, authorization);
+    let export = match processor.open_export(&token, password.as_deref()).await {
+        Ok(export) => export,
+        Err(error) => {
+            let code = match error.as_str() {
+                "Invalid export token" => ErrorCode::Forbidden,
+                "Export expired" => ErrorCode::Gone,
+                "Export not found" => ErrorCode::NotFound,
+                _ => ErrorCode::Unauthenticated,
+            };
+            let mut response = ApiError::new(code, error).into_response();
+            if code == ErrorCode::Unauthenticated {
+                response.headers_mut().insert(
+                    warp::http::header::WWW_AUTHENTICATE,
+                    warp::http::HeaderValue::from_static("Basic realm=\"export\""),
+                );
+            }
+            return Ok(response);
+        },
+    };
+
+    let file = match tokio::fs::File::open(&export.path).await {
+        Ok(file) => file,
+        Err(e) => return Ok(job_results_error_reply(format!("Export unavailable: {}", e))),
+    };
+    let chunks = futures::stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; EXPORT_STREAM_CHUNK];
+        let read = tokio::io::AsyncReadExt::read(&mut file, &mut chunk).await?;
+        if read == 0 {
+            return Ok::<_, std::io::Error>(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((Bytes::from(chunk), file)))
+    });
+    let mut response = warp::reply::Response::new(warp::hyper::Body::wrap_stream(chunks));
+    let headers = response.headers_mut();
+    headers.insert(warp::http::header::CONTENT_TYPE, warp::http::HeaderValue::from_static(export.content_type));
+    headers.insert(warp::http::header::CONTENT_LENGTH, warp::http::HeaderValue::from(export.size_bytes));
+    if let Ok(disposition) = warp::http::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", export.file_name)) {
+        headers.insert(warp::http::header::CONTENT_DISPOSITION, disposition);
+    }
+    Ok(response)
+}
+
+/// Splits an uploaded body into individual records: a JSON array, a single object, or NDJSON lines.
+async fn read_record_body<S, B>(content_type: Option<&str>, mut body: S) -> Result<Vec<Result<Value, String>>, String>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Unpin,
+    B: Buf,
+{
+    let is_ndjson = content_type.is_some_and(|ct| {
+        ct.starts_with("application/x-ndjson") || ct.starts_with("application/jsonl")
+    });
+    let mut items = Vec::new();
+    let mut buffer: Vec<u8> = Vec::new();
+
+    let parse_line = |line: &[u8]| -> Option<Result<Value, String>> {
+        let line = std::str::from_utf8(line).map_err(|e| e.to_string());
+        match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(serde_json::from_str(line).map_err(|e| e.to_string())),
+            Err(e) => Some(Err(e)),
+        }
+    };
+
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| e.to_string())?;
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            buffer.extend_from_slice(bytes);
+            let len = bytes.len();
+            chunk.advance(len);
+        }
+
+        // Parse complete NDJSON lines as they arrive instead of buffering the whole stream
+        if is_ndjson {
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                items.extend(parse_line(&line));
+            }
+        }
+    }
+
+    if is_ndjson {
+        items.extend(parse_line(&buffer));
+        return Ok(items);
+    }
+
+    match serde_json::from_slice::<Value>(&buffer).map_err(|e| e.to_string())? {
+        Value::Array(values) => items.extend(values.into_iter().map(Ok)),
+        value => items.push(Ok(value)),
+    }
+    Ok(items)
+}
+
+pub async fn write_records_handler<S, B>(
+    source_id: String,
+    content_type: Option<String>,
+    body: S,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Unpin,
+    B: Buf,
+{
+    match read_record_body(content_type.as_deref(), body).await {
+        Ok(items) => {
+            let response = processor.append_records(&source_id, items).await;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::OK,
+            ).into_response())
+        },
+        Err(error) => Ok(ApiError::new(ErrorCode::InvalidRequest, error).into_response()),
+    }
+}
+
+const MAX_UPLOAD_BYTES: u64 = 512 * 1024 * 1024;
+
+async fn read_upload_parts(mut form: FormData) -> Result<Vec<(String, Option<String>, Bytes)>, String> {
+    let mut files = Vec::new();
+
+    while let Some(part) = form.next().await {
+        let part = part.map_err(|e| e.to_string())?;
+        let Some(file_name) = part.filename().map(str::to_string) else {
+            continue;
+        };
+        let content_type = part.content_type().map(str::to_string);
+
+        let mut contents = Vec::new();
+        let mut stream = part.stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| e.to_string())?;
+            contents.extend_from_slice(chunk.chunk());
+        }
+        files.push((file_name, content_type, Bytes::from(contents)));
+    }
+
+    Ok(files)
+}
+
+pub async fn upload_source_handler(
+    source_id: String,
+    form: FormData,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let result = match read_upload_parts(form).await {
+        Ok(files) if files.is_empty() => Err("No files in upload".to_string()),
+        Ok(files) => processor.upload_source(&source_id, files).await,
+        Err(error) => Err(error),
+    };
+
+    match result {
+        Ok((records, version, rejected)) => {
+            let response = json!({
+                "success": true,
+                "source_id": source_id,
+                "records": records,
+                "version": version,
+                "rejected": rejected
+            });
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::CREATED,
+            ).into_response())
+        },
+        Err(error) => Ok(ApiError::new(ErrorCode::InvalidRequest, error).into_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordKeyQuery {
+    pub key_field: Option<String>,
+}
+
+fn source_error_reply(error: String) -> warp::reply::Response {
+    let code = match error.as_str() {
+        "Source not found" => ErrorCode::NotFound,
+        e if e.ends_with("unfinished jobs") => ErrorCode::Conflict,
+        e if e.ends_with("not allowed by policy") => ErrorCode::Forbidden,
+        e if ["Source id", "Credential ", "Unterminated credential", "Invalid URL", "File not found"].iter().any(|prefix| e.starts_with(prefix)) => {
+            ErrorCode::ValidationFailed
+        },
+        _ => ErrorCode::Upstream,
+    };
+    ApiError::new(code, error).into_response()
+}
+
+pub async fn create_source_handler(
+    request: SourceRequest,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.create_source(request).await {
+        Ok(source) => Ok(warp::reply::with_status(warp::reply::json(&source), StatusCode::CREATED).into_response()),
+        Err(error) => Ok(source_error_reply(error)),
+    }
+}
+
+pub async fn list_sources_handler(
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let sources = processor.list_sources().await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&sources),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_source_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_source(&source_id).await {
+        Some(source) => Ok(warp::reply::with_status(warp::reply::json(&source), StatusCode::OK).into_response()),
+        None => Ok(ApiError::not_found("Source not found").into_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SourceRecordsQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// Comma separated data fields to return; every field when absent
+    pub fields: Option<String>,
+}
+
+pub async fn source_records_handler(
+    source_id: String,
+    query: SourceRecordsQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let window = ResultWindow { limit: query.limit, offset: query.offset, all: false };
+    let fields: Option<Vec<String>> = query.fields.as_deref().map(|fields| {
+        fields.split(',').map(str::trim).filter(|field| !field.is_empty()).map(str::to_string).collect()
+    });
+    match processor.preview_source(&source_id, &window, fields.as_deref()).await {
+        Ok(page) => Ok(warp::reply::with_status(warp::reply::json(&page), StatusCode::OK).into_response()),
+        Err(error) if error == "Source not found" => Ok(ApiError::not_found(error).into_response()),
+        Err(error) => Ok(ApiError::new(ErrorCode::InvalidRequest, error).into_response()),
+    }
+}
+
+pub async fn delete_source_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.delete_source(&source_id).await {
+        Ok(()) => {
+            let response = json!({
+                "success": true,
+                "message": "Source deleted"
+            });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        Err(error) => Ok(source_error_reply(error)),
+    }
+}
+
+fn record_error(error: String) -> ApiError {
+    if error.ends_with("not found") {
+        ApiError::not_found(error)
+    } else {
+        ApiError::invalid(error)
+    }
+}
+
+pub async fn patch_record_handler(
+    source_id: String,
+    key: String,
+    query: RecordKeyQuery,
+    patch: Value,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.patch_records(&source_id, &key, query.key_field.as_deref(), &patch).await {
+        Ok((records, version)) => {
+            let response = json!({
+                "success": true,
+                "version": version,
+                "records": records
+            });
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::OK,
+            ).into_response())
+        },
+        Err(error) => Ok(record_error(error).into_response()),
+    }
+}
+
+pub async fn delete_record_handler(
+    source_id: String,
+    key: String,
+    query: RecordKeyQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.delete_records(&source_id, &key, query.key_field.as_deref()).await {
+        Ok((deleted, version)) => {
+            let response = json!({
+                "success": true,
+                "version": version,
+                "deleted": deleted
+            });
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::OK,
+            ).into_response())
+        },
+        Err(error) => Ok(record_error(error).into_response()),
+    }
+}
+
+pub async fn record_audit_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let audit = processor.get_record_audit(&source_id).await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&audit),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_source_locale_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_source_locale(&source_id).await {
+        Some(locale) => Ok(warp::reply::with_status(warp::reply::json(&locale), StatusCode::OK).into_response()),
+        None => Ok(ApiError::not_found("Source has no locale").into_response()),
+    }
+}
+
+pub async fn set_source_locale_handler(
+    source_id: String,
+    locale: Locale,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.set_source_locale(&source_id, Some(locale)).await {
+        Ok(()) => {
+            let response = json!({
+                "success": true,
+                "message": "Applies to records loaded from now on"
+            });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        Err(error) => Ok(ApiError::invalid(error).into_response()),
+    }
+}
+
+pub async fn delete_source_locale_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let _ = processor.set_source_locale(&source_id, None).await;
+    Ok(warp::reply::with_status(warp::reply::json(&json!({ "success": true })), StatusCode::OK))
+}
+
+pub async fn get_expected_schema_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_expected_schema(&source_id).await {
+        Some(expected) => Ok(warp::reply::with_status(warp::reply::json(&expected), StatusCode::OK).into_response()),
+        None => Ok(ApiError::not_found("Source has no expected schema").into_response()),
+    }
+}
+
+pub async fn set_expected_schema_handler(
+    source_id: String,
+    expected: schema::ExpectedSchema,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.set_expected_schema(&source_id, Some(expected)).await {
+        Ok(()) => {
+            let response = json!({
+                "success": true,
+                "message": "Applies to records loaded from now on"
+            });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        Err(error) => Ok(ApiError::invalid(error).into_response()),
+    }
+}
+
+pub async fn delete_expected_schema_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let _ = processor.set_expected_schema(&source_id, None).await;
+    Ok(warp::reply::with_status(warp::reply::json(&json!({ "success": true })), StatusCode::OK))
+}
+
+pub async fn get_quarantine_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_quarantine(&source_id).await {
+        Some(records) => Ok(warp::reply::with_status(warp::reply::json(&records), StatusCode::OK).into_response()),
+        None => Ok(ApiError::not_found("Source has no expected schema").into_response()),
+    }
+}
+
+pub async fn clear_quarantine_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.clear_quarantine(&source_id).await {
+        Some(cleared) => {
+            let response = json!({ "success": true, "cleared": cleared });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        None => Ok(ApiError::not_found("Source has no expected schema").into_response()),
+    }
+}
+
+fn version_error_reply(error: String) -> warp::reply::Response {
+    let code = match error.as_str() {
+        e if e.ends_with("not found") || e.ends_with("is not retained") => ErrorCode::NotFound,
+        e if e.contains("pinned by") || e.ends_with("already") => ErrorCode::Conflict,
+        _ => ErrorCode::ValidationFailed,
+    };
+    ApiError::new(code, error).into_response()
+}
+
+pub async fn list_source_versions_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.list_source_versions(&source_id).await {
+        Some(versions) => Ok(warp::reply::with_status(warp::reply::json(&versions), StatusCode::OK).into_response()),
+        None => Ok(ApiError::not_found("Source not found").into_response()),
+    }
+}
+
+pub async fn snapshot_source_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.snapshot_source(&source_id).await {
+        Ok(version) => {
+            let response = json!({ "success": true, "version": version });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::CREATED).into_response())
+        },
+        Err(error) => Ok(version_error_reply(error)),
+    }
+}
+
+pub async fn delete_source_version_handler(
+    source_id: String,
+    version: u64,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.delete_source_version(&source_id, version).await {
+        Ok(()) => Ok(warp::reply::with_status(warp::reply::json(&json!({ "success": true })), StatusCode::OK).into_response()),
+        Err(error) => Ok(version_error_reply(error)),
+    }
+}
+
+pub async fn rollback_source_handler(
+    source_id: String,
+    version: u64,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.rollback_source(&source_id, version).await {
+        Ok(rolled_back) => {
+            let response = json!({ "success": true, "version": rolled_back, "restored_version": version });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        Err(error) => Ok(version_error_reply(error)),
+    }
+}
+
+pub async fn diff_source_versions_handler(
+    source_id: String,
+    query: VersionDiffQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.diff_source_versions(&source_id, &query).await {
+        Ok(diff) => Ok(warp::reply::with_status(warp::reply::json(&diff), StatusCode::OK).into_response()),
+        Err(error) => Ok(version_error_reply(error)),
+    }
+}
+
+pub async fn list_indexes_handler(
+    source_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.list_indexes(&source_id).await {
+        Some(indexes) => Ok(warp::reply::with_status(warp::reply::json(&indexes), StatusCode::OK).into_response()),
+        None => Ok(ApiError::not_found("Source not found").into_response()),
+    }
+}
+
+pub async fn create_index_handler(
+    source_id: String,
+    spec: index::IndexSpec,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.create_index(&source_id, spec).await {
+        Ok(info) => {
+            let response = json!({ "success": true, "index": info });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::CREATED).into_response())
+        },
+        Err(error) if error == "Source not found" => Ok(ApiError::not_found(error).into_response()),
+        Err(error) => Ok(ApiError::invalid(error).into_response()),
+    }
+}
+
+pub async fn drop_index_handler(
+    source_id: String,
+    field: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.drop_index(&source_id, &field).await {
+        Ok(()) => Ok(warp::reply::with_status(warp::reply::json(&json!({ "success": true })), StatusCode::OK).into_response()),
+        Err(error) => Ok(ApiError::not_found(error).into_response()),
+    }
+}
+
+fn query_error_code(error: &str) -> ErrorCode {
+    match error {
+        "Invalid share token" | "Share token expired" => ErrorCode::Forbidden,
+        e if e.ends_with("not found") => ErrorCode::NotFound,
+        e if e.starts_with("Result set exceeds") => ErrorCode::PayloadTooLarge,
+        _ => ErrorCode::ValidationFailed,
+    }
+}
+
+fn query_error_reply(error: String) -> warp::reply::Response {
+    ApiError::new(query_error_code(&error), error).into_response()
+}
+
+pub async fn save_query_handler(
+    query: SavedQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.save_query(query).await {
+        Ok(query) => {
+            let response = json!({
+                "success": true,
+                "query": query,
+                "url": format!("/queries/{}/results", query.id)
+            });
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::CREATED,
+            ).into_response())
+        },
+        Err(error) => Ok(query_error_reply(error)),
+    }
+}
+
+pub async fn list_queries_handler(
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let queries = processor.list_queries().await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&queries),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn delete_query_handler(
+    query_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.delete_query(&query_id).await {
+        Ok(()) => {
+            let response = json!({
+                "success": true,
+                "message": "Query deleted"
+            });
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::OK,
+            ).into_response())
+        },
+        Err(error) => Ok(query_error_reply(error)),
+    }
+}
+
+pub async fn query_results_handler(
+    query_id: String,
+    window: ResultWindow,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.run_saved_query(&query_id, &window).await {
+        Ok(results) => Ok(warp::reply::with_status(
+            warp::reply::json(&results),
+            StatusCode::OK,
+        ).into_response()),
+        Err(error) => Ok(query_error_reply(error)),
+    }
+}
+
+pub async fn run_query_handler(
+    query: AdHocQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match Box::pin(processor.run_query(query)).await {
+        Ok(results) => Ok(warp::reply::with_status(
+            warp::reply::json(&results),
+            StatusCode::OK,
+        ).into_response()),
+        Err(error) => Ok(query_error_reply(error)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareRequest {
+    pub expires_in: Option<String>,
+}
+
+const DEFAULT_SHARE_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub async fn share_query_handler(
+    query_id: String,
+    request: ShareRequest,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let expires_in = match request.expires_in.as_deref().map(parse_duration) {
+        Some(Some(expires_in)) => expires_in,
+        Some(None) => return Ok(query_error_reply("Invalid expiry duration".to_string())),
+        None => DEFAULT_SHARE_EXPIRY,
+    };
+
+    match processor.create_share_token(&query_id, expires_in).await {
+        Ok((token, expires_at)) => {
+            let response = json!({
+                "success": true,
+                "token": token,
+                "expires_at": expires_at,
+                "url": format!("/shared/{}", token)
+            });
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::CREATED,
+            ).into_response())
+        },
+        Err(error) => Ok(query_error_reply(error)),
+    }
+}
+
+pub async fn shared_results_handler(
+    token: String,
+    window: ResultWindow,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let results = match processor.verify_share_token(&token) {
+        Ok(query_id) => processor.run_saved_query(&query_id, &window).await,
+        Err(error) => Err(error),
+    };
+
+    match results {
+        Ok(results) => Ok(warp::reply::with_status(
+            warp::reply::json(&results),
+            StatusCode::OK,
+        ).into_response()),
+        Err(error) => Ok(query_error_reply(error)),
+    }
+}
+
+pub async fn cancel_job_handler(
+    job_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.cancel_job(&job_id).await {
+        Ok(()) => {
+            let response = json!({
+                "success": true,
+                "message": "Job cancelled"
+            });
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::OK,
+            ).into_response())
+        },
+        Err(error) if error == "Job not found" => Ok(ApiError::not_found(error).into_response()),
+        Err(error) => Ok(ApiError::new(ErrorCode::Conflict, error).into_response()),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct JobDeleteQuery {
+    /// Cancel the job first if it has not finished
+    #[serde(default)]
+    pub force: bool,
+    /// Also delete the job's result exports
+    #[serde(default)]
+    pub artifacts: bool,
+}
+
+pub async fn delete_job_handler(
+    job_id: String,
+    query: JobDeleteQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.delete_job(&job_id, query.force, query.artifacts).await {
+        Ok(exports_deleted) => {
+            let response = json!({
+                "success": true,
+                "message": "Job deleted",
+                "exports_deleted": exports_deleted
+            });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        Err(error) if error == "Job not found" => Ok(ApiError::not_found(error).into_response()),
+        Err(error) => Ok(ApiError::new(ErrorCode::Conflict, error).into_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobLogQuery {
+    /// Lowest level returned; every line when absent
+    pub level: Option<LogLevel>,
+    /// Only lines written after this time, for following a running job
+    pub since: Option<DateTime<Utc>>,
+    /// Only the last this many lines
+    pub tail: Option<usize>,
+}
+
+pub async fn job_logs_handler(
+    job_id: String,
+    query: JobLogQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_job_logs(&job_id, &query).await {
+        Some(lines) => {
+            let response = json!({
+                "job_id": job_id,
+                "lines": lines
+            });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        None => Ok(ApiError::not_found("Job not found").into_response()),
+    }
+}
+
+pub async fn list_dead_letters_handler(
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let dead_letters = processor.list_dead_letters().await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&dead_letters),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_dead_letter_handler(
+    job_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_dead_letter(&job_id).await {
+        Some(job) => Ok(warp::reply::with_status(warp::reply::json(&job), StatusCode::OK).into_response()),
+        None => Ok(ApiError::not_found(format!("Dead-lettered job {} not found", job_id)).into_response()),
+    }
+}
+
+pub async fn redrive_dead_letter_handler(
+    job_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    redrive_reply(processor.redrive_dead_letters(&[job_id]).await, 1, &processor)
+}
+
+/// Re-drives the whole dead-letter queue, oldest first.
+pub async fn redrive_all_handler(
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let job_ids: Vec<String> = processor.list_dead_letters().await.into_iter().map(|job| job.job_id).collect();
+    redrive_reply(processor.redrive_dead_letters(&job_ids).await, job_ids.len(), &processor)
+}
+
+/// `requested` is how many jobs were asked for; those past a full queue stay dead-lettered.
+fn redrive_reply(
+    result: Result<Vec<String>, String>,
+    requested: usize,
+    processor: &DataProcessor,
+) -> Result<warp::reply::Response, Rejection> {
+    let (depth, capacity) = processor.queue_depth();
+    let response = match result {
+        Ok(redriven) => {
+            let response = json!({
+                "success": true,
+                "not_redriven": requested - redriven.len(),
+                "redriven": redriven,
+                "queue_depth": depth
+            });
+            warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response()
+        },
+        Err(error) if error == QUEUE_FULL => ApiError::new(ErrorCode::QueueFull, error)
+            .with_details(json!({"queue_depth": depth, "queue_capacity": capacity}))
+            .into_response(),
+        Err(error) => ApiError::not_found(error).into_response(),
+    };
+    Ok(response)
+}
+
+pub async fn add_comment_handler(
+    job_id: String,
+    comment: JobComment,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.add_job_comment(&job_id, comment).await {
+        Ok(comment) => Ok(warp::reply::with_status(
+            warp::reply::json(&comment),
+            StatusCode::CREATED,
+        ).into_response()),
+        Err(error) if error == "Job not found" => Ok(ApiError::not_found(error).into_response()),
+        Err(error) => Ok(ApiError::invalid(error).into_response()),
+    }
+}
+
+pub async fn list_comments_handler(
+    job_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_job_comments(&job_id).await {
+        Some(comments) => Ok(warp::reply::with_status(
+            warp::reply::json(&comments),
+            StatusCode::OK,
+        ).into_response()),
+        None => Ok(ApiError::not_found("Job not found").into_response()),
+    }
+}
+
+fn schedule_error_reply(error: String) -> warp::reply::Response {
+    let code = if error.ends_with("not found") { ErrorCode::NotFound } else { ErrorCode::ValidationFailed };
+    ApiError::new(code, error).into_response()
+}
+
+pub async fn create_schedule_handler(
+    schedule: Schedule,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.create_schedule(schedule).await {
+        Ok(schedule) => Ok(warp::reply::with_status(
+            warp::reply::json(&schedule),
+            StatusCode::CREATED,
+        ).into_response()),
+        Err(error) => Ok(schedule_error_reply(error)),
     }
+}
 
-    fn validate_record(record: &DataRecord, rule: &ValidationRule) -> Result<(), String> {
-        let field_value = record.data.get(&rule.field);
-        
-        match &rule.rule_type {
-            ValidationType::Required => {
-                if field_value.is_none() || field_value == Some(&Value::Null) {
-                    return Err(format!("Field {} is required", rule.field));
-                }
-            },
-            ValidationType::DataType { expected_type } => {
-                if let Some(value) = field_value {
-                    let actual_type = match value {
-                        Value::String(_) => "string",
-                        Value::Number(_) => "number",
-                        Value::Bool(_) => "boolean",
-                        Value::Array(_) => "array",
-                        Value::Object(_) => "object",
-                        Value::Null => "null",
-                    };
-                    
-                    if actual_type != expected_type {
-                        return Err(format!("Field {} expected type {}, got {}", 
-                                         rule.field, expected_type, actual_type));
-                    }
-                }
-            },
-            ValidationType::Range { min, max } => {
-                if let Some(Value::Number(num)) = field_value {
-                    if let Some(val) = num.as_f64() {
-                        if val < *min || val > *max {
-                            return Err(format!("Field {} value {} out of range [{}, {}]", 
-                                             rule.field, val, min, max));
-                        }
-                    }
-                }
-            },
-            _ => {
-                // Placeholder for other validation types
-            }
-        }
-        
-        Ok(())
+#[derive(Debug, Deserialize)]
+pub struct RunCompareQuery {
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+pub async fn compare_runs_handler(
+    pipeline_id: String,
+    query: RunCompareQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.compare_runs(&pipeline_id, query.a.as_deref(), query.b.as_deref()).await {
+        Ok(comparison) => Ok(warp::reply::with_status(warp::reply::json(&comparison), StatusCode::OK).into_response()),
+        Err(error) if error.ends_with("finished runs") => Ok(ApiError::new(ErrorCode::Conflict, error).into_response()),
+        Err(error) => Ok(schedule_error_reply(error)),
     }
+}
 
-    async fn output_results(
-        data: &[DataRecord],
-        output_format: &OutputFormat,
-    ) -> Result<(), String> {
-        match output_format {
-            OutputFormat::Json => {
-                let json_output = serde_json::to_string_pretty(data)
-                    .map_err(|e| e.to_string())?;
-                
-                let mut file = std::fs::File::create("output.json")
-                    .map_err(|e| e.to_string())?;
-                file.write_all(json_output.as_bytes())
-                    .map_err(|e| e.to_string())?;
-                
-                println!("Results written to output.json");
-            },
-            OutputFormat::Csv => {
-                let mut wtr = csv::Writer::from_path("output.csv")
-                    .map_err(|e| e.to_string())?;
-                
-                // Write headers (simplified)
-                wtr.write_record(&["id", "timestamp", "source", "data"])
-                    .map_err(|e| e.to_string())?;
-                
-                for record in data {
-                    wtr.write_record(&[
-                        &record.id,
-                        &record.timestamp.to_rfc3339(),
-                        &record.source,
-                        &record.data.to_string(),
-                    ]).map_err(|e| e.to_string())?;
-                }
-                
-                wtr.flush().map_err(|e| e.to_string())?;
-                println!("Results written to output.csv");
-            },
-            OutputFormat::Api { endpoint, headers } => {
-                let client = Client::new();
-                let mut request = client.post(endpoint);
-                
-                for (key, value) in headers {
-                    request = request.header(key, value);
-                }
-                
-                let response = request.json(data).send().await
-                    .map_err(|e| e.to_string())?;
-                
-                if response.status().is_success() {
-                    println!("Results sent to API endpoint: {}", endpoint);
-                } else {
-                    return Err(format!("API request failed: {}", response.status()));
-                }
-            },
-            _ => {
-                println!("Output format not implemented yet");
-            }
-        }
-        
-        Ok(())
+pub async fn get_schedule_handler(
+    schedule_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.get_schedule(&schedule_id).await {
+        Ok(schedule) => Ok(warp::reply::with_status(warp::reply::json(&schedule), StatusCode::OK).into_response()),
+        Err(error) => Ok(schedule_error_reply(error)),
     }
+}
 
-    async fn update_metrics(metrics: Arc<RwLock<SystemMetrics>>, start_time: Instant) {
-        let mut interval = tokio::time::interval(Duration::from_secs(10));
-        
-        loop {
-            interval.tick().await;
-            
-            let mut metrics_guard = metrics.write().await;
-            metrics_guard.uptime_seconds = start_time.elapsed().as_secs();
-            
-            // In a real implementation, you'd collect actual system metrics
-            metrics_guard.cpu_usage = rand::random::<f64>() * 100.0;
-            metrics_guard.memory_usage = rand::random::<f64>() * 100.0;
-            metrics_guard.disk_usage = rand::random::<f64>() * 100.0;
-        }
+async fn set_schedule_enabled(
+    schedule_id: &str,
+    enabled: bool,
+    processor: &DataProcessor,
+) -> warp::reply::Response {
+    match processor.set_schedule_enabled(schedule_id, enabled).await {
+        Ok(schedule) => warp::reply::with_status(warp::reply::json(&schedule), StatusCode::OK).into_response(),
+        Err(error) => schedule_error_reply(error),
     }
 }
 
-// REST API handlers
-pub async fn health_handler() -> Result<impl Reply, Rejection> {
-    let health = json!({
-        "status": "healthy",
-        "service": "rust-data-processor",
-        "timestamp": Utc::now(),
-        "version": "1.0.0"
-    });
-    
+pub async fn enable_schedule_handler(
+    schedule_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    Ok(set_schedule_enabled(&schedule_id, true, &processor).await)
+}
+
+pub async fn disable_schedule_handler(
+    schedule_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    Ok(set_schedule_enabled(&schedule_id, false, &processor).await)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpcomingRunsQuery {
+    #[serde(default = "default_upcoming_runs")]
+    pub count: usize,
+}
+
+fn default_upcoming_runs() -> usize {
+    5
+}
+
+pub async fn upcoming_runs_handler(
+    schedule_id: String,
+    query: UpcomingRunsQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.upcoming_runs(&schedule_id, query.count).await {
+        Ok(upcoming) => {
+            let response = json!({
+                "schedule_id": schedule_id,
+                "next_run_at": upcoming.first(),
+                "upcoming": upcoming,
+            });
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        Err(error) => Ok(schedule_error_reply(error)),
+    }
+}
+
+pub async fn list_schedules_handler(
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let schedules = processor.list_schedules().await;
     Ok(warp::reply::with_status(
-        warp::reply::json(&health),
+        warp::reply::json(&schedules),
         StatusCode::OK,
     ))
 }
 
-pub async fn submit_job_handler(
-    job: ProcessingJob,
+pub async fn create_calendar_handler(
+    calendar: BusinessCalendar,
     processor: Arc<DataProcessor>,
 ) -> Result<impl Reply, Rejection> {
-    match processor.submit_job(job).await {
-        Ok(job_id) => {
+    match processor.create_calendar(calendar).await {
+        Ok(calendar) => Ok(warp::reply::with_status(
+            warp::reply::json(&calendar),
+            StatusCode::CREATED,
+        ).into_response()),
+        Err(error) => Ok(schedule_error_reply(error)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarQuery {
+    pub tenant: Option<String>,
+}
+
+pub async fn list_calendars_handler(
+    query: CalendarQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let calendars = processor.list_calendars(query.tenant.as_deref()).await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&calendars),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn add_window_handler(
+    window: MaintenanceWindow,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.add_maintenance_window(window).await {
+        Ok(window) => Ok(warp::reply::with_status(
+            warp::reply::json(&window),
+            StatusCode::CREATED,
+        ).into_response()),
+        Err(error) => Ok(schedule_error_reply(error)),
+    }
+}
+
+pub async fn list_windows_handler(
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let windows = processor.list_maintenance_windows().await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&windows),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn delete_window_handler(
+    window_id: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.delete_maintenance_window(&window_id).await {
+        Ok(()) => {
             let response = json!({
                 "success": true,
-                "job_id": job_id,
-                "message": "Job submitted successfully"
+                "message": "Maintenance window deleted"
             });
             Ok(warp::reply::with_status(
                 warp::reply::json(&response),
-                StatusCode::CREATED,
-            ))
+                StatusCode::OK,
+            ).into_response())
         },
-        Err(error) => {
+        Err(error) => Ok(schedule_error_reply(error)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CredentialRequest {
+    pub name: String,
+    pub secret: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateCredentialRequest {
+    pub secret: String,
+}
+
+fn credential_error_reply(error: String) -> warp::reply::Response {
+    let code = if error.ends_with("not found") {
+        ErrorCode::NotFound
+    } else if error.starts_with("Credential already exists") {
+        ErrorCode::Conflict
+    } else {
+        ErrorCode::ValidationFailed
+    };
+    ApiError::new(code, error).into_response()
+}
+
+pub async fn add_credential_handler(
+    request: CredentialRequest,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.add_credential(request).await {
+        Ok(info) => Ok(warp::reply::with_status(
+            warp::reply::json(&info),
+            StatusCode::CREATED,
+        ).into_response()),
+        Err(error) => Ok(credential_error_reply(error)),
+    }
+}
+
+pub async fn rotate_credential_handler(
+    name: String,
+    request: RotateCredentialRequest,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.rotate_credential(&name, request.secret).await {
+        Ok(info) => Ok(warp::reply::with_status(
+            warp::reply::json(&info),
+            StatusCode::OK,
+        ).into_response()),
+        Err(error) => Ok(credential_error_reply(error)),
+    }
+}
+
+pub async fn list_credentials_handler(
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let credentials = processor.list_credentials().await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&credentials),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn delete_credential_handler(
+    name: String,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    match processor.delete_credential(&name).await {
+        Ok(()) => {
             let response = json!({
-                "success": false,
-                "error": error
+                "success": true,
+                "message": "Credential deleted"
             });
             Ok(warp::reply::with_status(
                 warp::reply::json(&response),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ))
-        }
+                StatusCode::OK,
+            ).into_response())
+        },
+        Err(error) => Ok(credential_error_reply(error)),
     }
 }
 
-pub async fn get_job_handler(
-    job_id: String,
+#[derive(Debug, Deserialize)]
+pub struct JobPurgeQuery {
+    /// Completed, failed, cancelled or timedout; any finished status when absent
+    pub status: Option<String>,
+    /// Only jobs that finished before this time
+    pub before: Option<DateTime<Utc>>,
+}
+
+pub async fn purge_jobs_handler(
+    query: JobPurgeQuery,
     processor: Arc<DataProcessor>,
 ) -> Result<impl Reply, Rejection> {
-    match processor.get_job_status(&job_id).await {
-        Some(job) => Ok(warp::reply::with_status(
-            warp::reply::json(&job),
-            StatusCode::OK,
-        )),
-        None => {
+    let finished = [JobStatus::Completed, JobStatus::Failed, JobStatus::Cancelled, JobStatus::TimedOut];
+    let status = match &query.status {
+        Some(name) => match finished.into_iter().find(|status| format!("{:?}", status).eq_ignore_ascii_case(name)) {
+            Some(status) => Some(status),
+            None => {
+                let error = format!("Only finished jobs can be deleted, not '{}'", name);
+                return Ok(ApiError::new(ErrorCode::InvalidRequest, error).into_response());
+            },
+        },
+        None => None,
+    };
+    let purged = processor.purge_jobs(|job| {
+        status.as_ref().is_none_or(|status| job.status == *status)
+            && query.before.is_none_or(|before| job.completed_at.is_some_and(|at| at < before))
+    }).await;
+    println!("Deleted {} finished jobs on request", purged.len());
+    let response = json!({
+        "success": true,
+        "deleted": purged.len(),
+        "job_ids": purged
+    });
+    Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobArchiveQuery {
+    /// Comma separated job ids; every finished job when absent
+    pub ids: Option<String>,
+    /// `team=billing,env=prod`; see `TagFilter`
+    pub tag: Option<String>,
+}
+
+/// Downloads finished jobs and their retained results as a gzipped `JobArchive`.
+pub async fn export_jobs_handler(
+    query: JobArchiveQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let ids: Vec<String> = query.ids.as_deref().unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect();
+    let tags = query.tag.as_deref().map(TagFilter::parse).unwrap_or_default();
+    let encoded = match processor.export_jobs(&ids, &tags).await {
+        Ok(archive) => archive.encode()
+            .map(|bytes| (archive.jobs.len(), bytes))
+            .map_err(|error| ApiError::new(ErrorCode::Internal, error)),
+        Err(error) if error.ends_with("not found") => Err(ApiError::not_found(error)),
+        Err(error) => Err(ApiError::new(ErrorCode::Conflict, error)),
+    };
+    let (count, bytes) = match encoded {
+        Ok(encoded) => encoded,
+        Err(error) => return Ok(error.into_response()),
+    };
+    println!("Exported {} jobs to an archive", count);
+    let file_name = format!("jobs-{}.json.gz", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    let mut response = warp::reply::Response::new(bytes.into());
+    let headers = response.headers_mut();
+    headers.insert(warp::http::header::CONTENT_TYPE, warp::http::HeaderValue::from_static("application/gzip"));
+    if let Ok(disposition) = warp::http::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", file_name)) {
+        headers.insert(warp::http::header::CONTENT_DISPOSITION, disposition);
+    }
+    Ok(response)
+}
+
+/// Takes an archive from `GET /jobs/archive`, gzipped or not; see `DataProcessor::import_jobs`.
+pub async fn import_jobs_handler(
+    body: bytes::Bytes,
+    processor: Arc<DataProcessor>,
+) -> Result<impl Reply, Rejection> {
+    let imported = match JobArchive::decode(&body) {
+        Ok(archive) => processor.import_jobs(archive).await.map_err(ApiError::invalid),
+        Err(error) => Err(ApiError::new(ErrorCode::InvalidRequest, error)),
+    };
+    match imported {
+        Ok(import) => {
+            println!("Imported {} jobs, skipped {} already present", import.imported.len(), import.skipped.len());
             let response = json!({
-                "error": "Job not found"
+                "success": true,
+                "imported": import.imported,
+                "skipped": import.skipped
             });
-            Ok(warp::reply::with_status(
-                warp::reply::json(&response),
-                StatusCode::NOT_FOUND,
-            ))
-        }
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK).into_response())
+        },
+        Err(error) => Ok(error.into_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobTagQuery {
+    /// `team=billing,env=prod`; see `TagFilter`
+    pub tag: Option<String>,
+    /// Tag whose values the jobs are grouped by
+    pub group_by: Option<String>,
+}
+
+impl JobTagQuery {
+    fn filter(&self) -> TagFilter {
+        self.tag.as_deref().map(TagFilter::parse).unwrap_or_default()
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct JobList {
+    pub jobs: Vec<ProcessingJob>,
+}
+
+/// A plain job list, or `{group_by, groups: [{value, jobs}]}` when grouping by a tag.
 pub async fn list_jobs_handler(
+    query: JobTagQuery,
     processor: Arc<DataProcessor>,
 ) -> Result<impl Reply, Rejection> {
-    let jobs = processor.list_jobs().await;
+    let jobs = processor.list_jobs(&query.filter()).await;
+    let response = match &query.group_by {
+        Some(name) => {
+            let groups: Vec<TagGroup<JobList>> = group_by_tag(&jobs, name).into_iter()
+                .map(|(value, jobs)| TagGroup { value, content: JobList { jobs: jobs.into_iter().cloned().collect() } })
+                .collect();
+            json!({
+                "group_by": name,
+                "groups": groups
+            })
+        },
+        None => json!(jobs),
+    };
     Ok(warp::reply::with_status(
-        warp::reply::json(&jobs),
+        warp::reply::json(&response),
         StatusCode::OK,
     ))
 }
 
+/// How often `/ws` connections subscribed to `metrics` get a snapshot.
+const WS_METRICS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a `/ws` connection can subscribe to: `jobs` for every job, `job:<id>` for one, or
+/// `metrics`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Topic {
+    Jobs,
+    Job(String),
+    Metrics,
+}
+
+impl Topic {
+    fn parse(text: &str) -> Result<Self, String> {
+        match text.trim() {
+            "jobs" => Ok(Topic::Jobs),
+            "metrics" => Ok(Topic::Metrics),
+            other => match other.strip_prefix("job:") {
+                Some(job_id) if !job_id.is_empty() => Ok(Topic::Job(job_id.to_string())),
+                _ => Err(format!("Unknown topic '{}'; use jobs, job:<id> or metrics", other)),
+            },
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Topic::Jobs => "jobs".to_string(),
+            Topic::Job(job_id) => format!("job:{}", job_id),
+            Topic::Metrics => "metrics".to_string(),
+        }
+    }
+}
+
+/// A message from a `/ws` client, e.g. `{"subscribe": ["job:<id>", "metrics"]}`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TopicRequest {
+    #[serde(default)]
+    subscribe: Vec<String>,
+    #[serde(default)]
+    unsubscribe: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    /// Comma separated topics to start with
+    pub topics: Option<String>,
+}
+
+/// Pushes job status changes and periodic metrics over a WebSocket, for the topics the client
+/// asked for in the query string or in later `TopicRequest` messages.
+pub async fn ws_handler(
+    ws: warp::ws::Ws,
+    query: WsQuery,
+    processor: Arc<DataProcessor>,
+) -> Result<warp::reply::Response, Rejection> {
+    let topics: Result<HashSet<Topic>, String> = query.topics.as_deref().unwrap_or_default()
+        .split(',')
+        .filter(|topic| !topic.trim().is_empty())
+        .map(Topic::parse)
+        .collect();
+    match topics {
+        Ok(topics) => Ok(ws.on_upgrade(move |socket| serve_topics(socket, topics, processor)).into_response()),
+        Err(error) => Ok(ApiError::new(ErrorCode::InvalidRequest, error).into_response()),
+    }
+}
+
+async fn serve_topics(socket: warp::ws::WebSocket, mut topics: HashSet<Topic>, processor: Arc<DataProcessor>) {
+    use futures::SinkExt;
+
+    let (mut outgoing, mut incoming) = socket.split();
+    let mut updates = processor.job_updates.subscribe();
+    let mut metrics = tokio::time::interval(WS_METRICS_INTERVAL);
+    // Status of each unfinished job last sent, so progress and repeats raise nothing
+    let mut last_status: HashMap<String, JobStatus> = HashMap::new();
+    let text = |message: Value| warp::ws::Message::text(message.to_string());
+
+    let subscribed = |topics: &HashSet<Topic>| {
+        let mut names: Vec<String> = topics.iter().map(Topic::name).collect();
+        names.sort();
+        text(json!({"type": "subscribed", "topics": names}))
+    };
+    if outgoing.send(subscribed(&topics)).await.is_err() {
+        return;
+    }
+    loop {
+        let message = tokio::select! {
+            request = incoming.next() => {
+                let request = match request {
+                    Some(Ok(request)) if request.is_close() => break,
+                    Some(Ok(request)) if request.is_text() => request,
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) | None => break,
+                };
+                let parsed = serde_json::from_str::<TopicRequest>(request.to_str().unwrap_or_default())
+                    .map_err(|e| e.to_string())
+                    .and_then(|request| {
+                        let subscribe = request.subscribe.iter().map(|topic| Topic::parse(topic)).collect::<Result<Vec<_>, _>>()?;
+                        let unsubscribe = request.unsubscribe.iter().map(|topic| Topic::parse(topic)).collect::<Result<Vec<_>, _>>()?;
+                        Ok((subscribe, unsubscribe))
+                    });
+                match parsed {
+                    Ok((subscribe, unsubscribe)) => {
+                        // A new metrics subscriber gets a snapshot straight away
+                        if subscribe.contains(&Topic::Metrics) && !topics.contains(&Topic::Metrics) {
+                            metrics.reset_immediately();
+                        }
+                        topics.extend(subscribe);
+                        topics.retain(|topic| !unsubscribe.contains(topic));
+                        subscribed(&topics)
+                    },
+                    Err(error) => text(json!({"type": "error", "error": error})),
+                }
+            },
+            update = updates.recv() => {
+                let job = match update {
+                    Ok(job) => job,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let wanted = topics.contains(&Topic::Jobs) || topics.contains(&Topic::Job(job.id.clone()));
+                if !wanted || last_status.get(&job.id) == Some(&job.status) {
+                    continue;
+                }
+                match job.status.is_finished() {
+                    true => last_status.remove(&job.id),
+                    false => last_status.insert(job.id.clone(), job.status.clone()),
+                };
+                text(json!({"type": "job", "job": JobEventSummary::from(&job)}))
+            },
+            _ = metrics.tick() => {
+                if !topics.contains(&Topic::Metrics) {
+                    continue;
+                }
+                text(json!({"type": "metrics", "metrics": processor.get_metrics().await}))
+            },
+        };
+        if outgoing.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// With a tag filter or grouping, the system metrics also carry `jobs` counts for the selected
+/// jobs and per-value `groups`.
 pub async fn metrics_handler(
+    query: JobTagQuery,
     processor: Arc<DataProcessor>,
 ) -> Result<impl Reply, Rejection> {
     let metrics = processor.get_metrics().await;
+    if query.tag.is_none() && query.group_by.is_none() {
+        return Ok(warp::reply::with_status(warp::reply::json(&metrics), StatusCode::OK));
+    }
+    let (jobs, groups) = processor.job_counts(&query.filter(), query.group_by.as_deref()).await;
+    let mut response = json!(metrics);
+    response["jobs"] = json!(jobs);
+    if let Some(groups) = groups {
+        response["group_by"] = json!(query.group_by);
+        response["groups"] = json!(groups);
+    }
     Ok(warp::reply::with_status(
-        warp::reply::json(&metrics),
+        warp::reply::json(&response),
         StatusCode::OK,
     ))
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum StdinFormat {
+    Csv,
+    Ndjson,
+}
+
+const DEFAULT_RATE_LIMIT_BURST: u32 = 20;
+const DEFAULT_PORT: u16 = 8000;
+
+#[derive(Debug, Parser)]
+#[command(name = "data-processor", version, about = "High-performance data processing engine")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directory to watch for new input files
+    #[arg(long)]
+    watch_dir: Option<PathBuf>,
+
+    /// Glob pattern that watched file names must match
+    #[arg(long, default_value = "*")]
+    watch_pattern: String,
+
+    /// Job definition (JSON) submitted for every ingested file
+    #[arg(long, requires = "watch_dir")]
+    watch_job: Option<PathBuf>,
+
+    /// Ingest files already in the watch directory at startup
+    #[arg(long, requires = "watch_dir")]
+    watch_catch_up: bool,
+
+    /// Host, wildcard domain (`*.example.com`), IP or CIDR outputs may connect to; repeatable
+    #[arg(long = "egress-allow", value_name = "RULE")]
+    egress_allow: Vec<String>,
+
+    /// Memory (MiB) that admitted jobs may be projected to use together
+    #[arg(long)]
+    memory_limit_mb: Option<u64>,
+
+    /// Memory (MiB) sources' records may take; past it the least recently read sources are
+    /// spilled to the work directory and read back when next used
+    #[arg(long)]
+    source_memory_mb: Option<u64>,
+
+    /// Where sources' records persist; `memory` loses them on restart
+    #[arg(long, value_enum, default_value = "memory")]
+    source_storage: storage::StorageBackend,
+
+    /// Database file or directory of the source storage (default: <work dir>/sources)
+    #[arg(long)]
+    source_storage_path: Option<PathBuf>,
+
+    /// Dictionary-encode source string fields with at most this many distinct values
+    #[arg(long, value_name = "MAX_VALUES")]
+    dictionary_encode: Option<usize>,
+
+    /// Directory for scratch files such as staged outputs (default: <temp dir>/data-processor)
+    #[arg(long)]
+    work_dir: Option<PathBuf>,
+
+    /// Directory file outputs are written under; output paths cannot leave it (default: the
+    /// current directory)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Free space (MiB) the work directory must have for a job to start
+    #[arg(long, default_value_t = DEFAULT_WORK_DIR_MIN_FREE_MB)]
+    work_dir_min_free_mb: u64,
+
+    /// Most records one API response may return; larger result sets must be paged
+    #[arg(long, default_value_t = DEFAULT_MAX_RESULT_ROWS)]
+    max_result_rows: usize,
+
+    /// Missed schedule runs and backlog files replayed per minute after downtime
+    #[arg(long, default_value_t = DEFAULT_CATCH_UP_RATE)]
+    catch_up_rate: f64,
+
+    /// Jobs run at once
+    #[arg(long, default_value_t = DEFAULT_JOB_WORKERS)]
+    job_workers: usize,
+
+    /// Jobs that may wait for a worker before submissions are refused
+    #[arg(long, default_value_t = DEFAULT_MAX_QUEUED_JOBS)]
+    max_queued_jobs: usize,
+
+    /// Finished jobs kept, newest first; 0 keeps every job
+    #[arg(long, default_value_t = DEFAULT_RETAINED_JOBS)]
+    retain_jobs: usize,
+
+    /// Days a finished job is kept
+    #[arg(long)]
+    retain_job_days: Option<i64>,
+
+    /// Earlier versions kept per source when a load or rollback replaces its records, newest
+    /// first; 0 keeps none
+    #[arg(long, default_value_t = DEFAULT_RETAINED_VERSIONS)]
+    retain_source_versions: usize,
+
+    /// Days an earlier version of a source is kept after its records landed
+    #[arg(long)]
+    retain_source_version_days: Option<i64>,
+
+    /// URL notified of every job's lifecycle events, signed with DATA_PROCESSOR_WEBHOOK_SECRET; repeatable
+    #[arg(long = "job-webhook", value_name = "URL")]
+    job_webhooks: Vec<String>,
+
+    /// JSON file of per-tenant job quotas and the API keys tenants submit with; read again on `POST /admin/reload`
+    #[arg(long)]
+    quotas: Option<PathBuf>,
+
+    /// JSON file naming the OIDC issuer, audience and signing keys whose bearer tokens are
+    /// required, and how their claims map to roles; without it every route is open
+    #[arg(long)]
+    auth: Option<PathBuf>,
+
+    /// Sustained requests per second each client (API key, or IP without one) may make
+    #[arg(long, value_name = "PER_SECOND")]
+    rate_limit: Option<f64>,
+
+    /// Requests a client may make at once before the sustained rate applies
+    #[arg(long, default_value_t = DEFAULT_RATE_LIMIT_BURST, requires = "rate_limit")]
+    rate_limit_burst: u32,
+
+    /// Port the API listens on
+    #[arg(long, default_value_t = DEFAULT_PORT)]
+    port: u16,
+
+    /// PEM certificate chain to serve HTTPS with
+    #[arg(long, requires = "tls_key", conflicts_with = "acme_domains")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM bundle of the CAs client certificates must be signed by
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Whether a client certificate is required or only verified when presented
+    #[arg(long, value_enum, default_value_t = tls::ClientAuth::Required, requires = "tls_client_ca")]
+    tls_client_auth: tls::ClientAuth,
+
+    /// Domain to obtain a certificate for through ACME (Let's Encrypt); repeatable
+    #[arg(long = "acme-domain", value_name = "DOMAIN", requires = "acme_cache")]
+    acme_domains: Vec<String>,
+
+    /// Email address the ACME directory may send expiry notices to; repeatable
+    #[arg(long = "acme-contact", value_name = "EMAIL", requires = "acme_domains")]
+    acme_contacts: Vec<String>,
+
+    /// Directory keeping the ACME account and certificates across restarts
+    #[arg(long, requires = "acme_domains")]
+    acme_cache: Option<PathBuf>,
+
+    /// Use the ACME directory's staging environment
+    #[arg(long, requires = "acme_domains")]
+    acme_staging: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run a pipeline over records piped in on stdin and exit
+    Run {
+        /// Pipeline definition (YAML or JSON processing config); file outputs must set
+        /// `output_path`
+        pipeline: PathBuf,
+
+        /// Format of the records on stdin
+        #[arg(long, value_enum, default_value = "ndjson")]
+        input_format: StdinFormat,
+    },
+    /// Decrypt a file written with output encryption
+    Decrypt {
+        file: PathBuf,
+
+        /// Environment variable holding the passphrase
+        #[arg(long, default_value = "DATA_PROCESSOR_OUTPUT_KEY")]
+        key_env: String,
+
+        /// Where to write the plaintext (default: stdout)
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn load_pipeline_config(path: &Path) -> Result<ProcessingConfig, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    // Go through a JSON value so enums use the same `{Variant: {...}}` shape as the HTTP API
+    let value: Value = serde_yaml::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+fn load_quotas(path: &Path) -> Result<QuotaConfig, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())
+}
+
+fn load_auth_config(path: &Path) -> Result<auth::AuthConfig, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())
+}
+
+fn load_job_template(path: &Path) -> Result<ProcessingJob, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+
+    if let Some(Command::Decrypt { file, key_env, output }) = &cli.command {
+        let encryption = OutputEncryption::Aes256Gcm { key_env: key_env.clone() };
+        let result = encryption.key().and_then(|key| match output {
+            Some(output) => {
+                let mut writer = BufWriter::new(File::create(output).map_err(|e| e.to_string())?);
+                decrypt_output_file(file, &key, &mut writer)?;
+                writer.flush().map_err(|e| e.to_string())
+            },
+            None => decrypt_output_file(file, &key, &mut std::io::stdout().lock()),
+        });
+        if let Err(e) = result {
+            eprintln!("Could not decrypt {}: {}", file.display(), e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let quotas = match cli.quotas.as_deref().map(load_quotas).transpose() {
+        Ok(quotas) => quotas.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Could not load quotas: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let authenticator = match cli.auth.as_deref().map(|path| load_auth_config(path).and_then(auth::Authenticator::new)).transpose() {
+        Ok(authenticator) => authenticator.map(Arc::new),
+        Err(e) => {
+            eprintln!("Could not load auth config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(authenticator) = &authenticator {
+        // The provider may be down at startup; keys are fetched again on the first request
+        match authenticator.refresh().await {
+            Ok(keys) => println!("Loaded {} signing keys", keys),
+            Err(e) => eprintln!("Warning: could not load signing keys: {}", e),
+        }
+    }
+
+    let rate_limiter = match cli.rate_limit.map(|rate| ratelimit::RateLimiter::new(rate, cli.rate_limit_burst)).transpose() {
+        Ok(limiter) => limiter.map(Arc::new),
+        Err(e) => {
+            eprintln!("Invalid --rate-limit: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let catch_up = match CatchUpThrottle::new(cli.catch_up_rate) {
+        Ok(throttle) => throttle,
+        Err(e) => {
+            eprintln!("Invalid --catch-up-rate: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Initialize processor
-    let processor = Arc::new(DataProcessor::new());
+    let processor = Arc::new(
+        DataProcessor::new()
+            .with_catch_up_throttle(catch_up)
+            .with_max_queued_jobs(cli.max_queued_jobs)
+            .with_memory_limit(cli.memory_limit_mb.map(|mb| mb * 1024 * 1024))
+            .with_source_memory_budget(cli.source_memory_mb.map(|mb| mb * 1024 * 1024))
+            .with_dictionary_encoding(cli.dictionary_encode)
+            .with_max_result_rows(cli.max_result_rows)
+            .with_job_retention(JobRetention {
+                max_jobs: (cli.retain_jobs > 0).then_some(cli.retain_jobs),
+                max_age: cli.retain_job_days.map(chrono::Duration::days),
+            })
+            .with_version_retention(VersionRetention {
+                max_versions: Some(cli.retain_source_versions),
+                max_age: cli.retain_source_version_days.map(chrono::Duration::days),
+            })
+            .with_job_webhooks(cli.job_webhooks.clone())
+            .with_quotas(quotas),
+    );
+    if let Err(e) = processor.validate_callbacks(&processor.job_webhooks) {
+        eprintln!("Invalid --job-webhook: {}", e);
+        std::process::exit(1);
+    }
+    let work_dir_root = cli.work_dir.clone().unwrap_or_else(|| WorkDir::default().root().to_path_buf());
+    match WorkDir::new(work_dir_root.clone(), cli.work_dir_min_free_mb * 1024 * 1024) {
+        Ok(work_dir) => processor.set_work_dir(work_dir.with_output_root(cli.output_dir.clone().unwrap_or_default())).await,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let source_storage_path = cli.source_storage_path.clone().unwrap_or_else(|| work_dir_root.join("sources"));
+    let restored = match storage::open(cli.source_storage, &source_storage_path) {
+        Ok(Some(storage)) => processor.attach_storage(storage).await,
+        Ok(None) => Ok(0),
+        Err(e) => Err(e),
+    };
+    match restored {
+        Ok(0) => {},
+        Ok(restored) => println!("Restored {} sources from {}", restored, source_storage_path.display()),
+        Err(e) => {
+            eprintln!("Could not open source storage {}: {}", source_storage_path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    match EgressPolicy::parse(&cli.egress_allow) {
+        Ok(policy) => processor.set_egress_policy(policy).await,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(Command::Run { pipeline, input_format }) = cli.command {
+        let mut configuration = match load_pipeline_config(&pipeline) {
+            Ok(configuration) => configuration,
+            Err(e) => {
+                eprintln!("Could not load pipeline {}: {}", pipeline.display(), e);
+                std::process::exit(1);
+            }
+        };
+        if configuration.input_sources.is_empty() {
+            configuration.input_sources = vec!["stdin".to_string()];
+        }
+        for warning in configuration.deprecation_warnings() {
+            eprintln!("Warning: {} ({}): {}", warning.path, warning.code, warning.message);
+        }
+
+        match processor.run_stdin_pipeline(configuration, input_format).await {
+            Ok(job) => {
+                eprintln!("Processed {} records from stdin", job.processed_count);
+                return;
+            },
+            Err(e) => {
+                eprintln!("Pipeline failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
     
+    processor.remove_stale_job_dirs().await;
+    processor.resume_checkpointed_jobs().await;
+    processor.start_job_workers(cli.job_workers.max(1));
+
     // Load sample data
     if let Err(e) = processor.load_data_from_file("sample", "data/sample.csv").await {
         println!("Warning: Could not load sample data: {}", e);
     }
 
+    // Start directory watcher
+    if let Some(directory) = cli.watch_dir {
+        let pattern = match glob::Pattern::new(&cli.watch_pattern) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                eprintln!("Invalid watch pattern {}: {}", cli.watch_pattern, e);
+                std::process::exit(1);
+            }
+        };
+        let job_template = match cli.watch_job.as_deref().map(load_job_template).transpose() {
+            Ok(template) => template,
+            Err(e) => {
+                eprintln!("Could not load watch job: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        if let Err(e) = processor.watch_directory(WatchConfig {
+            directory,
+            pattern,
+            job_template,
+            catch_up: cli.watch_catch_up,
+        }) {
+            eprintln!("Could not start directory watcher: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    processor.start_scheduler();
+    processor.start_job_cleanup();
+    processor.start_job_webhooks();
+
     // Setup API routes
-    let health = warp::path("health")
+    let health = warp::path!("health")
         .and(warp::get())
         .and_then(health_handler);
 
-    let submit_job = warp::path("jobs")
+    let liveness = warp::path!("health" / "live")
+        .and(warp::get())
+        .and_then(liveness_handler);
+
+    // `/readyz` predates `/health/ready` and answers the same
+    let readiness = warp::path!("health" / "ready")
+        .or(warp::path!("readyz"))
+        .unify()
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(readiness_handler);
+
+    let submit_job = warp::path!("jobs")
         .and(warp::post())
-        .and(warp::body::json())
-        .and(warp::any().map(move || processor.clone()))
+        .and(json_body())
+        .and(warp::query::<JobSubmitQuery>())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(trace::context())
+        .and(with_processor(processor.clone()))
         .and_then(submit_job_handler);
 
+    let graphql = warp::path!("graphql")
+        .and(async_graphql_warp::graphql(graphql::schema(processor.clone())))
+        .and_then(|(schema, request): (graphql::ApiSchema, async_graphql::Request)| async move {
+            Ok::<_, std::convert::Infallible>(async_graphql_warp::GraphQLResponse::from(schema.execute(request).await))
+        });
+
+    let submit_batch = warp::path!("jobs" / "batch")
+        .and(warp::post())
+        .and(json_body())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(trace::context())
+        .and(with_processor(processor.clone()))
+        .and_then(submit_batch_handler);
+
+    let estimate_job = warp::path!("jobs" / "estimate")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(estimate_job_handler);
+
+    let export_jobs = warp::path!("jobs" / "archive")
+        .and(warp::get())
+        .and(warp::query::<JobArchiveQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(export_jobs_handler);
+
+    let import_jobs = warp::path!("jobs" / "import")
+        .and(warp::post())
+        .and(warp::body::bytes())
+        .and(with_processor(processor.clone()))
+        .and_then(import_jobs_handler);
+
     let get_job = warp::path!("jobs" / String)
         .and(warp::get())
-        .and(warp::any().map(move || processor.clone()))
+        .and(warp::query::<JobStatusQuery>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_processor(processor.clone()))
         .and_then(get_job_handler);
 
-    let list_jobs = warp::path("jobs")
+    let job_results = warp::path!("jobs" / String / "results")
+        .and(warp::get())
+        .and(warp::query::<JobResultsQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(job_results_handler);
+
+    let job_logs = warp::path!("jobs" / String / "logs")
+        .and(warp::get())
+        .and(warp::query::<JobLogQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(job_logs_handler);
+
+    let job_events = warp::path!("jobs" / String / "events")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(job_events_handler);
+
+    let job_dag = warp::path!("jobs" / String / "dag")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(job_dag_handler);
+
+    let rerun_job = warp::path!("jobs" / String / "rerun")
+        .and(warp::post())
+        .and(warp::body::bytes())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(trace::context())
+        .and(with_processor(processor.clone()))
+        .and_then(rerun_job_handler);
+
+    let cancel_job = warp::path!("jobs" / String / "cancel")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(cancel_job_handler);
+
+    let delete_job = warp::path!("jobs" / String)
+        .and(warp::delete())
+        .and(warp::query::<JobDeleteQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_job_handler);
+
+    let create_export = warp::path!("jobs" / String / "export")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(create_export_handler);
+
+    let download_export = warp::path!("exports" / String)
+        .and(warp::get())
+        .and(warp::header::optional::<String>("x-export-password"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_processor(processor.clone()))
+        .and_then(download_export_handler);
+
+    let list_jobs = warp::path!("jobs")
         .and(warp::get())
-        .and(warp::any().map(move || processor.clone()))
+        .and(warp::query::<JobTagQuery>())
+        .and(with_processor(processor.clone()))
         .and_then(list_jobs_handler);
 
+    let list_dead_letters = warp::path!("dead-letters")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_dead_letters_handler);
+
+    let get_dead_letter = warp::path!("dead-letters" / String)
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(get_dead_letter_handler);
+
+    let redrive_all = warp::path!("dead-letters" / "redrive")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(redrive_all_handler);
+
+    let redrive_dead_letter = warp::path!("dead-letters" / String / "redrive")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(redrive_dead_letter_handler);
+
+    let purge_jobs = warp::path!("jobs")
+        .and(warp::delete())
+        .and(warp::query::<JobPurgeQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(purge_jobs_handler);
+
+    let add_comment = warp::path!("jobs" / String / "comments")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(add_comment_handler);
+
+    let list_comments = warp::path!("jobs" / String / "comments")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_comments_handler);
+
+    let create_source = warp::path!("sources")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(create_source_handler);
+
+    let list_sources = warp::path!("sources")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_sources_handler);
+
+    let get_source = warp::path!("sources" / String)
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(get_source_handler);
+
+    let delete_source = warp::path!("sources" / String)
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_source_handler);
+
+    let source_records = warp::path!("sources" / String / "records")
+        .and(warp::get())
+        .and(warp::query::<SourceRecordsQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(source_records_handler);
+
+    let write_records = warp::path!("sources" / String / "records")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::stream())
+        .and(with_processor(processor.clone()))
+        .and_then(|source_id, content_type, body, processor| {
+            write_records_handler(source_id, content_type, Box::pin(body), processor)
+        });
+
+    let upload_source = warp::path!("sources" / String / "upload")
+        .and(warp::post())
+        .and(warp::multipart::form().max_length(MAX_UPLOAD_BYTES))
+        .and(with_processor(processor.clone()))
+        .and_then(upload_source_handler);
+
+    let patch_record = warp::path!("sources" / String / "records" / String)
+        .and(warp::patch())
+        .and(warp::query::<RecordKeyQuery>())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(patch_record_handler);
+
+    let delete_record = warp::path!("sources" / String / "records" / String)
+        .and(warp::delete())
+        .and(warp::query::<RecordKeyQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_record_handler);
+
+    let record_audit = warp::path!("sources" / String / "audit")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(record_audit_handler);
+
+    let get_source_locale = warp::path!("sources" / String / "locale")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(get_source_locale_handler);
+
+    let set_source_locale = warp::path!("sources" / String / "locale")
+        .and(warp::put())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(set_source_locale_handler);
+
+    let delete_source_locale = warp::path!("sources" / String / "locale")
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_source_locale_handler);
+
+    let list_source_versions = warp::path!("sources" / String / "versions")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_source_versions_handler);
+
+    let snapshot_source = warp::path!("sources" / String / "versions")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(snapshot_source_handler);
+
+    let delete_source_version = warp::path!("sources" / String / "versions" / u64)
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_source_version_handler);
+
+    let rollback_source = warp::path!("sources" / String / "versions" / u64 / "rollback")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(rollback_source_handler);
+
+    let diff_source_versions = warp::path!("sources" / String / "diff")
+        .and(warp::get())
+        .and(warp::query::<VersionDiffQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(diff_source_versions_handler);
+
+    let get_expected_schema = warp::path!("sources" / String / "schema")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(get_expected_schema_handler);
+
+    let set_expected_schema = warp::path!("sources" / String / "schema")
+        .and(warp::put())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(set_expected_schema_handler);
+
+    let delete_expected_schema = warp::path!("sources" / String / "schema")
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_expected_schema_handler);
+
+    let get_quarantine = warp::path!("sources" / String / "quarantine")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(get_quarantine_handler);
+
+    let clear_quarantine = warp::path!("sources" / String / "quarantine")
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(clear_quarantine_handler);
+
+    let list_indexes = warp::path!("sources" / String / "indexes")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_indexes_handler);
+
+    let create_index = warp::path!("sources" / String / "indexes")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(create_index_handler);
+
+    let drop_index = warp::path!("sources" / String / "indexes" / String)
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(drop_index_handler);
+
+    let run_query = warp::path!("query")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(run_query_handler);
+
+    let save_query = warp::path!("queries")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(save_query_handler);
+
+    let list_queries = warp::path!("queries")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_queries_handler);
+
+    let delete_query = warp::path!("queries" / String)
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_query_handler);
+
+    let query_results = warp::path!("queries" / String / "results")
+        .and(warp::get())
+        .and(warp::query::<ResultWindow>())
+        .and(with_processor(processor.clone()))
+        .and_then(query_results_handler);
+
+    let share_query = warp::path!("queries" / String / "share")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(share_query_handler);
+
+    let shared_results = warp::path!("shared" / String)
+        .and(warp::get())
+        .and(warp::query::<ResultWindow>())
+        .and(with_processor(processor.clone()))
+        .and_then(shared_results_handler);
+
+    let create_schedule = warp::path!("schedules")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(create_schedule_handler);
+
+    let list_schedules = warp::path!("schedules")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_schedules_handler);
+
+    let get_schedule = warp::path!("schedules" / String)
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(get_schedule_handler);
+
+    let enable_schedule = warp::path!("schedules" / String / "enable")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(enable_schedule_handler);
+
+    let disable_schedule = warp::path!("schedules" / String / "disable")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(disable_schedule_handler);
+
+    let upcoming_runs = warp::path!("schedules" / String / "upcoming")
+        .and(warp::get())
+        .and(warp::query::<UpcomingRunsQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(upcoming_runs_handler);
+
+    let compare_runs = warp::path!("pipelines" / String / "runs" / "compare")
+        .and(warp::get())
+        .and(warp::query::<RunCompareQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(compare_runs_handler);
+
+    let create_calendar = warp::path!("calendars")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(create_calendar_handler);
+
+    let list_calendars = warp::path!("calendars")
+        .and(warp::get())
+        .and(warp::query::<CalendarQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(list_calendars_handler);
+
+    let add_window = warp::path!("maintenance-windows")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(add_window_handler);
+
+    let list_windows = warp::path!("maintenance-windows")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_windows_handler);
+
+    let delete_window = warp::path!("maintenance-windows" / String)
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_window_handler);
+
+    let add_credential = warp::path!("credentials")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(add_credential_handler);
+
+    let list_credentials = warp::path!("credentials")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(list_credentials_handler);
+
+    let rotate_credential = warp::path!("credentials" / String / "rotate")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_processor(processor.clone()))
+        .and_then(rotate_credential_handler);
+
+    let delete_credential = warp::path!("credentials" / String)
+        .and(warp::delete())
+        .and(with_processor(processor.clone()))
+        .and_then(delete_credential_handler);
+
     let metrics = warp::path("metrics")
         .and(warp::get())
-        .and(warp::any().map(move || processor.clone()))
+        .and(warp::query::<JobTagQuery>())
+        .and(with_processor(processor.clone()))
         .and_then(metrics_handler);
 
+    let admin_status = warp::path!("admin" / "status")
+        .and(warp::get())
+        .and(with_processor(processor.clone()))
+        .and_then(admin_status_handler);
+
+    let pause_scheduler = warp::path!("admin" / "scheduler" / "pause")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(pause_scheduler_handler);
+
+    let resume_scheduler = warp::path!("admin" / "scheduler" / "resume")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(resume_scheduler_handler);
+
+    let drain = warp::path!("admin" / "drain")
+        .and(warp::post().or(warp::delete()).unify())
+        .and(warp::method())
+        .and(with_processor(processor.clone()))
+        .and_then(drain_handler);
+
+    let reload_sources = ReloadSources { quotas: cli.quotas.clone(), authenticator: authenticator.clone() };
+    let reload_config = warp::path!("admin" / "reload")
+        .and(warp::post())
+        .and(warp::any().map(move || reload_sources.clone()))
+        .and(with_processor(processor.clone()))
+        .and_then(reload_config_handler);
+
+    let refresh_metrics = warp::path!("admin" / "metrics" / "refresh")
+        .and(warp::post())
+        .and(with_processor(processor.clone()))
+        .and_then(refresh_metrics_handler);
+
+    let openapi_document = warp::path!("openapi.json")
+        .and(warp::get())
+        .and_then(openapi_handler);
+
+    let docs = warp::path!("docs")
+        .and(warp::get())
+        .and_then(docs_handler);
+
+    let dashboard_ws = warp::path("ws")
+        .and(warp::ws())
+        .and(warp::query::<WsQuery>())
+        .and(with_processor(processor.clone()))
+        .and_then(ws_handler);
+
+    let limit = warp::path::full()
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(warp::addr::remote())
+        .and(warp::any().map(move || rate_limiter.clone()))
+        .and(with_processor(processor.clone()))
+        .and_then(limit_request)
+        .untuple_one();
+
+    let access = warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::raw().map(Some).or(warp::any().map(|| None)).unify())
+        .and(warp::any().map(move || authenticator.clone()))
+        .and_then(authorize_request)
+        .untuple_one();
+
     let routes = health
+        .or(liveness)
+        .or(readiness)
         .or(submit_job)
+        .or(submit_batch)
+        .or(graphql)
+        .or(estimate_job)
+        .or(export_jobs)
+        .or(import_jobs)
         .or(get_job)
+        .or(job_results)
+        .or(job_logs)
+        .or(job_events)
+        .or(job_dag)
+        .or(cancel_job)
+        .or(delete_job)
+        .or(rerun_job)
+        .or(create_export)
+        .or(download_export)
+        .or(add_comment)
+        .or(list_comments)
         .or(list_jobs)
+        .or(purge_jobs)
+        .or(list_dead_letters)
+        .or(get_dead_letter)
+        .or(redrive_all)
+        .or(redrive_dead_letter)
+        // Boxed part way so the combined filter's future does not outgrow a worker's stack
+        .boxed()
+        .or(create_source)
+        .or(list_sources)
+        .or(get_source)
+        .or(delete_source)
+        .or(source_records)
+        .or(write_records)
+        .or(upload_source)
+        .or(patch_record)
+        .or(delete_record)
+        .or(record_audit)
+        .or(get_source_locale)
+        .or(set_source_locale)
+        .or(delete_source_locale)
+        .or(list_source_versions)
+        .or(snapshot_source)
+        .or(delete_source_version)
+        .or(rollback_source)
+        .or(diff_source_versions)
+        .or(get_expected_schema)
+        .or(set_expected_schema)
+        .or(delete_expected_schema)
+        .or(get_quarantine)
+        .or(clear_quarantine)
+        .or(list_indexes)
+        .or(create_index)
+        .or(drop_index)
+        .or(run_query)
+        .or(save_query)
+        .or(list_queries)
+        .or(delete_query)
+        .or(query_results)
+        .or(share_query)
+        .or(shared_results)
+        .or(create_schedule)
+        .or(list_schedules)
+        .or(get_schedule)
+        .or(enable_schedule)
+        .or(disable_schedule)
+        .or(upcoming_runs)
+        .or(compare_runs)
+        .or(create_calendar)
+        .or(list_calendars)
+        .or(add_window)
+        .or(list_windows)
+        .or(delete_window)
+        .or(add_credential)
+        .or(list_credentials)
+        .or(rotate_credential)
+        .or(delete_credential)
         .or(metrics)
-        .with(warp::cors().allow_any_origin().allow_any_method().allow_any_header());
+        .or(admin_status)
+        .or(pause_scheduler)
+        .or(resume_scheduler)
+        .or(drain)
+        .or(reload_config)
+        .or(refresh_metrics)
+        .or(dashboard_ws)
+        .or(openapi_document)
+        .or(docs)
+        .boxed();
+    // Every version serves the same routes; `versioning::negotiate` has checked the prefix
+    let versioned = warp::path("api")
+        .and(warp::path::param::<String>())
+        .map(|_version: String| ())
+        .untuple_one()
+        .and(routes.clone());
+    let api = warp::path::full()
+        .and(warp::header::optional::<String>("accept-version"))
+        .and_then(versioning::negotiate)
+        .and(warp::path::full())
+        .and(versioned.or(routes).unify())
+        .map(|version, path: warp::path::FullPath, reply| {
+            versioning::decorate(version, versioning::unversioned(path.as_str()), reply)
+        });
+    let routes = trace::context()
+        .and(limit.and(access).and(api).recover(error::handle_rejection))
+        .map(|trace: trace::TraceContext, reply| trace.decorate(reply))
+        .with(
+            warp::cors()
+                .allow_any_origin()
+                .allow_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"])
+                .allow_headers(vec![
+                    "content-type", "authorization", "x-export-password", "x-api-key", "accept-version", "x-request-id", "traceparent",
+                ])
+                .expose_headers(vec!["api-version", "deprecation", "link", "x-request-id", "traceparent"]),
+        );
 
-    println!("Rust Data Processor starting on http://localhost:8000");
-    
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 8000))
-        .await;
+    let address = std::net::SocketAddr::from(([0, 0, 0, 0], cli.port));
+    if let (Some(cert), Some(key)) = (&cli.tls_cert, &cli.tls_key) {
+        println!("Rust Data Processor starting on https://localhost:{}", cli.port);
+        let server = warp::serve(routes).tls().cert_path(cert).key_path(key);
+        let server = match (&cli.tls_client_ca, cli.tls_client_auth) {
+            (Some(ca), tls::ClientAuth::Required) => server.client_auth_required_path(ca),
+            (Some(ca), tls::ClientAuth::Optional) => server.client_auth_optional_path(ca),
+            (None, _) => server,
+        };
+        server.run(address).await;
+    } else if let Some(cache_dir) = cli.acme_cache.clone().filter(|_| !cli.acme_domains.is_empty()) {
+        let listener = match tokio::net::TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Could not listen on {}: {}", address, e);
+                std::process::exit(1);
+            }
+        };
+        println!("Rust Data Processor starting on https://{}:{}", cli.acme_domains[0], cli.port);
+        let settings = tls::AcmeSettings {
+            domains: cli.acme_domains.clone(),
+            contacts: cli.acme_contacts.clone(),
+            cache_dir,
+            staging: cli.acme_staging,
+        };
+        warp::serve(routes).run_incoming(tls::acme_incoming(listener, settings)).await;
+    } else {
+        println!("Rust Data Processor starting on http://localhost:{}", cli.port);
+        warp::serve(routes).run(address).await;
+    }
 }
@@ -0,0 +1,1046 @@
+//! The OpenAPI 3 description of the HTTP API, served at `/openapi.json` and browsable at
+//! `/docs`. Routes are listed by hand in `ROUTES`, so a route added to `main` goes here too;
+//! path parameters are read from the `{name}` segments of each path, and the role each route
+//! needs from `auth::required_role`.
+
+use serde_json::{json, Map, Value};
+
+use crate::auth;
+
+/// Where a parameter other than a path segment is given.
+enum In {
+    Query,
+    Header,
+}
+
+struct Param {
+    name: &'static str,
+    location: In,
+    kind: &'static str,
+    description: &'static str,
+}
+
+const fn query(name: &'static str, kind: &'static str, description: &'static str) -> Param {
+    Param { name, location: In::Query, kind, description }
+}
+
+const fn header(name: &'static str, description: &'static str) -> Param {
+    Param { name, location: In::Header, kind: "string", description }
+}
+
+enum Body {
+    None,
+    /// A JSON body matching the named schema
+    Json(&'static str),
+    /// Raw bytes of one of these media types
+    Raw(&'static [&'static str]),
+}
+
+enum Response {
+    /// JSON matching the named schema
+    Json(&'static str),
+    /// A list of the named schema
+    List(&'static str),
+    /// Something other than JSON, such as a file or a stream
+    Raw(&'static str, &'static str),
+    /// A switch to another protocol
+    Upgrade(&'static str),
+}
+
+struct Route {
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    params: &'static [Param],
+    body: Body,
+    /// Status of a successful call and what it returns
+    success: (u16, Response),
+}
+
+const API_KEY: Param = header("x-api-key", "Identifies the tenant whose quota the job counts against");
+const LIMIT: Param = query("limit", "integer", "Most records to return");
+const OFFSET: Param = query("offset", "integer", "Records to skip");
+const ALL: Param = query("all", "boolean", "Return the complete result set, refused when it exceeds the server's cap");
+const TAG_FILTER: Param = query("tag", "string", "Tag filter such as `team=billing,env=prod`; a bare name matches any value");
+
+const ACCESS_TOKEN: Param = query("access_token", "string", "Bearer token, for clients that cannot send an authorization header");
+
+const ROUTES: &[Route] = &[
+    Route {
+        method: "get", path: "/health", tag: "system", summary: "Liveness check",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/health/live", tag: "system", summary: "Liveness probe: the process is serving requests",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/health/ready", tag: "system",
+        summary: "Readiness probe: the work directory, credential store, job queue and background tasks can take work; 503 otherwise",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/readyz", tag: "system", summary: "Readiness check; the same as `/health/ready`",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/metrics", tag: "system", summary: "System metrics, with job counts when filtered or grouped by tag",
+        params: &[TAG_FILTER, query("group_by", "string", "Tag whose values the job counts are grouped by")],
+        body: Body::None, success: (200, Response::Json("SystemMetrics")),
+    },
+    Route {
+        method: "get", path: "/ws", tag: "system",
+        summary: "WebSocket pushing job status changes and metrics snapshots for the subscribed topics",
+        params: &[query("topics", "string", "Comma separated topics to start with: `jobs`, `job:<id>` or `metrics`"), ACCESS_TOKEN],
+        body: Body::None, success: (101, Response::Upgrade("Switches to the WebSocket protocol")),
+    },
+    Route {
+        method: "post", path: "/jobs", tag: "jobs", summary: "Submit a job, or check and preview it with `dry_run`",
+        params: &[
+            query("dry_run", "boolean", "Check the job and preview its output instead of submitting it"),
+            query("sample", "integer", "Input records a dry run processes"),
+            API_KEY,
+        ],
+        body: Body::Json("ProcessingJob"), success: (201, Response::Json("JobSubmitted")),
+    },
+    Route {
+        method: "get", path: "/jobs", tag: "jobs", summary: "List jobs, optionally grouped by a tag",
+        params: &[TAG_FILTER, query("group_by", "string", "Tag whose values the jobs are grouped by")],
+        body: Body::None, success: (200, Response::List("ProcessingJob")),
+    },
+    Route {
+        method: "delete", path: "/jobs", tag: "jobs", summary: "Delete finished jobs",
+        params: &[
+            query("status", "string", "Completed, Failed, Cancelled or TimedOut; any finished status when absent"),
+            query("before", "string", "Only jobs that finished before this time"),
+        ],
+        body: Body::None, success: (200, Response::Json("Deleted")),
+    },
+    Route {
+        method: "post", path: "/jobs/batch", tag: "jobs", summary: "Submit many jobs, all or nothing or each on its own",
+        params: &[API_KEY],
+        body: Body::Json("JobBatch"), success: (201, Response::Json("JobBatchResult")),
+    },
+    Route {
+        method: "post", path: "/jobs/estimate", tag: "jobs", summary: "Estimate a job's memory, disk and run time",
+        params: &[], body: Body::Json("ProcessingJob"), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/jobs/archive", tag: "jobs", summary: "Download finished jobs and their results as a gzipped archive",
+        params: &[query("ids", "string", "Comma separated job ids; every finished job when absent"), TAG_FILTER],
+        body: Body::None, success: (200, Response::Raw("application/gzip", "Gzipped JSON job archive")),
+    },
+    Route {
+        method: "post", path: "/jobs/import", tag: "jobs", summary: "Import a job archive from another instance",
+        params: &[], body: Body::Raw(&["application/gzip", "application/json"]), success: (200, Response::Json("JobImport")),
+    },
+    Route {
+        method: "get", path: "/jobs/{id}", tag: "jobs", summary: "Get a job, optionally waiting for it to change",
+        params: &[
+            query("wait", "string", "Long-poll for up to this long, e.g. `30s`"),
+            header("if-none-match", "ETag of the caller's copy; the wait only happens while it is current"),
+        ],
+        body: Body::None, success: (200, Response::Json("ProcessingJob")),
+    },
+    Route {
+        method: "delete", path: "/jobs/{id}", tag: "jobs", summary: "Delete a job",
+        params: &[
+            query("force", "boolean", "Cancel the job first if it has not finished"),
+            query("artifacts", "boolean", "Also delete the job's result exports"),
+        ],
+        body: Body::None, success: (200, Response::Json("Deleted")),
+    },
+    Route {
+        method: "get", path: "/jobs/{id}/results", tag: "jobs", summary: "Page through a completed job's records",
+        params: &[
+            LIMIT, OFFSET, ALL,
+            query("format", "string", "Response format of the records"),
+        ],
+        body: Body::None, success: (200, Response::Json("RecordPage")),
+    },
+    Route {
+        method: "get", path: "/jobs/{id}/logs", tag: "jobs", summary: "A job's recent log lines",
+        params: &[
+            query("level", "string", "Lowest level returned: info, warn or error"),
+            query("since", "string", "Only lines written after this time"),
+            query("tail", "integer", "Only the last this many lines"),
+        ],
+        body: Body::None, success: (200, Response::Json("JobLogs")),
+    },
+    Route {
+        method: "get", path: "/jobs/{id}/events", tag: "jobs",
+        summary: "Server-sent `status`, `progress`, `warning` and `error` events until the job finishes",
+        params: &[ACCESS_TOKEN], body: Body::None, success: (200, Response::Raw("text/event-stream", "Event stream")),
+    },
+    Route {
+        method: "get", path: "/jobs/{id}/dag", tag: "jobs", summary: "The dependency graph around a job",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/jobs/{id}/rerun", tag: "jobs", summary: "Submit a copy of a job, with optional overrides",
+        params: &[API_KEY], body: Body::Json("JobRerunOverrides"), success: (201, Response::Json("JobSubmitted")),
+    },
+    Route {
+        method: "post", path: "/jobs/{id}/cancel", tag: "jobs", summary: "Cancel a waiting, pending or running job",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "post", path: "/jobs/{id}/export", tag: "jobs", summary: "Write a completed job's results to a downloadable file",
+        params: &[], body: Body::Json("ExportRequest"), success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/jobs/{id}/comments", tag: "jobs", summary: "List a job's comments",
+        params: &[], body: Body::None, success: (200, Response::List("JobComment")),
+    },
+    Route {
+        method: "post", path: "/jobs/{id}/comments", tag: "jobs", summary: "Comment on a job",
+        params: &[], body: Body::Json("JobComment"), success: (201, Response::Json("JobComment")),
+    },
+    Route {
+        method: "get", path: "/exports/{token}", tag: "jobs", summary: "Download a result export",
+        params: &[
+            header("x-export-password", "Password of a protected export; Basic auth works too"),
+            header("authorization", "Basic credentials whose password opens a protected export"),
+        ],
+        body: Body::None, success: (200, Response::Raw("application/octet-stream", "The export file")),
+    },
+    Route {
+        method: "get", path: "/dead-letters", tag: "dead letters", summary: "List dead-lettered jobs, oldest first",
+        params: &[], body: Body::None, success: (200, Response::List("Object")),
+    },
+    Route {
+        method: "get", path: "/dead-letters/{id}", tag: "dead letters", summary: "Get a dead-lettered job",
+        params: &[], body: Body::None, success: (200, Response::Json("ProcessingJob")),
+    },
+    Route {
+        method: "post", path: "/dead-letters/redrive", tag: "dead letters", summary: "Re-drive every dead-lettered job",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/dead-letters/{id}/redrive", tag: "dead letters", summary: "Re-drive one dead-lettered job",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/sources", tag: "sources", summary: "Create or reload a source from a file, API, S3 object or database query",
+        params: &[], body: Body::Json("SourceRequest"), success: (201, Response::Json("Source")),
+    },
+    Route {
+        method: "get", path: "/sources", tag: "sources", summary: "List sources",
+        params: &[], body: Body::None, success: (200, Response::List("Source")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}", tag: "sources", summary: "A source with its inferred schema",
+        params: &[], body: Body::None, success: (200, Response::Json("Source")),
+    },
+    Route {
+        method: "delete", path: "/sources/{id}", tag: "sources", summary: "Delete a source no unfinished job reads",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/records", tag: "sources", summary: "A page of a source's records, to check field names and values",
+        params: &[LIMIT, OFFSET, query("fields", "string", "Comma separated data fields to return")],
+        body: Body::None, success: (200, Response::Json("SourcePage")),
+    },
+    Route {
+        method: "post", path: "/sources/{id}/records", tag: "sources", summary: "Append records to a source",
+        params: &[],
+        body: Body::Raw(&["application/json", "application/x-ndjson"]), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/sources/{id}/upload", tag: "sources", summary: "Replace a source with an uploaded file",
+        params: &[], body: Body::Raw(&["multipart/form-data"]), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "patch", path: "/sources/{id}/records/{record_id}", tag: "sources", summary: "Patch one record",
+        params: &[query("key_field", "string", "Data field identifying the record instead of its id")],
+        body: Body::Json("Object"), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "delete", path: "/sources/{id}/records/{record_id}", tag: "sources", summary: "Delete one record",
+        params: &[query("key_field", "string", "Data field identifying the record instead of its id")],
+        body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/audit", tag: "sources", summary: "The record changes made to a source",
+        params: &[], body: Body::None, success: (200, Response::List("Object")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/locale", tag: "sources", summary: "The locale a source's records are normalized with",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "put", path: "/sources/{id}/locale", tag: "sources", summary: "Set a source's locale",
+        params: &[], body: Body::Json("Object"), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "delete", path: "/sources/{id}/locale", tag: "sources", summary: "Stop normalizing a source",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/versions", tag: "sources", summary: "A source's retained versions, oldest first, then its current one",
+        params: &[], body: Body::None, success: (200, Response::List("SourceVersion")),
+    },
+    Route {
+        method: "post", path: "/sources/{id}/versions", tag: "sources", summary: "Retain a copy of a source's current version",
+        params: &[], body: Body::None, success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "delete", path: "/sources/{id}/versions/{version}", tag: "sources", summary: "Delete a retained version",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "post", path: "/sources/{id}/versions/{version}/rollback", tag: "sources", summary: "Restore a retained version as the source's next version",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/diff", tag: "sources", summary: "How a source's records changed between two versions",
+        params: &[
+            query("from", "integer", "Version to compare from"),
+            query("to", "integer", "Version to compare to; defaults to the current one"),
+            query("key_field", "string", "Data field identifying records across versions; without it records are matched by their whole data"),
+        ],
+        body: Body::None, success: (200, Response::Json("VersionDiff")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/schema", tag: "sources", summary: "The schema a source's records must conform to",
+        params: &[], body: Body::None, success: (200, Response::Json("ExpectedSchema")),
+    },
+    Route {
+        method: "put", path: "/sources/{id}/schema", tag: "sources", summary: "Register a source's expected schema",
+        params: &[], body: Body::Json("ExpectedSchema"), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "delete", path: "/sources/{id}/schema", tag: "sources", summary: "Stop enforcing a schema on a source",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/quarantine", tag: "sources", summary: "Records quarantined for not conforming to the source's schema",
+        params: &[], body: Body::None, success: (200, Response::List("QuarantinedRecord")),
+    },
+    Route {
+        method: "delete", path: "/sources/{id}/quarantine", tag: "sources", summary: "Discard a source's quarantined records",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/sources/{id}/indexes", tag: "sources", summary: "List a source's indexes",
+        params: &[], body: Body::None, success: (200, Response::List("Index")),
+    },
+    Route {
+        method: "post", path: "/sources/{id}/indexes", tag: "sources", summary: "Build an index on a source field, used by Filter, Join and keyed record edits",
+        params: &[], body: Body::Json("IndexSpec"), success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "delete", path: "/sources/{id}/indexes/{field}", tag: "sources", summary: "Drop a source's index on a field",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "post", path: "/query", tag: "queries", summary: "Run operations against a source and return the results inline",
+        params: &[], body: Body::Json("AdHocQuery"), success: (200, Response::Json("AdHocQueryResults")),
+    },
+    Route {
+        method: "post", path: "/queries", tag: "queries", summary: "Save a query",
+        params: &[], body: Body::Json("SavedQuery"), success: (201, Response::Json("SavedQuery")),
+    },
+    Route {
+        method: "get", path: "/queries", tag: "queries", summary: "List saved queries",
+        params: &[], body: Body::None, success: (200, Response::List("SavedQuery")),
+    },
+    Route {
+        method: "delete", path: "/queries/{id}", tag: "queries", summary: "Delete a saved query",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/queries/{id}/results", tag: "queries", summary: "Run a saved query",
+        params: &[LIMIT, OFFSET, ALL], body: Body::None, success: (200, Response::Json("RecordPage")),
+    },
+    Route {
+        method: "post", path: "/queries/{id}/share", tag: "queries", summary: "Create a signed, expiring link to a query's results",
+        params: &[], body: Body::Json("Object"), success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/shared/{token}", tag: "queries", summary: "Results behind a share link",
+        params: &[LIMIT, OFFSET, ALL], body: Body::None, success: (200, Response::Json("RecordPage")),
+    },
+    Route {
+        method: "post", path: "/schedules", tag: "schedules", summary: "Create a schedule",
+        params: &[], body: Body::Json("Object"), success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/schedules", tag: "schedules", summary: "List schedules",
+        params: &[], body: Body::None, success: (200, Response::List("Object")),
+    },
+    Route {
+        method: "get", path: "/schedules/{id}", tag: "schedules", summary: "Get a schedule",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/schedules/{id}/enable", tag: "schedules", summary: "Enable a schedule",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/schedules/{id}/disable", tag: "schedules", summary: "Disable a schedule",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/schedules/{id}/upcoming", tag: "schedules", summary: "A schedule's next run times",
+        params: &[query("count", "integer", "Runs to list")], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/pipelines/{id}/runs/compare", tag: "schedules", summary: "Compare two runs of a schedule",
+        params: &[
+            query("a", "string", "Job id of the first run; the second latest when absent"),
+            query("b", "string", "Job id of the second run; the latest when absent"),
+        ],
+        body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/calendars", tag: "schedules", summary: "Create a business calendar",
+        params: &[], body: Body::Json("Object"), success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/calendars", tag: "schedules", summary: "List business calendars",
+        params: &[query("tenant", "string", "Only this tenant's calendars")], body: Body::None, success: (200, Response::List("Object")),
+    },
+    Route {
+        method: "post", path: "/maintenance-windows", tag: "schedules", summary: "Add a maintenance window",
+        params: &[], body: Body::Json("Object"), success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/maintenance-windows", tag: "schedules", summary: "List maintenance windows",
+        params: &[], body: Body::None, success: (200, Response::List("Object")),
+    },
+    Route {
+        method: "delete", path: "/maintenance-windows/{id}", tag: "schedules", summary: "Delete a maintenance window",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "post", path: "/credentials", tag: "credentials", summary: "Store a credential",
+        params: &[], body: Body::Json("Object"), success: (201, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/credentials", tag: "credentials", summary: "List credentials without their secrets",
+        params: &[], body: Body::None, success: (200, Response::List("Object")),
+    },
+    Route {
+        method: "post", path: "/credentials/{name}/rotate", tag: "credentials", summary: "Replace a credential's secret",
+        params: &[], body: Body::Json("Object"), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "delete", path: "/credentials/{name}", tag: "credentials", summary: "Delete a credential",
+        params: &[], body: Body::None, success: (200, Response::Json("Status")),
+    },
+    Route {
+        method: "get", path: "/admin/status", tag: "admin", summary: "Whether the scheduler is paused and the queue draining, and the queue's jobs",
+        params: &[], body: Body::None, success: (200, Response::Json("AdminStatus")),
+    },
+    Route {
+        method: "post", path: "/admin/scheduler/pause", tag: "admin",
+        summary: "Stop schedules firing; runs that fall due meanwhile follow each schedule's misfire policy on resuming",
+        params: &[], body: Body::None, success: (200, Response::Json("AdminChange")),
+    },
+    Route {
+        method: "post", path: "/admin/scheduler/resume", tag: "admin", summary: "Let schedules fire again",
+        params: &[], body: Body::None, success: (200, Response::Json("AdminChange")),
+    },
+    Route {
+        method: "post", path: "/admin/drain", tag: "admin",
+        summary: "Refuse new jobs and report not ready while queued and running jobs finish",
+        params: &[], body: Body::None, success: (200, Response::Json("AdminChange")),
+    },
+    Route {
+        method: "delete", path: "/admin/drain", tag: "admin", summary: "Stop draining and accept jobs again",
+        params: &[], body: Body::None, success: (200, Response::Json("AdminChange")),
+    },
+    Route {
+        method: "post", path: "/admin/reload", tag: "admin",
+        summary: "Read the quotas file again and re-fetch the auth provider's signing keys",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "post", path: "/admin/metrics/refresh", tag: "admin", summary: "Sample system metrics now",
+        params: &[], body: Body::None, success: (200, Response::Json("SystemMetrics")),
+    },
+    Route {
+        method: "post", path: "/graphql", tag: "system",
+        summary: "Read jobs with their results and records, sources and metrics through GraphQL, selecting only the fields needed",
+        params: &[], body: Body::Json("Object"), success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/graphql", tag: "system", summary: "A GraphQL query passed as `query`, `variables` and `operationName` parameters",
+        params: &[query("query", "string", "The GraphQL document")],
+        body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/openapi.json", tag: "system", summary: "This document",
+        params: &[], body: Body::None, success: (200, Response::Json("Object")),
+    },
+    Route {
+        method: "get", path: "/docs", tag: "system", summary: "Swagger UI for this document",
+        params: &[], body: Body::None, success: (200, Response::Raw("text/html", "Swagger UI page")),
+    },
+];
+
+fn schema_ref(name: &str) -> Value {
+    json!({"$ref": format!("#/components/schemas/{}", name)})
+}
+
+fn response(response: &Response) -> Value {
+    match response {
+        Response::Json(name) => json!({"description": "Success", "content": {"application/json": {"schema": schema_ref(name)}}}),
+        Response::List(name) => json!({
+            "description": "Success",
+            "content": {"application/json": {"schema": {"type": "array", "items": schema_ref(name)}}}
+        }),
+        Response::Raw(media_type, description) => json!({"description": description, "content": {*media_type: {}}}),
+        Response::Upgrade(description) => json!({"description": description}),
+    }
+}
+
+fn operation(route: &Route) -> Value {
+    let mut parameters: Vec<Value> = route.path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "string"}}))
+        .collect();
+    parameters.extend(route.params.iter().map(|param| json!({
+        "name": param.name,
+        "in": match param.location { In::Query => "query", In::Header => "header" },
+        "description": param.description,
+        "schema": {"type": param.kind},
+    })));
+
+    let (status, success) = &route.success;
+    let mut operation = json!({
+        "tags": [route.tag],
+        "summary": route.summary,
+        "parameters": parameters,
+        "responses": {
+            status.to_string(): response(success),
+            "default": {"description": "Failure", "content": {"application/json": {"schema": schema_ref("Error")}}},
+        },
+    });
+    match route.body {
+        Body::None => {},
+        Body::Json(name) => {
+            operation["requestBody"] = json!({"required": true, "content": {"application/json": {"schema": schema_ref(name)}}});
+        },
+        Body::Raw(media_types) => {
+            let content: Map<String, Value> = media_types.iter().map(|media_type| (media_type.to_string(), json!({}))).collect();
+            operation["requestBody"] = json!({"required": true, "content": content});
+        },
+    }
+    match auth::required_role(route.method, route.path) {
+        Some(role) => {
+            operation["security"] = json!([{"bearer": []}]);
+            operation["x-required-role"] = json!(role.name());
+        },
+        None => operation["security"] = json!([]),
+    }
+    operation
+}
+
+fn schemas() -> Value {
+    let string = json!({"type": "string"});
+    let time = json!({"type": "string", "format": "date-time"});
+    let integer = json!({"type": "integer", "minimum": 0});
+    let tags = json!({"type": "object", "additionalProperties": {"type": "string"}});
+    let operations: Map<String, Value> = [
+        "Transform", "Filter", "Aggregate", "Join", "Sort", "Deduplicate", "Validate", "Sum", "Average", "Min", "Max", "Custom",
+    ].into_iter().map(|kind| (kind.to_string(), json!({"type": "object"}))).collect();
+    json!({
+        "Object": {"type": "object", "additionalProperties": true},
+        "Status": {
+            "type": "object",
+            "properties": {"success": {"type": "boolean"}, "message": string, "status": string},
+        },
+        "Error": {
+            "type": "object",
+            "required": ["code", "message", "error", "request_id"],
+            "properties": {
+                "success": {"type": "boolean", "enum": [false]},
+                "code": {
+                    "type": "string",
+                    "enum": [
+                        "invalid_request", "validation_failed", "unauthenticated", "forbidden", "not_found",
+                        "method_not_allowed", "unsupported_version", "conflict", "gone", "payload_too_large", "unsupported_media_type",
+                        "rate_limited", "queue_full", "quota_exceeded", "unavailable", "upstream", "internal",
+                    ],
+                },
+                "message": string,
+                "error": {"type": "string", "description": "Same as `message`"},
+                "details": {"type": "object", "additionalProperties": true},
+                "request_id": string,
+            },
+        },
+        "Deleted": {
+            "type": "object",
+            "properties": {"success": {"type": "boolean"}, "deleted": integer, "job_ids": {"type": "array", "items": string}},
+        },
+        "JobSubmitted": {
+            "type": "object",
+            "properties": {"success": {"type": "boolean"}, "job_id": string, "warnings": {"type": "array", "items": schema_ref("Object")}},
+        },
+        "JobStatus": {
+            "type": "string",
+            "enum": ["Waiting", "Pending", "Running", "Completed", "Failed", "Cancelled", "TimedOut"],
+        },
+        "Operation": {
+            "description": "One step of a pipeline, written `{\"<Kind>\": {...}}` or just `\"<Kind>\"` for kinds without fields",
+            "oneOf": [
+                {"type": "string", "enum": ["First", "Last", "Equal", "Distinct", "Skip", "Zero", "Count"]},
+                {
+                    "type": "object",
+                    "minProperties": 1,
+                    "maxProperties": 1,
+                    "properties": operations,
+                    "additionalProperties": false,
+                },
+            ],
+        },
+        "ProcessingConfig": {
+            "type": "object",
+            "required": ["operations"],
+            "properties": {
+                "input_sources": {"type": "array", "items": string},
+                "operations": {"type": "array", "items": schema_ref("Operation")},
+                "batch_size": integer,
+                "parallel_workers": integer,
+                "timeout_seconds": integer,
+                "retry_attempts": integer,
+                "output_format": string,
+                "output_path": string,
+                "output_compression": string,
+                "outputs": {"type": "array", "items": schema_ref("Object")},
+                "dead_letter": schema_ref("Object"),
+                "engine_version": integer,
+                "locale": schema_ref("Object"),
+                "decimal_fields": schema_ref("Object"),
+                "invariants": {"type": "array", "items": schema_ref("Object")},
+                "checkpoint_interval_secs": integer,
+                "pinned_versions": {"type": "object", "additionalProperties": integer},
+            },
+            "additionalProperties": true,
+        },
+        "JobCallback": {
+            "type": "object",
+            "required": ["url"],
+            "properties": {
+                "url": string,
+                "events": {"type": "array", "items": {"type": "string", "enum": ["started", "completed", "failed", "cancelled"]}},
+                "secret": string,
+            },
+        },
+        "ProcessingJob": {
+            "type": "object",
+            "required": ["name", "configuration"],
+            "properties": {
+                "id": string,
+                "name": string,
+                "status": schema_ref("JobStatus"),
+                "created_at": time,
+                "started_at": time,
+                "completed_at": time,
+                "input_count": integer,
+                "processed_count": integer,
+                "error_count": integer,
+                "configuration": schema_ref("ProcessingConfig"),
+                "results": {"type": "array", "items": schema_ref("Object")},
+                "comments": {"type": "array", "items": schema_ref("JobComment")},
+                "error": string,
+                "priority": {"type": "integer"},
+                "tags": tags,
+                "depends_on": {"type": "array", "items": string},
+                "callbacks": {"type": "array", "items": schema_ref("JobCallback")},
+                "progress": schema_ref("JobProgress"),
+                "tenant": string,
+                "rerun_of": string,
+                "imported_at": time,
+                "trace": {
+                    "type": "object",
+                    "description": "The submitting request's id and W3C trace",
+                    "properties": {"request_id": string, "trace_id": string, "span_id": string, "parent_id": string, "sampled": {"type": "boolean"}},
+                },
+            },
+            "additionalProperties": true,
+        },
+        "JobBatch": {
+            "type": "object",
+            "required": ["jobs"],
+            "properties": {
+                "jobs": {"type": "array", "items": schema_ref("ProcessingJob")},
+                "mode": {"type": "string", "enum": ["all_or_nothing", "best_effort"], "default": "all_or_nothing"},
+            },
+        },
+        "JobBatchResult": {
+            "type": "object",
+            "description": "Also the reply, with status 207, of a best-effort batch that was partly refused",
+            "properties": {
+                "success": {"type": "boolean"},
+                "submitted": integer,
+                "refused": integer,
+                "engine_version": integer,
+                "jobs": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {"index": integer, "job_id": string, "error": schema_ref("Error")},
+                    },
+                },
+            },
+        },
+        "JobRerunOverrides": {
+            "type": "object",
+            "properties": {
+                "name": string,
+                "configuration": {"type": "object", "description": "JSON merge patch applied to the original configuration"},
+                "priority": {"type": "integer"},
+                "tags": tags,
+                "depends_on": {"type": "array", "items": string},
+            },
+            "additionalProperties": false,
+        },
+        "JobProgress": {
+            "type": "object",
+            "properties": {
+                "stage": string,
+                "completed": integer,
+                "total": integer,
+                "fraction": {"type": "number"},
+                "eta_seconds": integer,
+            },
+        },
+        "JobComment": {
+            "type": "object",
+            "required": ["author", "text"],
+            "properties": {"id": string, "author": string, "text": string, "created_at": time},
+        },
+        "JobLogs": {
+            "type": "object",
+            "properties": {
+                "job_id": string,
+                "lines": {"type": "array", "items": {
+                    "type": "object",
+                    "properties": {
+                        "timestamp": time,
+                        "level": {"type": "string", "enum": ["info", "warn", "error"]},
+                        "message": string,
+                    },
+                }},
+            },
+        },
+        "JobImport": {
+            "type": "object",
+            "properties": {
+                "success": {"type": "boolean"},
+                "imported": {"type": "array", "items": string},
+                "skipped": {"type": "array", "items": string},
+            },
+        },
+        "ExportRequest": {
+            "type": "object",
+            "required": ["format"],
+            "properties": {
+                "format": string,
+                "output_compression": string,
+                "output_columns": {"type": "array", "items": schema_ref("Object")},
+                "expires_in": string,
+                "password": string,
+            },
+        },
+        "DataRecord": {
+            "type": "object",
+            "properties": {
+                "id": string,
+                "timestamp": time,
+                "data": {},
+                "source": string,
+                "processed": {"type": "boolean"},
+                "metadata": schema_ref("Object"),
+            },
+        },
+        "SourceDefinition": {
+            "description": "Written `{\"<Kind>\": {...}}`",
+            "type": "object",
+            "minProperties": 1,
+            "maxProperties": 1,
+            "properties": {
+                "File": {"type": "object", "required": ["path"], "properties": {"path": string}},
+                "Api": {
+                    "type": "object",
+                    "required": ["url"],
+                    "properties": {"url": string, "headers": {"type": "object", "additionalProperties": string}},
+                },
+                "S3": {
+                    "type": "object",
+                    "required": ["url"],
+                    "properties": {"url": string, "options": {"type": "object", "additionalProperties": string}},
+                },
+                "Database": {
+                    "type": "object",
+                    "required": ["connection_string", "query"],
+                    "properties": {"connection_string": string, "query": string},
+                },
+            },
+            "additionalProperties": false,
+        },
+        "SourceRequest": {
+            "type": "object",
+            "required": ["id", "definition"],
+            "properties": {"id": string, "definition": schema_ref("SourceDefinition")},
+        },
+        "Source": {
+            "type": "object",
+            "properties": {
+                "id": string,
+                "record_count": integer,
+                "version": integer,
+                "loaded_at": time,
+                "spilled": {"type": "boolean", "description": "The records are on disk, spilled or in the storage backend, until something reads them"},
+                "definition": schema_ref("SourceDefinition"),
+                "schema": {"type": "array", "items": schema_ref("Column")},
+                "schema_drift": schema_ref("SchemaDrift"),
+            },
+        },
+        "Column": {
+            "type": "object",
+            "required": ["name", "field_type"],
+            "properties": {
+                "name": string,
+                "field_type": {"type": "string", "enum": ["Boolean", "Integer", "Float", "Date", "Timestamp", "String", "Json"]},
+                "nullable": {"type": "boolean"},
+            },
+        },
+        "SourceVersion": {
+            "type": "object",
+            "properties": {
+                "version": integer,
+                "record_count": integer,
+                "loaded_at": time,
+                "current": {"type": "boolean"},
+                "spilled": {"type": "boolean"},
+            },
+        },
+        "VersionDiff": {
+            "type": "object",
+            "properties": {
+                "source_id": string,
+                "from": integer,
+                "to": integer,
+                "key_field": string,
+                "added": integer,
+                "removed": integer,
+                "changed": integer,
+                "unchanged": integer,
+                "added_records": {"type": "array", "items": schema_ref("Object")},
+                "removed_records": {"type": "array", "items": schema_ref("Object")},
+                "changed_records": {
+                    "type": "array",
+                    "items": {"type": "object", "properties": {"before": schema_ref("Object"), "after": schema_ref("Object")}},
+                },
+            },
+        },
+        "ExpectedSchema": {
+            "type": "object",
+            "required": ["columns"],
+            "properties": {
+                "columns": {"type": "array", "items": schema_ref("Column")},
+                "allow_extra_fields": {"type": "boolean"},
+                "on_violation": {"type": "string", "enum": ["reject", "quarantine"]},
+            },
+        },
+        "SchemaDrift": {
+            "type": "object",
+            "properties": {
+                "checked": integer,
+                "nonconforming": integer,
+                "rejected": integer,
+                "quarantined": integer,
+                "not_objects": integer,
+                "missing": {"type": "object", "additionalProperties": integer},
+                "mistyped": {
+                    "type": "object",
+                    "additionalProperties": {
+                        "type": "object",
+                        "properties": {"records": integer, "expected": string, "found": string},
+                    },
+                },
+                "unexpected": {"type": "object", "additionalProperties": integer},
+                "updated_at": time,
+            },
+        },
+        "QuarantinedRecord": {
+            "type": "object",
+            "properties": {
+                "record": schema_ref("DataRecord"),
+                "violations": {"type": "array", "items": string},
+                "quarantined_at": time,
+            },
+        },
+        "IndexSpec": {
+            "type": "object",
+            "required": ["field"],
+            "properties": {
+                "field": string,
+                "kind": {"type": "string", "enum": ["hash", "sorted"], "default": "hash"},
+            },
+        },
+        "Index": {
+            "type": "object",
+            "properties": {
+                "field": string,
+                "kind": {"type": "string", "enum": ["hash", "sorted"]},
+                "distinct_values": integer,
+                "records": integer,
+            },
+        },
+        "SourcePage": {
+            "type": "object",
+            "properties": {
+                "source_id": string,
+                "version": integer,
+                "total": integer,
+                "count": integer,
+                "offset": integer,
+                "truncated": {"type": "boolean"},
+                "next_offset": integer,
+                "records": {"type": "array", "items": schema_ref("DataRecord")},
+            },
+        },
+        "RecordPage": {
+            "type": "object",
+            "properties": {
+                "total": integer,
+                "count": integer,
+                "offset": integer,
+                "truncated": {"type": "boolean"},
+                "next_offset": integer,
+                "records": {"type": "array", "items": schema_ref("DataRecord")},
+            },
+        },
+        "AdHocQuery": {
+            "type": "object",
+            "required": ["source_id"],
+            "properties": {
+                "source_id": string,
+                "operations": {"type": "array", "items": schema_ref("Operation")},
+                "max_input": integer,
+                "timeout_seconds": integer,
+                "locale": schema_ref("Object"),
+                "null_semantics": schema_ref("Object"),
+                "limit": integer,
+                "offset": integer,
+            },
+        },
+        "AdHocQueryResults": {
+            "type": "object",
+            "properties": {
+                "source_id": string,
+                "source_version": integer,
+                "input_records": integer,
+                "input_truncated": {"type": "boolean"},
+                "total": integer,
+                "count": integer,
+                "offset": integer,
+                "truncated": {"type": "boolean"},
+                "next_offset": integer,
+                "elapsed_ms": integer,
+                "results": {"type": "array", "items": schema_ref("Object")},
+                "records": {"type": "array", "items": schema_ref("DataRecord")},
+            },
+        },
+        "SavedQuery": {
+            "type": "object",
+            "required": ["name", "source_id"],
+            "properties": {
+                "id": string,
+                "name": string,
+                "source_id": string,
+                "filter": schema_ref("Object"),
+                "fields": {"type": "array", "items": string},
+                "limit": integer,
+                "created_at": time,
+            },
+        },
+        "AdminStatus": {
+            "type": "object",
+            "properties": {
+                "scheduler_paused": {"type": "boolean"},
+                "draining": {"type": "boolean"},
+                "queue_depth": integer,
+                "queue_capacity": integer,
+                "waiting_jobs": integer,
+                "pending_jobs": integer,
+                "running_jobs": integer,
+            },
+        },
+        "AdminChange": {
+            "type": "object",
+            "properties": {
+                "changed": {"type": "boolean", "description": "False when the setting was already so"},
+                "status": schema_ref("AdminStatus"),
+            },
+        },
+        "SystemMetrics": {
+            "type": "object",
+            "properties": {
+                "cpu_usage": {"type": "number"},
+                "memory_usage": {"type": "number"},
+                "disk_usage": {"type": "number"},
+                "active_jobs": integer,
+                "total_records_processed": integer,
+                "average_processing_time_ms": {"type": "number"},
+                "error_rate": {"type": "number"},
+                "uptime_seconds": integer,
+                "catch_up_pending": integer,
+                "catch_up_completed": integer,
+            },
+        },
+    })
+}
+
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for route in ROUTES {
+        let path = paths.entry(route.path).or_insert_with(|| json!({}));
+        path[route.method] = operation(route);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Rust Data Processor",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Paths are relative to `/api/v1`. The same paths without the prefix answer as version 1 too, \
+                but are deprecated; their replies carry `Deprecation` and a `Link` to the versioned path. \
+                Every reply carries `X-Request-Id` and `traceparent`, continuing the request's own when it sends them.",
+        },
+        "servers": [{"url": "/api/v1"}],
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "bearerFormat": "JWT",
+                    "description": "A token from the identity provider, checked when the server runs with `--auth`",
+                },
+            },
+        },
+    })
+}
+
+/// Swagger UI, loaded from a CDN and pointed at `/openapi.json`.
+pub const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Rust Data Processor API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
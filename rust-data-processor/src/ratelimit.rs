@@ -0,0 +1,63 @@
+//! Per-client request rate limiting. Each client (its API key, or its IP address when it sends
+//! none or one no tenant has) has a token bucket holding up to `burst` requests and refilled at `per_second`; a
+//! request finding the bucket empty is refused with the time until a token is back.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before idle ones are dropped; a full bucket is the same as no bucket.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32) -> Result<Self, String> {
+        if !per_second.is_finite() || per_second <= 0.0 {
+            return Err("rate limit must be a positive number of requests per second".to_string());
+        }
+        if burst == 0 {
+            return Err("rate limit burst must be at least 1".to_string());
+        }
+        Ok(Self { per_second, burst: burst as f64, buckets: Mutex::new(HashMap::new()) })
+    }
+
+    /// Takes a token from the client's bucket, or says how long until one is available.
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            let full_after = self.burst / self.per_second;
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated).as_secs_f64() < full_after);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket { tokens: self.burst, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+    }
+}
+
+/// Routes left unlimited so orchestrators can always probe the service.
+pub fn exempt(path: &str) -> bool {
+    matches!(path, "/health" | "/health/live" | "/health/ready" | "/readyz")
+}
+
+/// A refused request, carrying the seconds to wait before retrying.
+#[derive(Debug)]
+pub struct Limited(pub u64);
+
+impl warp::reject::Reject for Limited {}
@@ -0,0 +1,468 @@
+//! Typed schemas of sources: each data field's type and whether records may lack a value for
+//! it. A source's schema is inferred from all its records as they are loaded, and text that
+//! reads as the inferred type (CSV cells, numbers quoted in JSON) is coerced to it, so Sort,
+//! Aggregate and Validate see numbers, booleans and ISO 8601 dates rather than strings.
+//!
+//! A source may also have an expected schema registered. Records loaded or appended to it are
+//! coerced to the expected types, and those that still do not conform are rejected or
+//! quarantined; every deviation is tallied in the source's drift report.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use crate::DataRecord;
+
+/// Quarantined records kept per source; past it the oldest are dropped
+pub const MAX_QUARANTINED_RECORDS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColumnType {
+    Boolean,
+    Integer,
+    Float,
+    /// `YYYY-MM-DD`
+    Date,
+    /// `YYYY-MM-DDTHH:MM:SS`, with a `Z` when the source gave an offset
+    Timestamp,
+    String,
+    /// Arrays and objects, or a mix of them with other values; never coerced
+    Json,
+}
+
+impl ColumnType {
+    /// The narrowest type holding values of both types.
+    fn widen(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Integer, ColumnType::Float) | (ColumnType::Float, ColumnType::Integer) => ColumnType::Float,
+            (ColumnType::Date, ColumnType::Timestamp) | (ColumnType::Timestamp, ColumnType::Date) => ColumnType::Timestamp,
+            (ColumnType::Json, _) | (_, ColumnType::Json) => ColumnType::Json,
+            _ => ColumnType::String,
+        }
+    }
+
+    /// The type a value reads as; `None` for null and blank text, which fit any type.
+    fn of(value: &Value) -> Option<ColumnType> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(ColumnType::Boolean),
+            Value::Number(n) if n.is_i64() || n.is_u64() => Some(ColumnType::Integer),
+            Value::Number(_) => Some(ColumnType::Float),
+            Value::String(text) => Self::of_text(text.trim()),
+            Value::Array(_) | Value::Object(_) => Some(ColumnType::Json),
+        }
+    }
+
+    fn of_text(text: &str) -> Option<ColumnType> {
+        if text.is_empty() {
+            return None;
+        }
+        Some(if parse_bool(text).is_some() {
+            ColumnType::Boolean
+        } else if let Some(number) = parse_number(text) {
+            Self::of(&Value::Number(number))?
+        } else if parse_date(text).is_some() {
+            ColumnType::Date
+        } else if parse_timestamp(text).is_some() {
+            ColumnType::Timestamp
+        } else {
+            ColumnType::String
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Column {
+    pub name: String,
+    pub field_type: ColumnType,
+    /// Some records have no value for the field: it is missing, null or blank. In an expected
+    /// schema, whether records may lack one.
+    #[serde(default)]
+    pub nullable: bool,
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    match text.to_ascii_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Plain decimal numbers only, so `1e3`, `0x1F` and `NaN` stay text, as do integers with
+/// leading zeros (`007`), which are usually codes. Numbers keep the digits they were written with.
+fn parse_number(text: &str) -> Option<Number> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+    let all_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !all_digits(integer) || (integer.len() > 1 && integer.starts_with('0')) || fraction.is_some_and(|f| !all_digits(f)) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+}
+
+/// RFC 3339 timestamps are converted to UTC; those without an offset are kept as written.
+fn parse_timestamp(text: &str) -> Option<String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+        return Some(timestamp.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::AutoSi, true));
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|timestamp| timestamp.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+}
+
+#[derive(Debug, Default)]
+struct Observed {
+    field_type: Option<ColumnType>,
+    /// Records with the field, null or not
+    present: usize,
+    null: bool,
+}
+
+/// Fields in the order records first have them.
+#[derive(Debug, Default)]
+struct Inference {
+    columns: Vec<(String, Observed)>,
+    index: HashMap<String, usize>,
+    records: usize,
+}
+
+impl Inference {
+    fn column(&mut self, name: &str) -> &mut Observed {
+        let position = match self.index.get(name) {
+            Some(position) => *position,
+            None => {
+                self.index.insert(name.to_string(), self.columns.len());
+                self.columns.push((name.to_string(), Observed::default()));
+                self.columns.len() - 1
+            },
+        };
+        &mut self.columns[position].1
+    }
+
+    fn observe(mut self, record: &DataRecord) -> Self {
+        let Value::Object(fields) = &record.data else {
+            return self;
+        };
+        self.records += 1;
+        for (name, value) in fields {
+            let value_type = ColumnType::of(value);
+            let column = self.column(name);
+            column.present += 1;
+            column.null |= value_type.is_none();
+            column.field_type = match (column.field_type, value_type) {
+                (Some(a), Some(b)) => Some(a.widen(b)),
+                (a, b) => a.or(b),
+            };
+        }
+        self
+    }
+
+    fn merge(mut self, other: Inference) -> Self {
+        self.records += other.records;
+        for (name, observed) in other.columns {
+            let column = self.column(&name);
+            column.present += observed.present;
+            column.null |= observed.null;
+            column.field_type = match (column.field_type, observed.field_type) {
+                (Some(a), Some(b)) => Some(a.widen(b)),
+                (a, b) => a.or(b),
+            };
+        }
+        self
+    }
+
+    fn finish(self) -> Vec<Column> {
+        let records = self.records;
+        self.columns.into_iter()
+            .map(|(name, observed)| Column {
+                name,
+                // Fields that are always null could hold anything
+                field_type: observed.field_type.unwrap_or(ColumnType::String),
+                nullable: observed.null || observed.present < records,
+            })
+            .collect()
+    }
+}
+
+/// The schema of the top-level data fields of `records`.
+pub fn infer(records: &[DataRecord]) -> Vec<Column> {
+    records.par_iter()
+        .fold(Inference::default, Inference::observe)
+        .reduce(Inference::default, Inference::merge)
+        .finish()
+}
+
+/// The schema of a source after records with schema `appended` were added to records with
+/// schema `existing`. Types only widen, so earlier records keep fitting.
+pub fn merge(existing: &[Column], appended: &[Column]) -> Vec<Column> {
+    let mut merged: Vec<Column> = existing.iter()
+        .map(|column| match appended.iter().find(|other| other.name == column.name) {
+            Some(other) => Column {
+                name: column.name.clone(),
+                field_type: column.field_type.widen(other.field_type),
+                nullable: column.nullable || other.nullable,
+            },
+            None => Column { nullable: true, ..column.clone() },
+        })
+        .collect();
+    for column in appended.iter().filter(|column| !existing.iter().any(|other| other.name == column.name)) {
+        merged.push(Column { nullable: true, ..column.clone() });
+    }
+    merged
+}
+
+/// Converts a value to the column's type where it reads as one; others are left as they are.
+fn coerce(value: &mut Value, field_type: ColumnType) {
+    let coerced = match (field_type, &*value) {
+        (ColumnType::String, Value::Bool(_) | Value::Number(_)) => Some(Value::String(value.to_string())),
+        (ColumnType::String | ColumnType::Json, _) => None,
+        (_, Value::String(text)) if text.trim().is_empty() => Some(Value::Null),
+        (ColumnType::Boolean, Value::String(text)) => parse_bool(text.trim()).map(Value::Bool),
+        (ColumnType::Integer | ColumnType::Float, Value::String(text)) => parse_number(text.trim()).map(Value::Number),
+        (ColumnType::Date, Value::String(text)) => {
+            parse_date(text.trim()).map(|date| Value::String(date.format("%Y-%m-%d").to_string()))
+        },
+        (ColumnType::Timestamp, Value::String(text)) => parse_timestamp(text.trim())
+            .or_else(|| parse_date(text.trim()).map(|date| date.format("%Y-%m-%dT00:00:00").to_string()))
+            .map(Value::String),
+        _ => None,
+    };
+    if let Some(coerced) = coerced {
+        *value = coerced;
+    }
+}
+
+/// Coerces the fields of `records` to the types of `schema`.
+pub fn coerce_records(records: &mut [DataRecord], schema: &[Column]) {
+    let columns: Vec<&Column> = schema.iter().filter(|column| column.field_type != ColumnType::Json).collect();
+    if columns.is_empty() {
+        return;
+    }
+    records.par_iter_mut().for_each(|record| {
+        for column in &columns {
+            if let Some(value) = record.data.get_mut(&column.name) {
+                coerce(value, column.field_type);
+            }
+        }
+    });
+}
+
+/// What happens to records that do not conform to a source's expected schema.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnViolation {
+    /// Dropped, and reported as rejected to the client that appended them
+    #[default]
+    Reject,
+    /// Kept aside with their violations, for `GET /sources/{id}/quarantine`
+    Quarantine,
+}
+
+/// The schema registered for a source, which records must conform to as they land.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpectedSchema {
+    pub columns: Vec<Column>,
+    /// Records may have fields the schema does not list
+    #[serde(default)]
+    pub allow_extra_fields: bool,
+    #[serde(default)]
+    pub on_violation: OnViolation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    NotAnObject,
+    /// A field that is not nullable is missing, null or blank
+    Missing(String),
+    Mistyped { field: String, expected: ColumnType, found: ColumnType },
+    Unexpected(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::NotAnObject => write!(f, "record is not a JSON object"),
+            Violation::Missing(field) => write!(f, "missing field {}", field),
+            Violation::Mistyped { field, expected, found } => write!(f, "field {} is {:?}, expected {:?}", field, found, expected),
+            Violation::Unexpected(field) => write!(f, "unexpected field {}", field),
+        }
+    }
+}
+
+impl ExpectedSchema {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.columns.is_empty() {
+            return Err("Expected schema must list at least one column".to_string());
+        }
+        for (position, column) in self.columns.iter().enumerate() {
+            if column.name.is_empty() {
+                return Err("Column names must be non-empty".to_string());
+            }
+            if self.columns[..position].iter().any(|other| other.name == column.name) {
+                return Err(format!("Column {} is listed twice", column.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Coerces each record's fields to the expected types, and lists how each record still does
+    /// not conform; empty for records that do.
+    pub fn check(&self, records: &mut [DataRecord]) -> Vec<Vec<Violation>> {
+        records.par_iter_mut().map(|record| self.check_record(record)).collect()
+    }
+
+    fn check_record(&self, record: &mut DataRecord) -> Vec<Violation> {
+        let Value::Object(fields) = &mut record.data else {
+            return vec![Violation::NotAnObject];
+        };
+        let mut violations = Vec::new();
+        for column in &self.columns {
+            let Some(value) = fields.get_mut(&column.name) else {
+                if !column.nullable {
+                    violations.push(Violation::Missing(column.name.clone()));
+                }
+                continue;
+            };
+            coerce(value, column.field_type);
+            match ColumnType::of(value) {
+                None if !column.nullable => violations.push(Violation::Missing(column.name.clone())),
+                Some(found) if found.widen(column.field_type) != column.field_type => violations.push(Violation::Mistyped {
+                    field: column.name.clone(),
+                    expected: column.field_type,
+                    found,
+                }),
+                _ => {},
+            }
+        }
+        if !self.allow_extra_fields {
+            for name in fields.keys().filter(|name| !self.columns.iter().any(|column| &column.name == *name)) {
+                violations.push(Violation::Unexpected(name.clone()));
+            }
+        }
+        violations
+    }
+}
+
+/// A field whose values did not read as the expected type.
+#[derive(Debug, Clone, Serialize)]
+pub struct TypeDrift {
+    pub records: usize,
+    pub expected: ColumnType,
+    /// The narrowest type holding every nonconforming value
+    pub found: ColumnType,
+}
+
+/// How the records that landed in a source since its expected schema was registered, or since
+/// it was last replaced, deviated from the schema.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SchemaDrift {
+    pub checked: usize,
+    pub nonconforming: usize,
+    /// Nonconforming records that were dropped
+    pub rejected: usize,
+    pub quarantined: usize,
+    /// Records that were not objects
+    pub not_objects: usize,
+    /// Fields that are not nullable, with how many records lacked each
+    pub missing: BTreeMap<String, usize>,
+    pub mistyped: BTreeMap<String, TypeDrift>,
+    /// Fields the schema does not list, with how many records had each
+    pub unexpected: BTreeMap<String, usize>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl SchemaDrift {
+    pub fn tally(&mut self, violations: &[Vec<Violation>], on_violation: OnViolation) {
+        self.checked += violations.len();
+        for record in violations.iter().filter(|record| !record.is_empty()) {
+            self.nonconforming += 1;
+            match on_violation {
+                OnViolation::Reject => self.rejected += 1,
+                OnViolation::Quarantine => self.quarantined += 1,
+            }
+            for violation in record {
+                match violation {
+                    Violation::NotAnObject => self.not_objects += 1,
+                    Violation::Missing(field) => *self.missing.entry(field.clone()).or_default() += 1,
+                    Violation::Mistyped { field, expected, found } => {
+                        let drift = self.mistyped.entry(field.clone())
+                            .or_insert(TypeDrift { records: 0, expected: *expected, found: *found });
+                        drift.records += 1;
+                        drift.found = drift.found.widen(*found);
+                    },
+                    Violation::Unexpected(field) => *self.unexpected.entry(field.clone()).or_default() += 1,
+                }
+            }
+        }
+        self.updated_at = Some(Utc::now());
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedRecord {
+    pub record: DataRecord,
+    pub violations: Vec<String>,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+/// A source's expected schema with what enforcing it has turned up.
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub expected: ExpectedSchema,
+    pub drift: SchemaDrift,
+    pub quarantine: VecDeque<QuarantinedRecord>,
+}
+
+impl Registration {
+    pub fn new(expected: ExpectedSchema) -> Self {
+        Self { expected, drift: SchemaDrift::default(), quarantine: VecDeque::new() }
+    }
+
+    /// Keeps the records that conform, coerced to the expected types, and quarantines the rest
+    /// when the schema says so. Returns why each record given was refused, or `None` when it
+    /// was kept. Records that `replace` a source start its drift report over.
+    pub fn enforce(&mut self, records: &mut Vec<DataRecord>, replace: bool) -> Vec<Option<String>> {
+        let violations = self.expected.check(records);
+        if replace {
+            self.drift = SchemaDrift::default();
+        }
+        self.drift.tally(&violations, self.expected.on_violation);
+
+        let mut refusals = Vec::with_capacity(violations.len());
+        let mut kept = Vec::with_capacity(records.len());
+        for (record, violations) in records.drain(..).zip(violations) {
+            if violations.is_empty() {
+                kept.push(record);
+                refusals.push(None);
+                continue;
+            }
+            let violations: Vec<String> = violations.iter().map(Violation::to_string).collect();
+            let outcome = match self.expected.on_violation {
+                OnViolation::Reject => "rejected",
+                OnViolation::Quarantine => "quarantined",
+            };
+            refusals.push(Some(format!("Does not conform to the source's schema ({}): {}", outcome, violations.join("; "))));
+            if self.expected.on_violation == OnViolation::Quarantine {
+                if self.quarantine.len() >= MAX_QUARANTINED_RECORDS {
+                    self.quarantine.pop_front();
+                }
+                self.quarantine.push_back(QuarantinedRecord { record, violations, quarantined_at: Utc::now() });
+            }
+        }
+        *records = kept;
+        refusals
+    }
+}
@@ -0,0 +1,202 @@
+//! Fixed-size summaries behind the approximate aggregate functions: distinct counts
+//! (HyperLogLog), quantiles (t-digest) and heavy hitters (count-min sketch). Each uses a bounded
+//! amount of memory per group no matter how many values it sees.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+fn hash_with_seed<T: Hash + ?Sized>(seed: u64, value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Distinct-count estimator with `2^precision` one-byte registers; the standard error is
+/// about `1.04 / sqrt(2^precision)` (1.6% at the default precision of 12).
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub const MIN_PRECISION: u8 = 4;
+    pub const MAX_PRECISION: u8 = 16;
+
+    pub fn new(precision: u8) -> Self {
+        let precision = precision.clamp(Self::MIN_PRECISION, Self::MAX_PRECISION);
+        Self { precision, registers: vec![0; 1 << precision] }
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, value: &T) {
+        let hash = hash_with_seed(0, value);
+        let index = (hash >> (64 - self.precision)) as usize;
+        // The sentinel bit caps the rank when the remaining bits are all zero
+        let remaining = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = remaining.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|rank| 2f64.powi(-(*rank as i32))).sum();
+        let raw = alpha * m * m / sum;
+
+        let zeros = self.registers.iter().filter(|rank| **rank == 0).count();
+        // Linear counting is more accurate while many registers are still empty
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+/// Merging t-digest: values are buffered and periodically folded into centroids whose size
+/// shrinks towards the tails, so extreme quantiles stay accurate.
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    total: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for TDigest {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Self {
+        Self {
+            compression,
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            total: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn insert(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        self.buffer.push(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.buffer.len() >= (self.compression as usize) * 5 {
+            self.compress();
+        }
+    }
+
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut points: Vec<(f64, f64)> = std::mem::take(&mut self.centroids);
+        points.extend(self.buffer.drain(..).map(|value| (value, 1.0)));
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self.total = points.iter().map(|(_, weight)| weight).sum();
+
+        let mut merged: Vec<(f64, f64)> = Vec::with_capacity(self.compression as usize * 2);
+        let mut cumulative = 0.0;
+        for (mean, weight) in points {
+            if let Some(last) = merged.last_mut() {
+                let q = (cumulative + (last.1 + weight) / 2.0) / self.total;
+                let limit = (4.0 * self.total * q * (1.0 - q) / self.compression).max(1.0);
+                if last.1 + weight <= limit {
+                    last.0 += (mean - last.0) * weight / (last.1 + weight);
+                    last.1 += weight;
+                    continue;
+                }
+                cumulative += last.1;
+            }
+            merged.push((mean, weight));
+        }
+        self.centroids = merged;
+    }
+
+    /// Estimates the `q` quantile (0.0-1.0); `None` until a value has been inserted.
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        self.compress();
+        let first = self.centroids.first()?;
+        if self.centroids.len() == 1 {
+            return Some(first.0);
+        }
+
+        let target = q.clamp(0.0, 1.0) * self.total;
+        let mut previous = (0.0, self.min);
+        let mut cumulative = 0.0;
+        for (mean, weight) in &self.centroids {
+            let center = cumulative + weight / 2.0;
+            if target < center {
+                let span = center - previous.0;
+                let fraction = if span > 0.0 { (target - previous.0) / span } else { 0.0 };
+                return Some(previous.1 + (mean - previous.1) * fraction);
+            }
+            previous = (center, *mean);
+            cumulative += weight;
+        }
+
+        let span = self.total - previous.0;
+        let fraction = if span > 0.0 { (target - previous.0) / span } else { 1.0 };
+        Some(previous.1 + (self.max - previous.1) * fraction)
+    }
+}
+
+/// Heavy hitters: a count-min sketch estimates every value's frequency while only the `k`
+/// values with the highest estimates are kept by name.
+pub struct TopK {
+    k: usize,
+    width: usize,
+    counts: Vec<u64>,
+    candidates: HashMap<String, u64>,
+}
+
+impl TopK {
+    const DEPTH: usize = 4;
+    /// Largest `k` tracked; the sketch is 64 counters wide per tracked value.
+    pub const MAX_K: usize = 10_000;
+
+    pub fn new(k: usize) -> Self {
+        let k = k.min(Self::MAX_K);
+        let width = k.checked_mul(64).map_or(Self::MAX_K * 64, |width| width.clamp(1024, Self::MAX_K * 64));
+        Self { k, width, counts: vec![0; width * Self::DEPTH], candidates: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, value: &str) {
+        let mut estimate = u64::MAX;
+        for row in 0..Self::DEPTH {
+            let cell = row * self.width + (hash_with_seed(row as u64 + 1, value) % self.width as u64) as usize;
+            self.counts[cell] += 1;
+            estimate = estimate.min(self.counts[cell]);
+        }
+
+        if let Some(count) = self.candidates.get_mut(value) {
+            *count = estimate;
+        } else if self.candidates.len() < self.k {
+            self.candidates.insert(value.to_string(), estimate);
+        } else if let Some((smallest, count)) = self.candidates.iter().min_by_key(|(_, count)| **count) {
+            if estimate > *count {
+                let smallest = smallest.clone();
+                self.candidates.remove(&smallest);
+                self.candidates.insert(value.to_string(), estimate);
+            }
+        }
+    }
+
+    /// Values by descending estimated count; estimates can only overcount.
+    pub fn top(&self) -> Vec<(String, u64)> {
+        let mut top: Vec<(String, u64)> = self.candidates.iter().map(|(value, count)| (value.clone(), *count)).collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top
+    }
+}
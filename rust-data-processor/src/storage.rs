@@ -0,0 +1,306 @@
+//! Where sources' records persist between restarts. By default they live only in memory (and
+//! in spill files, which do not outlive the server). A persistent backend keeps every source's
+//! records on disk as they change, so a restarted server has its sources back without
+//! re-ingesting them, and a source the memory budget pushes out is simply dropped from memory
+//! and read back from the backend when next used.
+//!
+//! Backends store whole records (id, timestamp, source, metadata and data) so reading one back
+//! gives exactly what was stored; dictionary encoding is applied again once it is in memory.
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{DataProcessor, DataRecord, FieldType, ParquetCompression, SchemaField};
+
+/// Records per row group in the Parquet backend's files
+const PARQUET_ROW_GROUP_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StorageBackend {
+    /// Records are kept in memory only and lost on restart
+    Memory,
+    /// An embedded sled database, one tree of records per source
+    Sled,
+    /// A directory of Parquet files per source, one file per load or append
+    Parquet,
+}
+
+/// One source as a backend lists it at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredEntry {
+    pub source_id: String,
+    pub len: usize,
+    /// When records last landed, by a load or an append
+    pub loaded_at: Option<DateTime<Utc>>,
+}
+
+pub trait SourceStorage: Send + Sync + Debug {
+    fn list(&self) -> Result<Vec<StoredEntry>, String>;
+
+    fn load(&self, source_id: &str) -> Result<Vec<DataRecord>, String>;
+
+    /// Replaces the source's records.
+    fn save(&self, source_id: &str, records: &[DataRecord], loaded_at: Option<DateTime<Utc>>) -> Result<(), String>;
+
+    /// Adds records after those already stored.
+    fn append(&self, source_id: &str, records: &[DataRecord], loaded_at: Option<DateTime<Utc>>) -> Result<(), String>;
+
+    fn remove(&self, source_id: &str) -> Result<(), String>;
+}
+
+/// Opens the backend at `path`; `None` for `Memory`.
+pub fn open(backend: StorageBackend, path: &Path) -> Result<Option<Arc<dyn SourceStorage>>, String> {
+    Ok(match backend {
+        StorageBackend::Memory => None,
+        StorageBackend::Sled => Some(Arc::new(SledStorage::open(path)?)),
+        StorageBackend::Parquet => Some(Arc::new(ParquetStorage::open(path)?)),
+    })
+}
+
+/// Sources' entries live in the `sources` tree; each source's records in `records/<id>`, keyed
+/// by their position as big-endian integers so they iterate in order.
+#[derive(Debug)]
+pub struct SledStorage {
+    db: sled::Db,
+    entries: sled::Tree,
+}
+
+impl SledStorage {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let db = sled::open(path).map_err(|e| format!("Could not open sled database {}: {}", path.display(), e))?;
+        let entries = db.open_tree("sources").map_err(|e| e.to_string())?;
+        Ok(SledStorage { db, entries })
+    }
+
+    fn records(&self, source_id: &str) -> Result<sled::Tree, String> {
+        self.db.open_tree(format!("records/{}", source_id)).map_err(|e| e.to_string())
+    }
+
+    fn entry(&self, source_id: &str) -> Result<Option<StoredEntry>, String> {
+        self.entries.get(source_id)
+            .map_err(|e| e.to_string())?
+            .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+            .transpose()
+    }
+
+    /// Writes records from position `start` and the source's entry, then flushes both.
+    fn write(&self, source_id: &str, records: &[DataRecord], start: usize, loaded_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        let tree = self.records(source_id)?;
+        let mut batch = sled::Batch::default();
+        for (index, record) in records.iter().enumerate() {
+            let value = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+            batch.insert(((start + index) as u64).to_be_bytes().to_vec(), value);
+        }
+        tree.apply_batch(batch).map_err(|e| e.to_string())?;
+        let entry = StoredEntry { source_id: source_id.to_string(), len: start + records.len(), loaded_at };
+        self.entries.insert(source_id, serde_json::to_vec(&entry).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        self.db.flush().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+impl SourceStorage for SledStorage {
+    fn list(&self) -> Result<Vec<StoredEntry>, String> {
+        self.entries.iter()
+            .values()
+            .map(|bytes| serde_json::from_slice(&bytes.map_err(|e| e.to_string())?).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    fn load(&self, source_id: &str) -> Result<Vec<DataRecord>, String> {
+        self.records(source_id)?
+            .iter()
+            .values()
+            .map(|bytes| serde_json::from_slice(&bytes.map_err(|e| e.to_string())?).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    fn save(&self, source_id: &str, records: &[DataRecord], loaded_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        self.records(source_id)?.clear().map_err(|e| e.to_string())?;
+        self.write(source_id, records, 0, loaded_at)
+    }
+
+    fn append(&self, source_id: &str, records: &[DataRecord], loaded_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        let start = self.entry(source_id)?.map_or(0, |entry| entry.len);
+        self.write(source_id, records, start, loaded_at)
+    }
+
+    fn remove(&self, source_id: &str) -> Result<(), String> {
+        self.entries.remove(source_id).map_err(|e| e.to_string())?;
+        self.db.drop_tree(format!("records/{}", source_id)).map_err(|e| e.to_string())?;
+        self.db.flush().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// What `manifest.json` in a source's directory records: its entry and the files holding its
+/// records, in order.
+#[derive(Debug, Serialize, Deserialize)]
+struct ParquetManifest {
+    #[serde(flatten)]
+    entry: StoredEntry,
+    parts: Vec<String>,
+}
+
+/// Each source is a directory, named by its hex-encoded id so any id makes a valid name,
+/// holding a manifest and Parquet files. A record's data and metadata are stored as JSON text
+/// columns, since a source's fields need not keep one type across records.
+#[derive(Debug)]
+pub struct ParquetStorage {
+    root: PathBuf,
+}
+
+impl ParquetStorage {
+    pub fn open(root: &Path) -> Result<Self, String> {
+        std::fs::create_dir_all(root).map_err(|e| format!("Could not create storage directory {}: {}", root.display(), e))?;
+        Ok(ParquetStorage { root: root.to_path_buf() })
+    }
+
+    fn dir(&self, source_id: &str) -> PathBuf {
+        self.root.join(hex::encode(source_id))
+    }
+
+    fn manifest(dir: &Path) -> Result<Option<ParquetManifest>, String> {
+        let path = dir.join("manifest.json");
+        match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).map(Some).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Replaces the manifest through a rename, so a crash leaves either the old or the new one.
+    fn write_manifest(dir: &Path, manifest: &ParquetManifest) -> Result<(), String> {
+        let staged = dir.join("manifest.json.tmp");
+        let mut writer = BufWriter::new(File::create(&staged).map_err(|e| e.to_string())?);
+        serde_json::to_writer(&mut writer, manifest).map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())?;
+        drop(writer);
+        std::fs::rename(&staged, dir.join("manifest.json")).map_err(|e| e.to_string())
+    }
+
+    fn schema() -> Vec<SchemaField> {
+        [("id", FieldType::String), ("timestamp", FieldType::String), ("source", FieldType::String),
+            ("processed", FieldType::Boolean), ("metadata", FieldType::String), ("data", FieldType::String)]
+            .into_iter()
+            .map(|(name, field_type)| SchemaField { name: name.to_string(), field_type })
+            .collect()
+    }
+
+    /// Writes `records` as a new file in `dir`, returning its name.
+    fn write_part(dir: &Path, records: &[DataRecord]) -> Result<String, String> {
+        // The envelope fields are written as they are; the rest go through `data` as JSON text
+        let rows: Vec<DataRecord> = records.iter()
+            .map(|record| DataRecord {
+                data: json!({
+                    "processed": record.processed,
+                    "metadata": serde_json::to_string(&record.metadata).unwrap_or_default(),
+                    "data": serde_json::to_string(&record.data).unwrap_or_default(),
+                }),
+                metadata: Default::default(),
+                ..record.clone()
+            })
+            .collect();
+        let name = format!("part-{}.parquet", Uuid::new_v4());
+        let file = File::create(dir.join(&name)).map_err(|e| e.to_string())?;
+        DataProcessor::encode_parquet(BufWriter::new(file), &rows, &Self::schema(), PARQUET_ROW_GROUP_SIZE, ParquetCompression::Zstd)?
+            .flush()
+            .map_err(|e| e.to_string())?;
+        Ok(name)
+    }
+
+    fn read_part(path: &Path, records: &mut Vec<DataRecord>) -> Result<(), String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let reader = SerializedFileReader::new(file).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for row in reader.get_row_iter(None).map_err(|e| e.to_string())? {
+            let row = row.map_err(|e| e.to_string())?.to_json_value();
+            let text = |name: &str| row.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+            let parse = |name: &str| match row.get(name) {
+                Some(Value::String(text)) => serde_json::from_str(text).map_err(|e| format!("{}: column {}: {}", path.display(), name, e)),
+                Some(value) => Ok(value.clone()),
+                None => Ok(Value::Null),
+            };
+            let timestamp = DateTime::parse_from_rfc3339(&text("timestamp"))
+                .map_err(|e| format!("{}: column timestamp: {}", path.display(), e))?
+                .with_timezone(&Utc);
+            records.push(DataRecord {
+                id: text("id"),
+                timestamp,
+                data: parse("data")?,
+                source: text("source"),
+                processed: row.get("processed").and_then(Value::as_bool).unwrap_or_default(),
+                metadata: serde_json::from_value(parse("metadata")?).unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl SourceStorage for ParquetStorage {
+    fn list(&self) -> Result<Vec<StoredEntry>, String> {
+        let entries = std::fs::read_dir(&self.root).map_err(|e| format!("{}: {}", self.root.display(), e))?;
+        let mut listed = Vec::new();
+        for entry in entries {
+            let dir = entry.map_err(|e| e.to_string())?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            if let Some(manifest) = Self::manifest(&dir)? {
+                listed.push(manifest.entry);
+            }
+        }
+        Ok(listed)
+    }
+
+    fn load(&self, source_id: &str) -> Result<Vec<DataRecord>, String> {
+        let dir = self.dir(source_id);
+        let manifest = Self::manifest(&dir)?.ok_or_else(|| format!("Source {} is not stored", source_id))?;
+        let mut records = Vec::with_capacity(manifest.entry.len);
+        for part in &manifest.parts {
+            Self::read_part(&dir.join(part), &mut records)?;
+        }
+        Ok(records)
+    }
+
+    fn save(&self, source_id: &str, records: &[DataRecord], loaded_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        let dir = self.dir(source_id);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let previous = Self::manifest(&dir)?;
+        let part = Self::write_part(&dir, records)?;
+        let entry = StoredEntry { source_id: source_id.to_string(), len: records.len(), loaded_at };
+        Self::write_manifest(&dir, &ParquetManifest { entry, parts: vec![part] })?;
+        // The old files are only removed once the new manifest no longer names them
+        for part in previous.into_iter().flat_map(|manifest| manifest.parts) {
+            let _ = std::fs::remove_file(dir.join(part));
+        }
+        Ok(())
+    }
+
+    fn append(&self, source_id: &str, records: &[DataRecord], loaded_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        let dir = self.dir(source_id);
+        let Some(mut manifest) = Self::manifest(&dir)? else {
+            return self.save(source_id, records, loaded_at);
+        };
+        manifest.parts.push(Self::write_part(&dir, records)?);
+        manifest.entry.len += records.len();
+        manifest.entry.loaded_at = loaded_at;
+        Self::write_manifest(&dir, &manifest)
+    }
+
+    fn remove(&self, source_id: &str) -> Result<(), String> {
+        match std::fs::remove_dir_all(self.dir(source_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! HTTPS without a reverse proxy. The server either reads a certificate and key from files
+//! (optionally requiring or accepting client certificates signed by a given CA), or obtains
+//! and renews one from an ACME directory such as Let's Encrypt, answering the TLS-ALPN-01
+//! challenge on its own port.
+
+use std::io;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use futures::Stream;
+use rustls_acme::caches::DirCache;
+use rustls_acme::AcmeConfig;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+
+/// Whether clients must present a certificate signed by the client CA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ClientAuth {
+    /// Connections without a certificate are accepted; one that is presented must verify
+    Optional,
+    Required,
+}
+
+#[derive(Debug, Clone)]
+pub struct AcmeSettings {
+    pub domains: Vec<String>,
+    /// Email addresses the ACME directory may send expiry notices to
+    pub contacts: Vec<String>,
+    /// Where the account key and certificates are kept between restarts
+    pub cache_dir: PathBuf,
+    /// Use the directory's staging environment, whose certificates browsers do not trust
+    pub staging: bool,
+}
+
+/// Connections accepted on `listener`, each wrapped in TLS with a certificate kept current
+/// through ACME. Polling the stream also drives ordering and renewing the certificate.
+pub fn acme_incoming(
+    listener: TcpListener,
+    settings: AcmeSettings,
+) -> impl Stream<Item = io::Result<impl AsyncRead + AsyncWrite + Send + Unpin + 'static>> + Send {
+    let tcp = futures::stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await.map(|(stream, _)| stream);
+        Some((accepted, listener))
+    });
+    AcmeConfig::new(settings.domains)
+        .contact(settings.contacts.iter().map(|email| format!("mailto:{}", email)))
+        .cache(DirCache::new(settings.cache_dir))
+        .directory_lets_encrypt(!settings.staging)
+        .tokio_incoming(Box::pin(tcp), vec![b"http/1.1".to_vec()])
+}
@@ -0,0 +1,119 @@
+//! Request correlation. Every request gets an id, taken from its `X-Request-Id` when the
+//! client sends a usable one, and joins the W3C trace of its `traceparent`, or starts a new one.
+//! Both are echoed on the reply, stamped on the jobs a request submits and carried on those jobs'
+//! log lines, so a job that fails in the background can be traced back to the call that
+//! started it.
+
+use std::convert::Infallible;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::http::HeaderValue;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::error::ApiError;
+
+/// Longest `X-Request-Id` taken from a client; longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub request_id: String,
+    /// 32 lowercase hex digits
+    pub trace_id: String,
+    /// This server's span in the trace, 16 lowercase hex digits
+    pub span_id: String,
+    /// The caller's span, when it sent a `traceparent`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub sampled: bool,
+    /// The client chose `request_id`
+    #[serde(skip)]
+    supplied: bool,
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    hex::encode(buffer)
+}
+
+/// Parses `00-<trace id>-<parent id>-<flags>`, refusing the all-zero ids the spec forbids.
+fn parse_traceparent(value: &str) -> Option<(String, String, bool)> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let hex = |text: &str, len: usize| {
+        text.len() == len && text.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) && text.bytes().any(|b| b != b'0')
+    };
+    if version.len() != 2 || version == "ff" || !hex(trace_id, 32) || !hex(parent_id, 16) || flags.len() != 2 {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some((trace_id.to_string(), parent_id.to_string(), flags & 1 == 1))
+}
+
+impl TraceContext {
+    pub fn from_headers(request_id: Option<&str>, traceparent: Option<&str>) -> Self {
+        let request_id = request_id
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()));
+        let (trace_id, parent_id, sampled) = match traceparent.and_then(parse_traceparent) {
+            Some((trace_id, parent_id, sampled)) => (trace_id, Some(parent_id), sampled),
+            None => (random_hex(16), None, false),
+        };
+        TraceContext {
+            supplied: request_id.is_some(),
+            request_id: request_id.map_or_else(|| Uuid::new_v4().to_string(), str::to_string),
+            trace_id,
+            span_id: random_hex(8),
+            parent_id,
+            sampled,
+        }
+    }
+
+    /// The `traceparent` naming this server's span, for replies and calls made on the trace's behalf.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, u8::from(self.sampled))
+    }
+
+    /// How log lines name the request and trace.
+    pub fn log_label(&self) -> String {
+        format!("request_id={} trace_id={}", self.request_id, self.trace_id)
+    }
+
+    /// Echoes the request id and trace on a reply. A request id the client chose replaces the
+    /// reply's, an error body's included; otherwise one a handler already put on the reply, such
+    /// as an error's own, is kept.
+    pub fn decorate(&self, reply: impl Reply) -> Response {
+        let mut response = reply.into_response();
+        if self.supplied {
+            if let Some(error) = response.extensions_mut().remove::<ApiError>() {
+                response = error.with_request_id(self.request_id.clone()).into_response();
+            }
+        }
+        let headers = response.headers_mut();
+        if !headers.contains_key("x-request-id") || self.supplied {
+            if let Ok(value) = HeaderValue::from_str(&self.request_id) {
+                headers.insert("x-request-id", value);
+            }
+        }
+        // A handler that stamped a job with its own context has put it on the reply already
+        if !headers.contains_key("traceparent") {
+            if let Ok(value) = HeaderValue::from_str(&self.traceparent()) {
+                headers.insert("traceparent", value);
+            }
+        }
+        response
+    }
+}
+
+/// The request's trace context, read from its headers.
+pub fn context() -> impl Filter<Extract = (TraceContext,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: warp::http::HeaderMap| {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        TraceContext::from_headers(header("x-request-id"), header("traceparent"))
+    })
+}
@@ -0,0 +1,86 @@
+//! API versions. Every route is served under `/api/v{N}` for each supported version, and a
+//! reply says which version answered it in `API-Version`. The unversioned paths predate
+//! versioning: they keep answering as version 1 for existing automation, but reply with
+//! `Deprecation` and a `Link` to their versioned successor. A client may also ask for a version
+//! with `Accept-Version`, which must agree with the path's when both are given.
+//!
+//! Routes are declared once, unversioned, and mounted under every version; a later version
+//! that changes a request or reply branches on the `ApiVersion` `negotiate` settles on.
+
+use warp::http::HeaderValue;
+use warp::reply::Response;
+use warp::Reply;
+
+/// Versions served, oldest first; the last is the current one
+pub const SUPPORTED: &[u32] = &[1];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiVersion {
+    pub number: u32,
+    /// Reached through an unversioned path
+    pub legacy: bool,
+}
+
+/// A version the server does not serve, or a path and `Accept-Version` that disagree.
+#[derive(Debug)]
+pub struct Unsupported(pub String);
+
+impl warp::reject::Reject for Unsupported {}
+
+/// The version named by a path's `/api/v{N}` prefix, and the rest of the path.
+fn split(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix("/api/v")?;
+    let end = rest.find('/').unwrap_or(rest.len());
+    Some((&rest[..end], &rest[end..]))
+}
+
+/// The route path without any version prefix, as the route table and access rules name it.
+pub fn unversioned(path: &str) -> &str {
+    match split(path) {
+        Some((_, "")) => "/",
+        Some((_, rest)) => rest,
+        None => path,
+    }
+}
+
+fn parse(version: &str) -> Result<u32, Unsupported> {
+    version.trim().trim_start_matches(['v', 'V']).parse::<u32>()
+        .ok()
+        .filter(|number| SUPPORTED.contains(number))
+        .ok_or_else(|| Unsupported(format!("API version {} is not supported", version.trim())))
+}
+
+/// Decides which version answers a request, from its path and `Accept-Version` header.
+pub async fn negotiate(path: warp::path::FullPath, accept_version: Option<String>) -> Result<ApiVersion, warp::Rejection> {
+    let requested = accept_version.as_deref().map(parse).transpose().map_err(warp::reject::custom)?;
+    let version = match split(path.as_str()) {
+        Some((version, _)) => {
+            let number = parse(version).map_err(warp::reject::custom)?;
+            if requested.is_some_and(|requested| requested != number) {
+                return Err(warp::reject::custom(Unsupported(format!(
+                    "Accept-Version {} does not match the path's version {}", requested.unwrap_or_default(), number,
+                ))));
+            }
+            ApiVersion { number, legacy: false }
+        },
+        // Unversioned paths answer as the first version unless the client asks for another
+        None => ApiVersion { number: requested.unwrap_or(SUPPORTED[0]), legacy: true },
+    };
+    Ok(version)
+}
+
+/// Marks a reply with the version that produced it, and an unversioned one as deprecated in
+/// favour of its versioned path.
+pub fn decorate(version: ApiVersion, path: &str, reply: impl Reply) -> Response {
+    let mut response = reply.into_response();
+    let headers = response.headers_mut();
+    headers.insert("api-version", HeaderValue::from(version.number));
+    if version.legacy {
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        let successor = format!("</api/v{}{}>; rel=\"successor-version\"", version.number, path);
+        if let Ok(value) = HeaderValue::from_str(&successor) {
+            headers.insert("link", value);
+        }
+    }
+    response
+}
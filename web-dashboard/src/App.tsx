@@ -120,6 +120,14 @@ interface Job {
   input_count: number;
   processed_count: number;
   error_count: number;
+  comments?: JobComment[];
+}
+
+interface JobComment {
+  id: string;
+  author: string;
+  text: string;
+  created_at: string;
 }
 
 interface SystemMetrics {
@@ -131,6 +139,8 @@ interface SystemMetrics {
   average_processing_time_ms: number;
   error_rate: number;
   uptime_seconds: number;
+  catch_up_pending?: number;
+  catch_up_completed?: number;
 }
 
 interface CodeTemplate {